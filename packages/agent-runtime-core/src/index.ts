@@ -183,6 +183,7 @@ export type ToolErrorCode =
   | "RETRY_EXHAUSTED"
   | "VALIDATION_ERROR"
   | "PROMPT_INJECTION_BLOCKED"
+  | "CANCELLED"
   | "DUPLICATE_FAILED_ACTION"; // Manus spec: prevent repeating exact same failed action
 
 // ============================================================================
@@ -1050,6 +1051,8 @@ export interface ToolEvent {
   outputHash: string;
   durationMs: number;
   createdAt: number;
+  /** The full tool gateway audit event, as JSON. */
+  auditRecord?: string;
 }
 
 export interface ModelEvent {
@@ -1143,6 +1146,66 @@ export interface McpServerConfig {
   url?: string;
   manifest?: McpManifest;
   sandbox?: SandboxConfig;
+  /**
+   * Fetch tools via `tools/list` on registration and when the server reports changes.
+   * Discovered tools keep the annotations of the manifest tool with the same name.
+   */
+  discoverTools?: boolean;
+  /**
+   * Annotations for discovered tools that the manifest does not list. Without them such a
+   * tool needs a grant for its tool id (`serverId:name`).
+   */
+  toolAccess?: McpAccessRequirements;
+  /** Grants needed to read or subscribe to the server's resources. */
+  resourceAccess?: McpAccessRequirements;
+  /** Grants needed to get the server's prompts. */
+  promptAccess?: McpAccessRequirements;
+  /** Keep a stdio server running: ping it and restart it when it crashes or hangs. */
+  supervision?: McpSupervisionConfig;
+}
+
+export interface McpSupervisionConfig {
+  pingIntervalMs?: number;
+  /** How long a start or a ping may take. */
+  pingTimeoutMs?: number;
+  /** Failed pings in a row after which a degraded server is restarted. */
+  maxPingFailures?: number;
+  /** Delay before the first restart, doubled for each one after up to `maxBackoffMs`. */
+  restartBackoffMs?: number;
+  maxBackoffMs?: number;
+  /**
+   * Restarts allowed before the server is given up on and its calls are refused. A server
+   * that stays up for longer than `maxBackoffMs` is allowed them afresh.
+   */
+  maxRestarts?: number;
+}
+
+export type McpServerState = "starting" | "ready" | "degraded" | "crashed";
+
+export interface McpServerHealth {
+  serverId: string;
+  /** Only set for supervised servers. */
+  state?: McpServerState;
+  calls: number;
+  failures: number;
+  p95LatencyMs?: number;
+  restarts: number;
+  lastError?: string;
+}
+
+export interface McpServerLifecycleEvent {
+  sequence: number;
+  serverId: string;
+  state: McpServerState;
+  reason?: string;
+  createdAt: number;
+}
+
+/** Checked against capability grants the same way as a tool's annotations. */
+export interface McpAccessRequirements {
+  requiredScopes?: string[];
+  policyAction?: CoworkPolicyActionLike;
+  requiresConfirmation?: boolean;
 }
 
 export interface ToolRegistryEntry {
@@ -1158,6 +1221,38 @@ export interface CapabilityGrant {
   expiresAt?: number;
   scope?: string;
   approvalId?: string;
+  constraints?: GrantConstraints;
+  /** Calls that have used the grant so far (read-only). */
+  uses?: number;
+}
+
+/** Narrows what a grant covers; a grant whose constraints reject a call does not count. */
+export interface GrantConstraints {
+  /** Only calls from this run may use the grant. */
+  runId?: string;
+  /** Uses allowed over the grant's lifetime. */
+  maxInvocations?: number;
+  rateLimit?: { maxInvocations: number; windowMs: number };
+  /** Every listed argument must be present and match. */
+  arguments?: ArgumentConstraint[];
+}
+
+export interface ArgumentConstraint {
+  /** A top-level argument name, or a JSON pointer such as `/target/path`. */
+  argument: string;
+  /** `*` matches within a path segment, `**` across segments; paths are normalized first. */
+  pathGlobs?: string[];
+  /** Hosts or URLs; a pattern matches the host and its subdomains, `*.` only subdomains. */
+  hostPatterns?: string[];
+}
+
+/** A grant a call used, with its count after the call. */
+export interface GrantConsumption {
+  grantId: string;
+  capability: string;
+  uses: number;
+  /** Uses left under `maxInvocations`, when the grant has one. */
+  remaining?: number;
 }
 
 export interface ToolInvocation {
@@ -1168,35 +1263,253 @@ export interface ToolInvocation {
   grantIds: string[];
   redactKeys?: string[];
   timeoutMs?: number;
+  /** Repair common type mistakes (such as numbers sent as strings) before schema validation. */
+  coerceArguments?: boolean;
+  /** What the call touches, for tools with a `policyAction`. */
+  policyContext?: Omit<CoworkPolicyInputLike, "action">;
+}
+
+export interface ToolPolicyConfig {
+  /** Evaluated against each tool's `policyAction`. */
+  coworkPolicy?: CoworkPolicyConfig;
+  /** Park calls that need confirmation until `resolveApproval` instead of rejecting them. */
+  approvals?: boolean;
+  approvalTimeoutMs?: number;
+}
+
+export interface ToolPolicyDecision {
+  decision: CoworkPolicyDecisionType;
+  reason: string;
+  riskTags: CoworkRiskTag[];
+  ruleId?: string;
+}
+
+export interface ToolApprovalRequest {
+  approvalId: string;
+  toolId: string;
+  requestId: string;
+  runId?: string;
+  arguments: Record<string, unknown>;
+  reason: string;
+  policy?: ToolPolicyDecision;
+  requestedAt: number;
+  expiresAt: number;
+}
+
+export interface ToolApprovalDecisionInput {
+  approvalId: string;
+  /** `edit` approves the call with replacement `arguments`, which are validated again. */
+  action: "approve" | "deny" | "edit";
+  arguments?: Record<string, unknown>;
+  reason?: string;
+}
+
+export interface ToolApprovalRecord {
+  approvalId: string;
+  outcome: "approved" | "denied" | "edited" | "expired" | "cancelled";
+  reason?: string;
+  decidedAt: number;
+  /** Hash of the arguments an edit substituted; `inputHash` covers the originals. */
+  editedInputHash?: string;
+}
+
+/**
+ * `progress` and `partial` events may repeat, after an `approval` event if the call had to
+ * wait for one; the stream ends with one `result`.
+ */
+export interface ToolStreamEvent {
+  type: "progress" | "partial" | "approval" | "result";
+  progress?: number;
+  total?: number;
+  message?: string;
+  /** Content the server attached to a progress notification, ahead of the result. */
+  content?: ToolContent[];
+  approval?: ToolApprovalRequest;
+  result?: MCPToolResult;
+}
+
+export interface ToolStreamHandle {
+  next(): Promise<ToolStreamEvent | null>;
+  /** Sends `notifications/cancelled`; the stream ends with a `CANCELLED` result. */
+  cancel(): void;
 }
 
 export interface ToolAuditEvent {
   sequence: number;
   toolId: string;
   requestId: string;
+  runId?: string;
   grantIds: string[];
   inputHash: string;
   outputHash: string;
+  /** The redacted input and output, when payload capture is on. */
+  input?: unknown;
+  output?: unknown;
   success: boolean;
   durationMs: number;
   createdAt: number;
+  policyDecision?: ToolPolicyDecision;
+  approval?: ToolApprovalRecord;
+  consumedGrants: GrantConsumption[];
+  /** Size of the result content before it was truncated to fit the tool's limit. */
+  originalResultBytes?: number;
+  /** The hash of the event before this one, chaining the log. */
+  prevHash: string;
+  /** SHA-256 of the event without this field. */
+  hash: string;
+}
+
+/**
+ * Caps on tool result size. Oversize content is truncated and the full content spilled to
+ * an artifact file, referenced from the result as a `resource` item.
+ */
+export interface ToolResultLimits {
+  default?: ToolResultLimit;
+  /** Keyed by tool id; replaces the default for that tool. */
+  tools?: Record<string, ToolResultLimit>;
+  /** Defaults to `tool-gateway-artifacts` in the system temp directory. */
+  artifactDir?: string;
+}
+
+export interface ToolResultLimit {
+  maxBytes?: number;
+  /** Estimated at four bytes per token. */
+  maxTokens?: number;
+  /** Which part of the text is kept. Defaults to `head`. */
+  strategy?: ToolTruncationStrategy;
+}
+
+export type ToolTruncationStrategy = "head" | "tail" | "middle_elide";
+
+export type AuditSinkConfig =
+  | { type: "file"; path: string }
+  | { type: "persistence_store"; dbPath: string; encryptionKeyRef?: string };
+
+export interface ToolAuditConfig {
+  /** Record redacted inputs and outputs alongside their hashes. */
+  capturePayloads?: boolean;
+  /** Where events are persisted as they are recorded. An existing log is continued. */
+  sink?: AuditSinkConfig;
+}
+
+export interface AuditChainReport {
+  valid: boolean;
+  /** How many events verified before the first broken one. */
+  checked: number;
+  brokenAt?: number;
+  error?: string;
 }
 
 export interface ToolGatewaySnapshot {
   tools: ToolRegistryEntry[];
   grants: CapabilityGrant[];
   auditCursor: number;
+  discoveryFailures: ToolDiscoveryFailure[];
+  catalogs: McpServerCatalog[];
+  resourceSubscriptions: McpResourceSubscription[];
+  pendingApprovals: ToolApprovalRequest[];
+  /** The last error writing to the audit sink, if any write failed. */
+  auditSinkError?: string;
+  servers: McpServerHealth[];
+  /** The most recent state changes of supervised servers, oldest first. */
+  lifecycleEvents: McpServerLifecycleEvent[];
+}
+
+export interface ToolDiscoveryFailure {
+  serverId: string;
+  error: string;
+  failedAt: number;
+}
+
+export interface McpResource {
+  uri: string;
+  name: string;
+  title?: string;
+  description?: string;
+  mimeType?: string;
+  size?: number;
+  annotations?: Record<string, unknown>;
+}
+
+export interface McpResourceTemplate {
+  /** RFC 6570 URI template, e.g. `file:///{path}`. */
+  uriTemplate: string;
+  name: string;
+  title?: string;
+  description?: string;
+  mimeType?: string;
+  annotations?: Record<string, unknown>;
+}
+
+export interface McpPrompt {
+  name: string;
+  title?: string;
+  description?: string;
+  arguments?: Array<{ name: string; description?: string; required?: boolean }>;
+}
+
+/** Lists last fetched for a server; lists never requested are absent. */
+export interface McpServerCatalog {
+  serverId: string;
+  resources?: McpResource[];
+  resourceTemplates?: McpResourceTemplate[];
+  prompts?: McpPrompt[];
+}
+
+export interface McpResourceSubscription {
+  serverId: string;
+  uri: string;
+  subscribedAt: number;
+  updatedAt?: number;
+  /** Bumped on each `notifications/resources/updated`. */
+  updateCount: number;
+}
+
+export interface McpResourceRequest {
+  serverId: string;
+  uri: string;
+  requestId: string;
+  runId?: string;
+  grantIds: string[];
+  redactKeys?: string[];
+  timeoutMs?: number;
+}
+
+export interface McpPromptRequest {
+  serverId: string;
+  name: string;
+  arguments?: Record<string, string>;
+  requestId: string;
+  runId?: string;
+  grantIds: string[];
+  redactKeys?: string[];
+  timeoutMs?: number;
 }
 
 // ============================================================================
 // Model Fabric Types (Track AS)
 // ============================================================================
 
-export type MessageRole = "system" | "user" | "assistant";
+export type MessageRole = "system" | "user" | "assistant" | "tool";
+
+export type ImageSource =
+  | { type: "base64"; mediaType: string; data: string }
+  | { type: "url"; url: string };
+
+export type ContentPart =
+  | { type: "text"; text: string }
+  | { type: "image"; source: ImageSource }
+  | { type: "tool_use"; id: string; name: string; input: unknown }
+  | { type: "tool_result"; toolUseId: string; content: string; isError?: boolean }
+  | { type: "thinking"; thinking: string; signature?: string };
 
 export interface Message {
   role: MessageRole;
-  content: string;
+  content: string | ContentPart[];
+  /** Call answered by a `tool` message. */
+  toolCallId?: string;
+  /** Tool calls previously issued by an `assistant` message. */
+  toolCalls?: ToolCall[];
 }
 
 export interface Tool {
@@ -1215,6 +1528,20 @@ export interface TokenUsage {
   inputTokens: number;
   outputTokens: number;
   totalTokens: number;
+  /** Portion of `inputTokens` served from the provider prompt cache. */
+  cachedInputTokens?: number;
+}
+
+export type ResponseFormatType = "json_object" | "json_schema";
+
+export interface ResponseFormat {
+  type: ResponseFormatType;
+  name?: string;
+  /** JSON Schema (2020-12 subset) the content must satisfy; required for `json_schema`. */
+  schema?: Record<string, unknown>;
+  strict?: boolean;
+  /** Retry once with the validation errors fed back when the output does not validate. */
+  repair?: boolean;
 }
 
 export interface CompletionRequest {
@@ -1226,6 +1553,12 @@ export interface CompletionRequest {
   tools?: Tool[];
   topP?: number;
   timeoutMs?: number;
+  /** Structured output mode; supported by `complete()` only. */
+  responseFormat?: ResponseFormat;
+  /** Gemini native only: name of a `cachedContents/...` resource to reuse. */
+  cachedContent?: string;
+  /** Forces the response cache on or off, overriding the temperature-0 rule. */
+  cache?: boolean;
 }
 
 export type FinishReason = "stop" | "length" | "tool_calls" | "content_filter" | "error";
@@ -1233,13 +1566,47 @@ export type FinishReason = "stop" | "length" | "tool_calls" | "content_filter" |
 export interface CompletionResponse {
   content: string;
   toolCalls?: ToolCall[];
+  /** Assistant turn as ordered parts, ready to be sent back in the next request. */
+  contentParts?: ContentPart[];
+  /** Validated JSON content when `responseFormat` was requested. */
+  parsed?: unknown;
   usage: TokenUsage;
   finishReason: FinishReason;
   model: string;
   latencyMs: number;
-}
-
-export type StreamChunkType = "content" | "tool_call" | "usage" | "done" | "error";
+  /** Gemini native only: per-category safety ratings of the returned candidate. */
+  safetyRatings?: SafetyRating[];
+}
+
+export interface SafetyRating {
+  category: string;
+  probability: string;
+  blocked?: boolean;
+}
+
+export type StreamChunkType =
+  | "content"
+  | "thinking"
+  | "tool_call"
+  | "usage"
+  | "done"
+  | "error"
+  | "fallback"
+  | "cancelled";
+
+export type ModelFabricErrorCode =
+  | "auth"
+  | "rate_limited"
+  | "overloaded"
+  | "timeout"
+  | "network"
+  | "invalid_request"
+  | "context_length_exceeded"
+  | "budget_exceeded"
+  | "output_validation"
+  | "cancelled"
+  | "queue_timeout"
+  | "unknown";
 
 export interface StreamChunk {
   type: StreamChunkType;
@@ -1247,21 +1614,79 @@ export interface StreamChunk {
   toolCall?: ToolCall;
   usage?: TokenUsage;
   error?: string;
+  errorCode?: ModelFabricErrorCode;
   finishReason?: FinishReason;
+  /** Model that took over the stream; set on `fallback` chunks. */
+  model?: string;
+  /** Signature closing a thinking block; set on the last `thinking` chunk of a block. */
+  signature?: string;
 }
 
 export type ProviderKind = "openai" | "anthropic" | "gemini" | "local";
 
+/** Wire API spoken by a provider; `native` selects the Gemini or Ollama API. */
+export type ProviderApi = "openai_compatible" | "native";
+
 export interface ProviderConfigRecord {
   providerId: string;
   kind: ProviderKind;
-  authRef: string;
+  /**
+   * `env:NAME`, `file:/path` or `secret:KEY` (persistence store secret, see
+   * `configureCredentials`). A bare value is read as an env var name; it is never used
+   * as the key itself. Optional for native `local` providers, which send no key
+   * without it.
+   */
+  authRef?: string;
+  /** Required for `local` providers, except native ones, which default to http://localhost:11434. */
   baseUrl?: string;
   timeoutMs?: number;
   maxRetries?: number;
+  retryBaseDelayMs?: number;
+  retryMaxDelayMs?: number;
+  circuitFailureThreshold?: number;
+  circuitCooldownMs?: number;
   organizationId?: string;
   modelIds: string[];
   defaultModelId?: string;
+  contextWindows?: Record<string, number>;
+  api?: ProviderApi;
+  /** Ollama native only: how long the model stays loaded, e.g. "5m". */
+  keepAlive?: string;
+  /** Gemini native only: passed through as `safetySettings`. */
+  safetySettings?: Record<string, unknown>[];
+  /** Provider-wide limits; requests over them queue in arrival order. */
+  limits?: RateLimits;
+  /** Per-model limits, applied on top of `limits`. */
+  modelLimits?: Record<string, RateLimits>;
+  /** Queued requests fail with `queue_timeout` after this long; unset waits. */
+  maxQueueWaitMs?: number;
+}
+
+export interface RateLimits {
+  maxInFlight?: number;
+  requestsPerMinute?: number;
+  tokensPerMinute?: number;
+}
+
+export interface LimiterSnapshot {
+  /** `providerId`, or `providerId/modelId` for a per-model limit. */
+  key: string;
+  inFlight: number;
+  queued: number;
+  requestsLastMinute: number;
+  tokensLastMinute: number;
+}
+
+export type RouteStrategy = "priority" | "weighted" | "lowest_latency" | "cheapest";
+
+/** Context field that pins a weighted split to one arm. */
+export type StickyKey = "run_id" | "worker_id" | "task_type";
+
+export interface RouteArm {
+  modelId: string;
+  weight: number;
+  /** Name reported on usage events; defaults to `modelId`. */
+  armId?: string;
 }
 
 export interface RouteRule {
@@ -1271,33 +1696,162 @@ export interface RouteRule {
   taskType?: string;
   modelId: string;
   fallbackModelIds?: string[];
+  /** Defaults to `priority`: `modelId`, then `fallbackModelIds` in order. */
+  strategy?: RouteStrategy;
+  arms?: RouteArm[];
+  stickyKey?: StickyKey;
 }
 
 export interface ModelUsageEvent {
   eventId: string;
   providerId: string;
   modelId: string;
+  runId?: string;
   inputTokens: number;
   outputTokens: number;
   totalTokens: number;
+  cachedInputTokens?: number;
   latencyMs: number;
   costUsd?: number;
+  /** Served from the response cache; no provider was called and `costUsd` is 0. */
+  cacheHit: boolean;
+  /** The stream was cancelled; token counts cover what arrived before that. */
+  cancelled: boolean;
+  /** Time spent queued for provider capacity before the call went out. */
+  queueMs: number;
+  /** Strategy of the route that picked the model. */
+  routeStrategy?: RouteStrategy;
+  /** Arm id (or model id) the route chose. */
+  routeArm?: string;
   createdAt: number;
 }
 
+export interface ModelPricing {
+  modelId: string;
+  inputPerMillionUsd: number;
+  outputPerMillionUsd: number;
+  cachedInputPerMillionUsd?: number;
+  /** Epoch milliseconds from which this price applies. */
+  effectiveFrom?: number;
+}
+
+export type BudgetScope = "global" | "worker" | "task_type" | "run";
+
+export type BudgetAction = "reject" | "downgrade";
+
+export interface BudgetRule {
+  budgetId: string;
+  /** How consumption is partitioned: one ledger per worker, task type or run. */
+  scope: BudgetScope;
+  workerId?: string;
+  taskType?: string;
+  maxTokens?: number;
+  maxCostUsd?: number;
+  /** Rolling window; omit for a lifetime budget. */
+  windowMs?: number;
+  action?: BudgetAction;
+  /** Must be served by a loaded provider; it replaces the route's primary model. */
+  downgradeModelId?: string;
+}
+
+export interface BudgetUsageSnapshot {
+  budgetId: string;
+  partition: string;
+  tokens: number;
+  costUsd: number;
+}
+
+export type ProviderCircuitState = "closed" | "open" | "half_open";
+
+export interface ProviderHealthSnapshot {
+  providerId: string;
+  circuitState: ProviderCircuitState;
+  consecutiveFailures: number;
+  totalRequests: number;
+  totalFailures: number;
+  lastError?: string;
+  lastErrorCode?: ModelFabricErrorCode;
+  lastErrorAt?: number;
+  openedAt?: number;
+  latencyP50Ms?: number;
+  latencyP95Ms?: number;
+}
+
 export interface ModelFabricSnapshot {
   providers: ProviderConfigRecord[];
   routes: RouteRule[];
   usageCursor: number;
+  providerHealth: ProviderHealthSnapshot[];
+  pricing: ModelPricing[];
+  budgets: BudgetRule[];
+  budgetUsage: BudgetUsageSnapshot[];
+  cassette?: CassetteStatus;
+  responseCache?: ResponseCacheStatus;
+  limiters: LimiterSnapshot[];
+}
+
+export interface CredentialStoreConfig {
+  /** Persistence store database whose `secrets` table backs `secret:` auth refs. */
+  dbPath: string;
+  /** Env var (or literal) holding the store's 32-byte AES-256-GCM key. */
+  encryptionKeyRef?: string;
+}
+
+export interface ResponseCacheConfig {
+  /** Entries older than this are misses; unset keeps them until evicted. */
+  ttlMs?: number;
+  /** Least recently used entries are evicted beyond this bound (default 1000). */
+  maxEntries?: number;
+  /** Directory for the on-disk backend; entries are reloaded on configure. */
+  path?: string;
+  /** Also cache requests with a temperature above 0. */
+  anyTemperature?: boolean;
+}
+
+export interface ResponseCacheStatus {
+  entries: number;
+  maxEntries: number;
+  ttlMs?: number;
+  path?: string;
+  hits: number;
+  misses: number;
+}
+
+/** `record` writes every provider attempt to the cassette; `replay` serves them offline. */
+export type CassetteMode = "record" | "replay";
+
+/**
+ * How replayed requests are matched: `strict` compares model, messages, tools and
+ * sampling; `messages` ignores sampling; `sequential` serves recorded order per model.
+ */
+export type CassetteMatch = "strict" | "messages" | "sequential";
+
+export interface CassetteConfig {
+  mode: CassetteMode;
+  path: string;
+  match?: CassetteMatch;
+}
+
+export interface CassetteStatus {
+  mode: CassetteMode;
+  path: string;
+  match: CassetteMatch;
+  interactions: number;
+  replayed: number;
 }
 
 export interface ModelFabricContext {
   workerId?: string;
   taskType?: string;
+  runId?: string;
+  /** Id accepted by `ModelFabric.cancel()` while the request runs. */
+  requestId?: string;
 }
 
 export interface ModelStreamHandle {
   next(): Promise<StreamChunk | null>;
+  /** Drops the provider connection; the stream ends with a `cancelled` chunk. */
+  cancel(): void;
 }
 
 // ============================================================================
@@ -3,6 +3,7 @@ use aes_gcm::{Aes256Gcm, Nonce};
 use base64::{engine::general_purpose::STANDARD as BASE64, Engine as _};
 use napi::bindgen_prelude::Result as NapiResult;
 use napi::Error as NapiError;
+#[cfg(feature = "node")]
 use napi_derive::napi;
 use rand::RngCore;
 use rusqlite::{params, Connection};
@@ -70,115 +71,121 @@ const MIGRATIONS: &[&str] = &[
     CREATE INDEX IF NOT EXISTS idx_task_runs_status ON task_runs(status);
     CREATE INDEX IF NOT EXISTS idx_task_runs_started_at ON task_runs(started_at);
     "#,
+    r#"
+    ALTER TABLE tool_events ADD COLUMN audit_record TEXT;
+    "#,
 ];
 
-#[napi(object)]
+#[cfg_attr(feature = "node", napi(object))]
 pub struct PersistenceConfig {
-    #[napi(js_name = "dbPath")]
+    #[cfg_attr(feature = "node", napi(js_name = "dbPath"))]
     pub db_path: String,
-    #[napi(js_name = "encryptionKeyRef")]
+    #[cfg_attr(feature = "node", napi(js_name = "encryptionKeyRef"))]
     pub encryption_key_ref: Option<String>,
 }
 
-#[napi(object)]
+#[cfg_attr(feature = "node", napi(object))]
 #[derive(Clone)]
 pub struct TaskRun {
-    #[napi(js_name = "runId")]
+    #[cfg_attr(feature = "node", napi(js_name = "runId"))]
     pub run_id: String,
     pub goal: String,
     pub status: String,
-    #[napi(js_name = "startedAt")]
+    #[cfg_attr(feature = "node", napi(js_name = "startedAt"))]
     pub started_at: i64,
-    #[napi(js_name = "endedAt")]
+    #[cfg_attr(feature = "node", napi(js_name = "endedAt"))]
     pub ended_at: Option<i64>,
     pub metadata: Option<Value>,
 }
 
-#[napi(object)]
+#[cfg_attr(feature = "node", napi(object))]
 #[derive(Clone)]
 pub struct ToolEvent {
-    #[napi(js_name = "eventId")]
+    #[cfg_attr(feature = "node", napi(js_name = "eventId"))]
     pub event_id: String,
-    #[napi(js_name = "runId")]
+    #[cfg_attr(feature = "node", napi(js_name = "runId"))]
     pub run_id: String,
-    #[napi(js_name = "toolId")]
+    #[cfg_attr(feature = "node", napi(js_name = "toolId"))]
     pub tool_id: String,
-    #[napi(js_name = "inputHash")]
+    #[cfg_attr(feature = "node", napi(js_name = "inputHash"))]
     pub input_hash: String,
-    #[napi(js_name = "outputHash")]
+    #[cfg_attr(feature = "node", napi(js_name = "outputHash"))]
     pub output_hash: String,
-    #[napi(js_name = "durationMs")]
+    #[cfg_attr(feature = "node", napi(js_name = "durationMs"))]
     pub duration_ms: i64,
-    #[napi(js_name = "createdAt")]
+    #[cfg_attr(feature = "node", napi(js_name = "createdAt"))]
     pub created_at: i64,
+    /// The full audit event as JSON, for callers that keep more than the hashes.
+    #[cfg_attr(feature = "node", napi(js_name = "auditRecord"))]
+    pub audit_record: Option<String>,
 }
 
-#[napi(object)]
+#[cfg_attr(feature = "node", napi(object))]
 #[derive(Clone)]
 pub struct ModelEvent {
-    #[napi(js_name = "eventId")]
+    #[cfg_attr(feature = "node", napi(js_name = "eventId"))]
     pub event_id: String,
-    #[napi(js_name = "runId")]
+    #[cfg_attr(feature = "node", napi(js_name = "runId"))]
     pub run_id: String,
-    #[napi(js_name = "providerId")]
+    #[cfg_attr(feature = "node", napi(js_name = "providerId"))]
     pub provider_id: String,
-    #[napi(js_name = "modelId")]
+    #[cfg_attr(feature = "node", napi(js_name = "modelId"))]
     pub model_id: String,
-    #[napi(js_name = "inputTokens")]
+    #[cfg_attr(feature = "node", napi(js_name = "inputTokens"))]
     pub input_tokens: i64,
-    #[napi(js_name = "outputTokens")]
+    #[cfg_attr(feature = "node", napi(js_name = "outputTokens"))]
     pub output_tokens: i64,
-    #[napi(js_name = "totalTokens")]
+    #[cfg_attr(feature = "node", napi(js_name = "totalTokens"))]
     pub total_tokens: i64,
-    #[napi(js_name = "costUsd")]
+    #[cfg_attr(feature = "node", napi(js_name = "costUsd"))]
     pub cost_usd: Option<f64>,
-    #[napi(js_name = "createdAt")]
+    #[cfg_attr(feature = "node", napi(js_name = "createdAt"))]
     pub created_at: i64,
 }
 
-#[napi(object)]
+#[cfg_attr(feature = "node", napi(object))]
 #[derive(Clone)]
 pub struct WorkspaceEvent {
-    #[napi(js_name = "eventId")]
+    #[cfg_attr(feature = "node", napi(js_name = "eventId"))]
     pub event_id: String,
-    #[napi(js_name = "sessionId")]
+    #[cfg_attr(feature = "node", napi(js_name = "sessionId"))]
     pub session_id: String,
     pub kind: String,
-    #[napi(js_name = "payloadHash")]
+    #[cfg_attr(feature = "node", napi(js_name = "payloadHash"))]
     pub payload_hash: String,
-    #[napi(js_name = "createdAt")]
+    #[cfg_attr(feature = "node", napi(js_name = "createdAt"))]
     pub created_at: i64,
 }
 
-#[napi(object)]
+#[cfg_attr(feature = "node", napi(object))]
 pub struct ExportBundle {
-    #[napi(js_name = "taskRuns")]
+    #[cfg_attr(feature = "node", napi(js_name = "taskRuns"))]
     pub task_runs: Vec<TaskRun>,
-    #[napi(js_name = "toolEvents")]
+    #[cfg_attr(feature = "node", napi(js_name = "toolEvents"))]
     pub tool_events: Vec<ToolEvent>,
-    #[napi(js_name = "modelEvents")]
+    #[cfg_attr(feature = "node", napi(js_name = "modelEvents"))]
     pub model_events: Vec<ModelEvent>,
-    #[napi(js_name = "workspaceEvents")]
+    #[cfg_attr(feature = "node", napi(js_name = "workspaceEvents"))]
     pub workspace_events: Vec<WorkspaceEvent>,
 }
 
-#[napi(object)]
+#[cfg_attr(feature = "node", napi(object))]
 pub struct TaskRunFilter {
-    #[napi(js_name = "runId")]
+    #[cfg_attr(feature = "node", napi(js_name = "runId"))]
     pub run_id: Option<String>,
     pub status: Option<Vec<String>>,
-    #[napi(js_name = "startedAfter")]
+    #[cfg_attr(feature = "node", napi(js_name = "startedAfter"))]
     pub started_after: Option<i64>,
-    #[napi(js_name = "startedBefore")]
+    #[cfg_attr(feature = "node", napi(js_name = "startedBefore"))]
     pub started_before: Option<i64>,
     pub limit: Option<i64>,
 }
 
-#[napi(object)]
+#[cfg_attr(feature = "node", napi(object))]
 pub struct ExportFilter {
-    #[napi(js_name = "runId")]
+    #[cfg_attr(feature = "node", napi(js_name = "runId"))]
     pub run_id: Option<String>,
-    #[napi(js_name = "sessionId")]
+    #[cfg_attr(feature = "node", napi(js_name = "sessionId"))]
     pub session_id: Option<String>,
     pub since: Option<i64>,
     pub until: Option<i64>,
@@ -198,14 +205,14 @@ struct PersistenceState {
     db_path: Option<String>,
 }
 
-#[napi]
+#[cfg_attr(feature = "node", napi)]
 pub struct PersistenceStore {
     state: Arc<Mutex<PersistenceState>>,
 }
 
-#[napi]
+#[cfg_attr(feature = "node", napi)]
 impl PersistenceStore {
-    #[napi(constructor)]
+    #[cfg_attr(feature = "node", napi(constructor))]
     pub fn new() -> Self {
         Self {
             state: Arc::new(Mutex::new(PersistenceState {
@@ -215,7 +222,7 @@ impl PersistenceStore {
         }
     }
 
-    #[napi]
+    #[cfg_attr(feature = "node", napi)]
     pub fn open(&self, config: PersistenceConfig) -> NapiResult<()> {
         if config.db_path.trim().is_empty() {
             return Err(napi_error("dbPath is required"));
@@ -247,7 +254,7 @@ impl PersistenceStore {
         Ok(())
     }
 
-    #[napi(js_name = "saveTaskRun")]
+    #[cfg_attr(feature = "node", napi(js_name = "saveTaskRun"))]
     pub fn save_task_run(&self, task_run: TaskRun) -> NapiResult<()> {
         let conn = self.connection()?;
         let metadata = task_run
@@ -277,7 +284,7 @@ impl PersistenceStore {
         Ok(())
     }
 
-    #[napi(js_name = "updateTaskRunStatus")]
+    #[cfg_attr(feature = "node", napi(js_name = "updateTaskRunStatus"))]
     pub fn update_task_run_status(
         &self,
         run_id: String,
@@ -298,7 +305,7 @@ impl PersistenceStore {
         Ok(())
     }
 
-    #[napi(js_name = "listTaskRuns")]
+    #[cfg_attr(feature = "node", napi(js_name = "listTaskRuns"))]
     pub fn list_task_runs(&self, filter: Option<TaskRunFilter>) -> NapiResult<Vec<TaskRun>> {
         let conn = self.connection()?;
         let mut clauses: Vec<String> = Vec::new();
@@ -378,7 +385,7 @@ impl PersistenceStore {
         Ok(results)
     }
 
-    #[napi(js_name = "saveToolEvent")]
+    #[cfg_attr(feature = "node", napi(js_name = "saveToolEvent"))]
     pub fn save_tool_event(&self, event: ToolEvent) -> NapiResult<()> {
         let conn = self.connection()?;
         conn.execute(
@@ -390,15 +397,17 @@ impl PersistenceStore {
               input_hash,
               output_hash,
               duration_ms,
-              created_at
-            ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+              created_at,
+              audit_record
+            ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
             ON CONFLICT(event_id) DO UPDATE SET
               run_id = excluded.run_id,
               tool_id = excluded.tool_id,
               input_hash = excluded.input_hash,
               output_hash = excluded.output_hash,
               duration_ms = excluded.duration_ms,
-              created_at = excluded.created_at
+              created_at = excluded.created_at,
+              audit_record = COALESCE(excluded.audit_record, tool_events.audit_record)
             "#,
             params![
                 event.event_id,
@@ -407,14 +416,15 @@ impl PersistenceStore {
                 event.input_hash,
                 event.output_hash,
                 event.duration_ms,
-                event.created_at
+                event.created_at,
+                event.audit_record
             ],
         )
         .map_err(to_napi_error)?;
         Ok(())
     }
 
-    #[napi(js_name = "saveModelEvent")]
+    #[cfg_attr(feature = "node", napi(js_name = "saveModelEvent"))]
     pub fn save_model_event(&self, event: ModelEvent) -> NapiResult<()> {
         let conn = self.connection()?;
         conn.execute(
@@ -456,7 +466,7 @@ impl PersistenceStore {
         Ok(())
     }
 
-    #[napi(js_name = "saveWorkspaceEvent")]
+    #[cfg_attr(feature = "node", napi(js_name = "saveWorkspaceEvent"))]
     pub fn save_workspace_event(&self, event: WorkspaceEvent) -> NapiResult<()> {
         let conn = self.connection()?;
         conn.execute(
@@ -486,7 +496,7 @@ impl PersistenceStore {
         Ok(())
     }
 
-    #[napi(js_name = "storeSecret")]
+    #[cfg_attr(feature = "node", napi(js_name = "storeSecret"))]
     pub fn store_secret(&self, key: String, plaintext: String) -> NapiResult<()> {
         let conn = self.connection()?;
         let encryption_key = self.encryption_key()?;
@@ -506,7 +516,7 @@ impl PersistenceStore {
         Ok(())
     }
 
-    #[napi(js_name = "loadSecret")]
+    #[cfg_attr(feature = "node", napi(js_name = "loadSecret"))]
     pub fn load_secret(&self, key: String) -> NapiResult<Option<String>> {
         let conn = self.connection()?;
         let encryption_key = self.encryption_key()?;
@@ -526,7 +536,7 @@ impl PersistenceStore {
         Ok(Some(decrypted))
     }
 
-    #[napi(js_name = "exportBundle")]
+    #[cfg_attr(feature = "node", napi(js_name = "exportBundle"))]
     pub fn export_bundle(&self, filter: Option<ExportFilter>) -> NapiResult<ExportBundle> {
         let conn = self.connection()?;
         let filter = filter.unwrap_or(ExportFilter {
@@ -550,7 +560,7 @@ impl PersistenceStore {
         })
     }
 
-    #[napi]
+    #[cfg_attr(feature = "node", napi)]
     pub fn reset(&self) -> NapiResult<()> {
         let conn = self.connection()?;
         conn.execute_batch(
@@ -597,12 +607,29 @@ impl PersistenceStore {
 }
 
 fn apply_migrations(conn: &Connection) -> NapiResult<()> {
+    // The first migration creates schema_migrations and is safe to re-run.
     conn.execute_batch(MIGRATIONS[0]).map_err(to_napi_error)?;
-    conn.execute(
-        "INSERT OR IGNORE INTO schema_migrations (version) VALUES (1)",
-        [],
-    )
-    .map_err(to_napi_error)?;
+    for (index, migration) in MIGRATIONS.iter().enumerate() {
+        let version = index as i64 + 1;
+        let applied: bool = conn
+            .query_row(
+                "SELECT EXISTS(SELECT 1 FROM schema_migrations WHERE version = ?1)",
+                [version],
+                |row| row.get(0),
+            )
+            .map_err(to_napi_error)?;
+        if applied {
+            continue;
+        }
+        if index > 0 {
+            conn.execute_batch(migration).map_err(to_napi_error)?;
+        }
+        conn.execute(
+            "INSERT INTO schema_migrations (version) VALUES (?1)",
+            [version],
+        )
+        .map_err(to_napi_error)?;
+    }
     Ok(())
 }
 
@@ -675,6 +702,7 @@ fn query_tool_events(conn: &Connection, filter: &ExportFilter) -> NapiResult<Vec
                 output_hash: row.get(4)?,
                 duration_ms: row.get(5)?,
                 created_at: row.get(6)?,
+                audit_record: row.get(7)?,
             })
         })
         .map_err(to_napi_error)?;
@@ -775,7 +803,7 @@ fn build_event_query(
 
     let mut sql = match table {
         "tool_events" => format!(
-            "SELECT event_id, run_id, tool_id, input_hash, output_hash, duration_ms, created_at FROM {table}"
+            "SELECT event_id, run_id, tool_id, input_hash, output_hash, duration_ms, created_at, audit_record FROM {table}"
         ),
         "model_events" => format!(
             "SELECT event_id, run_id, provider_id, model_id, input_tokens, output_tokens, total_tokens, cost_usd, created_at FROM {table}"
@@ -1010,6 +1038,7 @@ mod tests {
                 output_hash: "hash-out".to_string(),
                 duration_ms: 10,
                 created_at: now_ms(),
+                audit_record: None,
             })
             .unwrap();
 
@@ -1,13 +1,26 @@
 import { createRequire } from "node:module";
 import type {
+  AuditChainReport,
   CapabilityGrant,
+  MCPToolResult,
   McpManifest,
+  McpPrompt,
+  McpPromptRequest,
+  McpResource,
+  McpResourceRequest,
+  McpResourceTemplate,
   McpServerConfig,
+  ToolApprovalDecisionInput,
+  ToolApprovalRequest,
+  ToolAuditConfig,
   ToolAuditEvent,
   ToolEvent,
   ToolGatewaySnapshot,
   ToolInvocation,
+  ToolPolicyConfig,
   ToolRegistryEntry,
+  ToolResultLimits,
+  ToolStreamHandle,
 } from "@ku0/agent-runtime-core";
 import type { PersistenceStore } from "@ku0/agent-runtime-persistence";
 import type { NativeToolGatewayBinding, ToolGatewayBinding } from "@ku0/tool-gateway-rs/node";
@@ -91,8 +104,8 @@ export class ToolGateway {
     this.native.registerManifest(manifest);
   }
 
-  registerServer(config: McpServerConfig): void {
-    this.native.registerServer(config);
+  async registerServer(config: McpServerConfig): Promise<void> {
+    await this.native.registerServer(config);
   }
 
   listTools(): ToolRegistryEntry[] {
@@ -100,14 +113,71 @@ export class ToolGateway {
   }
 
   async callTool(invocation: ToolInvocation) {
+    return this.audited(invocation, () => this.native.callTool(invocation));
+  }
+
+  /** Audit events are flushed once the stream yields its result. */
+  async callToolStream(invocation: ToolInvocation): Promise<ToolStreamHandle> {
     if ((this.persistenceStore || this.onAuditEvents) && invocation.runId) {
       this.runIdByRequest.set(invocation.requestId, invocation.runId);
     }
-    try {
-      return await this.native.callTool(invocation);
-    } finally {
-      this.flushAuditEvents();
-    }
+    const handle = await this.native.callToolStream(invocation);
+    return {
+      next: async () => {
+        const event = await handle.next();
+        if (!event || event.type === "result") {
+          this.flushAuditEvents();
+        }
+        return event;
+      },
+      cancel: () => handle.cancel(),
+    };
+  }
+
+  listResources(serverId: string): Promise<McpResource[]> {
+    return this.native.listResources(serverId);
+  }
+
+  listResourceTemplates(serverId: string): Promise<McpResourceTemplate[]> {
+    return this.native.listResourceTemplates(serverId);
+  }
+
+  readResource(request: McpResourceRequest): Promise<MCPToolResult> {
+    return this.audited(request, () => this.native.readResource(request));
+  }
+
+  subscribeResource(request: McpResourceRequest): Promise<MCPToolResult> {
+    return this.audited(request, () => this.native.subscribeResource(request));
+  }
+
+  unsubscribeResource(request: McpResourceRequest): Promise<MCPToolResult> {
+    return this.audited(request, () => this.native.unsubscribeResource(request));
+  }
+
+  listPrompts(serverId: string): Promise<McpPrompt[]> {
+    return this.native.listPrompts(serverId);
+  }
+
+  getPrompt(request: McpPromptRequest): Promise<MCPToolResult> {
+    return this.audited(request, () => this.native.getPrompt(request));
+  }
+
+  setPolicy(config: ToolPolicyConfig): void {
+    this.native.setPolicy(config);
+  }
+
+  /** Caps tool result size; oversize results are truncated and spilled to artifacts. */
+  setResultLimits(limits: ToolResultLimits): void {
+    this.native.setResultLimits(limits);
+  }
+
+  /** Calls parked until `resolveApproval`; streaming calls also emit an `approval` event. */
+  listApprovals(): ToolApprovalRequest[] {
+    return this.native.listApprovals();
+  }
+
+  resolveApproval(decision: ToolApprovalDecisionInput): void {
+    this.native.resolveApproval(decision);
   }
 
   grantCapability(grant: CapabilityGrant): string {
@@ -122,6 +192,16 @@ export class ToolGateway {
     return this.native.drainAuditEvents(after, limit);
   }
 
+  /** Sets payload capture and the sink the hash-chained audit log is persisted to. */
+  configureAudit(config: ToolAuditConfig): Promise<void> {
+    return this.native.configureAudit(config);
+  }
+
+  /** Verifies the given events, or else the persisted log, or else the in-memory one. */
+  verifyAuditChain(events?: ToolAuditEvent[]): Promise<AuditChainReport> {
+    return this.native.verifyAuditChain(events);
+  }
+
   getSnapshot(): ToolGatewaySnapshot {
     return this.native.getSnapshot();
   }
@@ -132,6 +212,20 @@ export class ToolGateway {
     this.native.reset();
   }
 
+  private async audited(
+    request: { requestId: string; runId?: string },
+    run: () => Promise<MCPToolResult>
+  ): Promise<MCPToolResult> {
+    if ((this.persistenceStore || this.onAuditEvents) && request.runId) {
+      this.runIdByRequest.set(request.requestId, request.runId);
+    }
+    try {
+      return await run();
+    } finally {
+      this.flushAuditEvents();
+    }
+  }
+
   private flushAuditEvents(): void {
     if (!this.persistenceStore && !this.onAuditEvents) {
       return;
@@ -165,7 +259,7 @@ export class ToolGateway {
   }
 
   private toToolEvent(event: ToolAuditEvent): ToolEvent {
-    const runId = this.runIdByRequest.get(event.requestId) ?? event.requestId;
+    const runId = event.runId ?? this.runIdByRequest.get(event.requestId) ?? event.requestId;
     this.runIdByRequest.delete(event.requestId);
 
     return {
@@ -176,6 +270,7 @@ export class ToolGateway {
       outputHash: event.outputHash,
       durationMs: event.durationMs,
       createdAt: event.createdAt,
+      auditRecord: JSON.stringify(event),
     };
   }
 }
@@ -81,7 +81,9 @@ export class ModelFabricProvider implements LLMProvider {
     const start = performance.now();
     try {
       const context = this.resolveContext(request);
-      const response = await this.fabric.complete(this.toFabricRequest(request), context);
+      const response = await this.fabric.complete(this.toFabricRequest(request), context, {
+        signal: request.signal,
+      });
       this.recordSuccess(response.usage, response.latencyMs ?? performance.now() - start);
       this.drainUsageEvents();
       return response as CompletionResponse;
@@ -97,20 +99,32 @@ export class ModelFabricProvider implements LLMProvider {
 
     try {
       const context = this.resolveContext(request);
-      const handle = this.fabric.stream(this.toFabricRequest(request), context);
+      const handle = await this.fabric.stream(this.toFabricRequest(request), context);
+      // Aborting, or abandoning the iterator early, drops the provider connection.
+      const cancel = () => handle.cancel();
+      request.signal?.addEventListener("abort", cancel, { once: true });
+      let drained = false;
 
-      for (;;) {
-        const chunk = (await handle.next()) as FabricStreamChunk | null;
-        if (!chunk) {
-          break;
+      try {
+        for (;;) {
+          const chunk = (await handle.next()) as FabricStreamChunk | null;
+          if (!chunk) {
+            drained = true;
+            break;
+          }
+          if (chunk.type === "usage" && chunk.usage) {
+            latestUsage = chunk.usage as CompletionResponse["usage"];
+          }
+          if ((chunk.type === "done" || chunk.type === "cancelled") && chunk.usage) {
+            latestUsage = chunk.usage as CompletionResponse["usage"];
+          }
+          yield chunk as StreamChunk;
         }
-        if (chunk.type === "usage" && chunk.usage) {
-          latestUsage = chunk.usage as CompletionResponse["usage"];
+      } finally {
+        request.signal?.removeEventListener("abort", cancel);
+        if (!drained) {
+          cancel();
         }
-        if (chunk.type === "done" && chunk.usage) {
-          latestUsage = chunk.usage as CompletionResponse["usage"];
-        }
-        yield chunk as StreamChunk;
       }
 
       const latencyMs = performance.now() - start;
@@ -0,0 +1,458 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::sync::{LazyLock, Mutex, PoisonError};
+
+/// Schemas arrive with every request, so the pattern cache is dropped rather than grown
+/// once it holds this many.
+const MAX_CACHED_PATTERNS: usize = 256;
+
+/// Compiled `pattern` keywords by source, `None` for invalid ones.
+static PATTERNS: LazyLock<Mutex<HashMap<String, Option<Regex>>>> =
+    LazyLock::new(Default::default);
+
+/// One failed JSON Schema keyword. `path` points at the offending value (`$` is the
+/// document root), `keyword` names the schema keyword that rejected it.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SchemaViolation {
+    pub path: String,
+    pub keyword: String,
+    pub message: String,
+}
+
+impl SchemaViolation {
+    fn new(path: &str, keyword: &str, message: impl Into<String>) -> Self {
+        Self {
+            path: path.to_string(),
+            keyword: keyword.to_string(),
+            message: message.into(),
+        }
+    }
+}
+
+/// Validates `instance` against the subset of JSON Schema 2020-12 that structured output
+/// and tool input schemas use: `type`, `enum`, `const`, string/number/array/object bounds, `properties`,
+/// `required`, `additionalProperties`, `items`/`prefixItems`, the combinators and local
+/// `$ref`s. Unknown keywords are ignored, as the specification requires.
+pub fn validate(schema: &Value, instance: &Value) -> Vec<SchemaViolation> {
+    let mut violations = Vec::new();
+    let validator = Validator {
+        root: schema,
+        entered: RefCell::default(),
+    };
+    validator.check(schema, instance, "$", &mut violations);
+    violations
+}
+
+struct Validator<'a> {
+    root: &'a Value,
+    /// The `$ref` targets being checked and the value each is checked against. Entering one
+    /// again means the references loop without getting any further into the value.
+    entered: RefCell<Vec<(*const Value, *const Value)>>,
+}
+
+impl Validator<'_> {
+    fn check(&self, schema: &Value, instance: &Value, path: &str, out: &mut Vec<SchemaViolation>) {
+        let schema = match schema {
+            Value::Bool(true) => return,
+            Value::Bool(false) => {
+                out.push(SchemaViolation::new(
+                    path,
+                    "false",
+                    "no value is allowed here",
+                ));
+                return;
+            }
+            Value::Object(schema) => schema,
+            _ => return,
+        };
+
+        if let Some(reference) = schema.get("$ref").and_then(|value| value.as_str()) {
+            match self.resolve(reference) {
+                Some(target) => {
+                    let entry = (target as *const Value, instance as *const Value);
+                    if self.entered.borrow().contains(&entry) {
+                        out.push(SchemaViolation::new(
+                            path,
+                            "$ref",
+                            format!("reference {reference} loops back on itself"),
+                        ));
+                    } else {
+                        self.entered.borrow_mut().push(entry);
+                        self.check(target, instance, path, out);
+                        self.entered.borrow_mut().pop();
+                    }
+                }
+                None => out.push(SchemaViolation::new(
+                    path,
+                    "$ref",
+                    format!("unresolvable reference {reference}"),
+                )),
+            }
+        }
+
+        if let Some(expected) = schema.get("type") {
+            let names: Vec<&str> = match expected {
+                Value::String(name) => vec![name.as_str()],
+                Value::Array(names) => names.iter().filter_map(|name| name.as_str()).collect(),
+                _ => Vec::new(),
+            };
+            if !names.is_empty() && !names.iter().any(|name| type_matches(name, instance)) {
+                out.push(SchemaViolation::new(
+                    path,
+                    "type",
+                    format!(
+                        "expected {}, got {}",
+                        names.join(" or "),
+                        type_name(instance)
+                    ),
+                ));
+                return;
+            }
+        }
+
+        if let Some(allowed) = schema.get("enum").and_then(|value| value.as_array())
+            && !allowed.contains(instance)
+        {
+            out.push(SchemaViolation::new(
+                path,
+                "enum",
+                "value is not one of the allowed values",
+            ));
+        }
+        if let Some(expected) = schema.get("const")
+            && expected != instance
+        {
+            out.push(SchemaViolation::new(
+                path,
+                "const",
+                format!("expected {expected}"),
+            ));
+        }
+
+        match instance {
+            Value::String(text) => self.check_string(schema, text, path, out),
+            Value::Number(_) => check_number(schema, instance.as_f64().unwrap_or(0.0), path, out),
+            Value::Array(items) => self.check_array(schema, items, path, out),
+            Value::Object(map) => self.check_object(schema, map, path, out),
+            _ => {}
+        }
+
+        self.check_combinators(schema, instance, path, out);
+    }
+
+    fn check_string(
+        &self,
+        schema: &serde_json::Map<String, Value>,
+        text: &str,
+        path: &str,
+        out: &mut Vec<SchemaViolation>,
+    ) {
+        let length = text.chars().count() as u64;
+        if let Some(min) = schema.get("minLength").and_then(|value| value.as_u64())
+            && length < min
+        {
+            out.push(SchemaViolation::new(
+                path,
+                "minLength",
+                format!("shorter than {min} characters"),
+            ));
+        }
+        if let Some(max) = schema.get("maxLength").and_then(|value| value.as_u64())
+            && length > max
+        {
+            out.push(SchemaViolation::new(
+                path,
+                "maxLength",
+                format!("longer than {max} characters"),
+            ));
+        }
+        if let Some(pattern) = schema.get("pattern").and_then(|value| value.as_str()) {
+            match compile(pattern) {
+                Some(regex) if !regex.is_match(text) => out.push(SchemaViolation::new(
+                    path,
+                    "pattern",
+                    format!("does not match {pattern}"),
+                )),
+                Some(_) => {}
+                None => out.push(SchemaViolation::new(
+                    path,
+                    "pattern",
+                    format!("invalid pattern {pattern}"),
+                )),
+            }
+        }
+    }
+
+    fn check_array(
+        &self,
+        schema: &serde_json::Map<String, Value>,
+        items: &[Value],
+        path: &str,
+        out: &mut Vec<SchemaViolation>,
+    ) {
+        let count = items.len() as u64;
+        if let Some(min) = schema.get("minItems").and_then(|value| value.as_u64())
+            && count < min
+        {
+            out.push(SchemaViolation::new(
+                path,
+                "minItems",
+                format!("fewer than {min} items"),
+            ));
+        }
+        if let Some(max) = schema.get("maxItems").and_then(|value| value.as_u64())
+            && count > max
+        {
+            out.push(SchemaViolation::new(
+                path,
+                "maxItems",
+                format!("more than {max} items"),
+            ));
+        }
+        if schema.get("uniqueItems").and_then(|value| value.as_bool()) == Some(true) {
+            let duplicated = items
+                .iter()
+                .enumerate()
+                .any(|(index, item)| items[..index].contains(item));
+            if duplicated {
+                out.push(SchemaViolation::new(
+                    path,
+                    "uniqueItems",
+                    "items are not unique",
+                ));
+            }
+        }
+
+        let prefix = schema
+            .get("prefixItems")
+            .and_then(|value| value.as_array())
+            .map(|value| value.as_slice())
+            .unwrap_or_default();
+        for (index, item) in items.iter().enumerate() {
+            let item_schema = prefix.get(index).or_else(|| schema.get("items"));
+            if let Some(item_schema) = item_schema {
+                self.check(item_schema, item, &format!("{path}[{index}]"), out);
+            }
+        }
+    }
+
+    fn check_object(
+        &self,
+        schema: &serde_json::Map<String, Value>,
+        map: &serde_json::Map<String, Value>,
+        path: &str,
+        out: &mut Vec<SchemaViolation>,
+    ) {
+        let count = map.len() as u64;
+        if let Some(min) = schema.get("minProperties").and_then(|value| value.as_u64())
+            && count < min
+        {
+            let message = format!("fewer than {min} properties");
+            out.push(SchemaViolation::new(path, "minProperties", message));
+        }
+        if let Some(max) = schema.get("maxProperties").and_then(|value| value.as_u64())
+            && count > max
+        {
+            let message = format!("more than {max} properties");
+            out.push(SchemaViolation::new(path, "maxProperties", message));
+        }
+        if let Some(required) = schema.get("required").and_then(|value| value.as_array()) {
+            for key in required.iter().filter_map(|key| key.as_str()) {
+                if !map.contains_key(key) {
+                    out.push(SchemaViolation::new(
+                        &format!("{path}.{key}"),
+                        "required",
+                        "missing required property",
+                    ));
+                }
+            }
+        }
+
+        let properties = schema.get("properties").and_then(|value| value.as_object());
+        let additional = schema.get("additionalProperties");
+        for (key, value) in map {
+            let child_path = format!("{path}.{key}");
+            match properties.and_then(|properties| properties.get(key)) {
+                Some(property_schema) => self.check(property_schema, value, &child_path, out),
+                None => match additional {
+                    Some(Value::Bool(false)) => out.push(SchemaViolation::new(
+                        &child_path,
+                        "additionalProperties",
+                        "property is not allowed",
+                    )),
+                    Some(additional_schema) => {
+                        self.check(additional_schema, value, &child_path, out)
+                    }
+                    None => {}
+                },
+            }
+        }
+    }
+
+    fn check_combinators(
+        &self,
+        schema: &serde_json::Map<String, Value>,
+        instance: &Value,
+        path: &str,
+        out: &mut Vec<SchemaViolation>,
+    ) {
+        if let Some(all_of) = schema.get("allOf").and_then(|value| value.as_array()) {
+            for branch in all_of {
+                self.check(branch, instance, path, out);
+            }
+        }
+        if let Some(any_of) = schema.get("anyOf").and_then(|value| value.as_array())
+            && !any_of.iter().any(|branch| self.is_valid(branch, instance))
+        {
+            out.push(SchemaViolation::new(
+                path,
+                "anyOf",
+                "value matches none of the allowed schemas",
+            ));
+        }
+        if let Some(one_of) = schema.get("oneOf").and_then(|value| value.as_array()) {
+            let matches = one_of
+                .iter()
+                .filter(|branch| self.is_valid(branch, instance))
+                .count();
+            if matches != 1 {
+                out.push(SchemaViolation::new(
+                    path,
+                    "oneOf",
+                    format!("value matches {matches} schemas, expected exactly one"),
+                ));
+            }
+        }
+        if let Some(not) = schema.get("not")
+            && self.is_valid(not, instance)
+        {
+            out.push(SchemaViolation::new(
+                path,
+                "not",
+                "value matches a disallowed schema",
+            ));
+        }
+    }
+
+    fn is_valid(&self, schema: &Value, instance: &Value) -> bool {
+        let mut violations = Vec::new();
+        self.check(schema, instance, "$", &mut violations);
+        violations.is_empty()
+    }
+
+    fn resolve(&self, reference: &str) -> Option<&Value> {
+        resolve_ref(self.root, reference)
+    }
+}
+
+/// Resolves a document-local reference such as `#/$defs/Item` against `root`.
+pub fn resolve_ref<'a>(root: &'a Value, reference: &str) -> Option<&'a Value> {
+    let pointer = reference.strip_prefix('#')?;
+    if pointer.is_empty() {
+        return Some(root);
+    }
+    root.pointer(pointer)
+}
+
+fn compile(pattern: &str) -> Option<Regex> {
+    let mut patterns = PATTERNS.lock().unwrap_or_else(PoisonError::into_inner);
+    if let Some(regex) = patterns.get(pattern) {
+        return regex.clone();
+    }
+    if patterns.len() >= MAX_CACHED_PATTERNS {
+        patterns.clear();
+    }
+    let regex = Regex::new(pattern).ok();
+    patterns.insert(pattern.to_string(), regex.clone());
+    regex
+}
+
+fn check_number(
+    schema: &serde_json::Map<String, Value>,
+    number: f64,
+    path: &str,
+    out: &mut Vec<SchemaViolation>,
+) {
+    let bound = |key: &str| schema.get(key).and_then(|value| value.as_f64());
+    if let Some(min) = bound("minimum")
+        && number < min
+    {
+        out.push(SchemaViolation::new(
+            path,
+            "minimum",
+            format!("less than {min}"),
+        ));
+    }
+    if let Some(max) = bound("maximum")
+        && number > max
+    {
+        out.push(SchemaViolation::new(
+            path,
+            "maximum",
+            format!("greater than {max}"),
+        ));
+    }
+    if let Some(min) = bound("exclusiveMinimum")
+        && number <= min
+    {
+        out.push(SchemaViolation::new(
+            path,
+            "exclusiveMinimum",
+            format!("not greater than {min}"),
+        ));
+    }
+    if let Some(max) = bound("exclusiveMaximum")
+        && number >= max
+    {
+        out.push(SchemaViolation::new(
+            path,
+            "exclusiveMaximum",
+            format!("not less than {max}"),
+        ));
+    }
+    if let Some(divisor) = bound("multipleOf").filter(|divisor| *divisor > 0.0) {
+        let quotient = number / divisor;
+        if (quotient - quotient.round()).abs() > 1e-9 {
+            out.push(SchemaViolation::new(
+                path,
+                "multipleOf",
+                format!("not a multiple of {divisor}"),
+            ));
+        }
+    }
+}
+
+/// Whether `instance` is of the JSON Schema type `name`; integral floats are integers.
+pub fn type_matches(name: &str, instance: &Value) -> bool {
+    match name {
+        "null" => instance.is_null(),
+        "boolean" => instance.is_boolean(),
+        "object" => instance.is_object(),
+        "array" => instance.is_array(),
+        "string" => instance.is_string(),
+        "number" => instance.is_number(),
+        "integer" => {
+            instance.is_i64()
+                || instance.is_u64()
+                || instance
+                    .as_f64()
+                    .is_some_and(|number| number.fract() == 0.0)
+        }
+        _ => false,
+    }
+}
+
+fn type_name(instance: &Value) -> &'static str {
+    match instance {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
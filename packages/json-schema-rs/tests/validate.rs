@@ -0,0 +1,61 @@
+use json_schema_rs::validate;
+use serde_json::json;
+
+#[test]
+fn patterns_are_checked_on_every_value_and_bad_ones_are_reported() {
+    let schema = json!({
+        "type": "object",
+        "properties": {
+            "id": { "type": "string", "pattern": "^[a-z]+-[0-9]+$" },
+            "tag": { "type": "string", "pattern": "(" },
+        },
+    });
+
+    for id in ["abc-1", "xyz-42"] {
+        assert!(validate(&schema, &json!({ "id": id })).is_empty());
+    }
+    let violations = validate(&schema, &json!({ "id": "ABC", "tag": "x" }));
+    let found: Vec<(&str, &str)> = violations
+        .iter()
+        .map(|violation| (violation.path.as_str(), violation.message.as_str()))
+        .collect();
+    assert_eq!(
+        found,
+        [
+            ("$.id", "does not match ^[a-z]+-[0-9]+$"),
+            ("$.tag", "invalid pattern ("),
+        ]
+    );
+}
+
+#[test]
+fn references_that_loop_are_reported_instead_of_followed_forever() {
+    let looping = json!({ "$ref": "#" });
+    let violations = validate(&looping, &json!(1));
+    assert_eq!(violations.len(), 1);
+    assert_eq!(violations[0].keyword, "$ref");
+    assert_eq!(violations[0].message, "reference # loops back on itself");
+
+    let mutual = json!({
+        "$defs": {
+            "a": { "anyOf": [{ "$ref": "#/$defs/b" }] },
+            "b": { "allOf": [{ "$ref": "#/$defs/a" }] },
+        },
+        "$ref": "#/$defs/a",
+    });
+    assert!(!validate(&mutual, &json!("x")).is_empty());
+
+    let list = json!({
+        "$defs": {
+            "node": {
+                "type": "object",
+                "properties": { "next": { "anyOf": [{ "type": "null" }, { "$ref": "#/$defs/node" }] } },
+            },
+        },
+        "$ref": "#/$defs/node",
+    });
+    let nested = json!({ "next": { "next": { "next": null } } });
+    assert!(validate(&list, &nested).is_empty());
+    let bad = json!({ "next": { "next": { "next": 3 } } });
+    assert!(!validate(&list, &bad).is_empty());
+}
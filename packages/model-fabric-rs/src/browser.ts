@@ -1,11 +1,16 @@
 import type {
+  BudgetRule,
+  CassetteConfig,
   CompletionRequest,
   CompletionResponse,
+  CredentialStoreConfig,
   ModelFabricContext,
   ModelFabricSnapshot,
+  ModelPricing,
   ModelStreamHandle,
   ModelUsageEvent,
   ProviderConfigRecord,
+  ResponseCacheConfig,
   RouteRule,
 } from "./types";
 
@@ -26,14 +31,47 @@ export class ModelFabric {
     throw new Error("Model fabric native bindings are not available in the browser.");
   }
 
+  loadPricing(_entries: ModelPricing[]): void {
+    throw new Error("Model fabric native bindings are not available in the browser.");
+  }
+
+  loadBudgets(_rules: BudgetRule[]): void {
+    throw new Error("Model fabric native bindings are not available in the browser.");
+  }
+
+  configureCache(_config: ResponseCacheConfig | null): void {
+    throw new Error("Model fabric native bindings are not available in the browser.");
+  }
+
+  configureCredentials(_config: CredentialStoreConfig | null): Promise<void> {
+    throw new Error("Model fabric native bindings are not available in the browser.");
+  }
+
+  rotateCredential(_authRef: string, _value: string): Promise<void> {
+    throw new Error("Model fabric native bindings are not available in the browser.");
+  }
+
+  setCassette(_config: CassetteConfig | null): void {
+    throw new Error("Model fabric native bindings are not available in the browser.");
+  }
+
   complete(
     _request: CompletionRequest,
-    _context?: ModelFabricContext
+    _context?: ModelFabricContext,
+    _options?: { signal?: AbortSignal }
   ): Promise<CompletionResponse> {
     throw new Error("Model fabric native bindings are not available in the browser.");
   }
 
-  stream(_request: CompletionRequest, _context?: ModelFabricContext): ModelStreamHandle {
+  cancel(_requestId: string): boolean {
+    throw new Error("Model fabric native bindings are not available in the browser.");
+  }
+
+  stream(_request: CompletionRequest, _context?: ModelFabricContext): Promise<ModelStreamHandle> {
+    throw new Error("Model fabric native bindings are not available in the browser.");
+  }
+
+  pullModel(_providerId: string, _modelId: string): Promise<Record<string, unknown>> {
     throw new Error("Model fabric native bindings are not available in the browser.");
   }
 
@@ -50,4 +88,5 @@ export class ModelFabric {
   }
 }
 
+export { ModelFabricError } from "./errors";
 export type { StreamChunk } from "./types";
@@ -0,0 +1,256 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use crate::ModelRequestContext;
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BudgetScope {
+  Global,
+  Worker,
+  TaskType,
+  Run,
+}
+
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BudgetAction {
+  #[default]
+  Reject,
+  Downgrade,
+}
+
+/// Token and/or spend limit. `scope` decides how consumption is partitioned (one
+/// ledger per worker, task type or run); `worker_id`/`task_type` restrict which
+/// requests the rule applies to, the same way they do on routes.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BudgetRule {
+  pub budget_id: String,
+  pub scope: BudgetScope,
+  pub worker_id: Option<String>,
+  pub task_type: Option<String>,
+  pub max_tokens: Option<u64>,
+  pub max_cost_usd: Option<f64>,
+  pub window_ms: Option<u64>,
+  pub action: Option<BudgetAction>,
+  pub downgrade_model_id: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BudgetUsageSnapshot {
+  pub budget_id: String,
+  pub partition: String,
+  pub tokens: u64,
+  pub cost_usd: f64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum BudgetDecision {
+  Allow,
+  Downgrade { budget_id: String, model_id: String },
+  Reject { budget_id: String, reason: String },
+}
+
+struct LedgerEntry {
+  at: i64,
+  tokens: u64,
+  cost_usd: f64,
+}
+
+#[derive(Default)]
+struct BudgetWindow {
+  entries: VecDeque<LedgerEntry>,
+  tokens: u64,
+  cost_usd: f64,
+}
+
+impl BudgetWindow {
+  fn prune(&mut self, window_ms: Option<u64>, now: i64) {
+    let Some(window_ms) = window_ms else {
+      return;
+    };
+    let cutoff = now.saturating_sub(window_ms as i64);
+    while self.entries.front().is_some_and(|entry| entry.at <= cutoff) {
+      if let Some(entry) = self.entries.pop_front() {
+        self.tokens = self.tokens.saturating_sub(entry.tokens);
+        self.cost_usd = (self.cost_usd - entry.cost_usd).max(0.0);
+      }
+    }
+  }
+
+  fn debit(&mut self, window_ms: Option<u64>, tokens: u64, cost_usd: f64, at: i64) {
+    self.tokens = self.tokens.saturating_add(tokens);
+    self.cost_usd += cost_usd;
+    if window_ms.is_some() {
+      self.entries.push_back(LedgerEntry { at, tokens, cost_usd });
+    }
+  }
+}
+
+#[derive(Default)]
+pub struct BudgetLedger {
+  rules: Vec<BudgetRule>,
+  windows: HashMap<(String, String), BudgetWindow>,
+}
+
+impl BudgetLedger {
+  /// Replaces the rules. `is_model` tells whether a downgrade model is served by a
+  /// configured provider.
+  pub fn load(
+    &mut self,
+    rules: Vec<BudgetRule>,
+    is_model: impl Fn(&str) -> bool,
+  ) -> Result<(), String> {
+    let mut budget_ids = HashSet::new();
+    for rule in &rules {
+      if rule.budget_id.trim().is_empty() {
+        return Err("budgetId must be non-empty".to_string());
+      }
+      if !budget_ids.insert(rule.budget_id.clone()) {
+        return Err(format!("duplicate budgetId {}", rule.budget_id));
+      }
+      if rule.max_tokens.is_none() && rule.max_cost_usd.is_none() {
+        return Err(format!(
+          "budget {} requires maxTokens or maxCostUsd",
+          rule.budget_id
+        ));
+      }
+      if rule.action == Some(BudgetAction::Downgrade) && rule.downgrade_model_id.is_none() {
+        return Err(format!(
+          "budget {} downgrade action requires downgradeModelId",
+          rule.budget_id
+        ));
+      }
+      if let Some(model_id) = &rule.downgrade_model_id {
+        if !is_model(model_id) {
+          return Err(format!(
+            "budget {} downgradeModelId {} is not a configured model",
+            rule.budget_id, model_id
+          ));
+        }
+      }
+    }
+
+    let mut ordered = rules;
+    ordered.sort_by(|a, b| a.budget_id.cmp(&b.budget_id));
+    self
+      .windows
+      .retain(|(budget_id, _), _| budget_ids.contains(budget_id));
+    self.rules = ordered;
+    Ok(())
+  }
+
+  pub fn rules(&self) -> Vec<BudgetRule> {
+    self.rules.clone()
+  }
+
+  /// Decides whether a request may be dispatched. A rejecting budget wins over a
+  /// downgrading one so a hard ceiling cannot be bypassed by a cheaper model.
+  pub fn check(&mut self, context: Option<&ModelRequestContext>, now: i64) -> BudgetDecision {
+    let mut downgrade: Option<BudgetDecision> = None;
+
+    for rule in &self.rules {
+      let Some(partition) = partition_for(rule, context) else {
+        continue;
+      };
+      let Some(window) = self.windows.get_mut(&(rule.budget_id.clone(), partition.clone())) else {
+        continue;
+      };
+      window.prune(rule.window_ms, now);
+
+      let tokens_exceeded = rule.max_tokens.is_some_and(|max| window.tokens >= max);
+      let cost_exceeded = rule.max_cost_usd.is_some_and(|max| window.cost_usd >= max);
+      if !tokens_exceeded && !cost_exceeded {
+        continue;
+      }
+
+      match (rule.action.unwrap_or_default(), &rule.downgrade_model_id) {
+        (BudgetAction::Downgrade, Some(model_id)) => {
+          downgrade.get_or_insert_with(|| BudgetDecision::Downgrade {
+            budget_id: rule.budget_id.clone(),
+            model_id: model_id.clone(),
+          });
+        }
+        _ => {
+          return BudgetDecision::Reject {
+            budget_id: rule.budget_id.clone(),
+            reason: format!(
+              "Budget {} exhausted for {partition} ({} tokens, ${:.4} spent)",
+              rule.budget_id, window.tokens, window.cost_usd
+            ),
+          };
+        }
+      }
+    }
+
+    downgrade.unwrap_or(BudgetDecision::Allow)
+  }
+
+  pub fn debit(
+    &mut self,
+    context: Option<&ModelRequestContext>,
+    tokens: u64,
+    cost_usd: f64,
+    now: i64,
+  ) {
+    for rule in &self.rules {
+      let Some(partition) = partition_for(rule, context) else {
+        continue;
+      };
+      let window = self
+        .windows
+        .entry((rule.budget_id.clone(), partition))
+        .or_default();
+      window.prune(rule.window_ms, now);
+      window.debit(rule.window_ms, tokens, cost_usd, now);
+    }
+  }
+
+  pub fn usage_snapshot(&self) -> Vec<BudgetUsageSnapshot> {
+    let mut usage: Vec<BudgetUsageSnapshot> = self
+      .windows
+      .iter()
+      .map(|((budget_id, partition), window)| BudgetUsageSnapshot {
+        budget_id: budget_id.clone(),
+        partition: partition.clone(),
+        tokens: window.tokens,
+        cost_usd: window.cost_usd,
+      })
+      .collect();
+    usage.sort_by(|a, b| {
+      a.budget_id
+        .cmp(&b.budget_id)
+        .then_with(|| a.partition.cmp(&b.partition))
+    });
+    usage
+  }
+
+  pub fn clear(&mut self) {
+    self.rules.clear();
+    self.windows.clear();
+  }
+}
+
+/// Returns the ledger partition for a request, or `None` when the rule does not
+/// apply (filter mismatch or the context lacks the scoped identifier).
+fn partition_for(rule: &BudgetRule, context: Option<&ModelRequestContext>) -> Option<String> {
+  let worker_id = context.and_then(|context| context.worker_id.as_ref());
+  let task_type = context.and_then(|context| context.task_type.as_ref());
+  let run_id = context.and_then(|context| context.run_id.as_ref());
+
+  if rule.worker_id.is_some() && rule.worker_id.as_ref() != worker_id {
+    return None;
+  }
+  if rule.task_type.is_some() && rule.task_type.as_ref() != task_type {
+    return None;
+  }
+
+  match rule.scope {
+    BudgetScope::Global => Some("*".to_string()),
+    BudgetScope::Worker => worker_id.cloned(),
+    BudgetScope::TaskType => task_type.cloned(),
+    BudgetScope::Run => run_id.cloned(),
+  }
+}
@@ -0,0 +1,301 @@
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::content::ContentPart;
+use crate::fingerprint;
+use crate::{
+  assistant_parts, CompletionRequest, CompletionResponse, StreamChunk, TokenUsage, ToolCall,
+};
+
+const DEFAULT_MAX_ENTRIES: usize = 1_000;
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResponseCacheConfig {
+  /// Entries older than this are treated as misses; unset keeps them forever.
+  pub ttl_ms: Option<u64>,
+  pub max_entries: Option<usize>,
+  /// Directory holding one JSON file per entry, reloaded when the cache is configured.
+  pub path: Option<String>,
+  /// Also cache requests sampled above temperature 0.
+  pub any_temperature: Option<bool>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResponseCacheStatus {
+  pub entries: usize,
+  pub max_entries: usize,
+  pub ttl_ms: Option<u64>,
+  pub path: Option<String>,
+  pub hits: u64,
+  pub misses: u64,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CacheEntry {
+  created_at: i64,
+  #[serde(default)]
+  last_used_at: i64,
+  response: CompletionResponse,
+}
+
+/// Completion cache keyed by provider, model and the normalized request. Only
+/// deterministic requests (temperature 0) are cached unless configured otherwise; a
+/// request can opt in or out with `cache: true | false`.
+pub struct ResponseCache {
+  config: ResponseCacheConfig,
+  entries: HashMap<String, CacheEntry>,
+  hits: u64,
+  misses: u64,
+}
+
+impl ResponseCache {
+  pub fn open(config: ResponseCacheConfig, now: i64) -> Result<Self, String> {
+    if config.max_entries == Some(0) {
+      return Err("response cache maxEntries must be greater than zero".to_string());
+    }
+    let mut cache = Self {
+      config,
+      entries: HashMap::new(),
+      hits: 0,
+      misses: 0,
+    };
+    if let Some(dir) = cache.dir() {
+      fs::create_dir_all(&dir)
+        .map_err(|error| format!("failed to create cache dir {}: {error}", dir.display()))?;
+      let listing = fs::read_dir(&dir)
+        .map_err(|error| format!("failed to read cache dir {}: {error}", dir.display()))?;
+      for file in listing.flatten() {
+        let path = file.path();
+        let Some(key) = entry_key(&path) else {
+          continue;
+        };
+        let entry = fs::read_to_string(&path)
+          .ok()
+          .and_then(|raw| serde_json::from_str::<CacheEntry>(&raw).ok());
+        match entry {
+          Some(entry) if !cache.expired(&entry, now) => {
+            cache.entries.insert(key, entry);
+          }
+          _ => {
+            let _ = fs::remove_file(&path);
+          }
+        }
+      }
+      cache.evict();
+    }
+    Ok(cache)
+  }
+
+  /// Cache key for the request, or `None` when it must not be served from cache.
+  pub fn key(
+    &self,
+    provider_id: &str,
+    model_id: &str,
+    request: &CompletionRequest,
+  ) -> Option<String> {
+    let cacheable = match request.cache {
+      Some(enabled) => enabled,
+      None => self.config.any_temperature.unwrap_or(false) || request.temperature == Some(0.0),
+    };
+    cacheable.then(|| {
+      fingerprint::hash(&json!({
+        "provider": provider_id,
+        "request": fingerprint::request_view(model_id, request, true),
+      }))
+    })
+  }
+
+  pub fn get(&mut self, key: &str, now: i64) -> Option<CompletionResponse> {
+    let expired = self.entries.get(key).map(|entry| self.expired(entry, now));
+    match expired {
+      Some(false) => {
+        self.hits += 1;
+        let entry = self.entries.get_mut(key)?;
+        entry.last_used_at = now;
+        Some(entry.response.clone())
+      }
+      Some(true) => {
+        self.remove(key);
+        self.misses += 1;
+        None
+      }
+      None => {
+        self.misses += 1;
+        None
+      }
+    }
+  }
+
+  /// Stores a response; the disk copy is best effort and a failed write only costs a
+  /// future miss after a restart.
+  pub fn put(&mut self, key: String, response: CompletionResponse, now: i64) {
+    let entry = CacheEntry {
+      created_at: now,
+      last_used_at: now,
+      response,
+    };
+    if let Some(dir) = self.dir() {
+      if let Ok(raw) = serde_json::to_string(&entry) {
+        let _ = fs::write(dir.join(format!("{key}.json")), raw);
+      }
+    }
+    self.entries.insert(key, entry);
+    self.evict();
+  }
+
+  pub fn status(&self) -> ResponseCacheStatus {
+    ResponseCacheStatus {
+      entries: self.entries.len(),
+      max_entries: self.max_entries(),
+      ttl_ms: self.config.ttl_ms,
+      path: self.config.path.clone(),
+      hits: self.hits,
+      misses: self.misses,
+    }
+  }
+
+  fn max_entries(&self) -> usize {
+    self.config.max_entries.unwrap_or(DEFAULT_MAX_ENTRIES)
+  }
+
+  fn expired(&self, entry: &CacheEntry, now: i64) -> bool {
+    self
+      .config
+      .ttl_ms
+      .is_some_and(|ttl_ms| now.saturating_sub(entry.created_at) >= ttl_ms as i64)
+  }
+
+  /// Drops least recently used entries until the size bound holds.
+  fn evict(&mut self) {
+    while self.entries.len() > self.max_entries() {
+      let oldest = self
+        .entries
+        .iter()
+        .min_by_key(|(_, entry)| entry.last_used_at.max(entry.created_at))
+        .map(|(key, _)| key.clone());
+      match oldest {
+        Some(key) => self.remove(&key),
+        None => break,
+      }
+    }
+  }
+
+  fn remove(&mut self, key: &str) {
+    self.entries.remove(key);
+    if let Some(dir) = self.dir() {
+      let _ = fs::remove_file(dir.join(format!("{key}.json")));
+    }
+  }
+
+  fn dir(&self) -> Option<PathBuf> {
+    self.config.path.as_ref().map(PathBuf::from)
+  }
+}
+
+fn entry_key(path: &Path) -> Option<String> {
+  if path.extension()? != "json" {
+    return None;
+  }
+  Some(path.file_stem()?.to_string_lossy().to_string())
+}
+
+/// Synthetic stream for a cached response: the recorded parts in order, then usage and
+/// the finish reason, matching what a live stream would have produced.
+pub fn replay_chunks(response: &CompletionResponse) -> Vec<StreamChunk> {
+  let parts = response
+    .content_parts
+    .clone()
+    .unwrap_or_else(|| assistant_parts(&response.content, response.tool_calls.as_deref()));
+  let mut chunks: Vec<StreamChunk> = parts
+    .into_iter()
+    .filter_map(|part| match part {
+      ContentPart::Text { text } => Some(StreamChunk {
+        content: Some(text),
+        ..StreamChunk::of("content")
+      }),
+      ContentPart::Thinking {
+        thinking,
+        signature,
+      } => Some(StreamChunk {
+        content: Some(thinking),
+        signature,
+        ..StreamChunk::of("thinking")
+      }),
+      ContentPart::ToolUse { id, name, input } => Some(StreamChunk {
+        tool_call: Some(ToolCall {
+          id,
+          name,
+          arguments: input.to_string(),
+        }),
+        ..StreamChunk::of("tool_call")
+      }),
+      ContentPart::Image { .. } | ContentPart::ToolResult { .. } => None,
+    })
+    .collect();
+  chunks.push(StreamChunk {
+    usage: Some(response.usage.clone()),
+    ..StreamChunk::of("usage")
+  });
+  chunks.push(StreamChunk {
+    finish_reason: Some(response.finish_reason.clone()),
+    ..StreamChunk::of("done")
+  });
+  chunks
+}
+
+/// Accumulates a live stream so a completed one can be cached like a completion.
+#[derive(Default)]
+pub struct StreamTranscript {
+  content: String,
+  thinking: String,
+  signature: Option<String>,
+  tool_calls: Vec<ToolCall>,
+  finish_reason: Option<String>,
+}
+
+impl StreamTranscript {
+  pub fn observe(&mut self, chunk: &StreamChunk) {
+    match chunk.chunk_type.as_str() {
+      "content" => self.content.push_str(chunk.content.as_deref().unwrap_or_default()),
+      "thinking" => {
+        self.thinking.push_str(chunk.content.as_deref().unwrap_or_default());
+        if chunk.signature.is_some() {
+          self.signature = chunk.signature.clone();
+        }
+      }
+      "tool_call" => self.tool_calls.extend(chunk.tool_call.clone()),
+      "done" => self.finish_reason = chunk.finish_reason.clone(),
+      _ => {}
+    }
+  }
+
+  /// The assembled response, or `None` when the stream never finished cleanly.
+  pub fn into_response(self, model_id: &str, usage: TokenUsage) -> Option<CompletionResponse> {
+    let finish_reason = self.finish_reason.filter(|reason| reason != "error")?;
+    let mut parts = Vec::new();
+    if !self.thinking.is_empty() {
+      parts.push(ContentPart::Thinking {
+        thinking: self.thinking,
+        signature: self.signature,
+      });
+    }
+    parts.extend(assistant_parts(&self.content, Some(self.tool_calls.as_slice())));
+    Some(CompletionResponse {
+      content: self.content,
+      tool_calls: Some(self.tool_calls).filter(|calls| !calls.is_empty()),
+      content_parts: Some(parts),
+      parsed: None,
+      usage,
+      finish_reason,
+      model: model_id.to_string(),
+      latency_ms: 0,
+      safety_ratings: None,
+    })
+  }
+}
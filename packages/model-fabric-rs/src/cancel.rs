@@ -0,0 +1,39 @@
+use std::sync::Arc;
+use tokio::sync::watch;
+
+use crate::error::{ProviderError, ProviderErrorKind};
+
+/// Cancellation flag shared between a `ModelStreamHandle` (or `ModelFabric::cancel`)
+/// and the task serving the request. Cancelling is idempotent.
+#[derive(Clone)]
+pub struct CancelSignal {
+  sender: Arc<watch::Sender<bool>>,
+}
+
+impl Default for CancelSignal {
+  fn default() -> Self {
+    Self {
+      sender: Arc::new(watch::channel(false).0),
+    }
+  }
+}
+
+impl CancelSignal {
+  pub fn cancel(&self) {
+    self.sender.send_replace(true);
+  }
+
+  /// Resolves once `cancel` was called; never resolves otherwise.
+  pub async fn cancelled(&self) {
+    let mut receiver = self.sender.subscribe();
+    let _ = receiver.wait_for(|cancelled| *cancelled).await;
+  }
+
+  pub fn same(&self, other: &CancelSignal) -> bool {
+    Arc::ptr_eq(&self.sender, &other.sender)
+  }
+}
+
+pub fn cancelled_error() -> ProviderError {
+  ProviderError::new(ProviderErrorKind::Cancelled, "Request cancelled")
+}
@@ -0,0 +1,264 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs;
+use std::path::Path;
+
+use crate::error::{ProviderError, ProviderErrorKind};
+use crate::fingerprint;
+use crate::{now_ms, CompletionRequest, CompletionResponse, ProviderResult, WireProtocol};
+
+const CASSETTE_VERSION: u32 = 1;
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CassetteMode {
+  /// Calls providers and writes every attempt to the cassette file.
+  Record,
+  /// Serves recorded interactions and never touches the network.
+  Replay,
+}
+
+/// How a replayed request is matched against the recorded ones.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CassetteMatch {
+  /// Model, messages, tools, sampling parameters and response format must agree.
+  #[default]
+  Strict,
+  /// Model, messages and tools must agree; sampling parameters may drift.
+  Messages,
+  /// Interactions for the model are served in recorded order, whatever was asked.
+  Sequential,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CassetteConfig {
+  pub mode: CassetteMode,
+  pub path: String,
+  #[serde(default, rename = "match")]
+  pub match_mode: CassetteMatch,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum InteractionKind {
+  Complete,
+  Stream,
+}
+
+/// One provider attempt. Completions keep the parsed response; streams keep the raw
+/// SSE/NDJSON lines so replay goes through the same decoder as a live stream.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CassetteInteraction {
+  pub kind: InteractionKind,
+  pub model_id: String,
+  pub protocol: WireProtocol,
+  pub request_hash: String,
+  pub messages_hash: String,
+  pub request: Value,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub response: Option<CompletionResponse>,
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub frames: Vec<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub error: Option<ProviderError>,
+  pub recorded_at: i64,
+}
+
+impl CassetteInteraction {
+  pub fn new(
+    kind: InteractionKind,
+    protocol: WireProtocol,
+    model_id: &str,
+    request: &CompletionRequest,
+  ) -> Self {
+    let RequestKeys {
+      request_hash,
+      messages_hash,
+      view,
+    } = RequestKeys::of(model_id, request);
+    Self {
+      kind,
+      model_id: model_id.to_string(),
+      protocol,
+      request_hash,
+      messages_hash,
+      request: view,
+      response: None,
+      frames: Vec::new(),
+      error: None,
+      recorded_at: now_ms(),
+    }
+  }
+
+  pub fn into_completion(self) -> ProviderResult<CompletionResponse> {
+    match (self.error, self.response) {
+      (Some(error), _) => Err(error),
+      (None, Some(response)) => Ok(response),
+      (None, None) => Err(ProviderError::new(
+        ProviderErrorKind::Unknown,
+        "cassette interaction has neither a response nor an error",
+      )),
+    }
+  }
+}
+
+struct RequestKeys {
+  request_hash: String,
+  messages_hash: String,
+  view: Value,
+}
+
+impl RequestKeys {
+  fn of(model_id: &str, request: &CompletionRequest) -> Self {
+    let view = fingerprint::request_view(model_id, request, true);
+    Self {
+      request_hash: fingerprint::hash(&view),
+      messages_hash: fingerprint::hash(&fingerprint::request_view(model_id, request, false)),
+      view,
+    }
+  }
+}
+
+#[derive(Serialize, Deserialize)]
+struct CassetteFile {
+  version: u32,
+  interactions: Vec<CassetteInteraction>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CassetteStatus {
+  pub mode: CassetteMode,
+  pub path: String,
+  #[serde(rename = "match")]
+  pub match_mode: CassetteMatch,
+  pub interactions: usize,
+  pub replayed: usize,
+}
+
+pub struct Cassette {
+  config: CassetteConfig,
+  interactions: Vec<CassetteInteraction>,
+  /// Replay count per interaction, parallel to `interactions`.
+  served: Vec<u32>,
+}
+
+impl Cassette {
+  /// Record mode starts an empty cassette (truncating the file); replay mode loads it.
+  pub fn open(config: CassetteConfig) -> Result<Self, String> {
+    let interactions = match config.mode {
+      CassetteMode::Record => Vec::new(),
+      CassetteMode::Replay => {
+        let raw = fs::read_to_string(&config.path)
+          .map_err(|error| format!("failed to read cassette {}: {error}", config.path))?;
+        let file: CassetteFile = serde_json::from_str(&raw)
+          .map_err(|error| format!("invalid cassette {}: {error}", config.path))?;
+        if file.version != CASSETTE_VERSION {
+          return Err(format!(
+            "unsupported cassette version {} in {}",
+            file.version, config.path
+          ));
+        }
+        file.interactions
+      }
+    };
+    let cassette = Self {
+      served: vec![0; interactions.len()],
+      config,
+      interactions,
+    };
+    if cassette.recording() {
+      cassette.persist()?;
+    }
+    Ok(cassette)
+  }
+
+  pub fn recording(&self) -> bool {
+    self.config.mode == CassetteMode::Record
+  }
+
+  pub fn replaying(&self) -> bool {
+    self.config.mode == CassetteMode::Replay
+  }
+
+  pub fn record(&mut self, interaction: CassetteInteraction) -> Result<(), String> {
+    self.interactions.push(interaction);
+    self.served.push(0);
+    self.persist()
+  }
+
+  /// Serves the first matching interaction not replayed yet; once all matches were
+  /// served the last one repeats. A miss is an `invalid_request` so it is never retried
+  /// or routed to a fallback model.
+  pub fn replay(
+    &mut self,
+    kind: InteractionKind,
+    model_id: &str,
+    request: &CompletionRequest,
+  ) -> ProviderResult<CassetteInteraction> {
+    let keys = RequestKeys::of(model_id, request);
+    let matches: Vec<usize> = self
+      .interactions
+      .iter()
+      .enumerate()
+      .filter(|(_, interaction)| {
+        interaction.kind == kind
+          && interaction.model_id == model_id
+          && match self.config.match_mode {
+            CassetteMatch::Strict => interaction.request_hash == keys.request_hash,
+            CassetteMatch::Messages => interaction.messages_hash == keys.messages_hash,
+            CassetteMatch::Sequential => true,
+          }
+      })
+      .map(|(index, _)| index)
+      .collect();
+
+    let Some(index) = matches
+      .iter()
+      .copied()
+      .find(|index| self.served[*index] == 0)
+      .or_else(|| matches.last().copied())
+    else {
+      return Err(ProviderError::new(
+        ProviderErrorKind::InvalidRequest,
+        format!(
+          "cassette {} has no {} interaction for {model_id} matching request {}",
+          self.config.path,
+          match kind {
+            InteractionKind::Complete => "complete",
+            InteractionKind::Stream => "stream",
+          },
+          keys.request_hash
+        ),
+      ));
+    };
+    self.served[index] += 1;
+    Ok(self.interactions[index].clone())
+  }
+
+  pub fn status(&self) -> CassetteStatus {
+    CassetteStatus {
+      mode: self.config.mode,
+      path: self.config.path.clone(),
+      match_mode: self.config.match_mode,
+      interactions: self.interactions.len(),
+      replayed: self.served.iter().filter(|count| **count > 0).count(),
+    }
+  }
+
+  fn persist(&self) -> Result<(), String> {
+    let file = CassetteFile {
+      version: CASSETTE_VERSION,
+      interactions: self.interactions.clone(),
+    };
+    let raw = serde_json::to_string_pretty(&file).map_err(|error| error.to_string())?;
+    if let Some(parent) = Path::new(&self.config.path).parent() {
+      fs::create_dir_all(parent).map_err(|error| error.to_string())?;
+    }
+    fs::write(&self.config.path, raw)
+      .map_err(|error| format!("failed to write cassette {}: {error}", self.config.path))
+  }
+}
@@ -0,0 +1,85 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Message body: either plain text or an ordered list of content parts.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum MessageContent {
+  Text(String),
+  Parts(Vec<ContentPart>),
+}
+
+impl Default for MessageContent {
+  fn default() -> Self {
+    MessageContent::Text(String::new())
+  }
+}
+
+impl MessageContent {
+  /// Concatenated text parts; images, tool blocks and thinking are skipped.
+  pub fn text(&self) -> String {
+    match self {
+      MessageContent::Text(text) => text.clone(),
+      MessageContent::Parts(parts) => parts
+        .iter()
+        .filter_map(|part| match part {
+          ContentPart::Text { text } => Some(text.as_str()),
+          _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join(""),
+    }
+  }
+
+  pub fn parts(&self) -> Vec<ContentPart> {
+    match self {
+      MessageContent::Text(text) if text.is_empty() => Vec::new(),
+      MessageContent::Text(text) => vec![ContentPart::Text { text: text.clone() }],
+      MessageContent::Parts(parts) => parts.clone(),
+    }
+  }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case", rename_all_fields = "camelCase")]
+pub enum ContentPart {
+  Text {
+    text: String,
+  },
+  Image {
+    source: ImageSource,
+  },
+  ToolUse {
+    id: String,
+    name: String,
+    input: Value,
+  },
+  ToolResult {
+    tool_use_id: String,
+    content: String,
+    is_error: Option<bool>,
+  },
+  Thinking {
+    thinking: String,
+    /// Opaque provider signature; Anthropic requires it when thinking blocks are
+    /// sent back alongside tool results.
+    signature: Option<String>,
+  },
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case", rename_all_fields = "camelCase")]
+pub enum ImageSource {
+  Base64 { media_type: String, data: String },
+  Url { url: String },
+}
+
+impl ImageSource {
+  /// URL form accepted by OpenAI-compatible `image_url` parts.
+  pub fn to_url(&self) -> String {
+    match self {
+      ImageSource::Base64 { media_type, data } => format!("data:{media_type};base64,{data}"),
+      ImageSource::Url { url } => url.clone(),
+    }
+  }
+}
@@ -0,0 +1,152 @@
+use persistence_store_rs::{PersistenceConfig, PersistenceStore};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::sync::Arc;
+
+use crate::error::{ProviderError, ProviderErrorKind};
+use crate::ProviderResult;
+
+/// Where a provider's `authRef` points. A ref without a scheme is read as an env var
+/// name, as before, but an unset variable is now an error rather than the key itself.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CredentialRef {
+  Env(String),
+  File(String),
+  Secret(String),
+}
+
+impl CredentialRef {
+  pub fn parse(auth_ref: &str) -> Result<Self, String> {
+    let auth_ref = auth_ref.trim();
+    let (scheme, name) = auth_ref.split_once(':').unwrap_or(("env", auth_ref));
+    if name.trim().is_empty() {
+      return Err(format!("authRef {auth_ref} names no credential"));
+    }
+    let name = name.trim().to_string();
+    match scheme {
+      "env" => Ok(CredentialRef::Env(name)),
+      "file" => Ok(CredentialRef::File(name)),
+      "secret" => Ok(CredentialRef::Secret(name)),
+      _ => Err(format!(
+        "authRef {auth_ref} has unknown scheme {scheme}; use env:, file: or secret:"
+      )),
+    }
+  }
+}
+
+/// Backing store for `secret:` refs.
+pub trait SecretSource: Send + Sync {
+  fn load(&self, key: &str) -> Result<Option<String>, String>;
+  fn store(&self, key: &str, value: &str) -> Result<(), String>;
+}
+
+impl SecretSource for PersistenceStore {
+  fn load(&self, key: &str) -> Result<Option<String>, String> {
+    self
+      .load_secret(key.to_string())
+      .map_err(|error| error.reason)
+  }
+
+  fn store(&self, key: &str, value: &str) -> Result<(), String> {
+    self
+      .store_secret(key.to_string(), value.to_string())
+      .map_err(|error| error.reason)
+  }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CredentialStoreConfig {
+  /// Persistence store database holding the `secrets` table.
+  pub db_path: String,
+  /// Env var (or literal) holding the store's AES-256-GCM key, as for the store itself.
+  pub encryption_key_ref: Option<String>,
+}
+
+/// Resolves `authRef`s on every request, so a rotated env var, file or secret is picked
+/// up by the next call without reloading providers.
+#[derive(Clone, Default)]
+pub struct Credentials {
+  secrets: Option<Arc<dyn SecretSource>>,
+}
+
+impl fmt::Debug for Credentials {
+  fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+    formatter
+      .debug_struct("Credentials")
+      .field("secrets", &self.secrets.is_some())
+      .finish()
+  }
+}
+
+impl Credentials {
+  /// Opens and migrates the store, which blocks.
+  pub fn open_store(config: CredentialStoreConfig) -> Result<Self, String> {
+    let store = PersistenceStore::new();
+    store
+      .open(PersistenceConfig {
+        db_path: config.db_path,
+        encryption_key_ref: config.encryption_key_ref,
+      })
+      .map_err(|error| error.reason)?;
+    Ok(Self {
+      secrets: Some(Arc::new(store)),
+    })
+  }
+
+  pub async fn resolve(&self, auth_ref: &str) -> ProviderResult<String> {
+    let value = match CredentialRef::parse(auth_ref).map_err(auth_error)? {
+      CredentialRef::Env(name) => std::env::var(&name)
+        .map_err(|_| auth_error(format!("env var {name} is not set")))?,
+      // File reads and secret lookups block, so they run off the request's task.
+      credential => {
+        let credentials = self.clone();
+        tokio::task::spawn_blocking(move || credentials.read(credential))
+          .await
+          .map_err(|error| auth_error(format!("credential lookup failed: {error}")))??
+      }
+    };
+    let value = value.trim();
+    if value.is_empty() {
+      return Err(auth_error(format!("credential {auth_ref} is empty")));
+    }
+    Ok(value.to_string())
+  }
+
+  fn read(&self, credential: CredentialRef) -> ProviderResult<String> {
+    Ok(match credential {
+      CredentialRef::Env(name) => std::env::var(&name)
+        .map_err(|_| auth_error(format!("env var {name} is not set")))?,
+      CredentialRef::File(path) => std::fs::read_to_string(&path)
+        .map_err(|error| auth_error(format!("failed to read credential file {path}: {error}")))?,
+      CredentialRef::Secret(key) => self
+        .secret_source()?
+        .load(&key)
+        .map_err(auth_error)?
+        .ok_or_else(|| auth_error(format!("secret {key} is not stored")))?,
+    })
+  }
+
+  /// Replaces the value behind a `secret:` ref. Env and file credentials rotate at their
+  /// source and need no call. Blocks on the store.
+  pub fn rotate(&self, auth_ref: &str, value: &str) -> Result<(), String> {
+    match CredentialRef::parse(auth_ref)? {
+      CredentialRef::Secret(key) => self
+        .secret_source()
+        .map_err(|error| error.message)?
+        .store(&key, value),
+      _ => Err(format!("authRef {auth_ref} is not a secret: ref")),
+    }
+  }
+
+  fn secret_source(&self) -> ProviderResult<&Arc<dyn SecretSource>> {
+    self
+      .secrets
+      .as_ref()
+      .ok_or_else(|| auth_error("secret: credentials need configureCredentials first"))
+  }
+}
+
+fn auth_error(message: impl Into<String>) -> ProviderError {
+  ProviderError::new(ProviderErrorKind::Auth, message)
+}
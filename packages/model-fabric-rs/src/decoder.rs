@@ -0,0 +1,232 @@
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use uuid::Uuid;
+
+use crate::{
+  gemini, map_anthropic_finish_reason, ollama, parse_anthropic_sse_line, parse_openai_sse_line,
+  StreamChunk, ToolCall, WireProtocol, STREAM_DONE_MARKER,
+};
+
+#[derive(Default)]
+struct PendingToolCall {
+  id: String,
+  name: String,
+  arguments: String,
+}
+
+/// Turns provider stream lines (SSE, or NDJSON for Ollama) into stream chunks. Tool calls
+/// arrive in fragments (OpenAI argument deltas keyed by index, Anthropic
+/// `input_json_delta` blocks), so they are buffered and emitted whole; a thinking block's
+/// signature is emitted when it closes.
+pub struct StreamDecoder {
+  protocol: WireProtocol,
+  tool_calls: BTreeMap<u64, PendingToolCall>,
+  signatures: BTreeMap<u64, String>,
+  stop_reason: Option<String>,
+}
+
+impl StreamDecoder {
+  pub fn new(protocol: WireProtocol) -> Self {
+    Self {
+      protocol,
+      tool_calls: BTreeMap::new(),
+      signatures: BTreeMap::new(),
+      stop_reason: None,
+    }
+  }
+
+  pub fn decode_line(&mut self, line: &str) -> Vec<StreamChunk> {
+    match self.protocol {
+      WireProtocol::OpenaiCompatible => self.decode_openai(line),
+      WireProtocol::Anthropic => self.decode_anthropic(line),
+      WireProtocol::Gemini => sse_data(line)
+        .map(|parsed| gemini::parse_stream_event(&parsed))
+        .unwrap_or_default(),
+      WireProtocol::Ollama => ollama::parse_stream_line(line),
+    }
+  }
+
+  /// Flushes tool calls whose stream ended without a finish marker.
+  pub fn finish(&mut self) -> Vec<StreamChunk> {
+    self.flush_tool_calls()
+  }
+
+  fn decode_openai(&mut self, line: &str) -> Vec<StreamChunk> {
+    let Some(parsed) = sse_data(line) else {
+      return Vec::new();
+    };
+    let choice = parsed.get("choices").and_then(|choices| choices.get(0));
+
+    if let Some(deltas) = choice
+      .and_then(|choice| choice.get("delta"))
+      .and_then(|delta| delta.get("tool_calls"))
+      .and_then(|value| value.as_array())
+    {
+      for delta in deltas {
+        let index = delta.get("index").and_then(|value| value.as_u64()).unwrap_or(0);
+        let pending = self.tool_calls.entry(index).or_default();
+        if let Some(id) = delta.get("id").and_then(|value| value.as_str()) {
+          pending.id = id.to_string();
+        }
+        let function = delta.get("function");
+        if let Some(name) = function
+          .and_then(|function| function.get("name"))
+          .and_then(|value| value.as_str())
+        {
+          pending.name.push_str(name);
+        }
+        if let Some(arguments) = function
+          .and_then(|function| function.get("arguments"))
+          .and_then(|value| value.as_str())
+        {
+          pending.arguments.push_str(arguments);
+        }
+      }
+    }
+
+    let mut chunks = Vec::new();
+    let finished = choice
+      .and_then(|choice| choice.get("finish_reason"))
+      .is_some_and(|reason| !reason.is_null());
+    if finished {
+      chunks.extend(self.flush_tool_calls());
+    }
+    chunks.extend(parse_openai_sse_line(line));
+    chunks
+  }
+
+  fn decode_anthropic(&mut self, line: &str) -> Vec<StreamChunk> {
+    let Some(parsed) = sse_data(line) else {
+      return Vec::new();
+    };
+    let index = parsed.get("index").and_then(|value| value.as_u64()).unwrap_or(0);
+
+    match parsed.get("type").and_then(|value| value.as_str()) {
+      Some("content_block_start") => {
+        let block = parsed.get("content_block");
+        if block.and_then(|block| block.get("type")).and_then(|value| value.as_str())
+          == Some("tool_use")
+        {
+          let field = |key: &str| {
+            block
+              .and_then(|block| block.get(key))
+              .and_then(|value| value.as_str())
+              .unwrap_or_default()
+              .to_string()
+          };
+          self.tool_calls.insert(
+            index,
+            PendingToolCall {
+              id: field("id"),
+              name: field("name"),
+              arguments: String::new(),
+            },
+          );
+        }
+        return Vec::new();
+      }
+      Some("content_block_delta") => {
+        let delta = parsed.get("delta");
+        let text = |key: &str| {
+          delta
+            .and_then(|delta| delta.get(key))
+            .and_then(|value| value.as_str())
+            .unwrap_or_default()
+        };
+        match delta.and_then(|delta| delta.get("type")).and_then(|value| value.as_str()) {
+          Some("input_json_delta") => {
+            if let Some(pending) = self.tool_calls.get_mut(&index) {
+              pending.arguments.push_str(text("partial_json"));
+            }
+            return Vec::new();
+          }
+          Some("thinking_delta") => {
+            return vec![StreamChunk {
+              content: Some(text("thinking").to_string()),
+              ..StreamChunk::of("thinking")
+            }];
+          }
+          Some("signature_delta") => {
+            self
+              .signatures
+              .entry(index)
+              .or_default()
+              .push_str(text("signature"));
+            return Vec::new();
+          }
+          _ => {}
+        }
+      }
+      Some("content_block_stop") => {
+        if let Some(pending) = self.tool_calls.remove(&index) {
+          return vec![tool_call_chunk(pending)];
+        }
+        if let Some(signature) = self.signatures.remove(&index) {
+          return vec![StreamChunk {
+            signature: Some(signature),
+            ..StreamChunk::of("thinking")
+          }];
+        }
+        return Vec::new();
+      }
+      Some("message_delta") => {
+        if let Some(reason) = parsed
+          .get("delta")
+          .and_then(|delta| delta.get("stop_reason"))
+          .and_then(|value| value.as_str())
+        {
+          self.stop_reason = Some(map_anthropic_finish_reason(reason));
+        }
+      }
+      Some("message_stop") => {
+        let mut chunks = self.flush_tool_calls();
+        if let Some(mut done) = parse_anthropic_sse_line(line) {
+          if let Some(reason) = self.stop_reason.take() {
+            done.finish_reason = Some(reason);
+          }
+          chunks.push(done);
+        }
+        return chunks;
+      }
+      _ => {}
+    }
+
+    parse_anthropic_sse_line(line).into_iter().collect()
+  }
+
+  fn flush_tool_calls(&mut self) -> Vec<StreamChunk> {
+    std::mem::take(&mut self.tool_calls)
+      .into_values()
+      .map(tool_call_chunk)
+      .collect()
+  }
+}
+
+fn tool_call_chunk(pending: PendingToolCall) -> StreamChunk {
+  let arguments = if pending.arguments.trim().is_empty() {
+    json!({}).to_string()
+  } else {
+    pending.arguments
+  };
+  let id = if pending.id.is_empty() {
+    Uuid::new_v4().to_string()
+  } else {
+    pending.id
+  };
+  StreamChunk {
+    tool_call: Some(ToolCall {
+      id,
+      name: pending.name,
+      arguments,
+    }),
+    ..StreamChunk::of("tool_call")
+  }
+}
+
+fn sse_data(line: &str) -> Option<Value> {
+  let data = line.strip_prefix("data: ")?.trim();
+  if data.is_empty() || data == STREAM_DONE_MARKER {
+    return None;
+  }
+  serde_json::from_str(data).ok()
+}
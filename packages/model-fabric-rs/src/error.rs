@@ -0,0 +1,190 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+const CONTEXT_LENGTH_MARKERS: [&str; 6] = [
+  "context_length_exceeded",
+  "maximum context length",
+  "context window",
+  "prompt is too long",
+  "too many tokens",
+  "input is too long",
+];
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ProviderErrorKind {
+  Auth,
+  RateLimited,
+  Overloaded,
+  Timeout,
+  Network,
+  InvalidRequest,
+  ContextLengthExceeded,
+  BudgetExceeded,
+  /// The model answered, but its output failed `responseFormat` validation.
+  OutputValidation,
+  /// The caller cancelled the request or stream.
+  Cancelled,
+  /// The request waited longer than `maxQueueWaitMs` for provider capacity.
+  QueueTimeout,
+  Unknown,
+}
+
+impl ProviderErrorKind {
+  pub fn code(self) -> &'static str {
+    match self {
+      ProviderErrorKind::Auth => "auth",
+      ProviderErrorKind::RateLimited => "rate_limited",
+      ProviderErrorKind::Overloaded => "overloaded",
+      ProviderErrorKind::Timeout => "timeout",
+      ProviderErrorKind::Network => "network",
+      ProviderErrorKind::InvalidRequest => "invalid_request",
+      ProviderErrorKind::ContextLengthExceeded => "context_length_exceeded",
+      ProviderErrorKind::BudgetExceeded => "budget_exceeded",
+      ProviderErrorKind::OutputValidation => "output_validation",
+      ProviderErrorKind::Cancelled => "cancelled",
+      ProviderErrorKind::QueueTimeout => "queue_timeout",
+      ProviderErrorKind::Unknown => "unknown",
+    }
+  }
+
+  pub fn is_retryable(self) -> bool {
+    matches!(
+      self,
+      ProviderErrorKind::RateLimited
+        | ProviderErrorKind::Overloaded
+        | ProviderErrorKind::Timeout
+        | ProviderErrorKind::Network
+    )
+  }
+
+  pub fn fallback_action(self) -> FallbackAction {
+    match self {
+      ProviderErrorKind::Auth => FallbackAction::SkipProvider,
+      ProviderErrorKind::ContextLengthExceeded => FallbackAction::LargerContext,
+      ProviderErrorKind::InvalidRequest
+      | ProviderErrorKind::BudgetExceeded
+      | ProviderErrorKind::OutputValidation
+      | ProviderErrorKind::Cancelled => FallbackAction::Abort,
+      _ => FallbackAction::NextCandidate,
+    }
+  }
+}
+
+/// How the candidate walk in `ModelFabric::complete` proceeds after a model failed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FallbackAction {
+  NextCandidate,
+  SkipProvider,
+  LargerContext,
+  Abort,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProviderError {
+  pub kind: ProviderErrorKind,
+  pub message: String,
+  pub status: Option<u16>,
+  pub retry_after_ms: Option<u64>,
+}
+
+impl ProviderError {
+  pub fn new(kind: ProviderErrorKind, message: impl Into<String>) -> Self {
+    Self {
+      kind,
+      message: message.into(),
+      status: None,
+      retry_after_ms: None,
+    }
+  }
+
+  pub fn from_status(status: u16, body: &str, retry_after_ms: Option<u64>) -> Self {
+    Self {
+      kind: classify_status(status, body),
+      message: format!("Provider error ({status}): {body}"),
+      status: Some(status),
+      retry_after_ms,
+    }
+  }
+
+  pub fn from_reqwest(error: reqwest::Error) -> Self {
+    let kind = if error.is_timeout() {
+      ProviderErrorKind::Timeout
+    } else if error.is_connect() || error.is_request() || error.is_body() {
+      ProviderErrorKind::Network
+    } else if error.is_decode() {
+      ProviderErrorKind::Unknown
+    } else if let Some(status) = error.status() {
+      classify_status(status.as_u16(), "")
+    } else {
+      ProviderErrorKind::Network
+    };
+    Self::new(kind, error.to_string())
+  }
+
+  /// An error the provider reported inside an otherwise successful stream, which has no
+  /// status to go by: it is treated as the server failing unless it is about length.
+  pub fn from_stream(message: &str) -> Self {
+    let kind = if mentions_context_length(message) {
+      ProviderErrorKind::ContextLengthExceeded
+    } else {
+      ProviderErrorKind::Overloaded
+    };
+    Self::new(kind, format!("Provider stream error: {message}"))
+  }
+
+  pub fn is_retryable(&self) -> bool {
+    self.kind.is_retryable()
+  }
+}
+
+/// `<code>: <message>`, the form in which the error reaches JS; the wrapper in `node.ts`
+/// turns the code into `ModelFabricError.code`.
+impl fmt::Display for ProviderError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}: {}", self.kind.code(), self.message)
+  }
+}
+
+fn classify_status(status: u16, body: &str) -> ProviderErrorKind {
+  match status {
+    401 | 403 => ProviderErrorKind::Auth,
+    408 => ProviderErrorKind::Timeout,
+    413 => ProviderErrorKind::ContextLengthExceeded,
+    429 => ProviderErrorKind::RateLimited,
+    400 | 404 | 422 => {
+      if mentions_context_length(body) {
+        ProviderErrorKind::ContextLengthExceeded
+      } else {
+        ProviderErrorKind::InvalidRequest
+      }
+    }
+    500..=599 => ProviderErrorKind::Overloaded,
+    _ => ProviderErrorKind::Unknown,
+  }
+}
+
+fn mentions_context_length(body: &str) -> bool {
+  let lowered = body.to_ascii_lowercase();
+  CONTEXT_LENGTH_MARKERS
+    .iter()
+    .any(|marker| lowered.contains(marker))
+}
+
+/// Reads `retry-after-ms` (OpenAI) or `retry-after` in delta-seconds form.
+pub fn parse_retry_after(headers: &reqwest::header::HeaderMap) -> Option<u64> {
+  if let Some(value) = headers
+    .get("retry-after-ms")
+    .and_then(|value| value.to_str().ok())
+    .and_then(|value| value.trim().parse::<f64>().ok())
+  {
+    return Some(value.max(0.0) as u64);
+  }
+
+  headers
+    .get(reqwest::header::RETRY_AFTER)
+    .and_then(|value| value.to_str().ok())
+    .and_then(|value| value.trim().parse::<f64>().ok())
+    .map(|seconds| (seconds.max(0.0) * 1000.0) as u64)
+}
@@ -0,0 +1,47 @@
+import type { ModelFabricErrorCode } from "./types";
+
+const ERROR_CODES: Record<ModelFabricErrorCode, true> = {
+  auth: true,
+  rate_limited: true,
+  overloaded: true,
+  timeout: true,
+  network: true,
+  invalid_request: true,
+  context_length_exceeded: true,
+  budget_exceeded: true,
+  output_validation: true,
+  cancelled: true,
+  queue_timeout: true,
+  unknown: true,
+};
+
+/** A failed model fabric request; `code` is the kind of provider error behind it. */
+export class ModelFabricError extends Error {
+  readonly code: ModelFabricErrorCode;
+
+  constructor(code: ModelFabricErrorCode, message: string, options?: ErrorOptions) {
+    super(message, options);
+    this.name = "ModelFabricError";
+    this.code = code;
+  }
+}
+
+function isErrorCode(value: string): value is ModelFabricErrorCode {
+  return Object.hasOwn(ERROR_CODES, value);
+}
+
+/**
+ * The native binding reports a provider error as `<code>: <message>`. Errors without a
+ * known code, such as invalid arguments, are returned unchanged.
+ */
+export function toModelFabricError(error: unknown): unknown {
+  if (!(error instanceof Error)) {
+    return error;
+  }
+  const separator = error.message.indexOf(": ");
+  const code = separator > 0 ? error.message.slice(0, separator) : "";
+  if (!isErrorCode(code)) {
+    return error;
+  }
+  return new ModelFabricError(code, error.message.slice(separator + 2), { cause: error });
+}
@@ -0,0 +1,85 @@
+use serde_json::{json, Map, Value};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+
+use crate::CompletionRequest;
+
+const ID_KEYS: [&str; 3] = ["id", "toolCallId", "toolUseId"];
+
+/// Normalized view of what a request asks the model, without transport settings such as
+/// `timeoutMs`. Text content is expanded to parts so `"hi"` and `[{type: text}]` agree,
+/// and tool call ids are replaced by their order of appearance because several
+/// providers never send ids and the decoder mints random ones.
+pub fn request_view(model_id: &str, request: &CompletionRequest, with_sampling: bool) -> Value {
+  let messages: Vec<Value> = request
+    .messages
+    .iter()
+    .map(|message| {
+      let mut value = serde_json::to_value(message).unwrap_or(Value::Null);
+      value["content"] = json!(message.content.parts());
+      value
+    })
+    .collect();
+  let mut messages = Value::Array(messages);
+  renumber_ids(&mut messages, &mut HashMap::new());
+
+  let mut view = json!({
+    "model": model_id,
+    "messages": messages,
+    "tools": request.tools,
+  });
+  if with_sampling {
+    view["temperature"] = json!(request.temperature);
+    view["topP"] = json!(request.top_p);
+    view["maxTokens"] = json!(request.max_tokens);
+    view["stopSequences"] = json!(request.stop_sequences);
+    view["responseFormat"] = json!(request.response_format);
+    view["cachedContent"] = json!(request.cached_content);
+  }
+  canonical(&view)
+}
+
+/// Hex SHA-256 of the canonical (key-sorted) serialization of `value`.
+pub fn hash(value: &Value) -> String {
+  let digest = Sha256::digest(canonical(value).to_string().as_bytes());
+  digest.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn canonical(value: &Value) -> Value {
+  match value {
+    Value::Object(map) => {
+      let mut keys: Vec<&String> = map.keys().collect();
+      keys.sort();
+      let mut sorted = Map::new();
+      for key in keys {
+        sorted.insert(key.clone(), canonical(&map[key]));
+      }
+      Value::Object(sorted)
+    }
+    Value::Array(items) => Value::Array(items.iter().map(canonical).collect()),
+    _ => value.clone(),
+  }
+}
+
+fn renumber_ids(value: &mut Value, seen: &mut HashMap<String, usize>) {
+  match value {
+    Value::Object(map) => {
+      for (key, child) in map.iter_mut() {
+        match child {
+          Value::String(id) if ID_KEYS.contains(&key.as_str()) => {
+            let next = seen.len();
+            let ordinal = *seen.entry(id.clone()).or_insert(next);
+            *child = json!(format!("#{ordinal}"));
+          }
+          _ => renumber_ids(child, seen),
+        }
+      }
+    }
+    Value::Array(items) => {
+      for item in items {
+        renumber_ids(item, seen);
+      }
+    }
+    _ => {}
+  }
+}
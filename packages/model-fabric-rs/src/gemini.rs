@@ -0,0 +1,387 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::content::{ContentPart, ImageSource};
+use crate::error::ProviderError;
+use crate::structured::ResponseFormatType;
+use crate::{
+  ensure_success, invalid_header, resolve_api_key, resolve_base_url, CompletionRequest,
+  CompletionResponse, Message, ProviderConfigRecord, ProviderResult, StreamChunk, TokenUsage,
+  Tool, ToolCall, DEFAULT_TIMEOUT_MS,
+};
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SafetyRating {
+  pub category: String,
+  pub probability: String,
+  pub blocked: Option<bool>,
+}
+
+/// Native `generateContent` call. Unlike the OpenAI-compatible endpoint this keeps safety
+/// ratings, function declarations and `cachedContent` references.
+pub async fn call(
+  provider: &ProviderConfigRecord,
+  model_id: &str,
+  request: &CompletionRequest,
+) -> ProviderResult<CompletionResponse> {
+  let start = tokio::time::Instant::now();
+  let response = send(provider, model_id, request, "generateContent").await?;
+  let parsed: Value = response.json().await.map_err(ProviderError::from_reqwest)?;
+  let mut completion = parse_response(&parsed, model_id);
+  completion.latency_ms = start.elapsed().as_millis() as u64;
+  Ok(completion)
+}
+
+pub async fn open_stream(
+  provider: &ProviderConfigRecord,
+  model_id: &str,
+  request: &CompletionRequest,
+) -> ProviderResult<reqwest::Response> {
+  send(provider, model_id, request, "streamGenerateContent?alt=sse").await
+}
+
+async fn send(
+  provider: &ProviderConfigRecord,
+  model_id: &str,
+  request: &CompletionRequest,
+  method: &str,
+) -> ProviderResult<reqwest::Response> {
+  let base_url = resolve_base_url(provider);
+  let api_key = resolve_api_key(provider).await?;
+  let timeout_ms = request
+    .timeout_ms
+    .or(provider.timeout_ms)
+    .unwrap_or(DEFAULT_TIMEOUT_MS);
+
+  let client = reqwest::Client::builder()
+    .timeout(Duration::from_millis(timeout_ms))
+    .build()
+    .map_err(ProviderError::from_reqwest)?;
+
+  let mut headers = reqwest::header::HeaderMap::new();
+  headers.insert(
+    reqwest::header::CONTENT_TYPE,
+    reqwest::header::HeaderValue::from_static("application/json"),
+  );
+  headers.insert(
+    "x-goog-api-key",
+    reqwest::header::HeaderValue::from_str(&api_key).map_err(invalid_header)?,
+  );
+
+  let response = client
+    .post(format!("{base_url}/models/{model_id}:{method}"))
+    .headers(headers)
+    .json(&build_body(provider, request))
+    .send()
+    .await
+    .map_err(ProviderError::from_reqwest)?;
+  ensure_success(response).await
+}
+
+fn build_body(provider: &ProviderConfigRecord, request: &CompletionRequest) -> Value {
+  let (system_instruction, contents) = format_contents(&request.messages);
+  let mut body = json!({ "contents": contents });
+
+  if let Some(system) = system_instruction {
+    body["systemInstruction"] = json!({ "parts": [{ "text": system }] });
+  }
+  if let Some(tools) = &request.tools {
+    body["tools"] = json!([{ "functionDeclarations": format_tools(tools) }]);
+  }
+  if let Some(cached_content) = &request.cached_content {
+    body["cachedContent"] = json!(cached_content);
+  }
+  if let Some(safety_settings) = &provider.safety_settings {
+    body["safetySettings"] = json!(safety_settings);
+  }
+
+  let mut generation_config = json!({});
+  if let Some(temperature) = request.temperature {
+    generation_config["temperature"] = json!(temperature);
+  }
+  if let Some(top_p) = request.top_p {
+    generation_config["topP"] = json!(top_p);
+  }
+  if let Some(max_tokens) = request.max_tokens {
+    generation_config["maxOutputTokens"] = json!(max_tokens);
+  }
+  if let Some(stop_sequences) = &request.stop_sequences {
+    generation_config["stopSequences"] = json!(stop_sequences);
+  }
+  if let Some(format) = &request.response_format {
+    generation_config["responseMimeType"] = json!("application/json");
+    if format.format_type == ResponseFormatType::JsonSchema {
+      generation_config["responseJsonSchema"] = json!(format.schema);
+    }
+  }
+  if generation_config.as_object().is_some_and(|config| !config.is_empty()) {
+    body["generationConfig"] = generation_config;
+  }
+
+  body
+}
+
+/// Gemini has no call ids on function responses, so results are matched back to the
+/// function name through the ids of earlier `tool_use` parts and assistant tool calls.
+fn format_contents(messages: &[Message]) -> (Option<String>, Vec<Value>) {
+  let mut system: Option<String> = None;
+  let mut contents: Vec<Value> = Vec::new();
+  let mut call_names: HashMap<String, String> = HashMap::new();
+
+  for message in messages {
+    if message.role == "system" {
+      let text = message.content.text();
+      system = Some(match system {
+        Some(existing) => format!("{existing}\n{text}"),
+        None => text,
+      });
+      continue;
+    }
+
+    let mut parts: Vec<Value> = Vec::new();
+    if message.role == "tool" {
+      let id = message.tool_call_id.clone().unwrap_or_default();
+      parts.push(function_response(&call_names, &id, &message.content.text()));
+    } else {
+      for part in message.content.parts() {
+        match part {
+          ContentPart::Text { text } => parts.push(json!({ "text": text })),
+          ContentPart::Image {
+            source: ImageSource::Base64 { media_type, data },
+          } => parts.push(json!({ "inlineData": { "mimeType": media_type, "data": data } })),
+          ContentPart::Image {
+            source: ImageSource::Url { url },
+          } => parts.push(json!({ "fileData": { "fileUri": url } })),
+          ContentPart::ToolUse { id, name, input } => {
+            call_names.insert(id, name.clone());
+            parts.push(json!({ "functionCall": { "name": name, "args": input } }));
+          }
+          ContentPart::ToolResult {
+            tool_use_id,
+            content,
+            ..
+          } => parts.push(function_response(&call_names, &tool_use_id, &content)),
+          ContentPart::Thinking { .. } => {}
+        }
+      }
+      for call in message.tool_calls.iter().flatten() {
+        call_names.insert(call.id.clone(), call.name.clone());
+        let args = serde_json::from_str::<Value>(&call.arguments).unwrap_or_else(|_| json!({}));
+        parts.push(json!({ "functionCall": { "name": call.name, "args": args } }));
+      }
+    }
+
+    if parts.is_empty() {
+      continue;
+    }
+    let role = if message.role == "assistant" { "model" } else { "user" };
+    match contents.last_mut() {
+      Some(last) if last["role"] == role => {
+        if let Some(existing) = last["parts"].as_array_mut() {
+          existing.extend(parts);
+        }
+      }
+      _ => contents.push(json!({ "role": role, "parts": parts })),
+    }
+  }
+
+  (system, contents)
+}
+
+fn function_response(call_names: &HashMap<String, String>, id: &str, content: &str) -> Value {
+  let name = call_names.get(id).map(String::as_str).unwrap_or(id);
+  let response = match serde_json::from_str::<Value>(content) {
+    Ok(Value::Object(object)) => Value::Object(object),
+    _ => json!({ "content": content }),
+  };
+  json!({ "functionResponse": { "name": name, "response": response } })
+}
+
+fn format_tools(tools: &[Tool]) -> Vec<Value> {
+  tools
+    .iter()
+    .map(|tool| {
+      json!({
+        "name": tool.name,
+        "description": tool.description,
+        "parameters": tool.parameters,
+      })
+    })
+    .collect()
+}
+
+pub fn parse_response(parsed: &Value, model_id: &str) -> CompletionResponse {
+  let candidate = parsed.get("candidates").and_then(|candidates| candidates.get(0));
+  let parts = parse_parts(candidate);
+
+  let content = parts
+    .iter()
+    .filter_map(|part| match part {
+      ContentPart::Text { text } => Some(text.as_str()),
+      _ => None,
+    })
+    .collect::<Vec<_>>()
+    .join("");
+  let tool_calls: Vec<ToolCall> = parts
+    .iter()
+    .filter_map(|part| match part {
+      ContentPart::ToolUse { id, name, input } => Some(ToolCall {
+        id: id.clone(),
+        name: name.clone(),
+        arguments: input.to_string(),
+      }),
+      _ => None,
+    })
+    .collect();
+
+  // A prompt blocked before generation has no candidate, only `promptFeedback`.
+  let finish_reason = match candidate
+    .and_then(|candidate| candidate.get("finishReason"))
+    .and_then(|value| value.as_str())
+  {
+    Some(reason) => map_finish_reason(reason, !tool_calls.is_empty()),
+    None if parsed.pointer("/promptFeedback/blockReason").is_some() => {
+      "content_filter".to_string()
+    }
+    None => "stop".to_string(),
+  };
+
+  let safety_ratings = candidate
+    .and_then(|candidate| candidate.get("safetyRatings"))
+    .or_else(|| parsed.pointer("/promptFeedback/safetyRatings"))
+    .and_then(|ratings| serde_json::from_value(ratings.clone()).ok());
+
+  CompletionResponse {
+    content,
+    tool_calls: Some(tool_calls).filter(|calls| !calls.is_empty()),
+    content_parts: Some(parts),
+    parsed: None,
+    usage: parsed.get("usageMetadata").map(parse_usage).unwrap_or_default(),
+    finish_reason,
+    model: parsed
+      .get("modelVersion")
+      .and_then(|value| value.as_str())
+      .unwrap_or(model_id)
+      .to_string(),
+    latency_ms: 0,
+    safety_ratings,
+  }
+}
+
+/// Decodes one `streamGenerateContent?alt=sse` frame. Gemini sends whole function calls,
+/// so no buffering is needed.
+pub fn parse_stream_event(parsed: &Value) -> Vec<StreamChunk> {
+  let candidate = parsed.get("candidates").and_then(|candidates| candidates.get(0));
+  let mut chunks: Vec<StreamChunk> = Vec::new();
+  let mut has_tool_call = false;
+
+  for part in parse_parts(candidate) {
+    match part {
+      ContentPart::Text { text } if !text.is_empty() => chunks.push(StreamChunk {
+        content: Some(text),
+        ..StreamChunk::of("content")
+      }),
+      ContentPart::Thinking { thinking, .. } => chunks.push(StreamChunk {
+        content: Some(thinking),
+        ..StreamChunk::of("thinking")
+      }),
+      ContentPart::ToolUse { id, name, input } => {
+        has_tool_call = true;
+        chunks.push(StreamChunk {
+          tool_call: Some(ToolCall {
+            id,
+            name,
+            arguments: input.to_string(),
+          }),
+          ..StreamChunk::of("tool_call")
+        });
+      }
+      _ => {}
+    }
+  }
+
+  if let Some(usage) = parsed.get("usageMetadata") {
+    chunks.push(StreamChunk {
+      usage: Some(parse_usage(usage)),
+      ..StreamChunk::of("usage")
+    });
+  }
+  if let Some(reason) = candidate
+    .and_then(|candidate| candidate.get("finishReason"))
+    .and_then(|value| value.as_str())
+  {
+    chunks.push(StreamChunk {
+      finish_reason: Some(map_finish_reason(reason, has_tool_call)),
+      ..StreamChunk::of("done")
+    });
+  }
+
+  chunks
+}
+
+fn parse_parts(candidate: Option<&Value>) -> Vec<ContentPart> {
+  let Some(parts) = candidate
+    .and_then(|candidate| candidate.pointer("/content/parts"))
+    .and_then(|value| value.as_array())
+  else {
+    return Vec::new();
+  };
+
+  parts
+    .iter()
+    .filter_map(|part| {
+      if let Some(call) = part.get("functionCall") {
+        return Some(ContentPart::ToolUse {
+          id: call
+            .get("id")
+            .and_then(|value| value.as_str())
+            .map(str::to_string)
+            .unwrap_or_else(|| Uuid::new_v4().to_string()),
+          name: call.get("name")?.as_str()?.to_string(),
+          input: call.get("args").cloned().unwrap_or_else(|| json!({})),
+        });
+      }
+      let text = part.get("text")?.as_str()?.to_string();
+      if part.get("thought").and_then(|value| value.as_bool()) == Some(true) {
+        Some(ContentPart::Thinking {
+          thinking: text,
+          signature: part
+            .get("thoughtSignature")
+            .and_then(|value| value.as_str())
+            .map(str::to_string),
+        })
+      } else {
+        Some(ContentPart::Text { text })
+      }
+    })
+    .collect()
+}
+
+/// Thinking tokens are billed as output, so they are counted there.
+fn parse_usage(value: &Value) -> TokenUsage {
+  let read = |key: &str| value.get(key).and_then(|value| value.as_u64());
+  let input = read("promptTokenCount").unwrap_or(0);
+  let output = read("candidatesTokenCount").unwrap_or(0) + read("thoughtsTokenCount").unwrap_or(0);
+  TokenUsage {
+    input_tokens: input,
+    output_tokens: output,
+    total_tokens: read("totalTokenCount").unwrap_or(input + output),
+    cached_input_tokens: read("cachedContentTokenCount"),
+  }
+}
+
+pub fn map_finish_reason(reason: &str, has_tool_calls: bool) -> String {
+  match reason {
+    "STOP" if has_tool_calls => "tool_calls".to_string(),
+    "MAX_TOKENS" => "length".to_string(),
+    "SAFETY" | "RECITATION" | "BLOCKLIST" | "PROHIBITED_CONTENT" | "SPII" | "IMAGE_SAFETY" => {
+      "content_filter".to_string()
+    }
+    "MALFORMED_FUNCTION_CALL" => "error".to_string(),
+    _ => "stop".to_string(),
+  }
+}
@@ -0,0 +1,183 @@
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+use crate::error::{ProviderError, ProviderErrorKind};
+use crate::ProviderConfigRecord;
+
+const DEFAULT_FAILURE_THRESHOLD: u32 = 5;
+const DEFAULT_COOLDOWN_MS: u64 = 30_000;
+const LATENCY_WINDOW: usize = 128;
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+  Closed,
+  Open,
+  HalfOpen,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct CircuitBreakerConfig {
+  pub failure_threshold: u32,
+  pub cooldown_ms: u64,
+}
+
+impl CircuitBreakerConfig {
+  pub fn for_provider(provider: &ProviderConfigRecord) -> Self {
+    Self {
+      failure_threshold: provider
+        .circuit_failure_threshold
+        .unwrap_or(DEFAULT_FAILURE_THRESHOLD)
+        .max(1),
+      cooldown_ms: provider.circuit_cooldown_ms.unwrap_or(DEFAULT_COOLDOWN_MS),
+    }
+  }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProviderHealthSnapshot {
+  pub provider_id: String,
+  pub circuit_state: CircuitState,
+  pub consecutive_failures: u32,
+  pub total_requests: u64,
+  pub total_failures: u64,
+  pub last_error: Option<String>,
+  pub last_error_code: Option<ProviderErrorKind>,
+  pub last_error_at: Option<i64>,
+  pub opened_at: Option<i64>,
+  pub latency_p50_ms: Option<u64>,
+  pub latency_p95_ms: Option<u64>,
+}
+
+/// Rolling health of one provider. The breaker opens after `failure_threshold`
+/// consecutive failures and turns half-open once `cooldown_ms` has elapsed. A half-open
+/// breaker lets one probe request through at a time: its success closes the breaker, its
+/// failure re-opens it for another cool-down.
+#[derive(Default)]
+pub struct ProviderHealth {
+  consecutive_failures: u32,
+  total_requests: u64,
+  total_failures: u64,
+  opened_at: Option<i64>,
+  /// When the half-open probe in flight started. A probe that never reports back, such
+  /// as a cancelled one, stops blocking others after a cool-down.
+  probe_started_at: Option<i64>,
+  last_error: Option<ProviderError>,
+  last_error_at: Option<i64>,
+  latencies: VecDeque<u64>,
+}
+
+impl ProviderHealth {
+  pub fn circuit_state(&self, config: &CircuitBreakerConfig, now: i64) -> CircuitState {
+    match self.opened_at {
+      None => CircuitState::Closed,
+      Some(opened_at) if now.saturating_sub(opened_at) >= config.cooldown_ms as i64 => {
+        CircuitState::HalfOpen
+      }
+      Some(_) => CircuitState::Open,
+    }
+  }
+
+  pub fn allows_request(&self, config: &CircuitBreakerConfig, now: i64) -> bool {
+    match self.circuit_state(config, now) {
+      CircuitState::Closed => true,
+      CircuitState::Open => false,
+      CircuitState::HalfOpen => self
+        .probe_started_at
+        .is_none_or(|started_at| now.saturating_sub(started_at) >= config.cooldown_ms as i64),
+    }
+  }
+
+  /// Claims a request to the provider, making it the probe when the breaker is half-open.
+  pub fn begin_request(&mut self, config: &CircuitBreakerConfig, now: i64) -> bool {
+    if !self.allows_request(config, now) {
+      return false;
+    }
+    if self.circuit_state(config, now) == CircuitState::HalfOpen {
+      self.probe_started_at = Some(now);
+    }
+    true
+  }
+
+  pub fn record_success(&mut self, latency_ms: u64) {
+    self.total_requests += 1;
+    self.consecutive_failures = 0;
+    self.opened_at = None;
+    self.probe_started_at = None;
+    if self.latencies.len() == LATENCY_WINDOW {
+      self.latencies.pop_front();
+    }
+    self.latencies.push_back(latency_ms);
+  }
+
+  pub fn record_failure(&mut self, config: &CircuitBreakerConfig, error: &ProviderError, now: i64) {
+    self.total_requests += 1;
+    self.total_failures += 1;
+    self.last_error = Some(error.clone());
+    self.last_error_at = Some(now);
+    self.probe_started_at = None;
+    if !trips_circuit(error.kind) {
+      return;
+    }
+
+    self.consecutive_failures = self.consecutive_failures.saturating_add(1);
+    let half_open = self.circuit_state(config, now) == CircuitState::HalfOpen;
+    if half_open || self.consecutive_failures >= config.failure_threshold {
+      self.opened_at = Some(now);
+    }
+  }
+
+  /// Median latency over the rolling window, `None` before the first success.
+  pub fn latency_p50_ms(&self) -> Option<u64> {
+    let mut sorted: Vec<u64> = self.latencies.iter().copied().collect();
+    sorted.sort_unstable();
+    percentile(&sorted, 50)
+  }
+
+  pub fn snapshot(
+    &self,
+    provider_id: &str,
+    config: &CircuitBreakerConfig,
+    now: i64,
+  ) -> ProviderHealthSnapshot {
+    let mut sorted: Vec<u64> = self.latencies.iter().copied().collect();
+    sorted.sort_unstable();
+    ProviderHealthSnapshot {
+      provider_id: provider_id.to_string(),
+      circuit_state: self.circuit_state(config, now),
+      consecutive_failures: self.consecutive_failures,
+      total_requests: self.total_requests,
+      total_failures: self.total_failures,
+      last_error: self.last_error.as_ref().map(|error| error.message.clone()),
+      last_error_code: self.last_error.as_ref().map(|error| error.kind),
+      last_error_at: self.last_error_at,
+      opened_at: self.opened_at,
+      latency_p50_ms: percentile(&sorted, 50),
+      latency_p95_ms: percentile(&sorted, 95),
+    }
+  }
+}
+
+/// Request-specific failures (bad payload, oversized prompt, bad key) say nothing
+/// about whether the provider is up, so they do not count towards opening the breaker.
+fn trips_circuit(kind: ProviderErrorKind) -> bool {
+  !matches!(
+    kind,
+    ProviderErrorKind::Auth
+      | ProviderErrorKind::InvalidRequest
+      | ProviderErrorKind::ContextLengthExceeded
+      | ProviderErrorKind::BudgetExceeded
+      | ProviderErrorKind::OutputValidation
+      | ProviderErrorKind::Cancelled
+      | ProviderErrorKind::QueueTimeout
+  )
+}
+
+fn percentile(sorted: &[u64], percentile: usize) -> Option<u64> {
+  if sorted.is_empty() {
+    return None;
+  }
+  let rank = (percentile * sorted.len()).div_ceil(100).max(1);
+  sorted.get(rank - 1).copied()
+}
@@ -1,17 +1,43 @@
-export { isModelFabricAvailable, ModelFabric } from "./node";
+export { isModelFabricAvailable, ModelFabric, ModelFabricError } from "./node";
 export type {
+  BudgetAction,
+  BudgetRule,
+  BudgetScope,
+  BudgetUsageSnapshot,
+  CassetteConfig,
+  CassetteMatch,
+  CassetteMode,
+  CassetteStatus,
   CompletionRequest,
   CompletionResponse,
+  ContentPart,
+  CredentialStoreConfig,
   FinishReason,
+  ImageSource,
+  LimiterSnapshot,
   Message,
   MessageRole,
   ModelFabricContext,
+  ModelFabricErrorCode,
   ModelFabricSnapshot,
+  ModelPricing,
   ModelStreamHandle,
   ModelUsageEvent,
+  ProviderApi,
+  ProviderCircuitState,
   ProviderConfigRecord,
+  ProviderHealthSnapshot,
   ProviderKind,
+  RateLimits,
+  ResponseCacheConfig,
+  ResponseCacheStatus,
+  ResponseFormat,
+  ResponseFormatType,
+  RouteArm,
   RouteRule,
+  RouteStrategy,
+  SafetyRating,
+  StickyKey,
   StreamChunk,
   StreamChunkType,
   TokenUsage,
@@ -1,3 +1,36 @@
+mod budget;
+mod cache;
+mod cancel;
+mod cassette;
+mod content;
+mod credentials;
+mod decoder;
+mod error;
+mod fingerprint;
+mod gemini;
+mod health;
+mod limits;
+mod ollama;
+mod pricing;
+mod retry;
+mod routing;
+mod structured;
+
+use budget::{BudgetDecision, BudgetLedger, BudgetRule, BudgetUsageSnapshot};
+use cache::{ResponseCache, ResponseCacheConfig, ResponseCacheStatus, StreamTranscript};
+use cancel::{cancelled_error, CancelSignal};
+use cassette::{Cassette, CassetteConfig, CassetteInteraction, CassetteStatus, InteractionKind};
+use content::{ContentPart, ImageSource, MessageContent};
+use credentials::{CredentialRef, CredentialStoreConfig, Credentials};
+use decoder::StreamDecoder;
+use error::{FallbackAction, ProviderError, ProviderErrorKind};
+use gemini::SafetyRating;
+use health::{CircuitBreakerConfig, ProviderHealth, ProviderHealthSnapshot};
+use limits::{Admission, Limiter, LimiterSnapshot, RateLimits};
+use pricing::{ModelPricing, PricingCatalog};
+use retry::RetryPolicy;
+use routing::{RouteArm, RouteDecision, RouteSignals, RouteStrategy, StickyKey};
+use structured::{describe_violations, ResponseFormat};
 use futures_util::StreamExt;
 use napi::bindgen_prelude::Result as NapiResult;
 use napi::Error as NapiError;
@@ -11,14 +44,16 @@ use tokio::sync::mpsc;
 use uuid::Uuid;
 
 const DEFAULT_TIMEOUT_MS: u64 = 30_000;
-const DEFAULT_MAX_RETRIES: u32 = 3;
 const DEFAULT_OPENAI_BASE_URL: &str = "https://api.openai.com/v1";
 const DEFAULT_GEMINI_BASE_URL: &str = "https://generativelanguage.googleapis.com/v1beta/openai";
+const DEFAULT_GEMINI_NATIVE_BASE_URL: &str = "https://generativelanguage.googleapis.com/v1beta";
 const DEFAULT_ANTHROPIC_BASE_URL: &str = "https://api.anthropic.com/v1";
+const DEFAULT_OLLAMA_BASE_URL: &str = "http://localhost:11434";
 const DEFAULT_ANTHROPIC_VERSION: &str = "2023-06-01";
 const STREAM_DONE_MARKER: &str = "[DONE]";
 
 type FabricResult<T> = std::result::Result<T, String>;
+type ProviderResult<T> = std::result::Result<T, ProviderError>;
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
 #[serde(rename_all = "snake_case")]
@@ -29,18 +64,69 @@ enum ProviderKind {
   Local,
 }
 
+/// Which API a `gemini` or `local` provider speaks. `native` selects Gemini's
+/// `generateContent` and Ollama's `/api/chat`; other kinds ignore it.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum ProviderApi {
+  #[default]
+  OpenaiCompatible,
+  Native,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+enum WireProtocol {
+  OpenaiCompatible,
+  Anthropic,
+  Gemini,
+  Ollama,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct ProviderConfigRecord {
   provider_id: String,
   kind: ProviderKind,
+  /// Empty only for native local (Ollama) providers, which then send no key.
+  #[serde(default)]
   auth_ref: String,
   base_url: Option<String>,
   timeout_ms: Option<u64>,
   max_retries: Option<u32>,
+  retry_base_delay_ms: Option<u64>,
+  retry_max_delay_ms: Option<u64>,
+  circuit_failure_threshold: Option<u32>,
+  circuit_cooldown_ms: Option<u64>,
   organization_id: Option<String>,
   model_ids: Vec<String>,
   default_model_id: Option<String>,
+  context_windows: Option<HashMap<String, u64>>,
+  api: Option<ProviderApi>,
+  /// Ollama `keep_alive` (e.g. `"5m"`, `"-1"`), sent with every native request.
+  keep_alive: Option<String>,
+  /// Gemini `safetySettings`, passed through verbatim on native requests.
+  safety_settings: Option<Vec<Value>>,
+  /// Provider-wide concurrency and rate limits; excess requests queue in order.
+  limits: Option<RateLimits>,
+  /// Extra limits for individual models, applied on top of `limits`.
+  model_limits: Option<HashMap<String, RateLimits>>,
+  /// Longest a request may queue before failing with `queue_timeout`; unset waits.
+  max_queue_wait_ms: Option<u64>,
+  /// Bound by the fabric when providers load; resolves `authRef` on each request.
+  #[serde(skip)]
+  credentials: Credentials,
+}
+
+impl ProviderConfigRecord {
+  fn protocol(&self) -> WireProtocol {
+    match (&self.kind, self.api.unwrap_or_default()) {
+      (ProviderKind::Anthropic, _) => WireProtocol::Anthropic,
+      (ProviderKind::Gemini, ProviderApi::Native) => WireProtocol::Gemini,
+      (ProviderKind::Local, ProviderApi::Native) => WireProtocol::Ollama,
+      _ => WireProtocol::OpenaiCompatible,
+    }
+  }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -52,6 +138,11 @@ struct RouteRule {
   task_type: Option<String>,
   model_id: String,
   fallback_model_ids: Option<Vec<String>>,
+  strategy: Option<RouteStrategy>,
+  /// Weighted split for the `weighted` strategy.
+  arms: Option<Vec<RouteArm>>,
+  /// Context field that keeps a weighted split stable; unset picks per request.
+  sticky_key: Option<StickyKey>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -60,11 +151,22 @@ struct ModelUsageEvent {
   event_id: String,
   provider_id: String,
   model_id: String,
+  run_id: Option<String>,
   input_tokens: u64,
   output_tokens: u64,
   total_tokens: u64,
+  cached_input_tokens: Option<u64>,
   latency_ms: u64,
   cost_usd: Option<f64>,
+  /// Served from the response cache; no provider was called.
+  cache_hit: bool,
+  /// The stream was cancelled; tokens are what the provider reported until then.
+  cancelled: bool,
+  /// Time spent waiting for provider capacity before the call went out.
+  queue_ms: u64,
+  /// Strategy of the route that picked the model, and the arm (or model) it chose.
+  route_strategy: Option<RouteStrategy>,
+  route_arm: Option<String>,
   created_at: i64,
 }
 
@@ -74,13 +176,26 @@ struct ModelFabricSnapshot {
   providers: Vec<ProviderConfigRecord>,
   routes: Vec<RouteRule>,
   usage_cursor: u64,
+  provider_health: Vec<ProviderHealthSnapshot>,
+  pricing: Vec<ModelPricing>,
+  budgets: Vec<BudgetRule>,
+  budget_usage: Vec<BudgetUsageSnapshot>,
+  cassette: Option<CassetteStatus>,
+  response_cache: Option<ResponseCacheStatus>,
+  limiters: Vec<LimiterSnapshot>,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct ModelRequestContext {
   worker_id: Option<String>,
   task_type: Option<String>,
+  run_id: Option<String>,
+  /// Caller-chosen id that `ModelFabric::cancel` accepts.
+  request_id: Option<String>,
+  /// Set by routing, never by callers; copied onto usage events.
+  #[serde(skip)]
+  route: Option<RouteDecision>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -94,13 +209,24 @@ struct CompletionRequest {
   tools: Option<Vec<Tool>>,
   top_p: Option<f64>,
   timeout_ms: Option<u64>,
+  response_format: Option<ResponseFormat>,
+  /// Gemini cached content resource (`cachedContents/...`) to prefix the prompt with.
+  cached_content: Option<String>,
+  /// Forces the response cache on or off for this request, overriding the
+  /// temperature-0 rule.
+  cache: Option<bool>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct Message {
   role: String,
-  content: String,
+  #[serde(default)]
+  content: MessageContent,
+  /// Call answered by a `tool` role message.
+  tool_call_id: Option<String>,
+  /// Tool calls previously issued by an `assistant` message.
+  tool_calls: Option<Vec<ToolCall>>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -119,12 +245,14 @@ struct ToolCall {
   arguments: String,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct TokenUsage {
   input_tokens: u64,
   output_tokens: u64,
   total_tokens: u64,
+  /// Portion of `input_tokens` served from the provider's prompt cache.
+  cached_input_tokens: Option<u64>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -132,10 +260,16 @@ struct TokenUsage {
 struct CompletionResponse {
   content: String,
   tool_calls: Option<Vec<ToolCall>>,
+  /// The assistant turn as ordered parts (thinking, text, tool_use), ready to be
+  /// sent back as the `content` of the next request's assistant message.
+  content_parts: Option<Vec<ContentPart>>,
+  /// Validated JSON content when a `responseFormat` was requested.
+  parsed: Option<Value>,
   usage: TokenUsage,
   finish_reason: String,
   model: String,
   latency_ms: u64,
+  safety_ratings: Option<Vec<SafetyRating>>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -147,7 +281,36 @@ struct StreamChunk {
   tool_call: Option<ToolCall>,
   usage: Option<TokenUsage>,
   error: Option<String>,
+  error_code: Option<ProviderErrorKind>,
   finish_reason: Option<String>,
+  model: Option<String>,
+  /// Signature closing a streamed thinking block.
+  signature: Option<String>,
+}
+
+impl StreamChunk {
+  fn of(chunk_type: &str) -> Self {
+    Self {
+      chunk_type: chunk_type.to_string(),
+      content: None,
+      tool_call: None,
+      usage: None,
+      error: None,
+      error_code: None,
+      finish_reason: None,
+      model: None,
+      signature: None,
+    }
+  }
+
+  fn fallback(model_id: &str, reason: Option<&ProviderError>) -> Self {
+    Self {
+      error: reason.map(|error| error.message.clone()),
+      error_code: reason.map(|error| error.kind),
+      model: Some(model_id.to_string()),
+      ..Self::of("fallback")
+    }
+  }
 }
 
 #[derive(Default)]
@@ -156,6 +319,16 @@ struct ModelFabricState {
   routes: Vec<RouteRule>,
   provider_by_id: HashMap<String, ProviderConfigRecord>,
   model_to_provider: HashMap<String, String>,
+  provider_health: HashMap<String, ProviderHealth>,
+  pricing: PricingCatalog,
+  budgets: BudgetLedger,
+  cassette: Option<Cassette>,
+  response_cache: Option<ResponseCache>,
+  /// Cancel signals of running requests that carry a `requestId`.
+  in_flight: HashMap<String, CancelSignal>,
+  /// Keyed by `providerId` and `providerId/modelId`; only limited keys have one.
+  limiters: HashMap<String, Arc<Limiter>>,
+  credentials: Credentials,
   usage_events: Vec<UsageRecord>,
   next_cursor: u64,
 }
@@ -166,6 +339,13 @@ impl ModelFabricState {
     self.routes.clear();
     self.provider_by_id.clear();
     self.model_to_provider.clear();
+    self.provider_health.clear();
+    self.pricing = PricingCatalog::default();
+    self.budgets.clear();
+    self.cassette = None;
+    self.response_cache = None;
+    self.limiters.clear();
+    self.credentials = Credentials::default();
     self.usage_events.clear();
     self.next_cursor = 0;
   }
@@ -174,6 +354,9 @@ impl ModelFabricState {
     validate_provider_configs(&records)?;
     let mut ordered = records;
     ordered.sort_by(|a, b| a.provider_id.cmp(&b.provider_id));
+    for provider in &mut ordered {
+      provider.credentials = self.credentials.clone();
+    }
 
     let mut provider_by_id = HashMap::new();
     let mut model_to_provider = HashMap::new();
@@ -185,12 +368,58 @@ impl ModelFabricState {
       }
     }
 
+    self.provider_health
+      .retain(|provider_id, _| provider_by_id.contains_key(provider_id));
+    self.limiters = self.rebuild_limiters(&ordered);
     self.providers = ordered;
     self.provider_by_id = provider_by_id;
     self.model_to_provider = model_to_provider;
     Ok(())
   }
 
+  /// Swaps the secret store behind `secret:` refs; loaded providers use it from their
+  /// next request on.
+  fn update_credentials(&mut self, credentials: Credentials) {
+    for provider in self.providers.iter_mut().chain(self.provider_by_id.values_mut()) {
+      provider.credentials = credentials.clone();
+    }
+    self.credentials = credentials;
+  }
+
+  /// Limiters whose limits did not change are kept, so requests already queued or in
+  /// flight keep counting against them across a reload.
+  fn rebuild_limiters(&self, providers: &[ProviderConfigRecord]) -> HashMap<String, Arc<Limiter>> {
+    let mut limiters = HashMap::new();
+    for provider in providers {
+      let provider_limits = provider
+        .limits
+        .iter()
+        .map(|limits| (provider.provider_id.clone(), limits));
+      let model_limits = provider.model_limits.iter().flatten().map(|(model_id, limits)| {
+        (format!("{}/{model_id}", provider.provider_id), limits)
+      });
+      for (key, limits) in provider_limits.chain(model_limits) {
+        let limiter = match self.limiters.get(&key) {
+          Some(existing) if existing.limits() == limits => Some(existing.clone()),
+          _ => Limiter::new(key.clone(), limits),
+        };
+        if let Some(limiter) = limiter {
+          limiters.insert(key, limiter);
+        }
+      }
+    }
+    limiters
+  }
+
+  /// Model limiter first, then the provider's, which is the order requests queue in.
+  fn limiters_for(&self, provider: &ProviderConfigRecord, model_id: &str) -> Vec<Arc<Limiter>> {
+    let provider_id = &provider.provider_id;
+    [format!("{provider_id}/{model_id}"), provider_id.clone()]
+      .iter()
+      .filter_map(|key| self.limiters.get(key).cloned())
+      .collect()
+  }
+
   fn update_routes(&mut self, routes: Vec<RouteRule>) {
     let mut ordered = routes;
     ordered.sort_by(|a, b| {
@@ -202,11 +431,115 @@ impl ModelFabricState {
   }
 
   fn snapshot(&self) -> ModelFabricSnapshot {
+    let now = now_ms();
+    let default_health = ProviderHealth::default();
+    let provider_health = self
+      .providers
+      .iter()
+      .map(|provider| {
+        let health = self
+          .provider_health
+          .get(&provider.provider_id)
+          .unwrap_or(&default_health);
+        health.snapshot(
+          &provider.provider_id,
+          &CircuitBreakerConfig::for_provider(provider),
+          now,
+        )
+      })
+      .collect();
+
     ModelFabricSnapshot {
       providers: self.providers.clone(),
       routes: self.routes.clone(),
       usage_cursor: self.next_cursor.saturating_sub(1),
+      provider_health,
+      pricing: self.pricing.entries(),
+      budgets: self.budgets.rules(),
+      budget_usage: self.budgets.usage_snapshot(),
+      cassette: self.cassette.as_ref().map(Cassette::status),
+      response_cache: self.response_cache.as_ref().map(ResponseCache::status),
+      limiters: {
+        let mut limiters: Vec<LimiterSnapshot> =
+          self.limiters.values().map(|limiter| limiter.snapshot()).collect();
+        limiters.sort_by(|a, b| a.key.cmp(&b.key));
+        limiters
+      },
+    }
+  }
+
+  /// Applies budget rules to the routed candidates: rejects with a `budget_exceeded`
+  /// error or puts the configured downgrade model in place of the primary, keeping the
+  /// route's fallbacks behind it.
+  fn apply_budgets(
+    &mut self,
+    context: Option<&ModelRequestContext>,
+    candidates: Vec<String>,
+  ) -> ProviderResult<Vec<String>> {
+    match self.budgets.check(context, now_ms()) {
+      BudgetDecision::Allow => Ok(candidates),
+      BudgetDecision::Downgrade { model_id, .. } => {
+        let mut downgraded = vec![model_id];
+        for fallback in candidates.into_iter().skip(1) {
+          if !downgraded.contains(&fallback) {
+            downgraded.push(fallback);
+          }
+        }
+        Ok(downgraded)
+      }
+      BudgetDecision::Reject { reason, .. } => {
+        Err(ProviderError::new(ProviderErrorKind::BudgetExceeded, reason))
+      }
+    }
+  }
+
+  fn provider_available(&self, model_id: &str, now: i64) -> bool {
+    let Some(provider) = self
+      .model_to_provider
+      .get(model_id)
+      .and_then(|provider_id| self.provider_by_id.get(provider_id))
+    else {
+      return true;
+    };
+    self
+      .provider_health
+      .get(&provider.provider_id)
+      .is_none_or(|health| {
+        health.allows_request(&CircuitBreakerConfig::for_provider(provider), now)
+      })
+  }
+
+  /// Claims the provider for a live attempt, which its circuit breaker may refuse. The
+  /// refusal is not a provider failure and is not recorded as one.
+  fn begin_attempt(&mut self, provider: &ProviderConfigRecord) -> ProviderResult<()> {
+    let config = CircuitBreakerConfig::for_provider(provider);
+    let health = self
+      .provider_health
+      .entry(provider.provider_id.clone())
+      .or_default();
+    if health.begin_request(&config, now_ms()) {
+      return Ok(());
     }
+    Err(ProviderError::new(
+      ProviderErrorKind::Overloaded,
+      format!("Provider {} is unavailable while its circuit is open", provider.provider_id),
+    ))
+  }
+
+  fn record_provider_success(&mut self, provider: &ProviderConfigRecord, latency_ms: u64) {
+    self
+      .provider_health
+      .entry(provider.provider_id.clone())
+      .or_default()
+      .record_success(latency_ms);
+  }
+
+  fn record_provider_failure(&mut self, provider: &ProviderConfigRecord, error: &ProviderError) {
+    self
+      .provider_health
+      .entry(provider.provider_id.clone())
+      .or_default()
+      .record_failure(&CircuitBreakerConfig::for_provider(provider), error, now_ms());
   }
 
   fn record_usage_event(
@@ -215,24 +548,93 @@ impl ModelFabricState {
     model_id: String,
     usage: &TokenUsage,
     latency_ms: u64,
+    queue_ms: u64,
+    context: Option<&ModelRequestContext>,
+  ) -> ModelUsageEvent {
+    let event = ModelUsageEvent {
+      queue_ms,
+      ..new_usage_event(provider_id, model_id, usage, latency_ms, context)
+    };
+    self.bill_usage_event(event, usage, context)
+  }
+
+  /// Usage of a cancelled stream up to the cancellation, priced and debited as usual.
+  fn record_cancelled_usage(
+    &mut self,
+    provider_id: String,
+    model_id: String,
+    usage: &TokenUsage,
+    latency_ms: u64,
+    queue_ms: u64,
+    context: Option<&ModelRequestContext>,
+  ) -> ModelUsageEvent {
+    let event = ModelUsageEvent {
+      cancelled: true,
+      queue_ms,
+      ..new_usage_event(provider_id, model_id, usage, latency_ms, context)
+    };
+    self.bill_usage_event(event, usage, context)
+  }
+
+  fn bill_usage_event(
+    &mut self,
+    mut event: ModelUsageEvent,
+    usage: &TokenUsage,
+    context: Option<&ModelRequestContext>,
+  ) -> ModelUsageEvent {
+    event.cost_usd = self.pricing.cost_usd(&event.model_id, usage, event.created_at);
+    self.budgets.debit(
+      context,
+      usage.total_tokens,
+      event.cost_usd.unwrap_or(0.0),
+      event.created_at,
+    );
+    self.push_usage_event(event)
+  }
+
+  /// A response served from the cache spent nothing: it is logged with `cacheHit` and
+  /// zero cost, and no budget is debited.
+  fn record_cache_hit(
+    &mut self,
+    provider_id: String,
+    model_id: String,
+    usage: &TokenUsage,
+    context: Option<&ModelRequestContext>,
   ) -> ModelUsageEvent {
-    let cursor = self.next_cursor;
-    self.next_cursor = self.next_cursor.saturating_add(1);
     let event = ModelUsageEvent {
-      event_id: Uuid::new_v4().to_string(),
-      provider_id,
-      model_id,
-      input_tokens: usage.input_tokens,
-      output_tokens: usage.output_tokens,
-      total_tokens: usage.total_tokens,
-      latency_ms,
-      cost_usd: None,
-      created_at: now_ms(),
+      cost_usd: Some(0.0),
+      cache_hit: true,
+      ..new_usage_event(provider_id, model_id, usage, 0, context)
     };
+    self.push_usage_event(event)
+  }
+
+  fn push_usage_event(&mut self, event: ModelUsageEvent) -> ModelUsageEvent {
+    let cursor = self.next_cursor;
+    self.next_cursor = self.next_cursor.saturating_add(1);
     self.usage_events.push(UsageRecord { cursor, event: event.clone() });
     event
   }
 
+  /// Looks up a cached response for the request when a cache is configured.
+  fn cached_response(
+    &mut self,
+    provider: &ProviderConfigRecord,
+    model_id: &str,
+    request: &CompletionRequest,
+  ) -> Option<(String, Option<CompletionResponse>)> {
+    let cache = self.response_cache.as_mut()?;
+    let key = cache.key(&provider.provider_id, model_id, request)?;
+    let cached = cache.get(&key, now_ms());
+    Some((key, cached))
+  }
+
+  fn cache_response(&mut self, key: String, response: &CompletionResponse) {
+    if let Some(cache) = self.response_cache.as_mut() {
+      cache.put(key, response.clone(), now_ms());
+    }
+  }
+
   fn drain_usage_events(&mut self, after: Option<u64>, limit: Option<usize>) -> Vec<ModelUsageEvent> {
     if self.usage_events.is_empty() {
       return Vec::new();
@@ -260,6 +662,103 @@ impl ModelFabricState {
   }
 }
 
+fn new_usage_event(
+  provider_id: String,
+  model_id: String,
+  usage: &TokenUsage,
+  latency_ms: u64,
+  context: Option<&ModelRequestContext>,
+) -> ModelUsageEvent {
+  let route = context.and_then(|context| context.route.as_ref());
+  ModelUsageEvent {
+    event_id: Uuid::new_v4().to_string(),
+    provider_id,
+    model_id,
+    run_id: context.and_then(|context| context.run_id.clone()),
+    input_tokens: usage.input_tokens,
+    output_tokens: usage.output_tokens,
+    total_tokens: usage.total_tokens,
+    cached_input_tokens: usage.cached_input_tokens,
+    latency_ms,
+    cost_usd: None,
+    cache_hit: false,
+    cancelled: false,
+    queue_ms: 0,
+    route_strategy: route.map(|route| route.strategy),
+    route_arm: route.and_then(|route| route.arm.clone()),
+    created_at: now_ms(),
+  }
+}
+
+/// Registers a request's cancel signal under its `requestId` for as long as it runs.
+struct InFlight {
+  state: Arc<Mutex<ModelFabricState>>,
+  request_id: String,
+  signal: CancelSignal,
+}
+
+impl InFlight {
+  fn register(
+    state: &Arc<Mutex<ModelFabricState>>,
+    context: Option<&ModelRequestContext>,
+    signal: &CancelSignal,
+  ) -> Option<Self> {
+    let request_id = context?.request_id.clone()?;
+    state
+      .lock()
+      .ok()?
+      .in_flight
+      .insert(request_id.clone(), signal.clone());
+    Some(Self {
+      state: state.clone(),
+      request_id,
+      signal: signal.clone(),
+    })
+  }
+}
+
+impl Drop for InFlight {
+  fn drop(&mut self) {
+    if let Ok(mut state) = self.state.lock() {
+      if state
+        .in_flight
+        .get(&self.request_id)
+        .is_some_and(|signal| signal.same(&self.signal))
+      {
+        state.in_flight.remove(&self.request_id);
+      }
+    }
+  }
+}
+
+impl RouteSignals for ModelFabricState {
+  fn latency_ms(&self, model_id: &str) -> Option<u64> {
+    self
+      .model_to_provider
+      .get(model_id)
+      .and_then(|provider_id| self.provider_health.get(provider_id))
+      .and_then(ProviderHealth::latency_p50_ms)
+  }
+
+  fn context_window(&self, model_id: &str) -> Option<u64> {
+    self
+      .model_to_provider
+      .get(model_id)
+      .and_then(|provider_id| self.provider_by_id.get(provider_id))
+      .and_then(|provider| context_window(provider, model_id))
+  }
+
+  fn cost_usd(&self, model_id: &str, input: u64, output: u64) -> Option<f64> {
+    let usage = TokenUsage {
+      input_tokens: input,
+      output_tokens: output,
+      total_tokens: input + output,
+      cached_input_tokens: None,
+    };
+    self.pricing.cost_usd(model_id, &usage, now_ms())
+  }
+}
+
 struct UsageRecord {
   cursor: u64,
   event: ModelUsageEvent,
@@ -270,6 +769,12 @@ pub struct ModelFabric {
   state: Arc<Mutex<ModelFabricState>>,
 }
 
+impl Default for ModelFabric {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
 #[napi]
 impl ModelFabric {
   #[napi(constructor)]
@@ -289,11 +794,96 @@ impl ModelFabric {
   #[napi]
   pub fn load_routes(&self, routes: Vec<Value>) -> NapiResult<()> {
     let parsed = parse_routes(routes)?;
+    routing::validate(&parsed).map_err(to_napi_error)?;
     let mut state = self.state.lock().map_err(to_napi_error)?;
     state.update_routes(parsed);
     Ok(())
   }
 
+  #[napi]
+  pub fn load_budgets(&self, rules: Vec<Value>) -> NapiResult<()> {
+    let parsed = parse_budgets(rules)?;
+    let mut state = self.state.lock().map_err(to_napi_error)?;
+    let state = &mut *state;
+    let model_to_provider = &state.model_to_provider;
+    state
+      .budgets
+      .load(parsed, |model_id| model_to_provider.contains_key(model_id))
+      .map_err(to_napi_error)
+  }
+
+  #[napi]
+  pub fn load_pricing(&self, entries: Vec<Value>) -> NapiResult<()> {
+    let parsed = parse_pricing(entries)?;
+    let catalog = PricingCatalog::load(parsed).map_err(to_napi_error)?;
+    let mut state = self.state.lock().map_err(to_napi_error)?;
+    state.pricing = catalog;
+    Ok(())
+  }
+
+  /// Enables the response cache with the given bounds; `null` disables and drops it.
+  #[napi]
+  pub fn configure_cache(&self, config: Option<Value>) -> NapiResult<()> {
+    let cache = match config {
+      Some(config) => {
+        let config: ResponseCacheConfig = serde_json::from_value(config).map_err(to_napi_error)?;
+        Some(ResponseCache::open(config, now_ms()).map_err(to_napi_error)?)
+      }
+      None => None,
+    };
+    let mut state = self.state.lock().map_err(to_napi_error)?;
+    state.response_cache = cache;
+    Ok(())
+  }
+
+  /// Opens the persistence store that backs `secret:` auth refs; `null` detaches it.
+  #[napi]
+  pub async fn configure_credentials(&self, config: Option<Value>) -> NapiResult<()> {
+    let credentials = match config {
+      Some(config) => {
+        let config: CredentialStoreConfig =
+          serde_json::from_value(config).map_err(to_napi_error)?;
+        tokio::task::spawn_blocking(move || Credentials::open_store(config))
+          .await
+          .map_err(to_napi_error)?
+          .map_err(to_napi_error)?
+      }
+      None => Credentials::default(),
+    };
+    let mut state = self.state.lock().map_err(to_napi_error)?;
+    state.update_credentials(credentials);
+    Ok(())
+  }
+
+  /// Stores a new value for a `secret:` auth ref. Requests already running keep the
+  /// old key; the next request reads the new one.
+  #[napi]
+  pub async fn rotate_credential(&self, auth_ref: String, value: String) -> NapiResult<()> {
+    let credentials = {
+      let state = self.state.lock().map_err(to_napi_error)?;
+      state.credentials.clone()
+    };
+    tokio::task::spawn_blocking(move || credentials.rotate(&auth_ref, &value))
+      .await
+      .map_err(to_napi_error)?
+      .map_err(to_napi_error)
+  }
+
+  /// Starts recording to or replaying from a cassette file; `null` turns it off.
+  #[napi]
+  pub fn set_cassette(&self, config: Option<Value>) -> NapiResult<()> {
+    let cassette = match config {
+      Some(config) => {
+        let config: CassetteConfig = serde_json::from_value(config).map_err(to_napi_error)?;
+        Some(Cassette::open(config).map_err(to_napi_error)?)
+      }
+      None => None,
+    };
+    let mut state = self.state.lock().map_err(to_napi_error)?;
+    state.cassette = cassette;
+    Ok(())
+  }
+
   #[napi]
   pub async fn complete(
     &self,
@@ -301,55 +891,18 @@ impl ModelFabric {
     context: Option<Value>,
   ) -> NapiResult<Value> {
     let request = parse_completion_request(request)?;
-    let context = parse_context(context)?;
-
-    let preferred_model = if request.model.trim().is_empty() {
-      None
-    } else {
-      Some(request.model.as_str())
-    };
-    let (candidates, provider_map, providers_by_id) = {
-      let state = self.state.lock().map_err(to_napi_error)?;
-      let candidates =
-        resolve_candidates(&state, context.as_ref(), preferred_model).map_err(to_napi_error)?;
-      (candidates, state.model_to_provider.clone(), state.provider_by_id.clone())
-    };
+    let mut context = parse_context(context)?;
 
-    let mut last_error: Option<String> = None;
-    for model_id in candidates {
-      let provider_id = match provider_map.get(&model_id) {
-        Some(id) => id.clone(),
-        None => {
-          last_error = Some(format!("Model {model_id} not registered"));
-          continue;
-        }
-      };
-      let provider = match providers_by_id.get(&provider_id) {
-        Some(provider) => provider.clone(),
-        None => {
-          last_error = Some(format!("Provider {provider_id} not registered"));
-          continue;
-        }
-      };
+    let candidates = self.plan_candidates(&request, &mut context)?;
 
-      match execute_completion(&provider, &model_id, &request).await {
-        Ok(response) => {
-          let usage = response.usage.clone();
-          let latency_ms = response.latency_ms;
-          let mut state = self.state.lock().map_err(to_napi_error)?;
-          state.record_usage_event(provider.provider_id.clone(), model_id.clone(), &usage, latency_ms);
-          return serde_json::to_value(response).map_err(to_napi_error);
-        }
-        Err(error) => {
-          last_error = Some(error);
-          continue;
-        }
-      }
+    // Losing the race drops the in-flight HTTP request, which closes the connection.
+    let cancel = CancelSignal::default();
+    let _in_flight = InFlight::register(&self.state, context.as_ref(), &cancel);
+    tokio::select! {
+      biased;
+      _ = cancel.cancelled() => Err(to_napi_error(cancelled_error())),
+      result = complete_with_fallback(&self.state, candidates, &request, context.as_ref()) => result,
     }
-
-    Err(NapiError::from_reason(
-      last_error.unwrap_or_else(|| "No available model route".to_string()),
-    ))
   }
 
   #[napi]
@@ -359,54 +912,77 @@ impl ModelFabric {
     context: Option<Value>,
   ) -> NapiResult<ModelStreamHandle> {
     let request = parse_completion_request(request)?;
-    let context = parse_context(context)?;
-
-    let preferred_model = if request.model.trim().is_empty() {
-      None
-    } else {
-      Some(request.model.as_str())
-    };
-    let (candidate, provider_map, providers_by_id) = {
-      let state = self.state.lock().map_err(to_napi_error)?;
-      let candidates =
-        resolve_candidates(&state, context.as_ref(), preferred_model).map_err(to_napi_error)?;
-      let primary = candidates.first().cloned().ok_or_else(|| {
-        NapiError::from_reason("No available model route".to_string())
-      })?;
-      (primary, state.model_to_provider.clone(), state.provider_by_id.clone())
-    };
+    let mut context = parse_context(context)?;
+    if request.response_format.is_some() {
+      return Err(to_napi_error(ProviderError::new(
+        ProviderErrorKind::InvalidRequest,
+        "responseFormat is only supported by complete()",
+      )));
+    }
 
-    let provider_id = provider_map
-      .get(&candidate)
-      .cloned()
-      .ok_or_else(|| NapiError::from_reason(format!("Model {candidate} not registered")))?;
-    let provider = providers_by_id
-      .get(&provider_id)
-      .cloned()
-      .ok_or_else(|| NapiError::from_reason(format!("Provider {provider_id} not registered")))?;
+    let candidates = self.plan_candidates(&request, &mut context)?;
 
     let (sender, receiver) = mpsc::unbounded_channel();
     let state = self.state.clone();
+    let cancel = CancelSignal::default();
+    let task_cancel = cancel.clone();
 
     tokio::spawn(async move {
+      let _in_flight = InFlight::register(&state, context.as_ref(), &task_cancel);
       let stream_sender = sender.clone();
-      if let Err(error) = execute_stream(state, stream_sender, provider, candidate, request).await {
+      let result =
+        execute_stream(state.clone(), stream_sender, candidates, request, context, task_cancel)
+          .await;
+      if let Err(error) = result {
         let _ = sender.send(StreamChunk {
-          chunk_type: "error".to_string(),
-          content: None,
-          tool_call: None,
-          usage: None,
-          error: Some(error),
-          finish_reason: None,
+          error: Some(error.message),
+          error_code: Some(error.kind),
+          ..StreamChunk::of("error")
         });
       }
     });
 
     Ok(ModelStreamHandle {
       receiver: Arc::new(tokio::sync::Mutex::new(receiver)),
+      cancel,
+    })
+  }
+
+  /// Cancels the in-flight `complete` or `stream` started with this `requestId` in its
+  /// context. Returns whether such a request was running.
+  #[napi]
+  pub fn cancel(&self, request_id: String) -> NapiResult<bool> {
+    let state = self.state.lock().map_err(to_napi_error)?;
+    Ok(match state.in_flight.get(&request_id) {
+      Some(signal) => {
+        signal.cancel();
+        true
+      }
+      None => false,
     })
   }
 
+  /// Pulls a model onto a native (Ollama) local provider and returns the pull status.
+  #[napi]
+  pub async fn pull_model(&self, provider_id: String, model_id: String) -> NapiResult<Value> {
+    let provider = {
+      let state = self.state.lock().map_err(to_napi_error)?;
+      state
+        .provider_by_id
+        .get(&provider_id)
+        .cloned()
+        .ok_or_else(|| to_napi_error(format!("Unknown provider {provider_id}")))?
+    };
+    if provider.protocol() != WireProtocol::Ollama {
+      return Err(to_napi_error(format!(
+        "provider {provider_id} does not use the native local API"
+      )));
+    }
+    ollama::pull_model(&provider, &model_id)
+      .await
+      .map_err(to_napi_error)
+  }
+
   #[napi]
   pub fn get_snapshot(&self) -> NapiResult<Value> {
     let state = self.state.lock().map_err(to_napi_error)?;
@@ -436,9 +1012,28 @@ impl ModelFabric {
   }
 }
 
+impl ModelFabric {
+  /// Resolves the route, applies budgets and binds each candidate to its provider. The
+  /// route decision is stored on the context so usage events can report it.
+  fn plan_candidates(
+    &self,
+    request: &CompletionRequest,
+    context: &mut Option<ModelRequestContext>,
+  ) -> NapiResult<Vec<ResolvedCandidate>> {
+    let mut state = self.state.lock().map_err(to_napi_error)?;
+    let (candidates, decision) =
+      resolve_candidates(&mut state, context.as_ref(), request).map_err(to_napi_error)?;
+    if let Some(decision) = decision {
+      context.get_or_insert_with(ModelRequestContext::default).route = Some(decision);
+    }
+    resolve_candidate_providers(&state, candidates).map_err(to_napi_error)
+  }
+}
+
 #[napi]
 pub struct ModelStreamHandle {
   receiver: Arc<tokio::sync::Mutex<mpsc::UnboundedReceiver<StreamChunk>>>,
+  cancel: CancelSignal,
 }
 
 #[napi]
@@ -451,6 +1046,13 @@ impl ModelStreamHandle {
       None => Ok(None),
     }
   }
+
+  /// Drops the provider connection. The stream then ends with a `cancelled` chunk
+  /// carrying the usage seen so far, which is also recorded as a usage event.
+  #[napi]
+  pub fn cancel(&self) {
+    self.cancel.cancel();
+  }
 }
 
 fn parse_records(records: Vec<Value>) -> NapiResult<Vec<ProviderConfigRecord>> {
@@ -467,8 +1069,26 @@ fn parse_routes(routes: Vec<Value>) -> NapiResult<Vec<RouteRule>> {
     .collect()
 }
 
+fn parse_budgets(rules: Vec<Value>) -> NapiResult<Vec<BudgetRule>> {
+  rules
+    .into_iter()
+    .map(|value| serde_json::from_value(value).map_err(to_napi_error))
+    .collect()
+}
+
+fn parse_pricing(entries: Vec<Value>) -> NapiResult<Vec<ModelPricing>> {
+  entries
+    .into_iter()
+    .map(|value| serde_json::from_value(value).map_err(to_napi_error))
+    .collect()
+}
+
 fn parse_completion_request(value: Value) -> NapiResult<CompletionRequest> {
-  serde_json::from_value(value).map_err(to_napi_error)
+  let request: CompletionRequest = serde_json::from_value(value).map_err(to_napi_error)?;
+  if let Some(format) = &request.response_format {
+    format.validate().map_err(to_napi_error)?;
+  }
+  Ok(request)
 }
 
 fn parse_context(value: Option<Value>) -> NapiResult<Option<ModelRequestContext>> {
@@ -487,12 +1107,20 @@ fn validate_provider_configs(records: &[ProviderConfigRecord]) -> FabricResult<(
       return Err("providerId must be non-empty".to_string());
     }
     if record.auth_ref.trim().is_empty() {
-      return Err(format!("authRef missing for provider {}", record.provider_id));
+      if record.protocol() != WireProtocol::Ollama {
+        return Err(format!("authRef missing for provider {}", record.provider_id));
+      }
+    } else {
+      CredentialRef::parse(&record.auth_ref)
+        .map_err(|error| format!("provider {}: {error}", record.provider_id))?;
     }
     if record.model_ids.is_empty() {
       return Err(format!("provider {} must include modelIds", record.provider_id));
     }
-    if record.kind == ProviderKind::Local && record.base_url.is_none() {
+    if record.kind == ProviderKind::Local
+      && record.protocol() != WireProtocol::Ollama
+      && record.base_url.is_none()
+    {
       return Err(format!("provider {} requires baseUrl", record.provider_id));
     }
     if !provider_ids.insert(record.provider_id.clone()) {
@@ -522,34 +1150,102 @@ fn validate_provider_configs(records: &[ProviderConfigRecord]) -> FabricResult<(
   Ok(())
 }
 
+/// Resolves the candidate models for a request and applies budgets to them, then drops
+/// models whose provider circuit is currently open. A downgrade model is filtered like
+/// any routed model.
 fn resolve_candidates(
+  state: &mut ModelFabricState,
+  context: Option<&ModelRequestContext>,
+  request: &CompletionRequest,
+) -> ProviderResult<(Vec<String>, Option<RouteDecision>)> {
+  let now = now_ms();
+  let (candidates, decision) = route_candidates(state, context, request)
+    .map_err(|message| ProviderError::new(ProviderErrorKind::InvalidRequest, message))?;
+  let candidates = state.apply_budgets(context, candidates)?;
+  let available: Vec<String> = candidates
+    .iter()
+    .filter(|model_id| state.provider_available(model_id, now))
+    .cloned()
+    .collect();
+
+  if available.is_empty() {
+    return Err(ProviderError::new(
+      ProviderErrorKind::Overloaded,
+      format!("Circuit open for every candidate provider ({})", candidates.join(", ")),
+    ));
+  }
+  Ok((available, decision))
+}
+
+fn route_candidates(
   state: &ModelFabricState,
   context: Option<&ModelRequestContext>,
-  preferred_model: Option<&str>,
-) -> FabricResult<Vec<String>> {
+  request: &CompletionRequest,
+) -> FabricResult<(Vec<String>, Option<RouteDecision>)> {
   if let Some(route) = resolve_route(&state.routes, context) {
-    let mut candidates = vec![route.model_id];
-    if let Some(fallbacks) = route.fallback_model_ids {
-      candidates.extend(fallbacks);
+    let (candidates, decision) = routing::plan(&route, request, context, state);
+    if candidates.is_empty() {
+      return Err(format!(
+        "Route {} has no model with a large enough context window",
+        route.rule_id
+      ));
     }
-    return Ok(candidates);
+    return Ok((candidates, Some(decision)));
   }
 
-  if let Some(model_id) = preferred_model {
-    if !model_id.trim().is_empty() && state.model_to_provider.contains_key(model_id) {
-      return Ok(vec![model_id.to_string()]);
-    }
+  let preferred_model = request.model.trim();
+  if !preferred_model.is_empty() && state.model_to_provider.contains_key(preferred_model) {
+    return Ok((vec![preferred_model.to_string()], None));
   }
 
   for provider in &state.providers {
     if let Some(default_model) = &provider.default_model_id {
-      return Ok(vec![default_model.clone()]);
+      return Ok((vec![default_model.clone()], None));
     }
   }
 
   Err("No route matched and no default model configured".to_string())
 }
 
+struct ResolvedCandidate {
+  model_id: String,
+  provider: ProviderConfigRecord,
+}
+
+fn resolve_candidate_providers(
+  state: &ModelFabricState,
+  candidates: Vec<String>,
+) -> ProviderResult<Vec<ResolvedCandidate>> {
+  let mut resolved = Vec::with_capacity(candidates.len());
+  let mut last_error: Option<ProviderError> = None;
+
+  for model_id in candidates {
+    let provider = state
+      .model_to_provider
+      .get(&model_id)
+      .and_then(|provider_id| state.provider_by_id.get(provider_id));
+    match provider {
+      Some(provider) => resolved.push(ResolvedCandidate {
+        model_id,
+        provider: provider.clone(),
+      }),
+      None => {
+        last_error = Some(ProviderError::new(
+          ProviderErrorKind::InvalidRequest,
+          format!("Model {model_id} not registered"),
+        ));
+      }
+    }
+  }
+
+  if resolved.is_empty() {
+    return Err(last_error.unwrap_or_else(|| {
+      ProviderError::new(ProviderErrorKind::InvalidRequest, "No available model route")
+    }));
+  }
+  Ok(resolved)
+}
+
 fn resolve_route(routes: &[RouteRule], context: Option<&ModelRequestContext>) -> Option<RouteRule> {
   for route in routes {
     if route_matches(route, context) {
@@ -583,76 +1279,617 @@ fn route_matches(route: &RouteRule, context: Option<&ModelRequestContext>) -> bo
   true
 }
 
-async fn execute_completion(
-  provider: &ProviderConfigRecord,
-  model_id: &str,
-  request: &CompletionRequest,
-) -> FabricResult<CompletionResponse> {
-  let retries = provider.max_retries.unwrap_or(DEFAULT_MAX_RETRIES);
-  let mut last_error: Option<String> = None;
-
-  for attempt in 0..=retries {
-    let result = match provider.kind {
-      ProviderKind::Openai | ProviderKind::Gemini | ProviderKind::Local => {
-        call_openai_compatible(provider, model_id, request).await
-      }
-      ProviderKind::Anthropic => call_anthropic(provider, model_id, request).await,
-    };
+/// Tracks which remaining candidates are still worth trying after failures,
+/// based on the class of each provider error.
+#[derive(Default)]
+struct FallbackTracker {
+  skipped_providers: HashSet<String>,
+  min_context_window: Option<u64>,
+}
+
+impl FallbackTracker {
+  fn should_skip(&self, provider: &ProviderConfigRecord, model_id: &str) -> bool {
+    if self.skipped_providers.contains(&provider.provider_id) {
+      return true;
+    }
+    match (self.min_context_window, context_window(provider, model_id)) {
+      (Some(required), Some(window)) => window <= required,
+      _ => false,
+    }
+  }
+
+  /// Returns `false` when the error should end the candidate walk.
+  fn record_failure(
+    &mut self,
+    provider: &ProviderConfigRecord,
+    model_id: &str,
+    error: &ProviderError,
+  ) -> bool {
+    match error.kind.fallback_action() {
+      FallbackAction::NextCandidate => true,
+      FallbackAction::SkipProvider => {
+        self.skipped_providers.insert(provider.provider_id.clone());
+        true
+      }
+      FallbackAction::LargerContext => {
+        if let Some(window) = context_window(provider, model_id) {
+          self.min_context_window =
+            Some(self.min_context_window.map_or(window, |current| current.max(window)));
+        }
+        true
+      }
+      FallbackAction::Abort => false,
+    }
+  }
+}
+
+fn context_window(provider: &ProviderConfigRecord, model_id: &str) -> Option<u64> {
+  provider
+    .context_windows
+    .as_ref()
+    .and_then(|windows| windows.get(model_id))
+    .copied()
+}
+
+async fn execute_completion(
+  state: &Arc<Mutex<ModelFabricState>>,
+  provider: &ProviderConfigRecord,
+  model_id: &str,
+  request: &CompletionRequest,
+) -> ProviderResult<CompletionResponse> {
+  let policy = RetryPolicy::for_provider(provider);
+  let replaying = cassette_replaying(state);
+  let mut attempt = 0;
+
+  loop {
+    let result = match cassette_replay(state, InteractionKind::Complete, model_id, request) {
+      Some(replayed) => replayed.and_then(CassetteInteraction::into_completion),
+      None => {
+        lock_state(state)?.begin_attempt(provider)?;
+        match provider.protocol() {
+          WireProtocol::OpenaiCompatible => {
+            call_openai_compatible(provider, model_id, request).await
+          }
+          WireProtocol::Anthropic => call_anthropic(provider, model_id, request).await,
+          WireProtocol::Gemini => gemini::call(provider, model_id, request).await,
+          WireProtocol::Ollama => ollama::call(provider, model_id, request).await,
+        }
+      }
+    };
+    cassette_record(state, || {
+      let kind = InteractionKind::Complete;
+      let mut interaction = CassetteInteraction::new(kind, provider.protocol(), model_id, request);
+      match &result {
+        Ok(response) => interaction.response = Some(response.clone()),
+        Err(error) => interaction.error = Some(error.clone()),
+      }
+      interaction
+    })?;
+
+    // Replays say nothing about the provider's health.
+    if !replaying {
+      if let Ok(mut state) = state.lock() {
+        match &result {
+          Ok(response) => state.record_provider_success(provider, response.latency_ms),
+          Err(error) => state.record_provider_failure(provider, error),
+        }
+      }
+    }
 
     match result {
       Ok(response) => return Ok(response),
+      Err(error) => match policy.next_delay_ms(attempt, &error) {
+        Some(delay_ms) => {
+          if !replaying {
+            tokio::time::sleep(Duration::from_millis(delay_ms)).await;
+          }
+          attempt += 1;
+        }
+        None => return Err(error),
+      },
+    }
+  }
+}
+
+async fn complete_with_fallback(
+  state: &Arc<Mutex<ModelFabricState>>,
+  candidates: Vec<ResolvedCandidate>,
+  request: &CompletionRequest,
+  context: Option<&ModelRequestContext>,
+) -> NapiResult<Value> {
+  let mut fallback = FallbackTracker::default();
+  let mut last_error: Option<ProviderError> = None;
+  for ResolvedCandidate { model_id, provider } in candidates {
+    if fallback.should_skip(&provider, &model_id) {
+      continue;
+    }
+
+    match complete_candidate(state, &provider, &model_id, request, context).await {
+      Ok(response) => return serde_json::to_value(response).map_err(to_napi_error),
       Err(error) => {
+        if !fallback.record_failure(&provider, &model_id, &error) {
+          return Err(to_napi_error(error));
+        }
         last_error = Some(error);
-        if attempt >= retries {
-          break;
+      }
+    }
+  }
+
+  Err(match last_error {
+    Some(error) => to_napi_error(error),
+    None => NapiError::from_reason("No available model route".to_string()),
+  })
+}
+
+/// Runs one candidate and records its usage. With a `responseFormat` the content is
+/// validated; when `repair` is set a failing answer is sent back once together with the
+/// violations before the request fails with `output_validation`.
+async fn complete_candidate(
+  state: &Arc<Mutex<ModelFabricState>>,
+  provider: &ProviderConfigRecord,
+  model_id: &str,
+  request: &CompletionRequest,
+  context: Option<&ModelRequestContext>,
+) -> ProviderResult<CompletionResponse> {
+  let cache_key = {
+    let mut state = lock_state(state)?;
+    match state.cached_response(provider, model_id, request) {
+      Some((_, Some(mut response))) => {
+        state.record_cache_hit(
+          provider.provider_id.clone(),
+          model_id.to_string(),
+          &response.usage,
+          context,
+        );
+        response.latency_ms = 0;
+        return Ok(response);
+      }
+      Some((key, None)) => Some(key),
+      None => None,
+    }
+  };
+  let mut current = request.clone();
+  let mut repaired = false;
+
+  loop {
+    let admission = admit_request(state, provider, model_id, &current).await?;
+    let mut response = execute_completion(state, provider, model_id, &current).await?;
+    admission.settle(response.usage.total_tokens);
+    let queue_ms = admission.queue_ms;
+    drop(admission);
+    lock_state(state)?.record_usage_event(
+      provider.provider_id.clone(),
+      model_id.to_string(),
+      &response.usage,
+      response.latency_ms,
+      queue_ms,
+      context,
+    );
+
+    let format = request.response_format.as_ref();
+    match format.map(|format| format.check(&response.content)).transpose() {
+      Ok(parsed) => {
+        response.parsed = parsed;
+        if let Some(key) = cache_key {
+          lock_state(state)?.cache_response(key, &response);
         }
+        return Ok(response);
+      }
+      Err(violations) if format.is_some_and(ResponseFormat::repair_enabled) && !repaired => {
+        repaired = true;
+        current.messages.push(Message {
+          role: "assistant".to_string(),
+          content: MessageContent::Text(response.content),
+          tool_call_id: None,
+          tool_calls: None,
+        });
+        current.messages.push(Message {
+          role: "user".to_string(),
+          content: MessageContent::Text(format!(
+            "Your previous response did not match the required format: {}. \
+             Reply again with only the corrected JSON.",
+            describe_violations(&violations)
+          )),
+          tool_call_id: None,
+          tool_calls: None,
+        });
+      }
+      Err(violations) => {
+        return Err(ProviderError::new(
+          ProviderErrorKind::OutputValidation,
+          format!(
+            "Response does not match the requested format: {}",
+            describe_violations(&violations)
+          ),
+        ));
       }
     }
   }
+}
+
+#[derive(Default)]
+struct StreamProgress {
+  usage: TokenUsage,
+  committed: bool,
+  /// Raw stream lines, collected while recording a cassette.
+  frames: Option<Vec<String>>,
+  /// Assembled response, collected when the request is cacheable.
+  transcript: Option<StreamTranscript>,
+}
 
-  Err(last_error.unwrap_or_else(|| "Provider request failed".to_string()))
+enum StreamSource {
+  Live(reqwest::Response),
+  Replay(Box<CassetteInteraction>),
 }
 
+/// Streams from the first candidate that connects. Failures before any content or
+/// tool call reached the consumer are retried and then fall back to the next candidate,
+/// announced with a `fallback` chunk; failures after that point end the stream.
 async fn execute_stream(
   state: Arc<Mutex<ModelFabricState>>,
   sender: mpsc::UnboundedSender<StreamChunk>,
-  provider: ProviderConfigRecord,
-  model_id: String,
+  candidates: Vec<ResolvedCandidate>,
   request: CompletionRequest,
-) -> FabricResult<()> {
-  let start = tokio::time::Instant::now();
-  let mut usage = TokenUsage {
-    input_tokens: 0,
-    output_tokens: 0,
-    total_tokens: 0,
+  context: Option<ModelRequestContext>,
+  cancel: CancelSignal,
+) -> ProviderResult<()> {
+  let primary_model = candidates.first().map(|candidate| candidate.model_id.clone());
+  let mut fallback = FallbackTracker::default();
+  let mut last_error: Option<ProviderError> = None;
+
+  for ResolvedCandidate { model_id, provider } in &candidates {
+    if fallback.should_skip(provider, model_id) {
+      continue;
+    }
+
+    let policy = RetryPolicy::for_provider(provider);
+    let mut attempt = 0;
+    let mut announced = primary_model.as_ref() == Some(model_id);
+    let cache_key = {
+      let mut state = lock_state(&state)?;
+      match state.cached_response(provider, model_id, &request) {
+        Some((_, Some(response))) => {
+          if !announced {
+            let _ = sender.send(StreamChunk::fallback(model_id, last_error.as_ref()));
+          }
+          state.record_cache_hit(
+            provider.provider_id.clone(),
+            model_id.clone(),
+            &response.usage,
+            context.as_ref(),
+          );
+          for chunk in cache::replay_chunks(&response) {
+            let _ = sender.send(chunk);
+          }
+          return Ok(());
+        }
+        Some((key, None)) => Some(key),
+        None => None,
+      }
+    };
+    let admitted = tokio::select! {
+      biased;
+      _ = cancel.cancelled() => Err(cancelled_error()),
+      admitted = admit_request(&state, provider, model_id, &request) => admitted,
+    };
+    let admission = match admitted {
+      Ok(admission) => admission,
+      Err(error) if error.kind == ProviderErrorKind::Cancelled => {
+        let _ = sender.send(StreamChunk::of("cancelled"));
+        return Ok(());
+      }
+      Err(error) => {
+        if !fallback.record_failure(provider, model_id, &error) {
+          return Err(error);
+        }
+        last_error = Some(error);
+        continue;
+      }
+    };
+    let error = loop {
+      let start = tokio::time::Instant::now();
+      let mut progress = StreamProgress {
+        frames: cassette_recording(&state).then(Vec::new),
+        transcript: cache_key.is_some().then(StreamTranscript::default),
+        ..StreamProgress::default()
+      };
+      let replayed = cassette_replay(&state, InteractionKind::Stream, model_id, &request);
+      let live = replayed.is_none();
+      let opened = match replayed {
+        // A recorded attempt that failed before any line arrived failed to open.
+        Some(Ok(interaction)) => match (interaction.frames.is_empty(), &interaction.error) {
+          (true, Some(error)) => Err(error.clone()),
+          _ => Ok(StreamSource::Replay(Box::new(interaction))),
+        },
+        Some(Err(error)) => Err(error),
+        None => {
+          if let Err(error) = lock_state(&state)?.begin_attempt(provider) {
+            break error;
+          }
+          tokio::select! {
+            biased;
+            _ = cancel.cancelled() => Err(cancelled_error()),
+            opened = open_stream(provider, model_id, &request) => opened.map(StreamSource::Live),
+          }
+        }
+      };
+      let result = match opened {
+        Ok(source) => {
+          if !announced {
+            let _ = sender.send(StreamChunk::fallback(model_id, last_error.as_ref()));
+            announced = true;
+          }
+          match source {
+            StreamSource::Live(response) => {
+              forward_stream(&sender, provider, response, &mut progress, &cancel).await
+            }
+            StreamSource::Replay(interaction) => {
+              replay_stream(&sender, *interaction, &mut progress)
+            }
+          }
+        }
+        Err(error) => Err(error),
+      };
+      if let Some(frames) = progress.frames.take() {
+        cassette_record(&state, || {
+          let kind = InteractionKind::Stream;
+          let mut interaction =
+            CassetteInteraction::new(kind, provider.protocol(), model_id, &request);
+          interaction.frames = frames;
+          interaction.error = result.as_ref().err().cloned();
+          interaction
+        })?;
+      }
+
+      let error = match result {
+        Ok(()) => {
+          let latency_ms = start.elapsed().as_millis() as u64;
+          admission.settle(progress.usage.total_tokens);
+          let mut state = lock_state(&state)?;
+          if live {
+            state.record_provider_success(provider, latency_ms);
+          }
+          state.record_usage_event(
+            provider.provider_id.clone(),
+            model_id.clone(),
+            &progress.usage,
+            latency_ms,
+            admission.queue_ms,
+            context.as_ref(),
+          );
+          let transcript = progress.transcript.take();
+          if let (Some(key), Some(transcript)) = (cache_key, transcript) {
+            if let Some(response) = transcript.into_response(model_id, progress.usage.clone()) {
+              state.cache_response(key, &response);
+            }
+          }
+          return Ok(());
+        }
+        Err(error) if error.kind == ProviderErrorKind::Cancelled => {
+          let latency_ms = start.elapsed().as_millis() as u64;
+          admission.settle(progress.usage.total_tokens);
+          lock_state(&state)?.record_cancelled_usage(
+            provider.provider_id.clone(),
+            model_id.clone(),
+            &progress.usage,
+            latency_ms,
+            admission.queue_ms,
+            context.as_ref(),
+          );
+          let _ = sender.send(StreamChunk {
+            usage: Some(progress.usage),
+            ..StreamChunk::of("cancelled")
+          });
+          return Ok(());
+        }
+        Err(error) => error,
+      };
+
+      if live {
+        if let Ok(mut state) = state.lock() {
+          state.record_provider_failure(provider, &error);
+        }
+      }
+      if progress.committed {
+        return Err(error);
+      }
+      match policy.next_delay_ms(attempt, &error) {
+        Some(delay_ms) => {
+          if !cassette_replaying(&state) {
+            tokio::select! {
+              _ = cancel.cancelled() => {}
+              _ = tokio::time::sleep(Duration::from_millis(delay_ms)) => {}
+            }
+          }
+          attempt += 1;
+        }
+        None => break error,
+      }
+    };
+
+    if !fallback.record_failure(provider, model_id, &error) {
+      return Err(error);
+    }
+    last_error = Some(error);
+  }
+
+  Err(last_error.unwrap_or_else(|| {
+    ProviderError::new(ProviderErrorKind::InvalidRequest, "No available model route")
+  }))
+}
+
+/// Waits for capacity under the provider's and model's limits. Replayed cassettes never
+/// reach the provider and skip the queue.
+async fn admit_request(
+  state: &Arc<Mutex<ModelFabricState>>,
+  provider: &ProviderConfigRecord,
+  model_id: &str,
+  request: &CompletionRequest,
+) -> ProviderResult<Admission> {
+  let limiters = if cassette_replaying(state) {
+    Vec::new()
+  } else {
+    lock_state(state)?.limiters_for(provider, model_id)
   };
+  let tokens = routing::estimate_tokens(request) + request.max_tokens.unwrap_or(0);
+  limits::admit(limiters, tokens, provider.max_queue_wait_ms).await
+}
+
+async fn open_stream(
+  provider: &ProviderConfigRecord,
+  model_id: &str,
+  request: &CompletionRequest,
+) -> ProviderResult<reqwest::Response> {
+  match provider.protocol() {
+    WireProtocol::OpenaiCompatible => {
+      open_openai_compatible_stream(provider, model_id, request).await
+    }
+    WireProtocol::Anthropic => open_anthropic_stream(provider, model_id, request).await,
+    WireProtocol::Gemini => gemini::open_stream(provider, model_id, request).await,
+    WireProtocol::Ollama => ollama::open_stream(provider, model_id, request).await,
+  }
+}
+
+async fn forward_stream(
+  sender: &mpsc::UnboundedSender<StreamChunk>,
+  provider: &ProviderConfigRecord,
+  response: reqwest::Response,
+  progress: &mut StreamProgress,
+  cancel: &CancelSignal,
+) -> ProviderResult<()> {
+  let mut decoder = StreamDecoder::new(provider.protocol());
+  let mut buffer = String::new();
+  let mut stream = response.bytes_stream();
+
+  loop {
+    // Returning drops `stream` and with it the provider connection.
+    let chunk = tokio::select! {
+      biased;
+      _ = cancel.cancelled() => return Err(cancelled_error()),
+      chunk = stream.next() => chunk,
+    };
+    let Some(chunk) = chunk else {
+      break;
+    };
+    let chunk = chunk.map_err(ProviderError::from_reqwest)?;
+    buffer.push_str(&String::from_utf8_lossy(&chunk));
+    let mut lines: Vec<&str> = buffer.split('\n').collect();
+    let remainder = lines.pop().unwrap_or("").to_string();
 
-  let result = match provider.kind {
-    ProviderKind::Openai | ProviderKind::Gemini | ProviderKind::Local => {
-      stream_openai_compatible(&sender, &provider, &model_id, &request, &mut usage).await
+    for line in lines {
+      let line = line.trim_end_matches('\r');
+      if let Some(frames) = progress.frames.as_mut() {
+        frames.push(line.to_string());
+      }
+      for parsed in decoder.decode_line(line) {
+        emit_chunk(sender, progress, parsed)?;
+      }
     }
-    ProviderKind::Anthropic => {
-      stream_anthropic(&sender, &provider, &model_id, &request, &mut usage).await
+
+    buffer = remainder;
+  }
+
+  // NDJSON bodies may end without a trailing newline.
+  let buffer = buffer.trim_end_matches('\r');
+  if let Some(frames) = progress.frames.as_mut().filter(|_| !buffer.is_empty()) {
+    frames.push(buffer.to_string());
+  }
+  let trailing = decoder.decode_line(buffer);
+  for parsed in trailing.into_iter().chain(decoder.finish()) {
+    emit_chunk(sender, progress, parsed)?;
+  }
+  Ok(())
+}
+
+/// Feeds recorded lines through the decoder of the recorded protocol, then fails with
+/// the recorded error, if any, exactly where the live stream failed.
+fn replay_stream(
+  sender: &mpsc::UnboundedSender<StreamChunk>,
+  interaction: CassetteInteraction,
+  progress: &mut StreamProgress,
+) -> ProviderResult<()> {
+  let mut decoder = StreamDecoder::new(interaction.protocol);
+  for frame in &interaction.frames {
+    for parsed in decoder.decode_line(frame) {
+      emit_chunk(sender, progress, parsed)?;
     }
-  };
+  }
+  if let Some(error) = interaction.error {
+    return Err(error);
+  }
+  for parsed in decoder.finish() {
+    emit_chunk(sender, progress, parsed)?;
+  }
+  Ok(())
+}
+
+fn cassette_recording(state: &Arc<Mutex<ModelFabricState>>) -> bool {
+  state
+    .lock()
+    .is_ok_and(|state| state.cassette.as_ref().is_some_and(Cassette::recording))
+}
+
+fn cassette_replaying(state: &Arc<Mutex<ModelFabricState>>) -> bool {
+  state
+    .lock()
+    .is_ok_and(|state| state.cassette.as_ref().is_some_and(Cassette::replaying))
+}
 
-  let latency_ms = start.elapsed().as_millis() as u64;
-  if result.is_ok() {
-    let mut state = state.lock().map_err(|_| "State lock poisoned".to_string())?;
-    state.record_usage_event(provider.provider_id.clone(), model_id, &usage, latency_ms);
+/// Serves the attempt from the loaded replay cassette; `None` means go to the network.
+fn cassette_replay(
+  state: &Arc<Mutex<ModelFabricState>>,
+  kind: InteractionKind,
+  model_id: &str,
+  request: &CompletionRequest,
+) -> Option<ProviderResult<CassetteInteraction>> {
+  let mut state = state.lock().ok()?;
+  let cassette = state.cassette.as_mut().filter(|cassette| cassette.replaying())?;
+  Some(cassette.replay(kind, model_id, request))
+}
+
+fn cassette_record(
+  state: &Arc<Mutex<ModelFabricState>>,
+  interaction: impl FnOnce() -> CassetteInteraction,
+) -> ProviderResult<()> {
+  let mut state = lock_state(state)?;
+  match state.cassette.as_mut().filter(|cassette| cassette.recording()) {
+    Some(cassette) => cassette
+      .record(interaction())
+      .map_err(|message| ProviderError::new(ProviderErrorKind::Unknown, message)),
+    None => Ok(()),
   }
+}
 
-  result
+/// Passes a decoded chunk on. An `error` chunk is the provider failing mid-stream, so it
+/// fails the attempt instead, to be retried or to end the stream like any other failure.
+fn emit_chunk(
+  sender: &mpsc::UnboundedSender<StreamChunk>,
+  progress: &mut StreamProgress,
+  chunk: StreamChunk,
+) -> ProviderResult<()> {
+  if chunk.chunk_type == "error" {
+    let message = chunk.error.as_deref().unwrap_or("stream failed");
+    return Err(ProviderError::from_stream(message));
+  }
+  update_usage_from_chunk(&chunk, &mut progress.usage);
+  if let Some(transcript) = progress.transcript.as_mut() {
+    transcript.observe(&chunk);
+  }
+  if matches!(chunk.chunk_type.as_str(), "content" | "tool_call" | "thinking") {
+    progress.committed = true;
+  }
+  let _ = sender.send(chunk);
+  Ok(())
 }
 
 async fn call_openai_compatible(
   provider: &ProviderConfigRecord,
   model_id: &str,
   request: &CompletionRequest,
-) -> FabricResult<CompletionResponse> {
+) -> ProviderResult<CompletionResponse> {
   let start = tokio::time::Instant::now();
   let base_url = resolve_base_url(provider);
-  let api_key = resolve_api_key(&provider.auth_ref)?;
+  let api_key = resolve_api_key(provider).await?;
   let timeout_ms = request
     .timeout_ms
     .or(provider.timeout_ms)
@@ -676,11 +1913,14 @@ async fn call_openai_compatible(
   if let Some(tools) = &request.tools {
     body["tools"] = json!(format_openai_tools(tools));
   }
+  if let Some(format) = &request.response_format {
+    body["response_format"] = format.openai_response_format();
+  }
 
   let client = reqwest::Client::builder()
     .timeout(Duration::from_millis(timeout_ms))
     .build()
-    .map_err(|error| error.to_string())?;
+    .map_err(ProviderError::from_reqwest)?;
 
   let mut headers = reqwest::header::HeaderMap::new();
   headers.insert(
@@ -690,13 +1930,13 @@ async fn call_openai_compatible(
   headers.insert(
     reqwest::header::AUTHORIZATION,
     reqwest::header::HeaderValue::from_str(&format!("Bearer {api_key}"))
-      .map_err(|error| error.to_string())?,
+      .map_err(invalid_header)?,
   );
   if provider.kind == ProviderKind::Openai {
     if let Some(org) = &provider.organization_id {
       headers.insert(
         "OpenAI-Organization",
-        reqwest::header::HeaderValue::from_str(org).map_err(|error| error.to_string())?,
+        reqwest::header::HeaderValue::from_str(org).map_err(invalid_header)?,
       );
     }
   }
@@ -707,19 +1947,14 @@ async fn call_openai_compatible(
     .json(&body)
     .send()
     .await
-    .map_err(|error| error.to_string())?;
-
-  if !response.status().is_success() {
-    let status = response.status();
-    let text = response.text().await.unwrap_or_default();
-    return Err(format!("Provider error ({status}): {text}"));
-  }
+    .map_err(ProviderError::from_reqwest)?;
+  let response = ensure_success(response).await?;
 
-  let parsed: Value = response.json().await.map_err(|error| error.to_string())?;
+  let parsed: Value = response.json().await.map_err(ProviderError::from_reqwest)?;
   let choice = parsed
     .get("choices")
     .and_then(|choices| choices.get(0))
-    .ok_or_else(|| "Missing choices".to_string())?;
+    .ok_or_else(|| ProviderError::new(ProviderErrorKind::Unknown, "Missing choices"))?;
 
   let content = choice
     .get("message")
@@ -735,19 +1970,17 @@ async fn call_openai_compatible(
     .map(|calls| {
       calls
         .iter()
-        .filter_map(|call| parse_openai_tool_call(call))
+        .filter_map(parse_openai_tool_call)
         .collect::<Vec<_>>()
     })
     .filter(|calls| !calls.is_empty());
 
+  let content_parts = Some(assistant_parts(&content, tool_calls.as_deref()));
+
   let usage = parsed
     .get("usage")
     .and_then(parse_openai_usage)
-    .unwrap_or(TokenUsage {
-      input_tokens: 0,
-      output_tokens: 0,
-      total_tokens: 0,
-    });
+    .unwrap_or_default();
 
   let finish_reason = choice
     .get("finish_reason")
@@ -764,10 +1997,13 @@ async fn call_openai_compatible(
   Ok(CompletionResponse {
     content,
     tool_calls,
+    content_parts,
+    parsed: None,
     usage,
     finish_reason,
     model,
     latency_ms: start.elapsed().as_millis() as u64,
+    safety_ratings: None,
   })
 }
 
@@ -775,13 +2011,13 @@ async fn call_anthropic(
   provider: &ProviderConfigRecord,
   model_id: &str,
   request: &CompletionRequest,
-) -> FabricResult<CompletionResponse> {
+) -> ProviderResult<CompletionResponse> {
   let start = tokio::time::Instant::now();
   let base_url = provider
     .base_url
     .clone()
     .unwrap_or_else(|| DEFAULT_ANTHROPIC_BASE_URL.to_string());
-  let api_key = resolve_api_key(&provider.auth_ref)?;
+  let api_key = resolve_api_key(provider).await?;
   let timeout_ms = request
     .timeout_ms
     .or(provider.timeout_ms)
@@ -807,14 +2043,23 @@ async fn call_anthropic(
   if let Some(stop_sequences) = &request.stop_sequences {
     body["stop_sequences"] = json!(stop_sequences);
   }
-  if let Some(tools) = &request.tools {
-    body["tools"] = json!(format_anthropic_tools(tools));
+  let mut tools = request
+    .tools
+    .as_deref()
+    .map(format_anthropic_tools)
+    .unwrap_or_default();
+  if let Some(format) = &request.response_format {
+    tools.push(format.anthropic_tool());
+    body["tool_choice"] = format.anthropic_tool_choice();
+  }
+  if !tools.is_empty() {
+    body["tools"] = json!(tools);
   }
 
   let client = reqwest::Client::builder()
     .timeout(Duration::from_millis(timeout_ms))
     .build()
-    .map_err(|error| error.to_string())?;
+    .map_err(ProviderError::from_reqwest)?;
 
   let mut headers = reqwest::header::HeaderMap::new();
   headers.insert(
@@ -823,7 +2068,7 @@ async fn call_anthropic(
   );
   headers.insert(
     "x-api-key",
-    reqwest::header::HeaderValue::from_str(&api_key).map_err(|error| error.to_string())?,
+    reqwest::header::HeaderValue::from_str(&api_key).map_err(invalid_header)?,
   );
   headers.insert(
     "anthropic-version",
@@ -836,15 +2081,10 @@ async fn call_anthropic(
     .json(&body)
     .send()
     .await
-    .map_err(|error| error.to_string())?;
+    .map_err(ProviderError::from_reqwest)?;
+  let response = ensure_success(response).await?;
 
-  if !response.status().is_success() {
-    let status = response.status();
-    let text = response.text().await.unwrap_or_default();
-    return Err(format!("Provider error ({status}): {text}"));
-  }
-
-  let parsed: Value = response.json().await.map_err(|error| error.to_string())?;
+  let parsed: Value = response.json().await.map_err(ProviderError::from_reqwest)?;
   let content = parsed
     .get("content")
     .and_then(|value| value.as_array())
@@ -869,34 +2109,20 @@ async fn call_anthropic(
     .map(|items| {
       items
         .iter()
-        .filter_map(|item| parse_anthropic_tool_call(item))
+        .filter_map(parse_anthropic_tool_call)
         .collect::<Vec<_>>()
     })
     .filter(|calls| !calls.is_empty());
 
+  let content_parts = parsed
+    .get("content")
+    .and_then(|value| value.as_array())
+    .map(|items| items.iter().filter_map(parse_anthropic_part).collect());
+
   let usage = parsed
     .get("usage")
-    .and_then(|value| value.as_object())
-    .map(|usage| {
-      let input = usage
-        .get("input_tokens")
-        .and_then(|value| value.as_u64())
-        .unwrap_or(0);
-      let output = usage
-        .get("output_tokens")
-        .and_then(|value| value.as_u64())
-        .unwrap_or(0);
-      TokenUsage {
-        input_tokens: input,
-        output_tokens: output,
-        total_tokens: input + output,
-      }
-    })
-    .unwrap_or(TokenUsage {
-      input_tokens: 0,
-      output_tokens: 0,
-      total_tokens: 0,
-    });
+    .map(parse_anthropic_usage)
+    .unwrap_or_default();
 
   let finish_reason = parsed
     .get("stop_reason")
@@ -910,25 +2136,58 @@ async fn call_anthropic(
     .unwrap_or(model_id)
     .to_string();
 
-  Ok(CompletionResponse {
+  let mut response = CompletionResponse {
     content,
     tool_calls,
+    content_parts,
+    parsed: None,
     usage,
     finish_reason,
     model,
     latency_ms: start.elapsed().as_millis() as u64,
-  })
+    safety_ratings: None,
+  };
+  if let Some(format) = &request.response_format {
+    take_structured_tool_call(&mut response, format.name());
+  }
+  Ok(response)
 }
 
-async fn stream_openai_compatible(
-  sender: &mpsc::UnboundedSender<StreamChunk>,
+/// Anthropic answers a forced structured-output tool with a `tool_use` block; its input
+/// becomes the response content, as if the model had replied with that JSON directly.
+fn take_structured_tool_call(response: &mut CompletionResponse, name: &str) {
+  let Some(calls) = response.tool_calls.take() else {
+    return;
+  };
+  let (structured, rest): (Vec<ToolCall>, Vec<ToolCall>) =
+    calls.into_iter().partition(|call| call.name == name);
+  response.tool_calls = Some(rest).filter(|calls| !calls.is_empty());
+
+  let Some(call) = structured.into_iter().next() else {
+    return;
+  };
+  response.content = call.arguments.clone();
+  if response.finish_reason == "tool_calls" && response.tool_calls.is_none() {
+    response.finish_reason = "stop".to_string();
+  }
+  if let Some(parts) = &mut response.content_parts {
+    for part in parts.iter_mut() {
+      if matches!(part, ContentPart::ToolUse { id, .. } if *id == call.id) {
+        *part = ContentPart::Text {
+          text: call.arguments.clone(),
+        };
+      }
+    }
+  }
+}
+
+async fn open_openai_compatible_stream(
   provider: &ProviderConfigRecord,
   model_id: &str,
   request: &CompletionRequest,
-  usage: &mut TokenUsage,
-) -> FabricResult<()> {
+) -> ProviderResult<reqwest::Response> {
   let base_url = resolve_base_url(provider);
-  let api_key = resolve_api_key(&provider.auth_ref)?;
+  let api_key = resolve_api_key(provider).await?;
   let timeout_ms = request
     .timeout_ms
     .or(provider.timeout_ms)
@@ -938,6 +2197,9 @@ async fn stream_openai_compatible(
     "model": model_id,
     "messages": format_openai_messages(&request.messages),
     "stream": true,
+    // Without this the stream carries no token counts, so it would be priced and budgeted
+    // as free.
+    "stream_options": { "include_usage": true },
   });
 
   if let Some(tools) = &request.tools {
@@ -959,7 +2221,7 @@ async fn stream_openai_compatible(
   let client = reqwest::Client::builder()
     .timeout(Duration::from_millis(timeout_ms))
     .build()
-    .map_err(|error| error.to_string())?;
+    .map_err(ProviderError::from_reqwest)?;
 
   let mut headers = reqwest::header::HeaderMap::new();
   headers.insert(
@@ -969,13 +2231,13 @@ async fn stream_openai_compatible(
   headers.insert(
     reqwest::header::AUTHORIZATION,
     reqwest::header::HeaderValue::from_str(&format!("Bearer {api_key}"))
-      .map_err(|error| error.to_string())?,
+      .map_err(invalid_header)?,
   );
   if provider.kind == ProviderKind::Openai {
     if let Some(org) = &provider.organization_id {
       headers.insert(
         "OpenAI-Organization",
-        reqwest::header::HeaderValue::from_str(org).map_err(|error| error.to_string())?,
+        reqwest::header::HeaderValue::from_str(org).map_err(invalid_header)?,
       );
     }
   }
@@ -986,49 +2248,20 @@ async fn stream_openai_compatible(
     .json(&body)
     .send()
     .await
-    .map_err(|error| error.to_string())?;
-
-  if !response.status().is_success() {
-    let status = response.status();
-    let text = response.text().await.unwrap_or_default();
-    return Err(format!("Provider error ({status}): {text}"));
-  }
-
-  let mut buffer = String::new();
-  let mut stream = response.bytes_stream();
-
-  while let Some(chunk) = stream.next().await {
-    let chunk = chunk.map_err(|error| error.to_string())?;
-    buffer.push_str(&String::from_utf8_lossy(&chunk));
-    let mut lines: Vec<&str> = buffer.split('\n').collect();
-    let remainder = lines.pop().unwrap_or("").to_string();
-
-    for line in lines {
-      let line = line.trim_end_matches('\r');
-      if let Some(parsed) = parse_openai_sse_line(line) {
-        update_usage_from_chunk(&parsed, usage);
-        let _ = sender.send(parsed);
-      }
-    }
-
-    buffer = remainder;
-  }
-
-  Ok(())
+    .map_err(ProviderError::from_reqwest)?;
+  ensure_success(response).await
 }
 
-async fn stream_anthropic(
-  sender: &mpsc::UnboundedSender<StreamChunk>,
+async fn open_anthropic_stream(
   provider: &ProviderConfigRecord,
   model_id: &str,
   request: &CompletionRequest,
-  usage: &mut TokenUsage,
-) -> FabricResult<()> {
+) -> ProviderResult<reqwest::Response> {
   let base_url = provider
     .base_url
     .clone()
     .unwrap_or_else(|| DEFAULT_ANTHROPIC_BASE_URL.to_string());
-  let api_key = resolve_api_key(&provider.auth_ref)?;
+  let api_key = resolve_api_key(provider).await?;
   let timeout_ms = request
     .timeout_ms
     .or(provider.timeout_ms)
@@ -1062,7 +2295,7 @@ async fn stream_anthropic(
   let client = reqwest::Client::builder()
     .timeout(Duration::from_millis(timeout_ms))
     .build()
-    .map_err(|error| error.to_string())?;
+    .map_err(ProviderError::from_reqwest)?;
 
   let mut headers = reqwest::header::HeaderMap::new();
   headers.insert(
@@ -1071,7 +2304,7 @@ async fn stream_anthropic(
   );
   headers.insert(
     "x-api-key",
-    reqwest::header::HeaderValue::from_str(&api_key).map_err(|error| error.to_string())?,
+    reqwest::header::HeaderValue::from_str(&api_key).map_err(invalid_header)?,
   );
   headers.insert(
     "anthropic-version",
@@ -1084,35 +2317,8 @@ async fn stream_anthropic(
     .json(&body)
     .send()
     .await
-    .map_err(|error| error.to_string())?;
-
-  if !response.status().is_success() {
-    let status = response.status();
-    let text = response.text().await.unwrap_or_default();
-    return Err(format!("Provider error ({status}): {text}"));
-  }
-
-  let mut buffer = String::new();
-  let mut stream = response.bytes_stream();
-
-  while let Some(chunk) = stream.next().await {
-    let chunk = chunk.map_err(|error| error.to_string())?;
-    buffer.push_str(&String::from_utf8_lossy(&chunk));
-    let mut lines: Vec<&str> = buffer.split('\n').collect();
-    let remainder = lines.pop().unwrap_or("").to_string();
-
-    for line in lines {
-      let line = line.trim_end_matches('\r');
-      if let Some(parsed) = parse_anthropic_sse_line(line) {
-        update_usage_from_chunk(&parsed, usage);
-        let _ = sender.send(parsed);
-      }
-    }
-
-    buffer = remainder;
-  }
-
-  Ok(())
+    .map_err(ProviderError::from_reqwest)?;
+  ensure_success(response).await
 }
 
 fn parse_openai_sse_line(line: &str) -> Option<StreamChunk> {
@@ -1127,12 +2333,8 @@ fn parse_openai_sse_line(line: &str) -> Option<StreamChunk> {
   let parsed: Value = serde_json::from_str(data).ok()?;
   if let Some(usage) = parsed.get("usage").and_then(parse_openai_usage) {
     return Some(StreamChunk {
-      chunk_type: "usage".to_string(),
-      content: None,
-      tool_call: None,
       usage: Some(usage),
-      error: None,
-      finish_reason: None,
+      ..StreamChunk::of("usage")
     });
   }
 
@@ -1144,40 +2346,16 @@ fn parse_openai_sse_line(line: &str) -> Option<StreamChunk> {
     .and_then(|value| value.as_str())
   {
     return Some(StreamChunk {
-      chunk_type: "content".to_string(),
       content: Some(content.to_string()),
-      tool_call: None,
-      usage: None,
-      error: None,
-      finish_reason: None,
+      ..StreamChunk::of("content")
     });
   }
 
-  if let Some(tool_calls) = choice
-    .get("delta")
-    .and_then(|delta| delta.get("tool_calls"))
-    .and_then(|value| value.as_array())
-  {
-    if let Some(tool_call) = tool_calls.get(0).and_then(parse_openai_tool_call) {
-      return Some(StreamChunk {
-        chunk_type: "tool_call".to_string(),
-        content: None,
-        tool_call: Some(tool_call),
-        usage: None,
-        error: None,
-        finish_reason: None,
-      });
-    }
-  }
-
   if let Some(reason) = choice.get("finish_reason").and_then(|value| value.as_str()) {
     return Some(StreamChunk {
-      chunk_type: "done".to_string(),
-      content: None,
-      tool_call: None,
       usage: parsed.get("usage").and_then(parse_openai_usage),
-      error: None,
       finish_reason: Some(map_openai_finish_reason(reason)),
+      ..StreamChunk::of("done")
     });
   }
 
@@ -1197,50 +2375,29 @@ fn parse_anthropic_sse_line(line: &str) -> Option<StreamChunk> {
   let event_type = parsed.get("type")?.as_str()?;
 
   match event_type {
-    "content_block_delta" => {
+    "content_block_delta"
       if parsed
         .get("delta")
         .and_then(|delta| delta.get("type"))
         .and_then(|value| value.as_str())
-        == Some("text_delta")
+        == Some("text_delta") =>
+    {
+      if let Some(text) = parsed
+        .get("delta")
+        .and_then(|delta| delta.get("text"))
+        .and_then(|value| value.as_str())
       {
-        if let Some(text) = parsed
-          .get("delta")
-          .and_then(|delta| delta.get("text"))
-          .and_then(|value| value.as_str())
-        {
-          return Some(StreamChunk {
-            chunk_type: "content".to_string(),
-            content: Some(text.to_string()),
-            tool_call: None,
-            usage: None,
-            error: None,
-            finish_reason: None,
-          });
-        }
+        return Some(StreamChunk {
+          content: Some(text.to_string()),
+          ..StreamChunk::of("content")
+        });
       }
     }
     "message_delta" => {
       if let Some(usage) = parsed.get("usage") {
-        let input = usage
-          .get("input_tokens")
-          .and_then(|value| value.as_u64())
-          .unwrap_or(0);
-        let output = usage
-          .get("output_tokens")
-          .and_then(|value| value.as_u64())
-          .unwrap_or(0);
         return Some(StreamChunk {
-          chunk_type: "usage".to_string(),
-          content: None,
-          tool_call: None,
-          usage: Some(TokenUsage {
-            input_tokens: input,
-            output_tokens: output,
-            total_tokens: input + output,
-          }),
-          error: None,
-          finish_reason: None,
+          usage: Some(parse_anthropic_usage(usage)),
+          ..StreamChunk::of("usage")
         });
       }
     }
@@ -1249,36 +2406,16 @@ fn parse_anthropic_sse_line(line: &str) -> Option<StreamChunk> {
         .get("message")
         .and_then(|message| message.get("usage"))
       {
-        let input = usage
-          .get("input_tokens")
-          .and_then(|value| value.as_u64())
-          .unwrap_or(0);
-        let output = usage
-          .get("output_tokens")
-          .and_then(|value| value.as_u64())
-          .unwrap_or(0);
         return Some(StreamChunk {
-          chunk_type: "usage".to_string(),
-          content: None,
-          tool_call: None,
-          usage: Some(TokenUsage {
-            input_tokens: input,
-            output_tokens: output,
-            total_tokens: input + output,
-          }),
-          error: None,
-          finish_reason: None,
+          usage: Some(parse_anthropic_usage(usage)),
+          ..StreamChunk::of("usage")
         });
       }
     }
     "message_stop" => {
       return Some(StreamChunk {
-        chunk_type: "done".to_string(),
-        content: None,
-        tool_call: None,
-        usage: None,
-        error: None,
         finish_reason: Some("stop".to_string()),
+        ..StreamChunk::of("done")
       });
     }
     _ => {}
@@ -1287,11 +2424,18 @@ fn parse_anthropic_sse_line(line: &str) -> Option<StreamChunk> {
   None
 }
 
+/// Usage arrives in pieces (Anthropic reports input on `message_start` and output on
+/// `message_delta`), so counters are merged rather than overwritten.
 fn update_usage_from_chunk(chunk: &StreamChunk, usage: &mut TokenUsage) {
   if let Some(chunk_usage) = &chunk.usage {
-    usage.input_tokens = chunk_usage.input_tokens;
-    usage.output_tokens = chunk_usage.output_tokens;
-    usage.total_tokens = chunk_usage.total_tokens;
+    usage.input_tokens = usage.input_tokens.max(chunk_usage.input_tokens);
+    usage.output_tokens = usage.output_tokens.max(chunk_usage.output_tokens);
+    usage.total_tokens = chunk_usage
+      .total_tokens
+      .max(usage.input_tokens + usage.output_tokens);
+    if chunk_usage.cached_input_tokens.is_some() {
+      usage.cached_input_tokens = usage.cached_input_tokens.max(chunk_usage.cached_input_tokens);
+    }
   }
 }
 
@@ -1327,6 +2471,9 @@ fn parse_anthropic_tool_call(value: &Value) -> Option<ToolCall> {
 }
 
 fn parse_openai_usage(value: &Value) -> Option<TokenUsage> {
+  if !value.is_object() {
+    return None;
+  }
   let input = value
     .get("prompt_tokens")
     .and_then(|value| value.as_u64())
@@ -1339,18 +2486,110 @@ fn parse_openai_usage(value: &Value) -> Option<TokenUsage> {
     .get("total_tokens")
     .and_then(|value| value.as_u64())
     .unwrap_or(input + output);
+  let cached = value
+    .get("prompt_tokens_details")
+    .and_then(|details| details.get("cached_tokens"))
+    .and_then(|value| value.as_u64());
   Some(TokenUsage {
     input_tokens: input,
     output_tokens: output,
     total_tokens: total,
+    cached_input_tokens: cached,
   })
 }
 
+/// Anthropic reports cache reads and writes separately from `input_tokens`; they are
+/// folded back in so `input_tokens` means the full prompt like it does for OpenAI.
+fn parse_anthropic_usage(value: &Value) -> TokenUsage {
+  let read = |key: &str| value.get(key).and_then(|value| value.as_u64());
+  let cache_read = read("cache_read_input_tokens");
+  let input = read("input_tokens").unwrap_or(0)
+    + read("cache_creation_input_tokens").unwrap_or(0)
+    + cache_read.unwrap_or(0);
+  let output = read("output_tokens").unwrap_or(0);
+  TokenUsage {
+    input_tokens: input,
+    output_tokens: output,
+    total_tokens: input + output,
+    cached_input_tokens: cache_read,
+  }
+}
+
 fn format_openai_messages(messages: &[Message]) -> Vec<Value> {
-  messages
-    .iter()
-    .map(|message| json!({ "role": message.role, "content": message.content }))
-    .collect()
+  let mut formatted: Vec<Value> = Vec::new();
+
+  for message in messages {
+    if message.role == "tool" {
+      formatted.push(json!({
+        "role": "tool",
+        "tool_call_id": message.tool_call_id,
+        "content": message.content.text(),
+      }));
+      continue;
+    }
+
+    let mut tool_calls: Vec<Value> = message
+      .tool_calls
+      .iter()
+      .flatten()
+      .map(|call| openai_tool_call(&call.id, &call.name, &call.arguments))
+      .collect();
+    let mut parts: Vec<Value> = Vec::new();
+    let mut has_image = false;
+
+    for part in message.content.parts() {
+      match part {
+        ContentPart::Text { text } => parts.push(json!({ "type": "text", "text": text })),
+        ContentPart::Image { source } => {
+          has_image = true;
+          parts.push(json!({ "type": "image_url", "image_url": { "url": source.to_url() } }));
+        }
+        ContentPart::ToolUse { id, name, input } => {
+          tool_calls.push(openai_tool_call(&id, &name, &input.to_string()));
+        }
+        // Each result is its own `tool` message and must directly follow the
+        // assistant turn that issued the call.
+        ContentPart::ToolResult {
+          tool_use_id,
+          content,
+          ..
+        } => formatted.push(json!({
+          "role": "tool",
+          "tool_call_id": tool_use_id,
+          "content": content,
+        })),
+        ContentPart::Thinking { .. } => {}
+      }
+    }
+
+    let parts_only = matches!(message.content, MessageContent::Parts(_));
+    if parts_only && parts.is_empty() && tool_calls.is_empty() {
+      continue;
+    }
+
+    let content = if has_image {
+      Value::Array(parts)
+    } else if parts.is_empty() && !tool_calls.is_empty() {
+      Value::Null
+    } else {
+      json!(message.content.text())
+    };
+    let mut entry = json!({ "role": message.role, "content": content });
+    if !tool_calls.is_empty() {
+      entry["tool_calls"] = Value::Array(tool_calls);
+    }
+    formatted.push(entry);
+  }
+
+  formatted
+}
+
+fn openai_tool_call(id: &str, name: &str, arguments: &str) -> Value {
+  json!({
+    "id": id,
+    "type": "function",
+    "function": { "name": name, "arguments": arguments },
+  })
 }
 
 fn format_openai_tools(tools: &[Tool]) -> Vec<Value> {
@@ -1375,15 +2614,34 @@ fn format_anthropic_messages(messages: &[Message]) -> (Option<String>, Vec<Value
 
   for message in messages {
     if message.role == "system" {
+      let text = message.content.text();
       system_prompt = Some(match system_prompt {
-        Some(existing) => format!("{existing}\n{}", message.content),
-        None => message.content.clone(),
+        Some(existing) => format!("{existing}\n{text}"),
+        None => text,
       });
+      continue;
+    }
+
+    let (role, content) = if message.role == "tool" {
+      let result = ContentPart::ToolResult {
+        tool_use_id: message.tool_call_id.clone().unwrap_or_default(),
+        content: message.content.text(),
+        is_error: None,
+      };
+      ("user", json!([anthropic_block(&result)]))
     } else {
-      formatted.push(json!({
-        "role": message.role,
-        "content": message.content,
-      }));
+      (message.role.as_str(), anthropic_content(message))
+    };
+
+    // Anthropic rejects consecutive turns with the same role, which is what one
+    // `tool` message per result produces, so such turns are merged.
+    match formatted.last_mut() {
+      Some(last) if last.get("role").and_then(|value| value.as_str()) == Some(role) => {
+        let mut blocks = anthropic_blocks(last["content"].take());
+        blocks.extend(anthropic_blocks(content));
+        last["content"] = Value::Array(blocks);
+      }
+      _ => formatted.push(json!({ "role": role, "content": content })),
     }
   }
 
@@ -1400,6 +2658,120 @@ fn format_anthropic_messages(messages: &[Message]) -> (Option<String>, Vec<Value
   (system_prompt, formatted)
 }
 
+fn anthropic_content(message: &Message) -> Value {
+  if let (MessageContent::Text(text), None) = (&message.content, &message.tool_calls) {
+    return json!(text);
+  }
+
+  let mut blocks: Vec<Value> = message
+    .content
+    .parts()
+    .iter()
+    .map(anthropic_block)
+    .collect();
+  for call in message.tool_calls.iter().flatten() {
+    let input = serde_json::from_str::<Value>(&call.arguments).unwrap_or_else(|_| json!({}));
+    blocks.push(json!({
+      "type": "tool_use",
+      "id": call.id,
+      "name": call.name,
+      "input": input,
+    }));
+  }
+  Value::Array(blocks)
+}
+
+fn anthropic_blocks(content: Value) -> Vec<Value> {
+  match content {
+    Value::Array(blocks) => blocks,
+    Value::String(text) => vec![json!({ "type": "text", "text": text })],
+    _ => Vec::new(),
+  }
+}
+
+fn anthropic_block(part: &ContentPart) -> Value {
+  match part {
+    ContentPart::Text { text } => json!({ "type": "text", "text": text }),
+    ContentPart::Image {
+      source: ImageSource::Base64 { media_type, data },
+    } => json!({
+      "type": "image",
+      "source": { "type": "base64", "media_type": media_type, "data": data },
+    }),
+    ContentPart::Image {
+      source: ImageSource::Url { url },
+    } => json!({ "type": "image", "source": { "type": "url", "url": url } }),
+    ContentPart::ToolUse { id, name, input } => json!({
+      "type": "tool_use",
+      "id": id,
+      "name": name,
+      "input": input,
+    }),
+    ContentPart::ToolResult {
+      tool_use_id,
+      content,
+      is_error,
+    } => {
+      let mut block = json!({
+        "type": "tool_result",
+        "tool_use_id": tool_use_id,
+        "content": content,
+      });
+      if let Some(is_error) = is_error {
+        block["is_error"] = json!(is_error);
+      }
+      block
+    }
+    ContentPart::Thinking {
+      thinking,
+      signature,
+    } => {
+      let mut block = json!({ "type": "thinking", "thinking": thinking });
+      if let Some(signature) = signature {
+        block["signature"] = json!(signature);
+      }
+      block
+    }
+  }
+}
+
+fn parse_anthropic_part(value: &Value) -> Option<ContentPart> {
+  let text = |key: &str| value.get(key).and_then(|value| value.as_str()).map(str::to_string);
+  match value.get("type")?.as_str()? {
+    "text" => Some(ContentPart::Text { text: text("text")? }),
+    "thinking" => Some(ContentPart::Thinking {
+      thinking: text("thinking")?,
+      signature: text("signature"),
+    }),
+    "tool_use" => {
+      let call = parse_anthropic_tool_call(value)?;
+      Some(ContentPart::ToolUse {
+        id: call.id,
+        name: call.name,
+        input: value.get("input").cloned().unwrap_or_else(|| json!({})),
+      })
+    }
+    _ => None,
+  }
+}
+
+fn assistant_parts(content: &str, tool_calls: Option<&[ToolCall]>) -> Vec<ContentPart> {
+  let mut parts = Vec::new();
+  if !content.is_empty() {
+    parts.push(ContentPart::Text {
+      text: content.to_string(),
+    });
+  }
+  for call in tool_calls.unwrap_or_default() {
+    parts.push(ContentPart::ToolUse {
+      id: call.id.clone(),
+      name: call.name.clone(),
+      input: serde_json::from_str(&call.arguments).unwrap_or_else(|_| json!({})),
+    });
+  }
+  parts
+}
+
 fn format_anthropic_tools(tools: &[Tool]) -> Vec<Value> {
   tools
     .iter()
@@ -1439,22 +2811,42 @@ fn resolve_base_url(provider: &ProviderConfigRecord) -> String {
 
   match provider.kind {
     ProviderKind::Openai => DEFAULT_OPENAI_BASE_URL.to_string(),
+    ProviderKind::Gemini if provider.protocol() == WireProtocol::Gemini => {
+      DEFAULT_GEMINI_NATIVE_BASE_URL.to_string()
+    }
     ProviderKind::Gemini => DEFAULT_GEMINI_BASE_URL.to_string(),
+    ProviderKind::Local if provider.protocol() == WireProtocol::Ollama => {
+      DEFAULT_OLLAMA_BASE_URL.to_string()
+    }
     ProviderKind::Local => DEFAULT_OPENAI_BASE_URL.to_string(),
     ProviderKind::Anthropic => DEFAULT_ANTHROPIC_BASE_URL.to_string(),
   }
 }
 
-fn resolve_api_key(auth_ref: &str) -> FabricResult<String> {
-  if auth_ref.trim().is_empty() {
-    return Err("authRef is empty".to_string());
-  }
-  if let Ok(value) = std::env::var(auth_ref) {
-    if !value.trim().is_empty() {
-      return Ok(value);
-    }
+async fn resolve_api_key(provider: &ProviderConfigRecord) -> ProviderResult<String> {
+  provider.credentials.resolve(&provider.auth_ref).await
+}
+
+async fn ensure_success(response: reqwest::Response) -> ProviderResult<reqwest::Response> {
+  if response.status().is_success() {
+    return Ok(response);
   }
-  Ok(auth_ref.to_string())
+  let status = response.status();
+  let retry_after_ms = error::parse_retry_after(response.headers());
+  let text = response.text().await.unwrap_or_default();
+  Err(ProviderError::from_status(status.as_u16(), &text, retry_after_ms))
+}
+
+fn invalid_header(error: reqwest::header::InvalidHeaderValue) -> ProviderError {
+  ProviderError::new(ProviderErrorKind::InvalidRequest, error.to_string())
+}
+
+fn lock_state(
+  state: &Arc<Mutex<ModelFabricState>>,
+) -> ProviderResult<std::sync::MutexGuard<'_, ModelFabricState>> {
+  state
+    .lock()
+    .map_err(|_| ProviderError::new(ProviderErrorKind::Unknown, "State lock poisoned"))
 }
 
 fn now_ms() -> i64 {
@@ -1471,25 +2863,227 @@ fn to_napi_error(error: impl std::fmt::Display) -> NapiError {
 #[cfg(test)]
 mod tests {
   use super::*;
+  use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
-  #[test]
-  fn rejects_missing_models() {
-    let record = ProviderConfigRecord {
-      provider_id: "openai".to_string(),
+  struct MockReply {
+    model: &'static str,
+    status: u16,
+    content_type: &'static str,
+    body: String,
+  }
+
+  struct MockProvider {
+    base_url: String,
+    requests: Arc<Mutex<Vec<Value>>>,
+  }
+
+  /// Serves canned replies chosen by the `model` field of the JSON body; replies for the
+  /// same model are used in order, the last one repeating. Request bodies are recorded.
+  async fn spawn_mock_provider(replies: Vec<MockReply>) -> MockProvider {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    let replies = Arc::new(replies);
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let served: Arc<Mutex<HashMap<String, usize>>> = Arc::default();
+    let recorded = requests.clone();
+
+    tokio::spawn(async move {
+      while let Ok((mut socket, _)) = listener.accept().await {
+        let replies = replies.clone();
+        let recorded = recorded.clone();
+        let served = served.clone();
+        tokio::spawn(async move {
+          let mut raw = Vec::new();
+          let mut buffer = [0u8; 4096];
+          loop {
+            let read = socket.read(&mut buffer).await.unwrap_or(0);
+            if read == 0 {
+              return;
+            }
+            raw.extend_from_slice(&buffer[..read]);
+            let text = String::from_utf8_lossy(&raw).to_string();
+            let Some(header_end) = text.find("\r\n\r\n") else {
+              continue;
+            };
+            let content_length = text[..header_end]
+              .lines()
+              .find_map(|line| {
+                let (name, value) = line.split_once(':')?;
+                name
+                  .eq_ignore_ascii_case("content-length")
+                  .then(|| value.trim().parse::<usize>().ok())?
+              })
+              .unwrap_or(0);
+            if raw.len() < header_end + 4 + content_length {
+              continue;
+            }
+            let body: Value = serde_json::from_slice(&raw[header_end + 4..]).unwrap_or(Value::Null);
+            // Gemini carries the model in the path (`/models/{model}:generateContent`).
+            let path_model = text
+              .split_whitespace()
+              .nth(1)
+              .and_then(|path| path.split("/models/").nth(1))
+              .and_then(|rest| rest.split(':').next())
+              .unwrap_or("");
+            let model = body
+              .get("model")
+              .and_then(|value| value.as_str())
+              .unwrap_or(path_model)
+              .to_string();
+            recorded.lock().unwrap().push(body);
+            let candidates: Vec<&MockReply> =
+              replies.iter().filter(|reply| reply.model == model).collect();
+            let index = {
+              let mut served = served.lock().unwrap();
+              let count = served.entry(model).or_default();
+              *count += 1;
+              *count - 1
+            };
+            let reply = candidates.get(index).or(candidates.last());
+            let (status, content_type, payload) = match reply {
+              Some(reply) => (reply.status, reply.content_type, reply.body.clone()),
+              None => (404, "application/json", "{}".to_string()),
+            };
+            let response = format!(
+              "HTTP/1.1 {status} Mock\r\ncontent-type: {content_type}\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{payload}",
+              payload.len()
+            );
+            let _ = socket.write_all(response.as_bytes()).await;
+            return;
+          }
+        });
+      }
+    });
+
+    MockProvider {
+      base_url: format!("http://{address}"),
+      requests,
+    }
+  }
+
+  static TEST_KEY: std::sync::Once = std::sync::Once::new();
+
+  fn build_provider(provider_id: &str, model_ids: &[&str]) -> ProviderConfigRecord {
+    TEST_KEY.call_once(|| std::env::set_var("MODEL_FABRIC_TEST_KEY", "key"));
+    ProviderConfigRecord {
+      provider_id: provider_id.to_string(),
       kind: ProviderKind::Openai,
-      auth_ref: "key".to_string(),
+      auth_ref: "env:MODEL_FABRIC_TEST_KEY".to_string(),
       base_url: None,
       timeout_ms: None,
       max_retries: None,
+      retry_base_delay_ms: None,
+      retry_max_delay_ms: None,
+      circuit_failure_threshold: None,
+      circuit_cooldown_ms: None,
       organization_id: None,
-      model_ids: vec![],
+      model_ids: model_ids.iter().map(|model_id| model_id.to_string()).collect(),
       default_model_id: None,
-    };
+      context_windows: None,
+      api: None,
+      keep_alive: None,
+      safety_settings: None,
+      limits: None,
+      model_limits: None,
+      max_queue_wait_ms: None,
+      credentials: Credentials::default(),
+    }
+  }
+
+  fn request_for(model: &str) -> CompletionRequest {
+    serde_json::from_value(json!({ "model": model, "messages": [] })).unwrap()
+  }
+
+  #[test]
+  fn rejects_missing_models() {
+    let record = build_provider("openai", &[]);
 
     let result = validate_provider_configs(&[record]);
     assert!(result.is_err());
   }
 
+  #[test]
+  fn native_local_providers_default_to_the_local_ollama_host() {
+    let mut provider = build_provider("ollama", &["llama3"]);
+    provider.kind = ProviderKind::Local;
+    assert!(validate_provider_configs(&[provider.clone()]).is_err());
+
+    provider.api = Some(ProviderApi::Native);
+    validate_provider_configs(&[provider.clone()]).unwrap();
+    assert_eq!(resolve_base_url(&provider), "http://localhost:11434");
+  }
+
+  #[tokio::test]
+  async fn keyless_native_local_providers_send_no_authorization() {
+    let mut provider = build_provider("ollama", &["llama3"]);
+    provider.kind = ProviderKind::Local;
+    provider.auth_ref = String::new();
+    assert!(validate_provider_configs(&[provider.clone()]).is_err());
+
+    provider.api = Some(ProviderApi::Native);
+    validate_provider_configs(&[provider.clone()]).unwrap();
+    let headers = ollama::headers(&provider).await.unwrap();
+    assert!(!headers.contains_key(reqwest::header::AUTHORIZATION));
+
+    provider.auth_ref = "env:MODEL_FABRIC_TEST_KEY".to_string();
+    let headers = ollama::headers(&provider).await.unwrap();
+    assert_eq!(headers[reqwest::header::AUTHORIZATION], "Bearer key");
+  }
+
+  #[tokio::test]
+  async fn auth_refs_resolve_through_credential_sources_without_raw_fallback() {
+    let dir = std::env::temp_dir().join(format!("model-fabric-credentials-{}", Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let key_file = dir.join("openai.key");
+    std::fs::write(&key_file, "sk-file\n").unwrap();
+
+    let mut from_file = build_provider("file", &["model-a"]);
+    from_file.auth_ref = format!("file:{}", key_file.display());
+    let mut from_secret = build_provider("secret", &["model-b"]);
+    from_secret.auth_ref = "secret:openai".to_string();
+    let mut raw = build_provider("raw", &["model-c"]);
+    raw.auth_ref = "sk-not-an-env-var".to_string();
+    let fabric = ModelFabric::new();
+    fabric
+      .load_providers(vec![json!(from_file), json!(from_secret), json!(raw)])
+      .unwrap();
+    let key_of = async |provider_id: &str| {
+      let provider = fabric.state.lock().unwrap().provider_by_id[provider_id].clone();
+      resolve_api_key(&provider).await
+    };
+
+    assert_eq!(key_of("file").await.unwrap(), "sk-file");
+    assert_eq!(key_of("raw").await.unwrap_err().kind, ProviderErrorKind::Auth);
+    assert_eq!(key_of("secret").await.unwrap_err().kind, ProviderErrorKind::Auth);
+
+    fabric
+      .configure_credentials(Some(json!({
+        "dbPath": dir.join("store.db"),
+        "encryptionKeyRef": "00112233445566778899aabbccddeeff00112233445566778899aabbccddeeff"
+      })))
+      .await
+      .unwrap();
+    fabric
+      .rotate_credential("secret:openai".to_string(), "sk-one".to_string())
+      .await
+      .unwrap();
+    assert_eq!(key_of("secret").await.unwrap(), "sk-one");
+    fabric
+      .rotate_credential("secret:openai".to_string(), "sk-two".to_string())
+      .await
+      .unwrap();
+    assert_eq!(key_of("secret").await.unwrap(), "sk-two");
+    assert!(fabric
+      .rotate_credential("env:OPENAI_API_KEY".to_string(), "sk".to_string())
+      .await
+      .is_err());
+
+    let mut unknown = build_provider("vault", &["model-d"]);
+    unknown.auth_ref = "vault:openai".to_string();
+    assert!(fabric.load_providers(vec![json!(unknown)]).is_err());
+    let _ = std::fs::remove_dir_all(&dir);
+  }
+
   #[test]
   fn route_selection_respects_priority_and_filters() {
     let routes = vec![
@@ -1500,6 +3094,9 @@ mod tests {
         task_type: None,
         model_id: "model-low".to_string(),
         fallback_model_ids: None,
+        strategy: None,
+        arms: None,
+        sticky_key: None,
       },
       RouteRule {
         rule_id: "a".to_string(),
@@ -1508,6 +3105,9 @@ mod tests {
         task_type: Some("task".to_string()),
         model_id: "model-high".to_string(),
         fallback_model_ids: None,
+        strategy: None,
+        arms: None,
+        sticky_key: None,
       },
     ];
 
@@ -1517,12 +3117,126 @@ mod tests {
     let context = ModelRequestContext {
       worker_id: Some("w1".to_string()),
       task_type: Some("task".to_string()),
+      run_id: None,
+      request_id: None,
+      route: None,
     };
 
     let selected = resolve_route(&state.routes, Some(&context)).unwrap();
     assert_eq!(selected.model_id, "model-high");
   }
 
+  #[test]
+  fn weighted_routes_stick_to_one_arm_per_run() {
+    let fabric = ModelFabric::new();
+    fabric
+      .state
+      .lock()
+      .unwrap()
+      .update_providers(vec![build_provider("openai", &["model-a", "model-b"])])
+      .unwrap();
+    fabric
+      .load_routes(vec![json!({
+        "ruleId": "split",
+        "priority": 0,
+        "modelId": "model-a",
+        "strategy": "weighted",
+        "stickyKey": "run_id",
+        "arms": [
+          { "modelId": "model-a", "weight": 1.0, "armId": "control" },
+          { "modelId": "model-b", "weight": 1.0, "armId": "treatment" }
+        ]
+      })])
+      .unwrap();
+
+    let mut state = fabric.state.lock().unwrap();
+    let mut arms = HashSet::new();
+    for run in 0..32 {
+      let context = ModelRequestContext {
+        run_id: Some(format!("run-{run}")),
+        ..Default::default()
+      };
+      let (first, decision) =
+        resolve_candidates(&mut state, Some(&context), &request_for("")).unwrap();
+      let (again, _) = resolve_candidates(&mut state, Some(&context), &request_for("")).unwrap();
+      assert_eq!(first, again);
+      assert_eq!(first.len(), 2);
+      let decision = decision.unwrap();
+      assert_eq!(decision.strategy, RouteStrategy::Weighted);
+      arms.insert(decision.arm.unwrap());
+    }
+    assert_eq!(arms.len(), 2);
+
+    let context = ModelRequestContext {
+      route: Some(RouteDecision {
+        strategy: RouteStrategy::Weighted,
+        arm: Some("treatment".to_string()),
+      }),
+      ..Default::default()
+    };
+    let event = new_usage_event(
+      "openai".to_string(),
+      "model-b".to_string(),
+      &TokenUsage::default(),
+      0,
+      Some(&context),
+    );
+    let event = serde_json::to_value(event).unwrap();
+    assert_eq!(event["routeStrategy"], "weighted");
+    assert_eq!(event["routeArm"], "treatment");
+
+    drop(state);
+    let invalid = fabric.load_routes(vec![json!({
+      "ruleId": "empty",
+      "priority": 0,
+      "modelId": "model-a",
+      "strategy": "weighted",
+      "arms": [{ "modelId": "model-b", "weight": 0.0 }]
+    })]);
+    assert!(invalid.is_err());
+  }
+
+  #[test]
+  fn cheapest_routes_skip_models_without_enough_context() {
+    let mut provider = build_provider("openai", &["model-tiny", "model-mid", "model-big"]);
+    provider.context_windows = Some(HashMap::from([
+      ("model-tiny".to_string(), 100),
+      ("model-mid".to_string(), 8_000),
+      ("model-big".to_string(), 128_000),
+    ]));
+    let fabric = ModelFabric::new();
+    fabric.state.lock().unwrap().update_providers(vec![provider]).unwrap();
+    fabric
+      .load_pricing(vec![
+        json!({ "modelId": "model-tiny", "inputPerMillionUsd": 0.1, "outputPerMillionUsd": 0.1 }),
+        json!({ "modelId": "model-mid", "inputPerMillionUsd": 1.0, "outputPerMillionUsd": 2.0 }),
+        json!({ "modelId": "model-big", "inputPerMillionUsd": 5.0, "outputPerMillionUsd": 15.0 }),
+      ])
+      .unwrap();
+    fabric
+      .load_routes(vec![json!({
+        "ruleId": "cheap",
+        "priority": 0,
+        "modelId": "model-big",
+        "fallbackModelIds": ["model-mid", "model-tiny"],
+        "strategy": "cheapest"
+      })])
+      .unwrap();
+
+    let request: CompletionRequest = serde_json::from_value(json!({
+      "model": "",
+      "maxTokens": 500,
+      "messages": [{ "role": "user", "content": "x".repeat(2_000) }]
+    }))
+    .unwrap();
+    let mut state = fabric.state.lock().unwrap();
+    let (candidates, decision) = resolve_candidates(&mut state, None, &request).unwrap();
+    assert_eq!(candidates, vec!["model-mid".to_string(), "model-big".to_string()]);
+    let decision = decision.unwrap();
+    assert_eq!(decision.strategy, RouteStrategy::Cheapest);
+    assert_eq!(decision.arm.as_deref(), Some("model-mid"));
+  }
+
   #[test]
   fn usage_events_include_latency_and_tokens() {
     let mut state = ModelFabricState::default();
@@ -1530,6 +3244,7 @@ mod tests {
       input_tokens: 10,
       output_tokens: 20,
       total_tokens: 30,
+      cached_input_tokens: None,
     };
 
     let event = state.record_usage_event(
@@ -1537,6 +3252,8 @@ mod tests {
       "model".to_string(),
       &usage,
       123,
+      0,
+      None,
     );
 
     assert_eq!(event.input_tokens, 10);
@@ -1547,22 +3264,1115 @@ mod tests {
 
   #[test]
   fn preferred_model_used_when_no_route_matches() {
-    let provider = ProviderConfigRecord {
-      provider_id: "openai".to_string(),
-      kind: ProviderKind::Openai,
-      auth_ref: "key".to_string(),
-      base_url: None,
-      timeout_ms: None,
-      max_retries: None,
-      organization_id: None,
-      model_ids: vec!["model-preferred".to_string()],
-      default_model_id: None,
-    };
+    let provider = build_provider("openai", &["model-preferred"]);
 
     let mut state = ModelFabricState::default();
     state.update_providers(vec![provider]).unwrap();
 
-    let candidates = resolve_candidates(&state, None, Some("model-preferred")).unwrap();
+    let (candidates, decision) =
+      resolve_candidates(&mut state, None, &request_for("model-preferred")).unwrap();
     assert_eq!(candidates, vec!["model-preferred".to_string()]);
+    assert!(decision.is_none());
+  }
+
+  #[test]
+  fn provider_errors_are_classified_by_status_and_body() {
+    let auth = ProviderError::from_status(401, "invalid api key", None);
+    assert_eq!(auth.kind, ProviderErrorKind::Auth);
+    assert!(!auth.is_retryable());
+
+    let limited = ProviderError::from_status(429, "slow down", Some(1500));
+    assert_eq!(limited.kind, ProviderErrorKind::RateLimited);
+    assert_eq!(limited.retry_after_ms, Some(1500));
+    assert!(limited.is_retryable());
+
+    let context = ProviderError::from_status(
+      400,
+      r#"{"error":{"code":"context_length_exceeded"}}"#,
+      None,
+    );
+    assert_eq!(context.kind, ProviderErrorKind::ContextLengthExceeded);
+
+    let invalid = ProviderError::from_status(400, "unknown parameter", None);
+    assert_eq!(invalid.kind, ProviderErrorKind::InvalidRequest);
+
+    let overloaded = ProviderError::from_status(529, "overloaded", None);
+    assert_eq!(overloaded.kind, ProviderErrorKind::Overloaded);
+    assert!(overloaded.to_string().starts_with("overloaded: "));
+  }
+
+  #[test]
+  fn retry_policy_backs_off_and_honours_retry_after() {
+    let mut provider = build_provider("openai", &["model"]);
+    provider.max_retries = Some(2);
+    provider.retry_base_delay_ms = Some(100);
+    provider.retry_max_delay_ms = Some(1_000);
+    let policy = RetryPolicy::for_provider(&provider);
+
+    let overloaded = ProviderError::from_status(503, "", None);
+    let first = policy.next_delay_ms(0, &overloaded).unwrap();
+    assert!((50..=100).contains(&first));
+    let second = policy.next_delay_ms(1, &overloaded).unwrap();
+    assert!((100..=200).contains(&second));
+    assert_eq!(policy.next_delay_ms(2, &overloaded), None);
+
+    let limited = ProviderError::from_status(429, "", Some(750));
+    assert_eq!(policy.next_delay_ms(0, &limited), Some(750));
+    let too_long = ProviderError::from_status(429, "", Some(5_000));
+    assert_eq!(policy.next_delay_ms(0, &too_long), None);
+
+    let auth = ProviderError::from_status(401, "", None);
+    assert_eq!(policy.next_delay_ms(0, &auth), None);
+  }
+
+  #[test]
+  fn fallback_tracker_skips_by_error_class() {
+    let mut primary = build_provider("primary", &["small", "large"]);
+    primary.context_windows = Some(HashMap::from([
+      ("small".to_string(), 8_000),
+      ("large".to_string(), 128_000),
+    ]));
+    let secondary = build_provider("secondary", &["other"]);
+
+    let mut tracker = FallbackTracker::default();
+    let context = ProviderError::from_status(400, "maximum context length is 8000 tokens", None);
+    assert!(tracker.record_failure(&primary, "small", &context));
+    assert!(tracker.should_skip(&primary, "small"));
+    assert!(!tracker.should_skip(&primary, "large"));
+    assert!(!tracker.should_skip(&secondary, "other"));
+
+    let auth = ProviderError::from_status(401, "bad key", None);
+    assert!(tracker.record_failure(&primary, "large", &auth));
+    assert!(tracker.should_skip(&primary, "large"));
+    assert!(!tracker.should_skip(&secondary, "other"));
+
+    let invalid = ProviderError::from_status(422, "bad schema", None);
+    assert!(!tracker.record_failure(&secondary, "other", &invalid));
+  }
+
+  #[tokio::test]
+  async fn stream_falls_back_before_first_chunk() {
+    let mock = spawn_mock_provider(vec![
+      MockReply {
+        model: "model-a",
+        status: 503,
+        content_type: "application/json",
+        body: r#"{"error":"unavailable"}"#.to_string(),
+      },
+      MockReply {
+        model: "model-b",
+        status: 200,
+        content_type: "text/event-stream",
+        body: [
+          r#"data: {"choices":[{"delta":{"content":"hi"}}]}"#,
+          r#"data: {"choices":[{"delta":{},"finish_reason":"stop"}]}"#,
+          "data: [DONE]",
+        ]
+        .join("\n\n"),
+      },
+    ])
+    .await;
+
+    let mut primary = build_provider("primary", &["model-a"]);
+    primary.base_url = Some(mock.base_url.clone());
+    primary.max_retries = Some(0);
+    let mut secondary = build_provider("secondary", &["model-b"]);
+    secondary.base_url = Some(mock.base_url);
+
+    let fabric = ModelFabric::new();
+    {
+      let mut state = fabric.state.lock().unwrap();
+      state.update_providers(vec![primary, secondary]).unwrap();
+      state.update_routes(vec![RouteRule {
+        rule_id: "default".to_string(),
+        priority: 0,
+        worker_id: None,
+        task_type: None,
+        model_id: "model-a".to_string(),
+        fallback_model_ids: Some(vec!["model-b".to_string()]),
+        strategy: None,
+        arms: None,
+        sticky_key: None,
+      }]);
+    }
+
+    let handle = fabric
+      .stream(json!({ "model": "", "messages": [{ "role": "user", "content": "hello" }] }), None)
+      .await
+      .unwrap();
+    let mut chunks = Vec::new();
+    while let Some(chunk) = handle.next().await.unwrap() {
+      chunks.push(chunk);
+    }
+
+    assert_eq!(chunks[0]["type"], "fallback");
+    assert_eq!(chunks[0]["model"], "model-b");
+    assert_eq!(chunks[0]["errorCode"], "overloaded");
+    assert_eq!(chunks[1]["content"], "hi");
+    assert_eq!(chunks.last().unwrap()["type"], "done");
+
+    let events = fabric.drain_usage_events(None, None).unwrap();
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0]["modelId"], "model-b");
+  }
+
+  #[test]
+  fn circuit_breaker_skips_open_providers_until_cooldown() {
+    let mut flaky = build_provider("flaky", &["model-a"]);
+    flaky.circuit_failure_threshold = Some(2);
+    flaky.circuit_cooldown_ms = Some(60_000);
+    let stable = build_provider("stable", &["model-b"]);
+
+    let mut state = ModelFabricState::default();
+    state.update_providers(vec![flaky.clone(), stable]).unwrap();
+    state.update_routes(vec![RouteRule {
+      rule_id: "default".to_string(),
+      priority: 0,
+      worker_id: None,
+      task_type: None,
+      model_id: "model-a".to_string(),
+      fallback_model_ids: Some(vec!["model-b".to_string()]),
+      strategy: None,
+      arms: None,
+      sticky_key: None,
+    }]);
+
+    state.record_provider_success(&flaky, 100);
+    state.record_provider_success(&flaky, 300);
+    let invalid = ProviderError::from_status(400, "bad request", None);
+    state.record_provider_failure(&flaky, &invalid);
+    state.record_provider_failure(&flaky, &invalid);
+    assert_eq!(resolve_candidates(&mut state, None, &request_for("")).unwrap().0.len(), 2);
+
+    let overloaded = ProviderError::from_status(503, "down", None);
+    state.record_provider_failure(&flaky, &overloaded);
+    state.record_provider_failure(&flaky, &overloaded);
+    let (candidates, _) = resolve_candidates(&mut state, None, &request_for("")).unwrap();
+    assert_eq!(candidates, vec!["model-b".to_string()]);
+
+    let snapshot = state.snapshot();
+    let health = &snapshot.provider_health[0];
+    assert_eq!(health.provider_id, "flaky");
+    assert_eq!(health.circuit_state, health::CircuitState::Open);
+    assert_eq!(health.consecutive_failures, 2);
+    assert_eq!(health.last_error_code, Some(ProviderErrorKind::Overloaded));
+    assert_eq!(health.latency_p50_ms, Some(100));
+    assert_eq!(health.latency_p95_ms, Some(300));
+
+    let config = CircuitBreakerConfig::for_provider(&flaky);
+    let opened_at = health.opened_at.unwrap();
+    let tracked = &state.provider_health["flaky"];
+    assert_eq!(
+      tracked.circuit_state(&config, opened_at + 60_000),
+      health::CircuitState::HalfOpen
+    );
+    let tracked = state.provider_health.get_mut("flaky").unwrap();
+    assert!(tracked.begin_request(&config, opened_at + 60_000));
+    assert!(!tracked.begin_request(&config, opened_at + 60_001));
+    assert!(!tracked.allows_request(&config, opened_at + 60_001));
+    tracked.record_failure(&config, &overloaded, opened_at + 60_002);
+    assert!(!tracked.begin_request(&config, opened_at + 60_003));
+    assert!(tracked.begin_request(&config, opened_at + 120_002));
+    tracked.record_success(50);
+    assert!(tracked.begin_request(&config, opened_at + 120_003));
+    assert!(tracked.begin_request(&config, opened_at + 120_004));
+  }
+
+  #[test]
+  fn usage_events_are_priced_from_the_catalog() {
+    let pricing = PricingCatalog::load(vec![
+      ModelPricing {
+        model_id: "model".to_string(),
+        input_per_million_usd: 3.0,
+        output_per_million_usd: 15.0,
+        cached_input_per_million_usd: Some(0.3),
+        effective_from: None,
+      },
+      ModelPricing {
+        model_id: "model".to_string(),
+        input_per_million_usd: 100.0,
+        output_per_million_usd: 100.0,
+        cached_input_per_million_usd: None,
+        effective_from: Some(i64::MAX),
+      },
+    ])
+    .unwrap();
+    let mut state = ModelFabricState {
+      pricing,
+      ..Default::default()
+    };
+
+    let usage = TokenUsage {
+      input_tokens: 1_000_000,
+      output_tokens: 100_000,
+      total_tokens: 1_100_000,
+      cached_input_tokens: Some(500_000),
+    };
+    let event =
+      state.record_usage_event("provider".to_string(), "model".to_string(), &usage, 1, 0, None);
+    let cost = event.cost_usd.unwrap();
+    assert!((cost - (1.5 + 0.15 + 1.5)).abs() < 1e-9);
+
+    let unpriced =
+      state.record_usage_event("provider".to_string(), "other".to_string(), &usage, 1, 0, None);
+    assert_eq!(unpriced.cost_usd, None);
+  }
+
+  #[tokio::test]
+  async fn openai_streams_request_usage_and_are_priced() {
+    let mock = spawn_mock_provider(vec![MockReply {
+      model: "model-a",
+      status: 200,
+      content_type: "text/event-stream",
+      body: [
+        r#"data: {"choices":[{"delta":{"content":"hi"}}]}"#,
+        r#"data: {"choices":[{"delta":{},"finish_reason":"stop"}]}"#,
+        r#"data: {"choices":[],"usage":{"prompt_tokens":1000,"completion_tokens":500,"total_tokens":1500}}"#,
+        "data: [DONE]",
+      ]
+      .join("\n\n"),
+    }])
+    .await;
+    let mut provider = build_provider("primary", &["model-a"]);
+    provider.base_url = Some(mock.base_url.clone());
+    let fabric = ModelFabric::new();
+    fabric.load_providers(vec![json!(provider)]).unwrap();
+    fabric
+      .load_pricing(vec![json!({
+        "modelId": "model-a",
+        "inputPerMillionUsd": 2.0,
+        "outputPerMillionUsd": 10.0,
+      })])
+      .unwrap();
+
+    let handle = fabric
+      .stream(json!({ "model": "model-a", "messages": [{ "role": "user", "content": "hello" }] }), None)
+      .await
+      .unwrap();
+    while handle.next().await.unwrap().is_some() {}
+
+    let requests = mock.requests.lock().unwrap().clone();
+    assert_eq!(requests[0]["stream_options"]["include_usage"], true);
+    let events = fabric.drain_usage_events(None, None).unwrap();
+    assert_eq!(events[0]["totalTokens"], 1500);
+    let cost = events[0]["costUsd"].as_f64().unwrap();
+    assert!((cost - 0.007).abs() < 1e-9);
+  }
+
+  #[test]
+  fn anthropic_stream_usage_is_merged_across_events() {
+    let mut usage = TokenUsage::default();
+    let start = parse_anthropic_sse_line(
+      r#"data: {"type":"message_start","message":{"usage":{"input_tokens":20,"cache_read_input_tokens":80,"output_tokens":1}}}"#,
+    )
+    .unwrap();
+    update_usage_from_chunk(&start, &mut usage);
+    let delta = parse_anthropic_sse_line(
+      r#"data: {"type":"message_delta","usage":{"output_tokens":42}}"#,
+    )
+    .unwrap();
+    update_usage_from_chunk(&delta, &mut usage);
+
+    assert_eq!(usage.input_tokens, 100);
+    assert_eq!(usage.cached_input_tokens, Some(80));
+    assert_eq!(usage.output_tokens, 42);
+    assert_eq!(usage.total_tokens, 142);
+  }
+
+  fn budget_rule(budget_id: &str, scope: budget::BudgetScope) -> BudgetRule {
+    BudgetRule {
+      budget_id: budget_id.to_string(),
+      scope,
+      worker_id: None,
+      task_type: None,
+      max_tokens: None,
+      max_cost_usd: None,
+      window_ms: None,
+      action: None,
+      downgrade_model_id: None,
+    }
+  }
+
+  #[test]
+  fn budgets_reject_or_downgrade_once_exhausted() {
+    let rules = vec![
+      BudgetRule {
+        max_tokens: Some(100),
+        ..budget_rule("per-run", budget::BudgetScope::Run)
+      },
+      BudgetRule {
+        task_type: Some("summarize".to_string()),
+        max_tokens: Some(50),
+        action: Some(budget::BudgetAction::Downgrade),
+        downgrade_model_id: Some("model-cheap".to_string()),
+        ..budget_rule("summaries", budget::BudgetScope::TaskType)
+      },
+    ];
+    let mut state = ModelFabricState::default();
+    state
+      .budgets
+      .load(rules, |model_id| model_id == "model-cheap")
+      .unwrap();
+
+    let context = ModelRequestContext {
+      worker_id: None,
+      task_type: Some("summarize".to_string()),
+      run_id: Some("run-1".to_string()),
+      request_id: None,
+      route: None,
+    };
+    let candidates = vec!["model-large".to_string(), "model-backup".to_string()];
+    let usage = TokenUsage {
+      input_tokens: 40,
+      output_tokens: 20,
+      total_tokens: 60,
+      cached_input_tokens: None,
+    };
+
+    assert_eq!(
+      state.apply_budgets(Some(&context), candidates.clone()).unwrap(),
+      candidates
+    );
+    let event = state.record_usage_event(
+      "provider".to_string(),
+      "model-large".to_string(),
+      &usage,
+      1,
+      0,
+      Some(&context),
+    );
+    assert_eq!(event.run_id.as_deref(), Some("run-1"));
+    assert_eq!(
+      state.apply_budgets(Some(&context), candidates.clone()).unwrap(),
+      vec!["model-cheap".to_string(), "model-backup".to_string()]
+    );
+
+    state.record_usage_event(
+      "provider".to_string(),
+      "model-cheap".to_string(),
+      &usage,
+      1,
+      0,
+      Some(&context),
+    );
+    let error = state
+      .apply_budgets(Some(&context), candidates.clone())
+      .unwrap_err();
+    assert_eq!(error.kind, ProviderErrorKind::BudgetExceeded);
+
+    let other_run = ModelRequestContext {
+      task_type: None,
+      run_id: Some("run-2".to_string()),
+      ..context
+    };
+    assert!(state.apply_budgets(Some(&other_run), candidates).is_ok());
+  }
+
+  #[test]
+  fn downgrades_use_configured_models_behind_the_circuit_breaker() {
+    let large = build_provider("large", &["model-large", "model-backup"]);
+    let mut cheap = build_provider("cheap", &["model-cheap"]);
+    cheap.circuit_failure_threshold = Some(1);
+    cheap.circuit_cooldown_ms = Some(60_000);
+    let mut state = ModelFabricState::default();
+    state.update_providers(vec![large, cheap.clone()]).unwrap();
+    state.update_routes(vec![RouteRule {
+      rule_id: "default".to_string(),
+      priority: 0,
+      worker_id: None,
+      task_type: None,
+      model_id: "model-large".to_string(),
+      fallback_model_ids: Some(vec!["model-backup".to_string()]),
+      strategy: None,
+      arms: None,
+      sticky_key: None,
+    }]);
+
+    let downgrade_to = |model_id: &str| BudgetRule {
+      max_tokens: Some(10),
+      action: Some(budget::BudgetAction::Downgrade),
+      downgrade_model_id: Some(model_id.to_string()),
+      ..budget_rule("global", budget::BudgetScope::Global)
+    };
+    let is_model = |model_id: &str| state.model_to_provider.contains_key(model_id);
+    let mut ledger = BudgetLedger::default();
+    let error = ledger
+      .load(vec![downgrade_to("model-missing")], is_model)
+      .unwrap_err();
+    assert!(error.contains("model-missing"), "{error}");
+    ledger.load(vec![downgrade_to("model-cheap")], is_model).unwrap();
+    state.budgets = ledger;
+    state.budgets.debit(None, 20, 0.0, now_ms());
+
+    let (candidates, _) = resolve_candidates(&mut state, None, &request_for("")).unwrap();
+    assert_eq!(candidates, vec!["model-cheap", "model-backup"]);
+
+    let overloaded = ProviderError::from_status(503, "down", None);
+    state.record_provider_failure(&cheap, &overloaded);
+    let (candidates, _) = resolve_candidates(&mut state, None, &request_for("")).unwrap();
+    assert_eq!(candidates, vec!["model-backup"]);
+  }
+
+  #[test]
+  fn windowed_budgets_release_expired_usage() {
+    let mut ledger = BudgetLedger::default();
+    ledger
+      .load(
+        vec![BudgetRule {
+          max_cost_usd: Some(1.0),
+          window_ms: Some(60_000),
+          ..budget_rule("hourly", budget::BudgetScope::Global)
+        }],
+        |_| true,
+      )
+      .unwrap();
+
+    ledger.debit(None, 10, 1.5, 1_000);
+    assert!(matches!(
+      ledger.check(None, 30_000),
+      BudgetDecision::Reject { .. }
+    ));
+    assert_eq!(ledger.check(None, 61_001), BudgetDecision::Allow);
+    assert!(ledger.usage_snapshot()[0].cost_usd.abs() < 1e-9);
+  }
+
+  fn tool_turn_messages() -> Vec<Message> {
+    serde_json::from_value(json!([
+      { "role": "system", "content": "Be terse." },
+      {
+        "role": "user",
+        "content": [
+          { "type": "text", "text": "What is in this picture?" },
+          { "type": "image", "source": { "type": "base64", "mediaType": "image/png", "data": "AAAA" } }
+        ]
+      },
+      {
+        "role": "assistant",
+        "content": [{ "type": "thinking", "thinking": "Need a lookup.", "signature": "sig" }],
+        "toolCalls": [
+          { "id": "call_1", "name": "lookup", "arguments": "{\"q\":\"cat\"}" },
+          { "id": "call_2", "name": "lookup", "arguments": "{\"q\":\"dog\"}" }
+        ]
+      },
+      { "role": "tool", "toolCallId": "call_1", "content": "a cat" },
+      {
+        "role": "user",
+        "content": [{ "type": "tool_result", "toolUseId": "call_2", "content": "no dog", "isError": true }]
+      }
+    ]))
+    .unwrap()
+  }
+
+  #[test]
+  fn openai_messages_carry_images_and_tool_turns() {
+    let formatted = format_openai_messages(&tool_turn_messages());
+
+    assert_eq!(formatted.len(), 5);
+    assert_eq!(
+      formatted[1]["content"][1]["image_url"]["url"],
+      "data:image/png;base64,AAAA"
+    );
+    assert_eq!(formatted[2]["content"], Value::Null);
+    assert_eq!(formatted[2]["tool_calls"][1]["id"], "call_2");
+    assert_eq!(formatted[3]["role"], "tool");
+    assert_eq!(formatted[3]["tool_call_id"], "call_1");
+    assert_eq!(formatted[4]["tool_call_id"], "call_2");
+    assert_eq!(formatted[4]["content"], "no dog");
+  }
+
+  #[test]
+  fn anthropic_messages_merge_tool_results_into_one_turn() {
+    let (system, formatted) = format_anthropic_messages(&tool_turn_messages());
+
+    assert_eq!(system.as_deref(), Some("Be terse."));
+    assert_eq!(formatted.len(), 3);
+    assert_eq!(formatted[0]["content"][1]["source"]["media_type"], "image/png");
+    let assistant = &formatted[1]["content"];
+    assert_eq!(assistant[0]["type"], "thinking");
+    assert_eq!(assistant[0]["signature"], "sig");
+    assert_eq!(assistant[1]["input"], json!({ "q": "cat" }));
+    let results = formatted[2]["content"].as_array().unwrap();
+    assert_eq!(results.len(), 2);
+    assert_eq!(results[0]["tool_use_id"], "call_1");
+    assert_eq!(results[1]["is_error"], true);
+  }
+
+  #[test]
+  fn stream_decoder_assembles_fragmented_tool_calls() {
+    let mut openai = StreamDecoder::new(WireProtocol::OpenaiCompatible);
+    let mut chunks = Vec::new();
+    for line in [
+      r#"data: {"choices":[{"delta":{"tool_calls":[{"index":0,"id":"call_1","function":{"name":"lookup","arguments":""}}]}}],"usage":null}"#,
+      r#"data: {"choices":[{"delta":{"tool_calls":[{"index":0,"function":{"arguments":"{\"q\":"}}]}}]}"#,
+      r#"data: {"choices":[{"delta":{"tool_calls":[{"index":0,"function":{"arguments":"\"cat\"}"}}]}}]}"#,
+      r#"data: {"choices":[{"delta":{},"finish_reason":"tool_calls"}]}"#,
+    ] {
+      chunks.extend(openai.decode_line(line));
+    }
+    assert_eq!(chunks.len(), 2);
+    let call = chunks[0].tool_call.as_ref().unwrap();
+    assert_eq!((call.id.as_str(), call.arguments.as_str()), ("call_1", r#"{"q":"cat"}"#));
+    assert_eq!(chunks[1].finish_reason.as_deref(), Some("tool_calls"));
+
+    let mut anthropic = StreamDecoder::new(WireProtocol::Anthropic);
+    let mut chunks = Vec::new();
+    for line in [
+      r#"data: {"type":"content_block_start","index":0,"content_block":{"type":"thinking","thinking":""}}"#,
+      r#"data: {"type":"content_block_delta","index":0,"delta":{"type":"thinking_delta","thinking":"Hmm."}}"#,
+      r#"data: {"type":"content_block_delta","index":0,"delta":{"type":"signature_delta","signature":"sig"}}"#,
+      r#"data: {"type":"content_block_stop","index":0}"#,
+      r#"data: {"type":"content_block_start","index":1,"content_block":{"type":"tool_use","id":"toolu_1","name":"lookup","input":{}}}"#,
+      r#"data: {"type":"content_block_delta","index":1,"delta":{"type":"input_json_delta","partial_json":"{\"q\":\"cat\"}"}}"#,
+      r#"data: {"type":"content_block_stop","index":1}"#,
+      r#"data: {"type":"message_delta","delta":{"stop_reason":"tool_use"},"usage":{"output_tokens":9}}"#,
+      r#"data: {"type":"message_stop"}"#,
+    ] {
+      chunks.extend(anthropic.decode_line(line));
+    }
+    let types: Vec<&str> = chunks.iter().map(|chunk| chunk.chunk_type.as_str()).collect();
+    assert_eq!(types, ["thinking", "thinking", "tool_call", "usage", "done"]);
+    assert_eq!(chunks[1].signature.as_deref(), Some("sig"));
+    assert_eq!(chunks[2].tool_call.as_ref().unwrap().arguments, r#"{"q":"cat"}"#);
+    assert_eq!(chunks[4].finish_reason.as_deref(), Some("tool_calls"));
+  }
+
+  #[test]
+  fn schema_violations_point_at_the_offending_value() {
+    let schema = json!({
+      "type": "object",
+      "required": ["title", "tags"],
+      "additionalProperties": false,
+      "properties": {
+        "title": { "type": "string", "minLength": 3 },
+        "tags": { "type": "array", "items": { "$ref": "#/$defs/tag" } }
+      },
+      "$defs": { "tag": { "enum": ["a", "b"] } }
+    });
+
+    assert!(json_schema_rs::validate(&schema, &json!({ "title": "abc", "tags": ["a"] })).is_empty());
+
+    let violations =
+      json_schema_rs::validate(&schema, &json!({ "title": "x", "tags": ["c"], "extra": 1 }));
+    let found: Vec<(&str, &str)> = violations
+      .iter()
+      .map(|violation| (violation.path.as_str(), violation.keyword.as_str()))
+      .collect();
+    assert_eq!(
+      found,
+      [
+        ("$.extra", "additionalProperties"),
+        ("$.tags[0]", "enum"),
+        ("$.title", "minLength"),
+      ]
+    );
+  }
+
+  #[tokio::test]
+  async fn structured_output_is_validated_and_repaired_once() {
+    let reply = |content: &str| MockReply {
+      model: "model-json",
+      status: 200,
+      content_type: "application/json",
+      body: json!({
+        "model": "model-json",
+        "choices": [{ "message": { "content": content }, "finish_reason": "stop" }],
+        "usage": { "prompt_tokens": 5, "completion_tokens": 5, "total_tokens": 10 }
+      })
+      .to_string(),
+    };
+    let mock = spawn_mock_provider(vec![
+      reply(r#"{"answer":"forty-two"}"#),
+      reply(r#"{"answer":42}"#),
+      reply("not json"),
+    ])
+    .await;
+    let mut provider = build_provider("openai", &["model-json"]);
+    provider.base_url = Some(mock.base_url.clone());
+
+    let fabric = ModelFabric::new();
+    fabric
+      .state
+      .lock()
+      .unwrap()
+      .update_providers(vec![provider])
+      .unwrap();
+
+    let request = |repair: bool| {
+      json!({
+        "model": "model-json",
+        "messages": [{ "role": "user", "content": "answer?" }],
+        "responseFormat": {
+          "type": "json_schema",
+          "name": "answer",
+          "schema": {
+            "type": "object",
+            "required": ["answer"],
+            "properties": { "answer": { "type": "integer" } }
+          },
+          "repair": repair
+        }
+      })
+    };
+
+    let response = fabric.complete(request(true), None).await.unwrap();
+    assert_eq!(response["parsed"], json!({ "answer": 42 }));
+    {
+      let requests = mock.requests.lock().unwrap();
+      assert_eq!(requests[0]["response_format"]["json_schema"]["name"], "answer");
+      let repair_messages = requests[1]["messages"].as_array().unwrap();
+      assert_eq!(repair_messages.len(), 3);
+      assert!(repair_messages[2]["content"]
+        .as_str()
+        .unwrap()
+        .contains("$.answer (type)"));
+    }
+    assert_eq!(fabric.drain_usage_events(None, None).unwrap().len(), 2);
+
+    let error = fabric.complete(request(false), None).await.unwrap_err();
+    assert!(error.reason.starts_with("output_validation:"));
+  }
+
+  #[tokio::test]
+  async fn gemini_native_maps_function_calls_and_safety_ratings() {
+    let mock = spawn_mock_provider(vec![MockReply {
+      model: "gemini-pro",
+      status: 200,
+      content_type: "application/json",
+      body: json!({
+        "candidates": [{
+          "content": { "role": "model", "parts": [
+            { "functionCall": { "name": "lookup", "args": { "q": "cat" } } }
+          ] },
+          "finishReason": "STOP",
+          "safetyRatings": [{ "category": "HARM_CATEGORY_HARASSMENT", "probability": "NEGLIGIBLE" }]
+        }],
+        "usageMetadata": {
+          "promptTokenCount": 30,
+          "candidatesTokenCount": 5,
+          "thoughtsTokenCount": 3,
+          "totalTokenCount": 38,
+          "cachedContentTokenCount": 20
+        }
+      })
+      .to_string(),
+    }])
+    .await;
+    let mut provider = build_provider("gemini", &["gemini-pro"]);
+    provider.kind = ProviderKind::Gemini;
+    provider.api = Some(ProviderApi::Native);
+    provider.base_url = Some(mock.base_url.clone());
+
+    let fabric = ModelFabric::new();
+    fabric
+      .state
+      .lock()
+      .unwrap()
+      .update_providers(vec![provider])
+      .unwrap();
+
+    let response = fabric
+      .complete(
+        json!({
+          "model": "gemini-pro",
+          "cachedContent": "cachedContents/abc",
+          "messages": [
+            { "role": "system", "content": "Be terse." },
+            { "role": "user", "content": "find a cat" },
+            { "role": "assistant", "content": "", "toolCalls": [
+              { "id": "call_1", "name": "lookup", "arguments": "{\"q\":\"dog\"}" }
+            ] },
+            { "role": "tool", "toolCallId": "call_1", "content": "{\"found\":false}" }
+          ],
+          "tools": [{ "name": "lookup", "description": "Look up", "parameters": { "type": "object" } }]
+        }),
+        None,
+      )
+      .await
+      .unwrap();
+
+    assert_eq!(response["finishReason"], "tool_calls");
+    assert_eq!(response["toolCalls"][0]["arguments"], r#"{"q":"cat"}"#);
+    assert_eq!(response["safetyRatings"][0]["probability"], "NEGLIGIBLE");
+    assert_eq!(response["usage"]["outputTokens"], 8);
+    assert_eq!(response["usage"]["cachedInputTokens"], 20);
+
+    let requests = mock.requests.lock().unwrap();
+    let body = &requests[0];
+    assert_eq!(body["systemInstruction"]["parts"][0]["text"], "Be terse.");
+    assert_eq!(body["cachedContent"], "cachedContents/abc");
+    assert_eq!(body["tools"][0]["functionDeclarations"][0]["name"], "lookup");
+    assert_eq!(body["contents"][1]["role"], "model");
+    let response_part = &body["contents"][2]["parts"][0]["functionResponse"];
+    assert_eq!(response_part["name"], "lookup");
+    assert_eq!(response_part["response"], json!({ "found": false }));
+  }
+
+  #[tokio::test]
+  async fn ollama_native_streams_ndjson() {
+    let mock = spawn_mock_provider(vec![MockReply {
+      model: "llama3",
+      status: 200,
+      content_type: "application/x-ndjson",
+      body: [
+        r#"{"model":"llama3","message":{"role":"assistant","content":"Hel"},"done":false}"#,
+        r#"{"model":"llama3","message":{"role":"assistant","content":"lo"},"done":false}"#,
+        r#"{"model":"llama3","message":{"role":"assistant","content":""},"done":true,"done_reason":"length","prompt_eval_count":12,"eval_count":2}"#,
+      ]
+      .join("\n"),
+    }])
+    .await;
+    let mut provider = build_provider("ollama", &["llama3"]);
+    provider.kind = ProviderKind::Local;
+    provider.api = Some(ProviderApi::Native);
+    provider.keep_alive = Some("10m".to_string());
+    provider.base_url = Some(mock.base_url.clone());
+
+    let fabric = ModelFabric::new();
+    fabric
+      .state
+      .lock()
+      .unwrap()
+      .update_providers(vec![provider])
+      .unwrap();
+
+    let handle = fabric
+      .stream(
+        json!({ "model": "llama3", "maxTokens": 2, "messages": [{ "role": "user", "content": "hi" }] }),
+        None,
+      )
+      .await
+      .unwrap();
+    let mut chunks = Vec::new();
+    while let Some(chunk) = handle.next().await.unwrap() {
+      chunks.push(chunk);
+    }
+
+    let types: Vec<&str> = chunks
+      .iter()
+      .map(|chunk| chunk["type"].as_str().unwrap())
+      .collect();
+    assert_eq!(types, ["content", "content", "usage", "done"]);
+    assert_eq!(chunks[3]["finishReason"], "length");
+
+    let events = fabric.drain_usage_events(None, None).unwrap();
+    assert_eq!(events[0]["inputTokens"], 12);
+    assert_eq!(events[0]["outputTokens"], 2);
+
+    let requests = mock.requests.lock().unwrap();
+    assert_eq!(requests[0]["keep_alive"], "10m");
+    assert_eq!(requests[0]["stream"], true);
+    assert_eq!(requests[0]["options"]["num_predict"], 2);
+  }
+
+  #[tokio::test]
+  async fn ollama_stream_errors_fail_the_attempt() {
+    let failing = r#"{"error":"model runner has unexpectedly stopped"}"#;
+    let mock = spawn_mock_provider(vec![
+      MockReply {
+        model: "llama3",
+        status: 200,
+        content_type: "application/x-ndjson",
+        body: failing.to_string(),
+      },
+      MockReply {
+        model: "llama3",
+        status: 200,
+        content_type: "application/x-ndjson",
+        body: [
+          r#"{"model":"llama3","message":{"role":"assistant","content":"Hi"},"done":false}"#,
+          r#"{"model":"llama3","message":{"role":"assistant","content":""},"done":true,"prompt_eval_count":3,"eval_count":1}"#,
+        ]
+        .join("\n"),
+      },
+      MockReply {
+        model: "mistral",
+        status: 200,
+        content_type: "application/x-ndjson",
+        body: [
+          r#"{"model":"mistral","message":{"role":"assistant","content":"Hal"},"done":false}"#,
+          failing,
+        ]
+        .join("\n"),
+      },
+    ])
+    .await;
+    let mut provider = build_provider("ollama", &["llama3", "mistral"]);
+    provider.kind = ProviderKind::Local;
+    provider.api = Some(ProviderApi::Native);
+    provider.base_url = Some(mock.base_url.clone());
+    provider.retry_base_delay_ms = Some(1);
+    let fabric = ModelFabric::new();
+    fabric.load_providers(vec![json!(provider)]).unwrap();
+    fabric
+      .configure_cache(Some(json!({ "anyTemperature": true })))
+      .unwrap();
+
+    let request = |model: &str| json!({ "model": model, "messages": [{ "role": "user", "content": "hi" }] });
+    let types = |chunks: &[Value]| {
+      chunks
+        .iter()
+        .map(|chunk| chunk["type"].as_str().unwrap().to_string())
+        .collect::<Vec<_>>()
+    };
+    let retried = collect_stream(&fabric, request("llama3")).await;
+    assert_eq!(types(&retried), ["content", "usage", "done"]);
+    let events = fabric.drain_usage_events(None, None).unwrap();
+    assert_eq!(events.len(), 1);
+
+    let failed = collect_stream(&fabric, request("mistral")).await;
+    assert_eq!(types(&failed), ["content", "error"]);
+    assert_eq!(failed[1]["errorCode"], "overloaded");
+    assert!(fabric.drain_usage_events(None, None).unwrap().is_empty());
+    let snapshot = fabric.get_snapshot().unwrap();
+    assert_eq!(snapshot["providerHealth"][0]["consecutiveFailures"], 1);
+    let again = collect_stream(&fabric, request("mistral")).await;
+    assert_eq!(types(&again), ["content", "error"]);
+  }
+
+  async fn collect_stream(fabric: &ModelFabric, request: Value) -> Vec<Value> {
+    let handle = fabric.stream(request, None).await.unwrap();
+    let mut chunks = Vec::new();
+    while let Some(chunk) = handle.next().await.unwrap() {
+      chunks.push(chunk);
+    }
+    chunks
+  }
+
+  #[tokio::test]
+  async fn cassette_replays_recorded_completions_and_streams_offline() {
+    let mock = spawn_mock_provider(vec![
+      MockReply {
+        model: "model-a",
+        status: 200,
+        content_type: "application/json",
+        body: json!({
+          "choices": [{ "message": { "content": "recorded" }, "finish_reason": "stop" }],
+          "usage": { "prompt_tokens": 3, "completion_tokens": 1, "total_tokens": 4 }
+        })
+        .to_string(),
+      },
+      MockReply {
+        model: "model-a",
+        status: 200,
+        content_type: "text/event-stream",
+        body: [
+          r#"data: {"choices":[{"delta":{"content":"stre"}}]}"#,
+          r#"data: {"choices":[{"delta":{"content":"amed"},"finish_reason":"stop"}]}"#,
+          "data: [DONE]",
+        ]
+        .join("\n\n"),
+      },
+    ])
+    .await;
+    let path = std::env::temp_dir()
+      .join(format!("cassette-{}.json", Uuid::new_v4()))
+      .to_string_lossy()
+      .to_string();
+    let request = json!({
+      "model": "model-a",
+      "temperature": 0.0,
+      "messages": [{ "role": "user", "content": "hi" }]
+    });
+
+    let mut provider = build_provider("primary", &["model-a"]);
+    provider.base_url = Some(mock.base_url.clone());
+    let recorder = ModelFabric::new();
+    recorder.load_providers(vec![json!(provider)]).unwrap();
+    recorder
+      .set_cassette(Some(json!({ "mode": "record", "path": path })))
+      .unwrap();
+    let recorded = recorder.complete(request.clone(), None).await.unwrap();
+    let recorded_chunks = collect_stream(&recorder, request.clone()).await;
+    assert_eq!(mock.requests.lock().unwrap().len(), 2);
+
+    // Nothing listens on the discard port, so any network access would fail.
+    provider.base_url = Some("http://127.0.0.1:9".to_string());
+    let player = ModelFabric::new();
+    player.load_providers(vec![json!(provider)]).unwrap();
+    player
+      .set_cassette(Some(json!({ "mode": "replay", "path": path })))
+      .unwrap();
+    let replayed = player.complete(request.clone(), None).await.unwrap();
+    assert_eq!(replayed["content"], recorded["content"]);
+    assert_eq!(replayed["usage"], recorded["usage"]);
+    assert_eq!(collect_stream(&player, request.clone()).await, recorded_chunks);
+    assert_eq!(player.drain_usage_events(None, None).unwrap().len(), 2);
+    let snapshot = player.get_snapshot().unwrap();
+    assert_eq!(snapshot["cassette"]["replayed"], 2);
+    assert_eq!(snapshot["providerHealth"][0]["totalRequests"], 0);
+
+    let mut drifted = request.clone();
+    drifted["temperature"] = json!(0.7);
+    let miss = player.complete(drifted.clone(), None).await.unwrap_err();
+    assert!(miss.reason.starts_with("invalid_request:"));
+
+    player
+      .set_cassette(Some(json!({ "mode": "replay", "path": path, "match": "messages" })))
+      .unwrap();
+    let loose = player.complete(drifted, None).await.unwrap();
+    assert_eq!(loose["content"], "recorded");
+    let _ = std::fs::remove_file(&path);
+  }
+
+  #[tokio::test]
+  async fn response_cache_serves_repeated_prompts_from_disk() {
+    let mock = spawn_mock_provider(vec![MockReply {
+      model: "model-a",
+      status: 200,
+      content_type: "application/json",
+      body: json!({
+        "choices": [{ "message": { "content": "cached" }, "finish_reason": "stop" }],
+        "usage": { "prompt_tokens": 3, "completion_tokens": 1, "total_tokens": 4 }
+      })
+      .to_string(),
+    }])
+    .await;
+    let dir = std::env::temp_dir().join(format!("response-cache-{}", Uuid::new_v4()));
+    let config = json!({ "path": dir.to_string_lossy(), "maxEntries": 8 });
+    let request = json!({
+      "model": "model-a",
+      "temperature": 0.0,
+      "messages": [{ "role": "user", "content": "hi" }]
+    });
+    let mut provider = build_provider("primary", &["model-a"]);
+    provider.base_url = Some(mock.base_url.clone());
+
+    let fabric = ModelFabric::new();
+    fabric.load_providers(vec![json!(provider)]).unwrap();
+    fabric.configure_cache(Some(config.clone())).unwrap();
+    fabric.complete(request.clone(), None).await.unwrap();
+    let hit = fabric.complete(request.clone(), None).await.unwrap();
+    assert_eq!(hit["content"], "cached");
+    let events = fabric.drain_usage_events(None, None).unwrap();
+    assert_eq!(events[0]["cacheHit"], false);
+    assert_eq!(events[1]["cacheHit"], true);
+    assert_eq!(events[1]["costUsd"], 0.0);
+
+    let chunks = collect_stream(&fabric, request.clone()).await;
+    let types: Vec<&str> = chunks
+      .iter()
+      .map(|chunk| chunk["type"].as_str().unwrap())
+      .collect();
+    assert_eq!(types, ["content", "usage", "done"]);
+    assert_eq!(chunks[0]["content"], "cached");
+
+    let mut sampled = request.clone();
+    sampled["temperature"] = json!(0.7);
+    fabric.complete(sampled, None).await.unwrap();
+    assert_eq!(mock.requests.lock().unwrap().len(), 2);
+
+    let restarted = ModelFabric::new();
+    restarted.load_providers(vec![json!(provider)]).unwrap();
+    restarted.configure_cache(Some(config)).unwrap();
+    restarted.complete(request, None).await.unwrap();
+    assert_eq!(mock.requests.lock().unwrap().len(), 2);
+    let status = &restarted.get_snapshot().unwrap()["responseCache"];
+    assert_eq!(status["hits"], 1);
+    let _ = std::fs::remove_dir_all(&dir);
+  }
+
+  /// Answers every request with `frames` and then stalls without closing the body.
+  async fn spawn_stalling_provider(frames: &'static str) -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+      while let Ok((mut socket, _)) = listener.accept().await {
+        tokio::spawn(async move {
+          let mut buffer = [0u8; 4096];
+          let _ = socket.read(&mut buffer).await;
+          let head = "HTTP/1.1 200 OK\r\ncontent-type: text/event-stream\r\n\r\n";
+          let _ = socket.write_all(format!("{head}{frames}").as_bytes()).await;
+          tokio::time::sleep(Duration::from_secs(60)).await;
+        });
+      }
+    });
+    format!("http://{address}")
+  }
+
+  #[tokio::test]
+  async fn cancelling_a_stream_records_partial_usage() {
+    let base_url = spawn_stalling_provider(concat!(
+      "data: {\"choices\":[{\"delta\":{\"content\":\"par\"}}]}\n\n",
+      "data: {\"choices\":[],\"usage\":{\"prompt_tokens\":5,\"completion_tokens\":1,\"total_tokens\":6}}\n\n",
+    ))
+    .await;
+    let mut provider = build_provider("primary", &["model-a"]);
+    provider.base_url = Some(base_url);
+    let fabric = ModelFabric::new();
+    fabric.load_providers(vec![json!(provider)]).unwrap();
+    let request = json!({ "model": "model-a", "messages": [{ "role": "user", "content": "hi" }] });
+
+    let handle = fabric.stream(request.clone(), None).await.unwrap();
+    assert_eq!(handle.next().await.unwrap().unwrap()["type"], "content");
+    assert_eq!(handle.next().await.unwrap().unwrap()["type"], "usage");
+    handle.cancel();
+    let last = handle.next().await.unwrap().unwrap();
+    assert_eq!(last["type"], "cancelled");
+    assert_eq!(last["usage"]["totalTokens"], 6);
+    assert!(handle.next().await.unwrap().is_none());
+
+    let events = fabric.drain_usage_events(None, None).unwrap();
+    assert_eq!(events[0]["cancelled"], true);
+    assert_eq!(events[0]["outputTokens"], 1);
+
+    let fabric = Arc::new(fabric);
+    let pending = tokio::spawn({
+      let fabric = fabric.clone();
+      async move {
+        fabric
+          .complete(request, Some(json!({ "requestId": "req-1" })))
+          .await
+      }
+    });
+    while !fabric.cancel("req-1".to_string()).unwrap() {
+      tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+    let error = pending.await.unwrap().unwrap_err();
+    assert!(error.reason.starts_with("cancelled:"));
+    assert!(!fabric.cancel("req-1".to_string()).unwrap());
+  }
+
+  #[tokio::test]
+  async fn provider_limits_queue_requests_in_order_and_time_out() {
+    let base_url = spawn_stalling_provider(concat!(
+      "data: {\"choices\":[{\"delta\":{\"content\":\"par\"}}]}\n\n",
+      "data: {\"choices\":[],\"usage\":{\"prompt_tokens\":5,\"completion_tokens\":1,\"total_tokens\":6}}\n\n",
+    ))
+    .await;
+    let mut provider = build_provider("primary", &["model-a"]);
+    provider.base_url = Some(base_url);
+    provider.limits = Some(RateLimits {
+      max_in_flight: Some(1),
+      ..RateLimits::default()
+    });
+    provider.max_queue_wait_ms = Some(200);
+    let fabric = Arc::new(ModelFabric::new());
+    fabric.load_providers(vec![json!(provider)]).unwrap();
+    let request = json!({ "model": "model-a", "messages": [{ "role": "user", "content": "hi" }] });
+
+    let first = fabric.stream(request.clone(), None).await.unwrap();
+    assert_eq!(first.next().await.unwrap().unwrap()["type"], "content");
+
+    let error = fabric.complete(request.clone(), None).await.unwrap_err();
+    assert!(error.reason.starts_with("queue_timeout:"), "{}", error.reason);
+
+    let second = fabric.stream(request, None).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let snapshot = fabric.get_snapshot().unwrap();
+    assert_eq!(snapshot["limiters"][0]["key"], "primary");
+    assert_eq!(snapshot["limiters"][0]["inFlight"], 1);
+    assert_eq!(snapshot["limiters"][0]["queued"], 1);
+
+    first.cancel();
+    assert_eq!(second.next().await.unwrap().unwrap()["type"], "content");
+    assert_eq!(second.next().await.unwrap().unwrap()["type"], "usage");
+    second.cancel();
+    while second.next().await.unwrap().is_some() {}
+
+    let events = fabric.drain_usage_events(None, None).unwrap();
+    assert_eq!(events.len(), 2);
+    assert_eq!(events[0]["queueMs"], 0);
+    assert!(events[1]["queueMs"].as_u64().unwrap() >= 50);
+    assert_eq!(fabric.get_snapshot().unwrap()["limiters"][0]["inFlight"], 0);
   }
 }
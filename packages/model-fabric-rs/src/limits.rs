@@ -0,0 +1,238 @@
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::Instant;
+
+use crate::error::{ProviderError, ProviderErrorKind};
+use crate::ProviderResult;
+
+const WINDOW: Duration = Duration::from_secs(60);
+
+/// Throughput limits for a provider, or for one of its models.
+#[derive(Clone, Debug, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct RateLimits {
+  pub max_in_flight: Option<u32>,
+  pub requests_per_minute: Option<u32>,
+  pub tokens_per_minute: Option<u64>,
+}
+
+impl RateLimits {
+  fn is_empty(&self) -> bool {
+    self.max_in_flight.is_none()
+      && self.requests_per_minute.is_none()
+      && self.tokens_per_minute.is_none()
+  }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LimiterSnapshot {
+  /// `providerId`, or `providerId/modelId` for a per-model limit.
+  pub key: String,
+  pub in_flight: usize,
+  pub queued: usize,
+  pub requests_last_minute: usize,
+  pub tokens_last_minute: u64,
+}
+
+/// Admission control for one provider or model. Waiters pass a FIFO gate one at a time,
+/// so a request never overtakes one that queued before it.
+pub struct Limiter {
+  key: String,
+  limits: RateLimits,
+  slots: Option<Arc<Semaphore>>,
+  gate: tokio::sync::Mutex<()>,
+  window: Mutex<Window>,
+  queued: AtomicUsize,
+}
+
+#[derive(Default)]
+struct Window {
+  next_id: u64,
+  /// Requests admitted in the last minute: (id, admitted at, tokens).
+  entries: VecDeque<(u64, Instant, u64)>,
+}
+
+impl Window {
+  fn prune(&mut self, now: Instant) {
+    while self
+      .entries
+      .front()
+      .is_some_and(|(_, at, _)| now.duration_since(*at) >= WINDOW)
+    {
+      self.entries.pop_front();
+    }
+  }
+
+  fn tokens(&self) -> u64 {
+    self.entries.iter().map(|(_, _, tokens)| tokens).sum()
+  }
+
+  /// Admits the request, or returns how long until the oldest entry leaves the window.
+  /// A request larger than the whole token budget is admitted into an empty window.
+  fn reserve(&mut self, limits: &RateLimits, tokens: u64, now: Instant) -> Result<u64, Duration> {
+    self.prune(now);
+    let requests_full = limits
+      .requests_per_minute
+      .is_some_and(|limit| self.entries.len() >= limit as usize);
+    let tokens_full = limits.tokens_per_minute.is_some_and(|limit| {
+      !self.entries.is_empty() && self.tokens().saturating_add(tokens) > limit
+    });
+    if requests_full || tokens_full {
+      let oldest = self.entries.front().map_or(now, |(_, at, _)| *at);
+      return Err((oldest + WINDOW).saturating_duration_since(now));
+    }
+    let id = self.next_id;
+    self.next_id += 1;
+    self.entries.push_back((id, now, tokens));
+    Ok(id)
+  }
+}
+
+impl Limiter {
+  /// `None` when the limits are all unset.
+  pub fn new(key: String, limits: &RateLimits) -> Option<Arc<Self>> {
+    if limits.is_empty() {
+      return None;
+    }
+    Some(Arc::new(Self {
+      key,
+      limits: limits.clone(),
+      slots: limits
+        .max_in_flight
+        .map(|max| Arc::new(Semaphore::new(max.max(1) as usize))),
+      gate: tokio::sync::Mutex::new(()),
+      window: Mutex::new(Window::default()),
+      queued: AtomicUsize::new(0),
+    }))
+  }
+
+  pub fn limits(&self) -> &RateLimits {
+    &self.limits
+  }
+
+  async fn acquire(self: &Arc<Self>, tokens: u64) -> Permit {
+    self.queued.fetch_add(1, Ordering::Relaxed);
+    let _queued = Dequeue(&self.queued);
+    let _turn = self.gate.lock().await;
+    let slot = match &self.slots {
+      Some(slots) => slots.clone().acquire_owned().await.ok(),
+      None => None,
+    };
+    loop {
+      let reserved = self.window().reserve(&self.limits, tokens, Instant::now());
+      match reserved {
+        Ok(entry) => {
+          return Permit {
+            limiter: self.clone(),
+            entry,
+            _slot: slot,
+          }
+        }
+        Err(wait) => tokio::time::sleep(wait).await,
+      }
+    }
+  }
+
+  fn window(&self) -> MutexGuard<'_, Window> {
+    self.window.lock().unwrap_or_else(PoisonError::into_inner)
+  }
+
+  pub fn snapshot(&self) -> LimiterSnapshot {
+    let (requests, tokens) = {
+      let mut window = self.window();
+      window.prune(Instant::now());
+      (window.entries.len(), window.tokens())
+    };
+    let in_flight = match (&self.slots, self.limits.max_in_flight) {
+      (Some(slots), Some(max)) => (max.max(1) as usize).saturating_sub(slots.available_permits()),
+      _ => 0,
+    };
+    LimiterSnapshot {
+      key: self.key.clone(),
+      in_flight,
+      queued: self.queued.load(Ordering::Relaxed),
+      requests_last_minute: requests,
+      tokens_last_minute: tokens,
+    }
+  }
+}
+
+struct Dequeue<'a>(&'a AtomicUsize);
+
+impl Drop for Dequeue<'_> {
+  fn drop(&mut self) {
+    self.0.fetch_sub(1, Ordering::Relaxed);
+  }
+}
+
+/// An in-flight slot (if limited) plus the request's entry in the rate window.
+struct Permit {
+  limiter: Arc<Limiter>,
+  entry: u64,
+  _slot: Option<OwnedSemaphorePermit>,
+}
+
+/// Capacity held by one provider call; slots are released on drop.
+pub struct Admission {
+  permits: Vec<Permit>,
+  pub queue_ms: u64,
+}
+
+impl Admission {
+  /// Replaces the token estimate with what the provider actually reported.
+  pub fn settle(&self, tokens: u64) {
+    for permit in &self.permits {
+      let mut window = permit.limiter.window();
+      if let Some(entry) = window.entries.iter_mut().find(|entry| entry.0 == permit.entry) {
+        entry.2 = tokens;
+      }
+    }
+  }
+}
+
+/// Queues for every limiter in order (model before provider). Waiting longer than
+/// `max_wait_ms` fails with `queue_timeout` so the caller can fall back elsewhere.
+pub async fn admit(
+  limiters: Vec<Arc<Limiter>>,
+  tokens: u64,
+  max_wait_ms: Option<u64>,
+) -> ProviderResult<Admission> {
+  let start = Instant::now();
+  if limiters.is_empty() {
+    return Ok(Admission {
+      permits: Vec::new(),
+      queue_ms: 0,
+    });
+  }
+  let keys: Vec<String> = limiters.iter().map(|limiter| limiter.key.clone()).collect();
+  let queue = async {
+    let mut permits = Vec::with_capacity(limiters.len());
+    for limiter in &limiters {
+      permits.push(limiter.acquire(tokens).await);
+    }
+    permits
+  };
+  let permits = match max_wait_ms {
+    Some(max_wait_ms) => tokio::time::timeout(Duration::from_millis(max_wait_ms), queue)
+      .await
+      .map_err(|_| {
+        ProviderError::new(
+          ProviderErrorKind::QueueTimeout,
+          format!(
+            "Waited more than {max_wait_ms}ms for capacity on {}",
+            keys.join(", ")
+          ),
+        )
+      })?,
+    None => queue.await,
+  };
+  Ok(Admission {
+    permits,
+    queue_ms: start.elapsed().as_millis() as u64,
+  })
+}
@@ -1,17 +1,25 @@
+import { randomUUID } from "node:crypto";
+
 import {
   loadNativeBinding,
   type NativeBindingLoadResult,
   resolvePackageRoot,
 } from "@ku0/native-bindings/node";
 
+import { toModelFabricError } from "./errors";
 import type {
+  BudgetRule,
+  CassetteConfig,
   CompletionRequest,
   CompletionResponse,
+  CredentialStoreConfig,
   ModelFabricContext,
   ModelFabricSnapshot,
+  ModelPricing,
   ModelStreamHandle,
   ModelUsageEvent,
   ProviderConfigRecord,
+  ResponseCacheConfig,
   RouteRule,
 } from "./types";
 
@@ -21,8 +29,16 @@ const NATIVE_PATH_ENV = "KU0_MODEL_FABRIC_NATIVE_PATH";
 interface NativeModelFabric {
   loadProviders(records: ProviderConfigRecord[]): void;
   loadRoutes(routes: RouteRule[]): void;
+  loadPricing(entries: ModelPricing[]): void;
+  loadBudgets(rules: BudgetRule[]): void;
+  configureCache(config: ResponseCacheConfig | null): void;
+  configureCredentials(config: CredentialStoreConfig | null): Promise<void>;
+  rotateCredential(authRef: string, value: string): Promise<void>;
+  setCassette(config: CassetteConfig | null): void;
   complete(request: CompletionRequest, context?: ModelFabricContext): Promise<CompletionResponse>;
-  stream(request: CompletionRequest, context?: ModelFabricContext): ModelStreamHandle;
+  stream(request: CompletionRequest, context?: ModelFabricContext): Promise<ModelStreamHandle>;
+  pullModel(providerId: string, modelId: string): Promise<Record<string, unknown>>;
+  cancel(requestId: string): boolean;
   getSnapshot(): ModelFabricSnapshot;
   drainUsageEvents(after?: number, limit?: number): ModelUsageEvent[];
   reset(): void;
@@ -86,6 +102,15 @@ function loadBinding(): NativeBinding {
   return result.binding;
 }
 
+/** Rejects with a `ModelFabricError` when the native error carries a provider error code. */
+async function withErrorCode<T>(call: () => Promise<T>): Promise<T> {
+  try {
+    return await call();
+  } catch (error) {
+    throw toModelFabricError(error);
+  }
+}
+
 export function isModelFabricAvailable(): boolean {
   try {
     loadBinding();
@@ -111,12 +136,67 @@ export class ModelFabric {
     this.fabric.loadRoutes(routes);
   }
 
-  complete(request: CompletionRequest, context?: ModelFabricContext): Promise<CompletionResponse> {
-    return this.fabric.complete(request, context);
+  loadPricing(entries: ModelPricing[]): void {
+    this.fabric.loadPricing(entries);
+  }
+
+  loadBudgets(rules: BudgetRule[]): void {
+    this.fabric.loadBudgets(rules);
+  }
+
+  configureCache(config: ResponseCacheConfig | null): void {
+    this.fabric.configureCache(config);
+  }
+
+  configureCredentials(config: CredentialStoreConfig | null): Promise<void> {
+    return this.fabric.configureCredentials(config);
+  }
+
+  /** Stores a new value for a `secret:` auth ref; the next request uses it. */
+  rotateCredential(authRef: string, value: string): Promise<void> {
+    return this.fabric.rotateCredential(authRef, value);
+  }
+
+  setCassette(config: CassetteConfig | null): void {
+    this.fabric.setCassette(config);
+  }
+
+  /**
+   * Aborting `options.signal` cancels the request through its `requestId`, which is
+   * generated when the context does not carry one.
+   */
+  async complete(
+    request: CompletionRequest,
+    context?: ModelFabricContext,
+    options?: { signal?: AbortSignal }
+  ): Promise<CompletionResponse> {
+    const signal = options?.signal;
+    if (!signal) {
+      return withErrorCode(() => this.fabric.complete(request, context));
+    }
+    signal.throwIfAborted();
+    const requestId = context?.requestId ?? randomUUID();
+    const onAbort = () => {
+      this.fabric.cancel(requestId);
+    };
+    signal.addEventListener("abort", onAbort, { once: true });
+    try {
+      return await withErrorCode(() => this.fabric.complete(request, { ...context, requestId }));
+    } finally {
+      signal.removeEventListener("abort", onAbort);
+    }
+  }
+
+  cancel(requestId: string): boolean {
+    return this.fabric.cancel(requestId);
+  }
+
+  stream(request: CompletionRequest, context?: ModelFabricContext): Promise<ModelStreamHandle> {
+    return withErrorCode(() => this.fabric.stream(request, context));
   }
 
-  stream(request: CompletionRequest, context?: ModelFabricContext): ModelStreamHandle {
-    return this.fabric.stream(request, context);
+  pullModel(providerId: string, modelId: string): Promise<Record<string, unknown>> {
+    return withErrorCode(() => this.fabric.pullModel(providerId, modelId));
   }
 
   getSnapshot(): ModelFabricSnapshot {
@@ -132,4 +212,5 @@ export class ModelFabric {
   }
 }
 
+export { ModelFabricError } from "./errors";
 export type { StreamChunk } from "./types";
@@ -0,0 +1,354 @@
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::content::{ContentPart, ImageSource};
+use crate::error::ProviderError;
+use crate::structured::ResponseFormatType;
+use crate::{
+  ensure_success, format_openai_tools, invalid_header, resolve_api_key, resolve_base_url,
+  CompletionRequest, CompletionResponse, Message, ProviderConfigRecord, ProviderResult,
+  StreamChunk, TokenUsage, ToolCall, DEFAULT_TIMEOUT_MS,
+};
+
+/// Native `/api/chat` call, which unlike Ollama's OpenAI-compatible endpoint honours
+/// `keep_alive` and reports load/eval counters.
+pub async fn call(
+  provider: &ProviderConfigRecord,
+  model_id: &str,
+  request: &CompletionRequest,
+) -> ProviderResult<CompletionResponse> {
+  let start = tokio::time::Instant::now();
+  let response = send(provider, model_id, request, false).await?;
+  let parsed: Value = response.json().await.map_err(ProviderError::from_reqwest)?;
+  let mut completion = parse_response(&parsed, model_id);
+  completion.latency_ms = start.elapsed().as_millis() as u64;
+  Ok(completion)
+}
+
+/// Opens a streaming chat; Ollama answers with NDJSON rather than SSE.
+pub async fn open_stream(
+  provider: &ProviderConfigRecord,
+  model_id: &str,
+  request: &CompletionRequest,
+) -> ProviderResult<reqwest::Response> {
+  send(provider, model_id, request, true).await
+}
+
+/// Pulls `model_id` onto the Ollama host and returns the final status object.
+pub async fn pull_model(provider: &ProviderConfigRecord, model_id: &str) -> ProviderResult<Value> {
+  let client = reqwest::Client::new();
+  let response = client
+    .post(format!("{}/api/pull", resolve_base_url(provider)))
+    .headers(headers(provider).await?)
+    .json(&json!({ "model": model_id, "stream": false }))
+    .send()
+    .await
+    .map_err(ProviderError::from_reqwest)?;
+  let response = ensure_success(response).await?;
+  response.json().await.map_err(ProviderError::from_reqwest)
+}
+
+async fn send(
+  provider: &ProviderConfigRecord,
+  model_id: &str,
+  request: &CompletionRequest,
+  stream: bool,
+) -> ProviderResult<reqwest::Response> {
+  let timeout_ms = request
+    .timeout_ms
+    .or(provider.timeout_ms)
+    .unwrap_or(DEFAULT_TIMEOUT_MS);
+  let client = reqwest::Client::builder()
+    .timeout(Duration::from_millis(timeout_ms))
+    .build()
+    .map_err(ProviderError::from_reqwest)?;
+
+  let response = client
+    .post(format!("{}/api/chat", resolve_base_url(provider)))
+    .headers(headers(provider).await?)
+    .json(&build_body(provider, model_id, request, stream))
+    .send()
+    .await
+    .map_err(ProviderError::from_reqwest)?;
+  ensure_success(response).await
+}
+
+/// A local Ollama needs no key, so `Authorization` is only sent when `authRef` is set,
+/// e.g. for a host behind an authenticating proxy.
+pub(crate) async fn headers(
+  provider: &ProviderConfigRecord,
+) -> ProviderResult<reqwest::header::HeaderMap> {
+  let mut headers = reqwest::header::HeaderMap::new();
+  headers.insert(
+    reqwest::header::CONTENT_TYPE,
+    reqwest::header::HeaderValue::from_static("application/json"),
+  );
+  if !provider.auth_ref.trim().is_empty() {
+    let api_key = resolve_api_key(provider).await?;
+    headers.insert(
+      reqwest::header::AUTHORIZATION,
+      reqwest::header::HeaderValue::from_str(&format!("Bearer {api_key}"))
+        .map_err(invalid_header)?,
+    );
+  }
+  Ok(headers)
+}
+
+fn build_body(
+  provider: &ProviderConfigRecord,
+  model_id: &str,
+  request: &CompletionRequest,
+  stream: bool,
+) -> Value {
+  let mut body = json!({
+    "model": model_id,
+    "messages": format_messages(&request.messages),
+    "stream": stream,
+  });
+
+  if let Some(tools) = &request.tools {
+    body["tools"] = json!(format_openai_tools(tools));
+  }
+  if let Some(keep_alive) = &provider.keep_alive {
+    body["keep_alive"] = json!(keep_alive);
+  }
+  if let Some(format) = &request.response_format {
+    body["format"] = match format.format_type {
+      ResponseFormatType::JsonObject => json!("json"),
+      ResponseFormatType::JsonSchema => json!(format.schema),
+    };
+  }
+
+  let mut options = json!({});
+  if let Some(temperature) = request.temperature {
+    options["temperature"] = json!(temperature);
+  }
+  if let Some(top_p) = request.top_p {
+    options["top_p"] = json!(top_p);
+  }
+  if let Some(max_tokens) = request.max_tokens {
+    options["num_predict"] = json!(max_tokens);
+  }
+  if let Some(stop_sequences) = &request.stop_sequences {
+    options["stop"] = json!(stop_sequences);
+  }
+  if options.as_object().is_some_and(|options| !options.is_empty()) {
+    body["options"] = options;
+  }
+
+  body
+}
+
+/// Ollama only accepts inline base64 images, so URL images are dropped. Tool results
+/// become `tool` messages carrying the function name, which Ollama uses instead of ids.
+fn format_messages(messages: &[Message]) -> Vec<Value> {
+  let mut formatted: Vec<Value> = Vec::new();
+  let mut call_names: HashMap<String, String> = HashMap::new();
+
+  for message in messages {
+    if message.role == "tool" {
+      let id = message.tool_call_id.clone().unwrap_or_default();
+      formatted.push(tool_message(&call_names, &id, &message.content.text()));
+      continue;
+    }
+
+    let mut images: Vec<String> = Vec::new();
+    let mut thinking = String::new();
+    let mut tool_calls: Vec<Value> = Vec::new();
+    for part in message.content.parts() {
+      match part {
+        ContentPart::Image {
+          source: ImageSource::Base64 { data, .. },
+        } => images.push(data),
+        ContentPart::ToolUse { id, name, input } => {
+          call_names.insert(id, name.clone());
+          tool_calls.push(json!({ "function": { "name": name, "arguments": input } }));
+        }
+        ContentPart::ToolResult {
+          tool_use_id,
+          content,
+          ..
+        } => formatted.push(tool_message(&call_names, &tool_use_id, &content)),
+        ContentPart::Thinking { thinking: text, .. } => thinking.push_str(&text),
+        ContentPart::Text { .. } | ContentPart::Image { .. } => {}
+      }
+    }
+    for call in message.tool_calls.iter().flatten() {
+      call_names.insert(call.id.clone(), call.name.clone());
+      let arguments = serde_json::from_str::<Value>(&call.arguments).unwrap_or_else(|_| json!({}));
+      tool_calls.push(json!({ "function": { "name": call.name, "arguments": arguments } }));
+    }
+
+    let content = message.content.text();
+    if content.is_empty() && images.is_empty() && tool_calls.is_empty() && thinking.is_empty() {
+      continue;
+    }
+    let mut entry = json!({ "role": message.role, "content": content });
+    if !images.is_empty() {
+      entry["images"] = json!(images);
+    }
+    if !thinking.is_empty() {
+      entry["thinking"] = json!(thinking);
+    }
+    if !tool_calls.is_empty() {
+      entry["tool_calls"] = json!(tool_calls);
+    }
+    formatted.push(entry);
+  }
+
+  formatted
+}
+
+fn tool_message(call_names: &HashMap<String, String>, id: &str, content: &str) -> Value {
+  let mut message = json!({ "role": "tool", "content": content });
+  if let Some(name) = call_names.get(id) {
+    message["tool_name"] = json!(name);
+  }
+  message
+}
+
+pub fn parse_response(parsed: &Value, model_id: &str) -> CompletionResponse {
+  let message = parsed.get("message");
+  let text = |key: &str| {
+    message
+      .and_then(|message| message.get(key))
+      .and_then(|value| value.as_str())
+      .unwrap_or_default()
+      .to_string()
+  };
+  let content = text("content");
+  let thinking = text("thinking");
+  let tool_calls = parse_tool_calls(message);
+
+  let mut parts = Vec::new();
+  if !thinking.is_empty() {
+    parts.push(ContentPart::Thinking {
+      thinking,
+      signature: None,
+    });
+  }
+  parts.extend(crate::assistant_parts(&content, Some(&tool_calls)));
+
+  CompletionResponse {
+    content,
+    finish_reason: map_finish_reason(
+      parsed.get("done_reason").and_then(|value| value.as_str()),
+      !tool_calls.is_empty(),
+    ),
+    tool_calls: Some(tool_calls).filter(|calls| !calls.is_empty()),
+    content_parts: Some(parts),
+    parsed: None,
+    usage: parse_usage(parsed),
+    model: parsed
+      .get("model")
+      .and_then(|value| value.as_str())
+      .unwrap_or(model_id)
+      .to_string(),
+    latency_ms: 0,
+    safety_ratings: None,
+  }
+}
+
+/// Decodes one NDJSON line of a streaming chat. Usage and the finish reason only arrive
+/// on the final `done` object.
+pub fn parse_stream_line(line: &str) -> Vec<StreamChunk> {
+  let line = line.trim();
+  if line.is_empty() {
+    return Vec::new();
+  }
+  let Ok(parsed) = serde_json::from_str::<Value>(line) else {
+    return Vec::new();
+  };
+  if let Some(error) = parsed.get("error").and_then(|value| value.as_str()) {
+    return vec![StreamChunk {
+      error: Some(error.to_string()),
+      ..StreamChunk::of("error")
+    }];
+  }
+
+  let message = parsed.get("message");
+  let mut chunks = Vec::new();
+  for (key, chunk_type) in [("thinking", "thinking"), ("content", "content")] {
+    if let Some(text) = message
+      .and_then(|message| message.get(key))
+      .and_then(|value| value.as_str())
+      .filter(|text| !text.is_empty())
+    {
+      chunks.push(StreamChunk {
+        content: Some(text.to_string()),
+        ..StreamChunk::of(chunk_type)
+      });
+    }
+  }
+  let tool_calls = parse_tool_calls(message);
+  let has_tool_calls = !tool_calls.is_empty();
+  chunks.extend(tool_calls.into_iter().map(|tool_call| StreamChunk {
+    tool_call: Some(tool_call),
+    ..StreamChunk::of("tool_call")
+  }));
+
+  if parsed.get("done").and_then(|value| value.as_bool()) == Some(true) {
+    chunks.push(StreamChunk {
+      usage: Some(parse_usage(&parsed)),
+      ..StreamChunk::of("usage")
+    });
+    chunks.push(StreamChunk {
+      finish_reason: Some(map_finish_reason(
+        parsed.get("done_reason").and_then(|value| value.as_str()),
+        has_tool_calls,
+      )),
+      ..StreamChunk::of("done")
+    });
+  }
+
+  chunks
+}
+
+fn parse_tool_calls(message: Option<&Value>) -> Vec<ToolCall> {
+  message
+    .and_then(|message| message.get("tool_calls"))
+    .and_then(|value| value.as_array())
+    .map(|calls| {
+      calls
+        .iter()
+        .filter_map(|call| {
+          let function = call.get("function")?;
+          Some(ToolCall {
+            id: call
+              .get("id")
+              .and_then(|value| value.as_str())
+              .map(str::to_string)
+              .unwrap_or_else(|| Uuid::new_v4().to_string()),
+            name: function.get("name")?.as_str()?.to_string(),
+            arguments: function
+              .get("arguments")
+              .map(|arguments| arguments.to_string())
+              .unwrap_or_else(|| "{}".to_string()),
+          })
+        })
+        .collect()
+    })
+    .unwrap_or_default()
+}
+
+fn parse_usage(value: &Value) -> TokenUsage {
+  let read = |key: &str| value.get(key).and_then(|value| value.as_u64()).unwrap_or(0);
+  let input = read("prompt_eval_count");
+  let output = read("eval_count");
+  TokenUsage {
+    input_tokens: input,
+    output_tokens: output,
+    total_tokens: input + output,
+    cached_input_tokens: None,
+  }
+}
+
+pub fn map_finish_reason(reason: Option<&str>, has_tool_calls: bool) -> String {
+  match reason {
+    Some("length") => "length".to_string(),
+    _ if has_tool_calls => "tool_calls".to_string(),
+    _ => "stop".to_string(),
+  }
+}
@@ -0,0 +1,94 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+use crate::TokenUsage;
+
+const TOKENS_PER_MILLION: f64 = 1_000_000.0;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ModelPricing {
+  pub model_id: String,
+  pub input_per_million_usd: f64,
+  pub output_per_million_usd: f64,
+  pub cached_input_per_million_usd: Option<f64>,
+  /// Epoch milliseconds from which this price applies; `None` means "always".
+  pub effective_from: Option<i64>,
+}
+
+/// Per-model price history. Each model keeps its entries ordered newest first so the
+/// lookup for an event time is the first entry that is already in effect.
+#[derive(Default)]
+pub struct PricingCatalog {
+  by_model: HashMap<String, Vec<ModelPricing>>,
+}
+
+impl PricingCatalog {
+  pub fn load(entries: Vec<ModelPricing>) -> Result<Self, String> {
+    let mut seen = HashSet::new();
+    let mut by_model: HashMap<String, Vec<ModelPricing>> = HashMap::new();
+
+    for entry in entries {
+      if entry.model_id.trim().is_empty() {
+        return Err("pricing modelId must be non-empty".to_string());
+      }
+      let prices = [
+        Some(entry.input_per_million_usd),
+        Some(entry.output_per_million_usd),
+        entry.cached_input_per_million_usd,
+      ];
+      if prices
+        .iter()
+        .flatten()
+        .any(|price| !price.is_finite() || *price < 0.0)
+      {
+        return Err(format!("pricing for {} must be non-negative", entry.model_id));
+      }
+      if !seen.insert((entry.model_id.clone(), entry.effective_from)) {
+        return Err(format!(
+          "duplicate pricing for {} with the same effectiveFrom",
+          entry.model_id
+        ));
+      }
+      by_model.entry(entry.model_id.clone()).or_default().push(entry);
+    }
+
+    for prices in by_model.values_mut() {
+      prices.sort_by_key(|entry| std::cmp::Reverse(entry.effective_from));
+    }
+
+    Ok(Self { by_model })
+  }
+
+  pub fn entries(&self) -> Vec<ModelPricing> {
+    let mut entries: Vec<ModelPricing> = self.by_model.values().flatten().cloned().collect();
+    entries.sort_by(|a, b| {
+      a.model_id
+        .cmp(&b.model_id)
+        .then_with(|| a.effective_from.cmp(&b.effective_from))
+    });
+    entries
+  }
+
+  pub fn price_at(&self, model_id: &str, at: i64) -> Option<&ModelPricing> {
+    self
+      .by_model
+      .get(model_id)?
+      .iter()
+      .find(|entry| entry.effective_from.is_none_or(|from| from <= at))
+  }
+
+  pub fn cost_usd(&self, model_id: &str, usage: &TokenUsage, at: i64) -> Option<f64> {
+    let pricing = self.price_at(model_id, at)?;
+    let cached = usage.cached_input_tokens.unwrap_or(0).min(usage.input_tokens);
+    let uncached = usage.input_tokens - cached;
+    let cached_rate = pricing
+      .cached_input_per_million_usd
+      .unwrap_or(pricing.input_per_million_usd);
+
+    let cost = uncached as f64 * pricing.input_per_million_usd
+      + cached as f64 * cached_rate
+      + usage.output_tokens as f64 * pricing.output_per_million_usd;
+    Some(cost / TOKENS_PER_MILLION)
+  }
+}
@@ -0,0 +1,54 @@
+use rand::Rng;
+
+use crate::error::ProviderError;
+use crate::ProviderConfigRecord;
+
+const DEFAULT_MAX_RETRIES: u32 = 3;
+const DEFAULT_RETRY_BASE_DELAY_MS: u64 = 500;
+const DEFAULT_RETRY_MAX_DELAY_MS: u64 = 20_000;
+
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+  pub max_retries: u32,
+  pub base_delay_ms: u64,
+  pub max_delay_ms: u64,
+}
+
+impl RetryPolicy {
+  pub fn for_provider(provider: &ProviderConfigRecord) -> Self {
+    let base_delay_ms = provider
+      .retry_base_delay_ms
+      .unwrap_or(DEFAULT_RETRY_BASE_DELAY_MS);
+    Self {
+      max_retries: provider.max_retries.unwrap_or(DEFAULT_MAX_RETRIES),
+      base_delay_ms,
+      max_delay_ms: provider
+        .retry_max_delay_ms
+        .unwrap_or(DEFAULT_RETRY_MAX_DELAY_MS)
+        .max(base_delay_ms),
+    }
+  }
+
+  /// Returns how long to wait before retrying `error`, or `None` when the error
+  /// should be surfaced immediately. A `Retry-After` longer than `max_delay_ms`
+  /// also gives up so the caller can move on to a fallback model instead of stalling.
+  pub fn next_delay_ms(&self, attempt: u32, error: &ProviderError) -> Option<u64> {
+    if attempt >= self.max_retries || !error.is_retryable() {
+      return None;
+    }
+
+    if let Some(retry_after_ms) = error.retry_after_ms {
+      if retry_after_ms > self.max_delay_ms {
+        return None;
+      }
+      return Some(retry_after_ms);
+    }
+
+    let ceiling = self
+      .base_delay_ms
+      .saturating_mul(1u64 << attempt.min(20))
+      .min(self.max_delay_ms);
+    let half = ceiling / 2;
+    Some(half + rand::thread_rng().gen_range(0..=ceiling - half))
+  }
+}
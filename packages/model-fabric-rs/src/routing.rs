@@ -0,0 +1,181 @@
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::fingerprint;
+use crate::{CompletionRequest, ModelRequestContext, RouteRule};
+
+/// Rough characters-per-token ratio used to size prompts before they are tokenized.
+const CHARS_PER_TOKEN: u64 = 4;
+/// Completion length assumed for cost ranking when the request sets no `maxTokens`.
+const DEFAULT_OUTPUT_ESTIMATE: u64 = 1_000;
+
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RouteStrategy {
+  /// `modelId` first, then `fallbackModelIds` in order.
+  #[default]
+  Priority,
+  /// Picks one of `arms` at random in proportion to its weight.
+  Weighted,
+  /// Orders the pool by the provider's median observed latency.
+  LowestLatency,
+  /// Orders the pool by estimated cost, dropping models whose context window is too small.
+  Cheapest,
+}
+
+/// One side of a weighted split; `armId` names it on usage events (defaults to the model).
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RouteArm {
+  pub model_id: String,
+  pub weight: f64,
+  pub arm_id: Option<String>,
+}
+
+/// Context field that pins a weighted split, so every request of e.g. one run lands
+/// on the same arm.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum StickyKey {
+  RunId,
+  WorkerId,
+  TaskType,
+}
+
+/// Outcome of routing, copied onto the request's usage events.
+#[derive(Clone, Debug, Default)]
+pub struct RouteDecision {
+  pub strategy: RouteStrategy,
+  pub arm: Option<String>,
+}
+
+/// What the strategies need to know about each model.
+pub trait RouteSignals {
+  fn latency_ms(&self, model_id: &str) -> Option<u64>;
+  fn context_window(&self, model_id: &str) -> Option<u64>;
+  /// Estimated USD cost of serving `input` prompt and `output` completion tokens.
+  fn cost_usd(&self, model_id: &str, input: u64, output: u64) -> Option<f64>;
+}
+
+pub fn validate(routes: &[RouteRule]) -> Result<(), String> {
+  for route in routes {
+    let arms = route.arms.as_deref().unwrap_or_default();
+    if arms
+      .iter()
+      .any(|arm| !arm.weight.is_finite() || arm.weight < 0.0)
+    {
+      return Err(format!("route {} has an arm with an invalid weight", route.rule_id));
+    }
+    if route.strategy == Some(RouteStrategy::Weighted)
+      && !arms.iter().any(|arm| arm.weight > 0.0)
+    {
+      return Err(format!(
+        "weighted route {} needs at least one arm with a positive weight",
+        route.rule_id
+      ));
+    }
+  }
+  Ok(())
+}
+
+/// Orders the candidate models of a matched route according to its strategy. The first
+/// entry is the model to try; the rest are fallbacks.
+pub fn plan(
+  route: &RouteRule,
+  request: &CompletionRequest,
+  context: Option<&ModelRequestContext>,
+  signals: &impl RouteSignals,
+) -> (Vec<String>, RouteDecision) {
+  let strategy = route.strategy.unwrap_or_default();
+  let mut pool = vec![route.model_id.clone()];
+  pool.extend(route.fallback_model_ids.iter().flatten().cloned());
+  pool.extend(route.arms.iter().flatten().map(|arm| arm.model_id.clone()));
+  let mut seen = std::collections::HashSet::new();
+  pool.retain(|model_id| seen.insert(model_id.clone()));
+
+  let mut arm = None;
+  match strategy {
+    RouteStrategy::Priority => {}
+    RouteStrategy::Weighted => {
+      let arms = route.arms.as_deref().unwrap_or_default();
+      if let Some(chosen) = pick_arm(route, arms, context) {
+        pool.retain(|model_id| model_id != &chosen.model_id);
+        pool.insert(0, chosen.model_id.clone());
+        arm = Some(chosen.arm_id.clone().unwrap_or_else(|| chosen.model_id.clone()));
+      }
+    }
+    RouteStrategy::LowestLatency => {
+      // Unmeasured models sort first so each gets measured once.
+      pool.sort_by_key(|model_id| signals.latency_ms(model_id).map_or((0, 0), |ms| (1, ms)));
+    }
+    RouteStrategy::Cheapest => {
+      let input = estimate_tokens(request);
+      let output = request.max_tokens.unwrap_or(DEFAULT_OUTPUT_ESTIMATE);
+      let required = input + request.max_tokens.unwrap_or(0);
+      pool.retain(|model_id| {
+        signals
+          .context_window(model_id)
+          .is_none_or(|window| window >= required)
+      });
+      pool.sort_by(|a, b| {
+        let cost = |model_id: &str| signals.cost_usd(model_id, input, output).unwrap_or(f64::MAX);
+        cost(a).total_cmp(&cost(b))
+      });
+    }
+  }
+
+  let arm = arm.or_else(|| pool.first().cloned());
+  (pool, RouteDecision { strategy, arm })
+}
+
+fn pick_arm<'a>(
+  route: &RouteRule,
+  arms: &'a [RouteArm],
+  context: Option<&ModelRequestContext>,
+) -> Option<&'a RouteArm> {
+  let total: f64 = arms.iter().map(|arm| arm.weight).sum();
+  if total <= 0.0 {
+    return None;
+  }
+  let sticky = route.sticky_key.and_then(|key| {
+    let context = context?;
+    match key {
+      StickyKey::RunId => context.run_id.clone(),
+      StickyKey::WorkerId => context.worker_id.clone(),
+      StickyKey::TaskType => context.task_type.clone(),
+    }
+  });
+  let fraction = match sticky {
+    Some(value) => {
+      let digest = fingerprint::hash(&json!([route.rule_id, value]));
+      let prefix = u64::from_str_radix(&digest[..16], 16).unwrap_or(0);
+      prefix as f64 / u64::MAX as f64
+    }
+    None => rand::random::<f64>(),
+  };
+
+  let mut threshold = fraction * total;
+  for arm in arms {
+    if threshold < arm.weight {
+      return Some(arm);
+    }
+    threshold -= arm.weight;
+  }
+  arms.iter().rev().find(|arm| arm.weight > 0.0)
+}
+
+/// Prompt size estimate from message text and tool definitions.
+pub fn estimate_tokens(request: &CompletionRequest) -> u64 {
+  let message_chars: usize = request
+    .messages
+    .iter()
+    .map(|message| message.content.text().len())
+    .sum();
+  let tool_chars: usize = request
+    .tools
+    .iter()
+    .flatten()
+    .map(|tool| tool.name.len() + tool.description.len() + tool.parameters.to_string().len())
+    .sum();
+  ((message_chars + tool_chars) as u64).div_ceil(CHARS_PER_TOKEN)
+}
@@ -0,0 +1,109 @@
+use json_schema_rs::SchemaViolation;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+const DEFAULT_FORMAT_NAME: &str = "structured_output";
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ResponseFormatType {
+  JsonObject,
+  JsonSchema,
+}
+
+/// Requested shape of the completion content. OpenAI-compatible providers receive it as
+/// `response_format`; Anthropic gets a single tool with the schema as its input schema
+/// and a forced `tool_choice`, whose input becomes the response content.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResponseFormat {
+  #[serde(rename = "type")]
+  pub format_type: ResponseFormatType,
+  pub name: Option<String>,
+  pub schema: Option<Value>,
+  pub strict: Option<bool>,
+  /// Retry once with the validation errors fed back when the output does not validate.
+  pub repair: Option<bool>,
+}
+
+impl ResponseFormat {
+  pub fn validate(&self) -> Result<(), String> {
+    if self.format_type == ResponseFormatType::JsonSchema
+      && !self.schema.as_ref().is_some_and(|schema| schema.is_object())
+    {
+      return Err("responseFormat json_schema requires a schema object".to_string());
+    }
+    if self.name.as_ref().is_some_and(|name| name.trim().is_empty()) {
+      return Err("responseFormat name must be non-empty".to_string());
+    }
+    Ok(())
+  }
+
+  pub fn name(&self) -> &str {
+    self.name.as_deref().unwrap_or(DEFAULT_FORMAT_NAME)
+  }
+
+  pub fn repair_enabled(&self) -> bool {
+    self.repair.unwrap_or(false)
+  }
+
+  pub fn openai_response_format(&self) -> Value {
+    match self.format_type {
+      ResponseFormatType::JsonObject => json!({ "type": "json_object" }),
+      ResponseFormatType::JsonSchema => {
+        let mut json_schema = json!({ "name": self.name(), "schema": self.schema });
+        if let Some(strict) = self.strict {
+          json_schema["strict"] = json!(strict);
+        }
+        json!({ "type": "json_schema", "json_schema": json_schema })
+      }
+    }
+  }
+
+  pub fn anthropic_tool(&self) -> Value {
+    json!({
+      "name": self.name(),
+      "description": "Respond by calling this tool with the final answer as its input.",
+      "input_schema": self.schema.clone().unwrap_or_else(|| json!({ "type": "object" })),
+    })
+  }
+
+  pub fn anthropic_tool_choice(&self) -> Value {
+    json!({ "type": "tool", "name": self.name() })
+  }
+
+  /// Parses `content` as JSON and checks it against the schema.
+  pub fn check(&self, content: &str) -> Result<Value, Vec<SchemaViolation>> {
+    let parsed: Value = serde_json::from_str(content.trim()).map_err(|error| {
+      vec![SchemaViolation {
+        path: "$".to_string(),
+        keyword: "json".to_string(),
+        message: format!("content is not valid JSON: {error}"),
+      }]
+    })?;
+
+    let violations = match (&self.format_type, &self.schema) {
+      (ResponseFormatType::JsonSchema, Some(schema)) => json_schema_rs::validate(schema, &parsed),
+      _ if !parsed.is_object() => vec![SchemaViolation {
+        path: "$".to_string(),
+        keyword: "type".to_string(),
+        message: "expected a JSON object".to_string(),
+      }],
+      _ => Vec::new(),
+    };
+
+    if violations.is_empty() {
+      Ok(parsed)
+    } else {
+      Err(violations)
+    }
+  }
+}
+
+pub fn describe_violations(violations: &[SchemaViolation]) -> String {
+  violations
+    .iter()
+    .map(|violation| format!("{} ({}): {}", violation.path, violation.keyword, violation.message))
+    .collect::<Vec<_>>()
+    .join("; ")
+}
@@ -1,16 +1,42 @@
 export type {
+  BudgetAction,
+  BudgetRule,
+  BudgetScope,
+  BudgetUsageSnapshot,
+  CassetteConfig,
+  CassetteMatch,
+  CassetteMode,
+  CassetteStatus,
   CompletionRequest,
   CompletionResponse,
+  ContentPart,
+  CredentialStoreConfig,
   FinishReason,
+  ImageSource,
+  LimiterSnapshot,
   Message,
   MessageRole,
   ModelFabricContext,
+  ModelFabricErrorCode,
   ModelFabricSnapshot,
+  ModelPricing,
   ModelStreamHandle,
   ModelUsageEvent,
+  ProviderApi,
+  ProviderCircuitState,
   ProviderConfigRecord,
+  ProviderHealthSnapshot,
   ProviderKind,
+  RateLimits,
+  ResponseCacheConfig,
+  ResponseCacheStatus,
+  ResponseFormat,
+  ResponseFormatType,
+  RouteArm,
   RouteRule,
+  RouteStrategy,
+  SafetyRating,
+  StickyKey,
   StreamChunk,
   StreamChunkType,
   TokenUsage,
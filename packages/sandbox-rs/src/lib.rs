@@ -292,6 +292,19 @@ impl Sandbox {
         Ok(result)
     }
 
+    /// Builds a command with piped stdio that runs under this sandbox's policy, for
+    /// long-lived children such as MCP servers. The caller spawns and owns the process,
+    /// so `timeoutMs`, `stdin` and `maxOutputBytes` do not apply.
+    pub fn command(
+        &self,
+        cmd: &str,
+        args: &[String],
+        options: &ExecOptions,
+    ) -> Result<std::process::Command, SandboxError> {
+        let request = self.build_exec_request(options)?;
+        PlatformExecutor::new().command(cmd, args, &request, &self.state.policy)
+    }
+
     #[napi]
     pub fn read(&self, path: String) -> NapiResult<Buffer> {
         let resolved = self.resolve_allowed_path(Path::new(&path)).map_err(to_napi_error)?;
@@ -494,18 +507,7 @@ pub(crate) fn run_command_with<F>(
 where
     F: FnOnce(&mut std::process::Command) -> Result<(), SandboxError>,
 {
-    let mut cmd = std::process::Command::new(command);
-    cmd.args(args);
-    if let Some(cwd) = &options.cwd {
-        cmd.current_dir(cwd);
-    }
-    for (key, value) in &options.env {
-        cmd.env(key, value);
-    }
-    cmd.stdin(std::process::Stdio::piped())
-        .stdout(std::process::Stdio::piped())
-        .stderr(std::process::Stdio::piped());
-
+    let mut cmd = build_command(command, args, options);
     configure(&mut cmd)?;
 
     let mut child = cmd.spawn()?;
@@ -535,6 +537,25 @@ where
     })
 }
 
+pub(crate) fn build_command(
+    command: &str,
+    args: &[String],
+    options: &ExecRequest,
+) -> std::process::Command {
+    let mut cmd = std::process::Command::new(command);
+    cmd.args(args);
+    if let Some(cwd) = &options.cwd {
+        cmd.current_dir(cwd);
+    }
+    for (key, value) in &options.env {
+        cmd.env(key, value);
+    }
+    cmd.stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped());
+    cmd
+}
+
 pub(crate) fn run_command(
     command: &str,
     args: &[String],
@@ -1,4 +1,7 @@
-use crate::{run_command_with, ExecRequest, ExecResult, NetworkAccess, SandboxError, SandboxPolicy};
+use crate::{
+    build_command, run_command_with, ExecRequest, ExecResult, NetworkAccess, SandboxError,
+    SandboxPolicy,
+};
 use std::collections::BTreeMap;
 use std::io;
 use std::os::unix::process::CommandExt;
@@ -36,6 +39,23 @@ impl PlatformExecutor {
             Ok(())
         })
     }
+
+    pub fn command(
+        &self,
+        command: &str,
+        args: &[String],
+        options: &ExecRequest,
+        policy: &SandboxPolicy,
+    ) -> Result<std::process::Command, SandboxError> {
+        let mut cmd = build_command(command, args, options);
+        let policy = policy.clone();
+        unsafe {
+            cmd.pre_exec(move || {
+                apply_sandbox_policy(&policy).map_err(|error| io::Error::other(error.to_string()))
+            });
+        }
+        Ok(cmd)
+    }
 }
 
 fn apply_sandbox_policy(policy: &SandboxPolicy) -> Result<(), SandboxError> {
@@ -1,7 +1,8 @@
 use std::path::Path;
 
 use crate::{
-    run_command, ExecRequest, ExecResult, NetworkAccess, SandboxError, SandboxPolicy,
+    build_command, run_command, ExecRequest, ExecResult, NetworkAccess, SandboxError,
+    SandboxPolicy,
 };
 
 const SANDBOX_EXECUTABLE: &str = "/usr/bin/sandbox-exec";
@@ -22,23 +23,42 @@ impl PlatformExecutor {
         options: &ExecRequest,
         policy: &SandboxPolicy,
     ) -> Result<ExecResult, SandboxError> {
-        if !Path::new(SANDBOX_EXECUTABLE).exists() {
-            return Err(SandboxError::ExecutionFailed(
-                "sandbox-exec not found at /usr/bin/sandbox-exec".to_string(),
-            ));
-        }
+        let seatbelt_args = seatbelt_args(command, args, policy)?;
+        run_command(SANDBOX_EXECUTABLE, &seatbelt_args, options)
+    }
+
+    pub fn command(
+        &self,
+        command: &str,
+        args: &[String],
+        options: &ExecRequest,
+        policy: &SandboxPolicy,
+    ) -> Result<std::process::Command, SandboxError> {
+        let seatbelt_args = seatbelt_args(command, args, policy)?;
+        Ok(build_command(SANDBOX_EXECUTABLE, &seatbelt_args, options))
+    }
+}
 
-        let (profile, params) = build_profile(policy);
-        let mut seatbelt_args = vec!["-p".to_string(), profile];
-        for (key, value) in params {
-            seatbelt_args.push(format!("-D{key}={}", value.to_string_lossy()));
-        }
-        seatbelt_args.push("--".to_string());
-        seatbelt_args.push(command.to_string());
-        seatbelt_args.extend(args.iter().cloned());
+fn seatbelt_args(
+    command: &str,
+    args: &[String],
+    policy: &SandboxPolicy,
+) -> Result<Vec<String>, SandboxError> {
+    if !Path::new(SANDBOX_EXECUTABLE).exists() {
+        return Err(SandboxError::ExecutionFailed(
+            "sandbox-exec not found at /usr/bin/sandbox-exec".to_string(),
+        ));
+    }
 
-        run_command(SANDBOX_EXECUTABLE, &seatbelt_args, options)
+    let (profile, params) = build_profile(policy);
+    let mut seatbelt_args = vec!["-p".to_string(), profile];
+    for (key, value) in params {
+        seatbelt_args.push(format!("-D{key}={}", value.to_string_lossy()));
     }
+    seatbelt_args.push("--".to_string());
+    seatbelt_args.push(command.to_string());
+    seatbelt_args.extend(args.iter().cloned());
+    Ok(seatbelt_args)
 }
 
 fn build_profile(policy: &SandboxPolicy) -> (String, Vec<(String, std::path::PathBuf)>) {
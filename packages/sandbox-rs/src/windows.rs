@@ -1,4 +1,4 @@
-use crate::{run_command, ExecRequest, ExecResult, SandboxError, SandboxPolicy};
+use crate::{build_command, run_command, ExecRequest, ExecResult, SandboxError, SandboxPolicy};
 
 pub struct PlatformExecutor;
 
@@ -16,4 +16,14 @@ impl PlatformExecutor {
     ) -> Result<ExecResult, SandboxError> {
         run_command(command, args, options)
     }
+
+    pub fn command(
+        &self,
+        command: &str,
+        args: &[String],
+        options: &ExecRequest,
+        _policy: &SandboxPolicy,
+    ) -> Result<std::process::Command, SandboxError> {
+        Ok(build_command(command, args, options))
+    }
 }
@@ -0,0 +1,314 @@
+use crate::types::{AuditChainReport, AuditSinkConfig, ToolAuditEvent};
+use persistence_store_rs::{ExportFilter, PersistenceConfig, PersistenceStore, ToolEvent};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::{HashSet, VecDeque};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+
+/// The `prevHash` of the first event in a chain.
+pub(crate) const GENESIS_HASH: &str =
+    "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// Durable storage for the audit chain.
+pub(crate) trait AuditSink: Send + Sync {
+    fn append(&self, event: &ToolAuditEvent) -> Result<(), String>;
+    /// Every stored event, in sequence order.
+    fn load(&self) -> Result<Vec<ToolAuditEvent>, String>;
+}
+
+pub(crate) fn open_sink(config: &AuditSinkConfig) -> Result<Box<dyn AuditSink>, String> {
+    match config {
+        AuditSinkConfig::File { path } => Ok(Box::new(FileSink::open(Path::new(path))?)),
+        AuditSinkConfig::PersistenceStore {
+            db_path,
+            encryption_key_ref,
+        } => {
+            let store = PersistenceStore::new();
+            store
+                .open(PersistenceConfig {
+                    db_path: db_path.clone(),
+                    encryption_key_ref: encryption_key_ref.clone(),
+                })
+                .map_err(|error| error.reason)?;
+            Ok(Box::new(StoreSink { store }))
+        }
+    }
+}
+
+/// Writes events to a sink outside the gateway's state lock. Events are queued in
+/// sequence order while the lock is held, and whichever caller flushes next writes every
+/// queued event, so the sink still receives them in order.
+pub(crate) struct AuditWriter {
+    sink: Box<dyn AuditSink>,
+    queued: Mutex<VecDeque<ToolAuditEvent>>,
+    /// Held while appending or loading.
+    writing: Mutex<()>,
+    error: Mutex<Option<String>>,
+}
+
+impl AuditWriter {
+    pub(crate) fn new(sink: Box<dyn AuditSink>) -> Self {
+        Self {
+            sink,
+            queued: Mutex::new(VecDeque::new()),
+            writing: Mutex::new(()),
+            error: Mutex::new(None),
+        }
+    }
+
+    pub(crate) fn queue(&self, event: ToolAuditEvent) {
+        lock(&self.queued).push_back(event);
+    }
+
+    /// Appends the queued events. A failing append does not stop the ones after it; the
+    /// error is kept for the snapshot and verification shows the gap.
+    pub(crate) fn flush(&self) {
+        let _writing = lock(&self.writing);
+        while let Some(event) = lock(&self.queued).pop_front() {
+            if let Err(error) = self.sink.append(&event) {
+                *lock(&self.error) = Some(error);
+            }
+        }
+    }
+
+    /// Every stored event, including the ones still queued.
+    pub(crate) fn load(&self) -> Result<Vec<ToolAuditEvent>, String> {
+        self.flush();
+        let _writing = lock(&self.writing);
+        self.sink.load()
+    }
+
+    pub(crate) fn error(&self) -> Option<String> {
+        lock(&self.error).clone()
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// One JSON event per line, appended as it is recorded.
+struct FileSink {
+    path: PathBuf,
+    file: Mutex<File>,
+}
+
+impl FileSink {
+    fn open(path: &Path) -> Result<Self, String> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|error| format!("failed to create audit log directory: {error}"))?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|error| format!("failed to open audit log {}: {error}", path.display()))?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            file: Mutex::new(file),
+        })
+    }
+}
+
+impl AuditSink for FileSink {
+    fn append(&self, event: &ToolAuditEvent) -> Result<(), String> {
+        let mut line = serde_json::to_vec(event).map_err(|error| error.to_string())?;
+        line.push(b'\n');
+        let mut file = lock(&self.file);
+        file.write_all(&line)
+            .and_then(|()| file.flush())
+            .map_err(|error| format!("failed to append to audit log: {error}"))
+    }
+
+    fn load(&self) -> Result<Vec<ToolAuditEvent>, String> {
+        let contents = std::fs::read_to_string(&self.path)
+            .map_err(|error| format!("failed to read audit log: {error}"))?;
+        contents
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(index, line)| {
+                serde_json::from_str(line)
+                    .map_err(|error| format!("invalid audit record on line {}: {error}", index + 1))
+            })
+            .collect()
+    }
+}
+
+/// Rows of the persistence store's `tool_events` table, with the whole event in
+/// `audit_record`.
+struct StoreSink {
+    store: PersistenceStore,
+}
+
+impl AuditSink for StoreSink {
+    fn append(&self, event: &ToolAuditEvent) -> Result<(), String> {
+        let audit_record = serde_json::to_string(event).map_err(|error| error.to_string())?;
+        self.store
+            .save_tool_event(ToolEvent {
+                event_id: format!("{}:{}", event.request_id, event.sequence),
+                run_id: event.run_id.clone().unwrap_or_else(|| event.request_id.clone()),
+                tool_id: event.tool_id.clone(),
+                input_hash: event.input_hash.clone(),
+                output_hash: event.output_hash.clone(),
+                duration_ms: event.duration_ms as i64,
+                created_at: event.created_at,
+                audit_record: Some(audit_record),
+            })
+            .map_err(|error| error.reason)
+    }
+
+    fn load(&self) -> Result<Vec<ToolAuditEvent>, String> {
+        let bundle = self
+            .store
+            .export_bundle(Some(ExportFilter {
+                run_id: None,
+                session_id: None,
+                since: None,
+                until: None,
+                limit: None,
+            }))
+            .map_err(|error| error.reason)?;
+        let mut events = bundle
+            .tool_events
+            .iter()
+            .filter_map(|event| event.audit_record.as_deref())
+            .map(|record| {
+                serde_json::from_str::<ToolAuditEvent>(record)
+                    .map_err(|error| format!("invalid audit record: {error}"))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        events.sort_by_key(|event| event.sequence);
+        Ok(events)
+    }
+}
+
+/// Hash of everything in the event but its own `hash`, so editing any field, or the link
+/// to the previous event, breaks the chain.
+pub(crate) fn event_hash(event: &ToolAuditEvent) -> String {
+    let mut value = serde_json::to_value(event).unwrap_or(Value::Null);
+    if let Value::Object(map) = &mut value {
+        map.remove("hash");
+    }
+    hash_payload(&value, &HashSet::new())
+}
+
+/// Checks that each event hashes to its `hash` and links to the one before it. A whole log
+/// must start at sequence 1; any other chain may start mid-way, but one starting at
+/// sequence 1 must start from the genesis hash.
+pub(crate) fn verify_chain(events: &[ToolAuditEvent], whole_log: bool) -> AuditChainReport {
+    if whole_log
+        && let Some(first) = events.first()
+        && first.sequence != 1
+    {
+        return AuditChainReport {
+            valid: false,
+            checked: 0,
+            broken_at: Some(1),
+            error: Some(format!(
+                "Audit log starts at event {}; the events before it are missing",
+                first.sequence
+            )),
+        };
+    }
+    let mut previous: Option<&ToolAuditEvent> = None;
+    for (index, event) in events.iter().enumerate() {
+        let problem = if event.hash != event_hash(event) {
+            Some("its contents do not match its hash".to_string())
+        } else {
+            match previous {
+                Some(previous) if event.sequence != previous.sequence + 1 => Some(format!(
+                    "it follows sequence {}, leaving a gap",
+                    previous.sequence
+                )),
+                Some(previous) if event.prev_hash != previous.hash => {
+                    Some("it does not link to the event before it".to_string())
+                }
+                None if event.sequence == 1 && event.prev_hash != GENESIS_HASH => {
+                    Some("the first event does not start from the genesis hash".to_string())
+                }
+                _ => None,
+            }
+        };
+        if let Some(problem) = problem {
+            return AuditChainReport {
+                valid: false,
+                checked: index as u64,
+                broken_at: Some(event.sequence),
+                error: Some(format!("Audit event {} is invalid: {problem}", event.sequence)),
+            };
+        }
+        previous = Some(event);
+    }
+    AuditChainReport {
+        valid: true,
+        checked: events.len() as u64,
+        broken_at: None,
+        error: None,
+    }
+}
+
+pub(crate) fn build_redaction_keys(custom: Option<&Vec<String>>) -> HashSet<String> {
+    let mut keys = HashSet::from([
+        "apikey".to_string(),
+        "api_key".to_string(),
+        "token".to_string(),
+        "secret".to_string(),
+        "password".to_string(),
+    ]);
+
+    if let Some(custom) = custom {
+        for key in custom {
+            keys.insert(key.to_ascii_lowercase());
+        }
+    }
+
+    keys
+}
+
+pub(crate) fn hash_payload(value: &Value, redaction_keys: &HashSet<String>) -> String {
+    let redacted = redact_value(value, redaction_keys);
+    let normalized = normalize_json(&redacted);
+    let payload = serde_json::to_vec(&normalized).unwrap_or_default();
+    let mut hasher = Sha256::new();
+    hasher.update(payload);
+    hex::encode(hasher.finalize())
+}
+
+fn normalize_json(value: &Value) -> Value {
+    match value {
+        Value::Object(map) => {
+            let mut entries: Vec<_> = map.iter().collect();
+            entries.sort_by_key(|(key, _)| *key);
+            let mut normalized = serde_json::Map::new();
+            for (key, value) in entries {
+                normalized.insert(key.clone(), normalize_json(value));
+            }
+            Value::Object(normalized)
+        }
+        Value::Array(values) => Value::Array(values.iter().map(normalize_json).collect()),
+        _ => value.clone(),
+    }
+}
+
+pub(crate) fn redact_value(value: &Value, redaction_keys: &HashSet<String>) -> Value {
+    match value {
+        Value::Object(map) => {
+            let mut redacted = serde_json::Map::new();
+            for (key, value) in map {
+                if redaction_keys.contains(&key.to_ascii_lowercase()) {
+                    redacted.insert(key.clone(), Value::String("<redacted>".to_string()));
+                } else {
+                    redacted.insert(key.clone(), redact_value(value, redaction_keys));
+                }
+            }
+            Value::Object(redacted)
+        }
+        Value::Array(values) => Value::Array(values.iter().map(|value| redact_value(value, redaction_keys)).collect()),
+        _ => value.clone(),
+    }
+}
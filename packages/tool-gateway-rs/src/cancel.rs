@@ -0,0 +1,33 @@
+use std::sync::Arc;
+use tokio::sync::watch;
+
+/// Cancellation flag shared between a `ToolStreamHandle` and the task running its call.
+/// Cancelling is idempotent.
+#[derive(Clone)]
+pub struct CancelSignal {
+    sender: Arc<watch::Sender<bool>>,
+}
+
+impl Default for CancelSignal {
+    fn default() -> Self {
+        Self {
+            sender: Arc::new(watch::channel(false).0),
+        }
+    }
+}
+
+impl CancelSignal {
+    pub fn cancel(&self) {
+        self.sender.send_replace(true);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        *self.sender.borrow()
+    }
+
+    /// Resolves once `cancel` was called; never resolves otherwise.
+    pub async fn cancelled(&self) {
+        let mut receiver = self.sender.subscribe();
+        let _ = receiver.wait_for(|cancelled| *cancelled).await;
+    }
+}
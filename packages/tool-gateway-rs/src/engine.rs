@@ -1,23 +1,45 @@
+use crate::audit::{
+    build_redaction_keys, event_hash, hash_payload, open_sink, redact_value, verify_chain,
+    AuditWriter, GENESIS_HASH,
+};
+use crate::cancel::CancelSignal;
+use crate::grants::{grant_rejection, GrantUse};
+use crate::limits::{limit_result, validate_limits};
+use crate::mcp::{McpClient, McpError, McpListing, McpNotification};
+use crate::policy::{policy_input, ToolPolicy};
+use crate::schema;
+use crate::supervisor::{supervise, ServerMonitor};
 use crate::types::{
-    CapabilityGrant, CapabilityGrantInput, McpManifest, McpServerConfig, McpTool, McpToolCall,
-    McpToolResult, McpTransport, ToolAuditEvent, ToolGatewaySnapshot, ToolInvocation,
-    ToolRegistryEntry, ToolGatewaySandboxConfig,
+    AuditChainReport, CapabilityGrant, CapabilityGrantInput, GrantConsumption, McpManifest,
+    McpPrompt, McpResource, McpResourceTemplate, McpServerConfig, McpTool, McpToolCall,
+    McpToolResult, McpTransport, PromptRequest, ResourceRequest, ResourceSubscription,
+    ServerCatalog, ToolAnnotations, ToolApprovalAction, ToolApprovalDecisionInput,
+    ToolApprovalOutcome, ToolApprovalRecord, ToolApprovalRequest, ToolAuditConfig, ToolAuditEvent,
+    ToolDiscoveryFailure, ToolError, ToolErrorCode, ToolGatewaySnapshot, ToolInvocation,
+    ToolPolicyConfig, ToolPolicyDecision, ToolRegistryEntry, ToolGatewaySandboxConfig,
+    ToolResultLimits, ToolStreamEvent,
 };
+use cowork_policy_rs::{parse_cowork_policy_config, CoworkPolicyDecisionType, CoworkPolicyEngine};
 use reqwest::Client;
 use reqwest::Url;
-#[cfg(not(test))]
-use sandbox_rs::{create_sandbox, EnvVar as SandboxEnvVar, ExecOptions as SandboxExecOptions};
-use serde_json::Value;
-use sha2::{Digest, Sha256};
-use std::collections::{BTreeMap, HashMap, HashSet};
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{mpsc, oneshot};
 use uuid::Uuid;
 
 const DEFAULT_TIMEOUT_MS: u64 = 30_000;
+/// Longest a streaming call may run while it keeps reporting progress.
+const MAX_STREAM_DURATION: Duration = Duration::from_secs(30 * 60);
+const DEFAULT_APPROVAL_TIMEOUT_MS: u64 = 5 * 60 * 1000;
 
+#[derive(Clone)]
 pub struct ToolGatewayEngine {
     state: Arc<Mutex<ToolGatewayState>>,
+    monitor: Arc<ServerMonitor>,
     http_client: Client,
 }
 
@@ -25,9 +47,40 @@ struct ToolGatewayState {
     registry: BTreeMap<String, ToolRegistryEntry>,
     manifests: HashMap<String, McpManifest>,
     servers: HashMap<String, McpServerConfig>,
+    clients: HashMap<String, Arc<McpClient>>,
     grants: HashMap<String, CapabilityGrant>,
     audit_events: Vec<ToolAuditEvent>,
     next_sequence: u64,
+    discovery_failures: HashMap<String, ToolDiscoveryFailure>,
+    catalogs: HashMap<String, ServerCatalog>,
+    /// Keyed by server id and uri.
+    subscriptions: BTreeMap<(String, String), ResourceSubscription>,
+    policy: Option<Arc<dyn ToolPolicy>>,
+    /// How long parked calls wait for a decision. Without it, calls that need
+    /// confirmation are rejected unless a grant carries an approval.
+    approval_timeout: Option<Duration>,
+    pending_approvals: HashMap<String, PendingApproval>,
+    /// Recent use times of grants with a rate limit.
+    grant_uses: HashMap<String, VecDeque<i64>>,
+    /// Whether audit events carry their redacted input and output.
+    audit_capture: bool,
+    audit_sink: Option<Arc<AuditWriter>>,
+    /// The hash the next audit event links to.
+    last_audit_hash: String,
+    /// Stops the supervisor of each supervised server.
+    supervisors: HashMap<String, CancelSignal>,
+    result_limits: Option<Arc<ToolResultLimits>>,
+}
+
+struct PendingApproval {
+    request: ToolApprovalRequest,
+    sender: oneshot::Sender<ToolApprovalDecisionInput>,
+}
+
+impl Default for ToolGatewayEngine {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl ToolGatewayEngine {
@@ -37,10 +90,24 @@ impl ToolGatewayEngine {
                 registry: BTreeMap::new(),
                 manifests: HashMap::new(),
                 servers: HashMap::new(),
+                clients: HashMap::new(),
                 grants: HashMap::new(),
                 audit_events: Vec::new(),
                 next_sequence: 0,
+                discovery_failures: HashMap::new(),
+                catalogs: HashMap::new(),
+                subscriptions: BTreeMap::new(),
+                policy: None,
+                approval_timeout: None,
+                pending_approvals: HashMap::new(),
+                grant_uses: HashMap::new(),
+                audit_capture: false,
+                audit_sink: None,
+                last_audit_hash: GENESIS_HASH.to_string(),
+                supervisors: HashMap::new(),
+                result_limits: None,
             })),
+            monitor: Arc::new(ServerMonitor::default()),
             http_client: Client::new(),
         }
     }
@@ -56,21 +123,7 @@ impl ToolGatewayEngine {
             return Err(format!("server_id already registered: {server_id}"));
         }
 
-        let mut tool_names = HashSet::new();
-        for tool in &manifest.tools {
-            if tool.name.trim().is_empty() {
-                return Err("tool name is required".to_string());
-            }
-            if tool.description.trim().is_empty() {
-                return Err(format!("tool description is required: {}", tool.name));
-            }
-            if tool.input_schema.is_null() {
-                return Err(format!("tool inputSchema is required: {}", tool.name));
-            }
-            if !tool_names.insert(tool.name.clone()) {
-                return Err(format!("duplicate tool name: {}", tool.name));
-            }
-        }
+        validate_tools(&manifest.tools)?;
 
         for tool in &manifest.tools {
             let tool_id = format!("{}:{}", server_id, tool.name);
@@ -91,7 +144,28 @@ impl ToolGatewayEngine {
         Ok(())
     }
 
-    pub fn register_server(&self, mut config: McpServerConfig) -> Result<(), String> {
+    /// Registers a server. With `discoverTools` the server is started and its tools are
+    /// listed before this returns; a failed listing is reported in the snapshot instead.
+    pub async fn register_server(&self, config: McpServerConfig) -> Result<(), String> {
+        let server_id = config.server_id.clone();
+        let discover = config.discover_tools.unwrap_or(false);
+        let supervision = config.supervision.clone();
+        let client = self.add_server(config)?;
+        watch_server(self.state.clone(), server_id.clone(), &client, discover);
+        if let Some(supervision) = &supervision {
+            let stop = CancelSignal::default();
+            if let Ok(mut state) = self.state.lock() {
+                state.supervisors.insert(server_id.clone(), stop.clone());
+            }
+            supervise(self.monitor.clone(), server_id.clone(), &client, supervision, stop);
+        }
+        if discover {
+            refresh_tools(&self.state, &server_id, &client).await;
+        }
+        Ok(())
+    }
+
+    fn add_server(&self, mut config: McpServerConfig) -> Result<Arc<McpClient>, String> {
         let mut state = self
             .state
             .lock()
@@ -102,6 +176,20 @@ impl ToolGatewayEngine {
             return Err(format!("server already registered: {server_id}"));
         }
 
+        let discover = config.discover_tools.unwrap_or(false);
+        match config.transport {
+            McpTransport::Stdio if config.command.is_none() => {
+                return Err("MCP stdio transport requires command".to_string());
+            }
+            McpTransport::Http | McpTransport::Websocket if config.url.is_none() => {
+                return Err(format!("MCP {:?} transport requires url", config.transport));
+            }
+            McpTransport::Http | McpTransport::Websocket if config.supervision.is_some() => {
+                return Err("Only stdio servers can be supervised".to_string());
+            }
+            _ => {}
+        }
+
         if let Some(manifest) = config.manifest.take() {
             if manifest.server_id != server_id {
                 return Err("manifest server_id does not match config".to_string());
@@ -117,11 +205,30 @@ impl ToolGatewayEngine {
         }
 
         if !state.manifests.contains_key(&server_id) {
-            return Err(format!("manifest not registered for server {server_id}"));
+            if !discover {
+                return Err(format!("manifest not registered for server {server_id}"));
+            }
+            state.manifests.insert(
+                server_id.clone(),
+                McpManifest {
+                    server_id: server_id.clone(),
+                    name: server_id.clone(),
+                    version: String::new(),
+                    description: None,
+                    tools: Vec::new(),
+                },
+            );
+        }
+        // Kept so that rediscovered tools can take their annotations from it.
+        if discover {
+            config.manifest = state.manifests.get(&server_id).cloned();
         }
 
+        let client = Arc::new(McpClient::new(config.clone(), self.http_client.clone()));
+        state.clients.insert(server_id.clone(), client.clone());
+        self.monitor.add(&server_id);
         state.servers.insert(server_id, config);
-        Ok(())
+        Ok(client)
     }
 
     pub fn list_tools(&self) -> Vec<ToolRegistryEntry> {
@@ -150,6 +257,8 @@ impl ToolGatewayEngine {
             expires_at: input.expires_at,
             scope: input.scope,
             approval_id: input.approval_id,
+            constraints: input.constraints,
+            uses: 0,
         };
 
         state.grants.insert(grant_id.clone(), grant);
@@ -159,6 +268,7 @@ impl ToolGatewayEngine {
     pub fn revoke_capability(&self, grant_id: &str) {
         if let Ok(mut state) = self.state.lock() {
             state.grants.remove(grant_id);
+            state.grant_uses.remove(grant_id);
         }
     }
 
@@ -170,7 +280,7 @@ impl ToolGatewayEngine {
         let mut events: Vec<ToolAuditEvent> = state
             .audit_events
             .iter()
-            .filter(|event| after.map_or(true, |cursor| event.sequence > cursor))
+            .filter(|event| after.is_none_or(|cursor| event.sequence > cursor))
             .cloned()
             .collect();
         if let Some(limit) = limit {
@@ -187,6 +297,13 @@ impl ToolGatewayEngine {
                     tools: Vec::new(),
                     grants: Vec::new(),
                     audit_cursor: 0,
+                    discovery_failures: Vec::new(),
+                    catalogs: Vec::new(),
+                    resource_subscriptions: Vec::new(),
+                    pending_approvals: Vec::new(),
+                    audit_sink_error: None,
+                    servers: self.monitor.health(),
+                    lifecycle_events: self.monitor.lifecycle_events(),
                 }
             }
         };
@@ -194,27 +311,261 @@ impl ToolGatewayEngine {
         let mut grants: Vec<CapabilityGrant> = state.grants.values().cloned().collect();
         grants.sort_by(|a, b| a.grant_id.cmp(&b.grant_id));
 
+        let mut discovery_failures: Vec<ToolDiscoveryFailure> =
+            state.discovery_failures.values().cloned().collect();
+        discovery_failures.sort_by(|a, b| a.server_id.cmp(&b.server_id));
+
+        let mut catalogs: Vec<ServerCatalog> = state.catalogs.values().cloned().collect();
+        catalogs.sort_by(|a, b| a.server_id.cmp(&b.server_id));
+
         ToolGatewaySnapshot {
             tools: state.registry.values().cloned().collect(),
             grants,
             audit_cursor: state.next_sequence,
+            discovery_failures,
+            catalogs,
+            resource_subscriptions: state.subscriptions.values().cloned().collect(),
+            pending_approvals: pending_approvals(&state),
+            audit_sink_error: state.audit_sink.as_ref().and_then(|writer| writer.error()),
+            servers: self.monitor.health(),
+            lifecycle_events: self.monitor.lifecycle_events(),
+        }
+    }
+
+    /// Sets whether audit events capture redacted payloads and where they are persisted.
+    /// A sink that already holds a chain is verified and continued across restarts; an
+    /// empty one first receives the events recorded so far. The sink is read and written
+    /// outside the state lock.
+    pub fn configure_audit(&self, config: ToolAuditConfig) -> Result<(), String> {
+        let sink = config.sink.as_ref().map(open_sink).transpose()?;
+        let stored = match &sink {
+            Some(sink) => sink.load()?,
+            None => Vec::new(),
+        };
+        if let Some(error) = verify_chain(&stored, true).error {
+            return Err(format!("Stored audit log is corrupt: {error}"));
+        }
+        let writer = sink.map(|sink| Arc::new(AuditWriter::new(sink)));
+
+        {
+            let mut state = self
+                .state
+                .lock()
+                .map_err(|_| "ToolGateway state lock poisoned".to_string())?;
+            match (stored.last(), &writer) {
+                (Some(_), _) if state.next_sequence > 0 => {
+                    return Err("Audit sink already holds events from another log".to_string());
+                }
+                (Some(last), _) => {
+                    state.next_sequence = last.sequence;
+                    state.last_audit_hash = last.hash.clone();
+                }
+                (None, Some(writer)) => {
+                    for event in &state.audit_events {
+                        writer.queue(event.clone());
+                    }
+                }
+                (None, None) => {}
+            }
+            state.audit_capture = config.capture_payloads.unwrap_or(false);
+            state.audit_sink = writer.clone();
+        }
+
+        // A sink that cannot take the events recorded so far is not kept.
+        if let Some(writer) = writer {
+            writer.flush();
+            if let Some(error) = writer.error() {
+                if let Ok(mut state) = self.state.lock()
+                    && state
+                        .audit_sink
+                        .as_ref()
+                        .is_some_and(|current| Arc::ptr_eq(current, &writer))
+                {
+                    state.audit_sink = None;
+                }
+                return Err(error);
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks the given events, or else the sink's log, or else the events held in
+    /// memory. The sink's or the in-memory log must also run from the first event to the
+    /// last one recorded.
+    pub fn verify_audit_chain(
+        &self,
+        events: Option<Vec<ToolAuditEvent>>,
+    ) -> Result<AuditChainReport, String> {
+        if let Some(events) = events {
+            return Ok(verify_chain(&events, false));
+        }
+        let (writer, next_sequence, recorded) = {
+            let state = self
+                .state
+                .lock()
+                .map_err(|_| "ToolGateway state lock poisoned".to_string())?;
+            let recorded = match &state.audit_sink {
+                Some(_) => Vec::new(),
+                None => state.audit_events.clone(),
+            };
+            (state.audit_sink.clone(), state.next_sequence, recorded)
+        };
+        let events = match writer {
+            Some(writer) => writer.load()?,
+            None => recorded,
+        };
+        let mut report = verify_chain(&events, true);
+        let last_sequence = events.last().map_or(0, |event| event.sequence);
+        if report.valid && last_sequence < next_sequence {
+            report.valid = false;
+            report.broken_at = Some(last_sequence + 1);
+            report.error = Some(format!(
+                "Audit log ends at event {last_sequence} of {next_sequence}"
+            ));
         }
+        Ok(report)
     }
 
+    /// Clears all state. Stdio servers are shut down in the background when a runtime is
+    /// available, and killed otherwise.
     pub fn reset(&self) {
-        if let Ok(mut state) = self.state.lock() {
-            state.registry.clear();
-            state.manifests.clear();
-            state.servers.clear();
-            state.grants.clear();
-            state.audit_events.clear();
-            state.next_sequence = 0;
+        let clients = match self.state.lock() {
+            Ok(mut state) => {
+                state.registry.clear();
+                state.manifests.clear();
+                state.servers.clear();
+                state.grants.clear();
+                state.audit_events.clear();
+                state.next_sequence = 0;
+                state.discovery_failures.clear();
+                state.catalogs.clear();
+                state.subscriptions.clear();
+                state.policy = None;
+                state.approval_timeout = None;
+                state.pending_approvals.clear();
+                state.grant_uses.clear();
+                state.audit_capture = false;
+                state.audit_sink = None;
+                state.last_audit_hash = GENESIS_HASH.to_string();
+                for (_, stop) in state.supervisors.drain() {
+                    stop.cancel();
+                }
+                self.monitor.clear();
+                state.result_limits = None;
+                std::mem::take(&mut state.clients)
+            }
+            Err(_) => return,
+        };
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            runtime.spawn(shutdown_clients(clients.into_values().collect()));
+        }
+    }
+
+    /// Gracefully stops every running stdio server, and the supervision of those that had
+    /// it. They restart on their next call.
+    pub async fn shutdown(&self) {
+        let clients: Vec<Arc<McpClient>> = match self.state.lock() {
+            Ok(mut state) => {
+                for (_, stop) in state.supervisors.drain() {
+                    stop.cancel();
+                }
+                state.clients.values().cloned().collect()
+            }
+            Err(_) => return,
+        };
+        self.monitor.unsupervise();
+        shutdown_clients(clients).await;
+    }
+
+    /// Sets the policy tool calls are ruled by and whether calls that need confirmation
+    /// are parked for approval. Calls already parked keep waiting.
+    pub fn set_policy(&self, config: ToolPolicyConfig) -> Result<(), String> {
+        let policy: Option<Arc<dyn ToolPolicy>> = match &config.cowork_policy {
+            Some(value) => {
+                let cowork_config = parse_cowork_policy_config(value)?;
+                Some(Arc::new(CoworkPolicyEngine::new(cowork_config)?))
+            }
+            None => None,
+        };
+        let approval_timeout = config.approvals.unwrap_or(false).then(|| {
+            Duration::from_millis(config.approval_timeout_ms.unwrap_or(DEFAULT_APPROVAL_TIMEOUT_MS))
+        });
+
+        let mut state = self
+            .state
+            .lock()
+            .map_err(|_| "ToolGateway state lock poisoned".to_string())?;
+        state.policy = policy;
+        state.approval_timeout = approval_timeout;
+        Ok(())
+    }
+
+    /// Sets the size limits tool results are truncated to. Calls already running keep the
+    /// limits they started with.
+    pub fn set_result_limits(&self, limits: ToolResultLimits) -> Result<(), String> {
+        validate_limits(&limits)?;
+        let mut state = self
+            .state
+            .lock()
+            .map_err(|_| "ToolGateway state lock poisoned".to_string())?;
+        state.result_limits = Some(Arc::new(limits));
+        Ok(())
+    }
+
+    pub fn list_approvals(&self) -> Vec<ToolApprovalRequest> {
+        match self.state.lock() {
+            Ok(state) => pending_approvals(&state),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    /// Settles a parked call. An `edit` must carry the arguments to run the call with.
+    pub fn resolve_approval(&self, decision: ToolApprovalDecisionInput) -> Result<(), String> {
+        if decision.action == ToolApprovalAction::Edit && decision.arguments.is_none() {
+            return Err("An edit decision needs arguments".to_string());
         }
+        let pending = self
+            .state
+            .lock()
+            .map_err(|_| "ToolGateway state lock poisoned".to_string())?
+            .pending_approvals
+            .remove(&decision.approval_id)
+            .ok_or_else(|| format!("Approval request not found: {}", decision.approval_id))?;
+        pending
+            .sender
+            .send(decision)
+            .map_err(|_| "Approval request is no longer waiting".to_string())
     }
 
     pub async fn call_tool(&self, invocation: ToolInvocation) -> McpToolResult {
+        self.run_tool(invocation, None).await
+    }
+
+    /// Starts a call that reports the server's `notifications/progress` (and any partial
+    /// content they carry) before its result. Here `timeoutMs` is the longest wait
+    /// between progress notifications rather than for the whole call.
+    pub fn call_tool_stream(&self, invocation: ToolInvocation) -> ToolCallStream {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        let cancel = CancelSignal::default();
+        let progress = ProgressSink {
+            sender: sender.clone(),
+            cancel: cancel.clone(),
+        };
+        let engine = self.clone();
+        tokio::spawn(async move {
+            let result = engine.run_tool(invocation, Some(progress)).await;
+            let _ = sender.send(ToolStreamEvent::result(result));
+        });
+        ToolCallStream { receiver, cancel }
+    }
+
+    async fn run_tool(
+        &self,
+        invocation: ToolInvocation,
+        progress: Option<ProgressSink>,
+    ) -> McpToolResult {
         let start = Instant::now();
-        let (tool_entry, server_config, grants, requires_confirmation) = {
+        let (tool_entry, server_config, policy, approval_timeout, result_limits) = {
             let state = match self.state.lock() {
                 Ok(guard) => guard,
                 Err(_) => {
@@ -240,66 +591,419 @@ impl ToolGatewayEngine {
                 }
             };
 
-            let mut grants = Vec::new();
-            for grant_id in &invocation.grant_ids {
-                if let Some(grant) = state.grants.get(grant_id) {
-                    grants.push(grant.clone());
-                }
-            }
+            let policy = state.policy.clone();
+            let result_limits = state.result_limits.clone();
+            (entry, server_config, policy, state.approval_timeout, result_limits)
+        };
 
-            let requires_confirmation = entry
-                .tool
-                .annotations
-                .as_ref()
+        let annotations = tool_entry.tool.annotations.as_ref();
+        let policy_decision = annotations
+            .and_then(|annotations| annotations.policy_action.as_deref())
+            .zip(policy)
+            .map(|(action, policy)| {
+                let input = policy_input(action, invocation.policy_context.as_ref());
+                ToolPolicyDecision::from(policy.evaluate(&input))
+            });
+        let mut audit = AuditRecord {
+            tool_id: tool_entry.tool_id.clone(),
+            request_id: &invocation.request_id,
+            run_id: invocation.run_id.as_deref(),
+            grant_ids: &invocation.grant_ids,
+            redact_keys: invocation.redact_keys.as_ref(),
+            input: &invocation.arguments,
+            policy_decision: policy_decision.clone(),
+            approval: None,
+            consumed_grants: Vec::new(),
+            original_result_bytes: None,
+        };
+
+        let policy_confirmation = policy_decision
+            .as_ref()
+            .filter(|decision| decision.decision == CoworkPolicyDecisionType::AllowWithConfirm)
+            .map(|decision| decision.reason.clone());
+        let requires_confirmation = policy_confirmation.is_some()
+            || annotations
                 .and_then(|annotations| annotations.requires_confirmation)
                 .unwrap_or(false);
-
-            (entry, server_config, grants, requires_confirmation)
+        // Checked first, so a denied call is audited as such whatever grants it lacks.
+        if let Some(decision) = &policy_decision
+            && decision.decision == CoworkPolicyDecisionType::Deny
+        {
+            let message = format!("Denied by policy: {}", decision.reason);
+            return self.finish(&audit, Err(ToolExecutionError::PermissionDenied(message)), start);
+        }
+        let grant_use = |arguments| GrantUse {
+            grant_ids: &invocation.grant_ids,
+            run_id: invocation.run_id.as_deref(),
+            arguments,
         };
+        let check = self.validate_grants(annotations, &grant_use(&invocation.arguments), false);
+        let approved = match check {
+            Ok(check) => check.approved,
+            Err(error) => return self.finish(&audit, Err(error), start),
+        };
+        // With approvals on, a missing confirmation parks the call below instead.
+        if requires_confirmation && !approved && approval_timeout.is_none() {
+            return self.finish(&audit, Err(unconfirmed()), start);
+        }
 
-        if let Some(error) = self.validate_grants(&tool_entry.tool, &invocation, &grants, requires_confirmation) {
-            let duration_ms = start.elapsed().as_millis() as u64;
-            self.record_audit(&tool_entry, &invocation, &error, duration_ms);
-            return error;
+        let input_schema = &tool_entry.tool.input_schema;
+        let coerce = invocation.coerce_arguments.unwrap_or(false);
+        let mut arguments = invocation.arguments.clone();
+        if let Some(error) = check_arguments(input_schema, &mut arguments, coerce) {
+            return self.finish(&audit, Ok(error), start);
+        }
+
+        if requires_confirmation
+            && !approved
+            && let Some(approval_timeout) = approval_timeout
+        {
+            let reason =
+                policy_confirmation.unwrap_or_else(|| "Tool requires confirmation".to_string());
+            let requested_at = now_epoch_ms();
+            let request = ToolApprovalRequest {
+                approval_id: Uuid::new_v4().to_string(),
+                tool_id: tool_entry.tool_id.clone(),
+                request_id: invocation.request_id.clone(),
+                run_id: invocation.run_id.clone(),
+                arguments: arguments.clone(),
+                reason,
+                policy: policy_decision.clone(),
+                requested_at,
+                expires_at: requested_at + approval_timeout.as_millis() as i64,
+            };
+            let (mut record, edited) =
+                self.await_approval(request, approval_timeout, progress.as_ref()).await;
+            if let Some(edited) = &edited {
+                let redaction_keys = build_redaction_keys(invocation.redact_keys.as_ref());
+                record.edited_input_hash = Some(hash_payload(edited, &redaction_keys));
+            }
+            let denial = approval_error(&record);
+            audit.approval = Some(record);
+            if let Some(error) = denial {
+                return self.finish(&audit, Err(error), start);
+            }
+            if let Some(edited) = edited {
+                arguments = edited;
+                if let Some(error) = check_arguments(input_schema, &mut arguments, coerce) {
+                    return self.finish(&audit, Ok(error), start);
+                }
+            }
+        }
+
+        // Checked again against the final arguments, which an approver may have edited.
+        match self.validate_grants(annotations, &grant_use(&arguments), true) {
+            Ok(check) => audit.consumed_grants = check.consumed,
+            Err(error) => return self.finish(&audit, Err(error), start),
         }
 
         let timeout_ms = invocation.timeout_ms.unwrap_or(DEFAULT_TIMEOUT_MS);
         let call = McpToolCall {
             id: Some(invocation.request_id.clone()),
             name: tool_entry.tool.name.clone(),
-            arguments: invocation.arguments.clone(),
+            arguments,
         };
 
-        let outcome = self.execute_tool(&server_config, &call, timeout_ms).await;
-        let result = match outcome {
-            Ok(result) => result,
-            Err(ToolExecutionError::Timeout(message)) => McpToolResult::timeout(message),
-            Err(ToolExecutionError::PermissionDenied(message)) => {
-                McpToolResult::permission_denied(message)
+        let mut outcome = self
+            .execute_tool(&server_config, &call, timeout_ms, progress.as_ref())
+            .await;
+        if let Ok(result) = &mut outcome
+            && let Some(limits) = &result_limits
+        {
+            audit.original_result_bytes = limit_result(limits, &tool_entry.tool_id, result);
+        }
+        self.finish(&audit, outcome, start)
+    }
+
+    /// Parks a call until its approval is resolved, it expires, or a streaming caller
+    /// cancels it. Returns the settled record and any edited arguments.
+    async fn await_approval(
+        &self,
+        request: ToolApprovalRequest,
+        timeout: Duration,
+        progress: Option<&ProgressSink>,
+    ) -> (ToolApprovalRecord, Option<Value>) {
+        let approval_id = request.approval_id.clone();
+        let (sender, receiver) = oneshot::channel();
+        if let Ok(mut state) = self.state.lock() {
+            let pending = PendingApproval {
+                request: request.clone(),
+                sender,
+            };
+            state.pending_approvals.insert(approval_id.clone(), pending);
+        }
+        if let Some(progress) = progress {
+            let _ = progress.sender.send(ToolStreamEvent::approval(request));
+        }
+
+        let cancelled = async {
+            match progress {
+                Some(progress) => progress.cancel.cancelled().await,
+                None => std::future::pending().await,
             }
-            Err(ToolExecutionError::SandboxViolation(message)) => McpToolResult::failure(
-                crate::types::ToolErrorCode::SandboxViolation,
-                message,
-            ),
-            Err(ToolExecutionError::Failure(message)) => {
-                McpToolResult::failure(crate::types::ToolErrorCode::ExecutionFailed, message)
+        };
+        let (outcome, reason, arguments) = tokio::select! {
+            decision = receiver => match decision {
+                Ok(decision) => {
+                    let outcome = match decision.action {
+                        ToolApprovalAction::Approve => ToolApprovalOutcome::Approved,
+                        ToolApprovalAction::Deny => ToolApprovalOutcome::Denied,
+                        ToolApprovalAction::Edit => ToolApprovalOutcome::Edited,
+                    };
+                    let edited = outcome == ToolApprovalOutcome::Edited;
+                    let arguments = decision.arguments.filter(|_| edited);
+                    (outcome, decision.reason, arguments)
+                }
+                // The gateway was reset while the call waited.
+                Err(_) => (
+                    ToolApprovalOutcome::Cancelled,
+                    Some("Approval request was discarded".to_string()),
+                    None,
+                ),
+            },
+            () = tokio::time::sleep(timeout) => (ToolApprovalOutcome::Expired, None, None),
+            () = cancelled => (ToolApprovalOutcome::Cancelled, None, None),
+        };
+        if let Ok(mut state) = self.state.lock() {
+            state.pending_approvals.remove(&approval_id);
+        }
+
+        let record = ToolApprovalRecord {
+            approval_id,
+            outcome,
+            reason,
+            decided_at: now_epoch_ms(),
+            edited_input_hash: None,
+        };
+        (record, arguments)
+    }
+
+    /// Lists the server's resources and keeps them in its catalog.
+    pub async fn list_resources(&self, server_id: &str) -> Result<Vec<McpResource>, String> {
+        let catalog = self.refresh_catalog(server_id, CatalogKind::Resources).await?;
+        Ok(catalog.resources.unwrap_or_default())
+    }
+
+    pub async fn list_resource_templates(
+        &self,
+        server_id: &str,
+    ) -> Result<Vec<McpResourceTemplate>, String> {
+        let catalog = self.refresh_catalog(server_id, CatalogKind::ResourceTemplates).await?;
+        Ok(catalog.resource_templates.unwrap_or_default())
+    }
+
+    pub async fn list_prompts(&self, server_id: &str) -> Result<Vec<McpPrompt>, String> {
+        let catalog = self.refresh_catalog(server_id, CatalogKind::Prompts).await?;
+        Ok(catalog.prompts.unwrap_or_default())
+    }
+
+    async fn refresh_catalog(
+        &self,
+        server_id: &str,
+        kind: CatalogKind,
+    ) -> Result<ServerCatalog, String> {
+        let client = {
+            let state = self
+                .state
+                .lock()
+                .map_err(|_| "ToolGateway state lock poisoned".to_string())?;
+            state.clients.get(server_id).cloned()
+        };
+        let client = client.ok_or_else(|| format!("server not registered: {server_id}"))?;
+        refresh_catalog(&self.state, server_id, &client, kind).await
+    }
+
+    /// Reads a resource; the contents become the result's content.
+    pub async fn read_resource(&self, request: ResourceRequest) -> McpToolResult {
+        let start = Instant::now();
+        let params = json!({ "uri": request.uri });
+        let mut audit = AuditRecord::resource(&request, "resources/read", &params);
+        let access = ServerAccess::Resources;
+        let run_id = request.run_id.as_deref();
+        let client = match self.authorize(&request.server_id, access, run_id, &mut audit) {
+            Ok(client) => client,
+            Err(error) => return self.finish(&audit, Err(error), start),
+        };
+        let called = Instant::now();
+        let outcome = client
+            .request("resources", "resources/read", params.clone(), timeout(request.timeout_ms))
+            .await
+            .map(|result| server_result_from_mcp(result, "contents"))
+            .map_err(ToolExecutionError::from);
+        self.record_call(&request.server_id, &outcome, called);
+        self.finish(&audit, outcome, start)
+    }
+
+    /// Subscribes to a resource. Updates show up in the snapshot's subscriptions.
+    pub async fn subscribe_resource(&self, request: ResourceRequest) -> McpToolResult {
+        let start = Instant::now();
+        let params = json!({ "uri": request.uri });
+        let mut audit = AuditRecord::resource(&request, "resources/subscribe", &params);
+        let access = ServerAccess::Resources;
+        let run_id = request.run_id.as_deref();
+        let client = match self.authorize(&request.server_id, access, run_id, &mut audit) {
+            Ok(client) => client,
+            Err(error) => return self.finish(&audit, Err(error), start),
+        };
+        // Recorded first, as the server may report an update before it answers.
+        let key = (request.server_id.clone(), request.uri.clone());
+        let added = match self.state.lock() {
+            Ok(mut state) if !state.subscriptions.contains_key(&key) => {
+                let subscription = ResourceSubscription {
+                    server_id: request.server_id.clone(),
+                    uri: request.uri.clone(),
+                    subscribed_at: now_epoch_ms(),
+                    updated_at: None,
+                    update_count: 0,
+                };
+                state.subscriptions.insert(key.clone(), subscription);
+                true
             }
+            _ => false,
+        };
+        let called = Instant::now();
+        let outcome = client
+            .subscribe_resource(&request.uri, timeout(request.timeout_ms))
+            .await
+            .map(|()| McpToolResult::empty())
+            .map_err(ToolExecutionError::from);
+        self.record_call(&request.server_id, &outcome, called);
+        if outcome.is_err()
+            && added
+            && let Ok(mut state) = self.state.lock()
+        {
+            state.subscriptions.remove(&key);
+        }
+        self.finish(&audit, outcome, start)
+    }
+
+    pub async fn unsubscribe_resource(&self, request: ResourceRequest) -> McpToolResult {
+        let start = Instant::now();
+        let params = json!({ "uri": request.uri });
+        let mut audit = AuditRecord::resource(&request, "resources/unsubscribe", &params);
+        let access = ServerAccess::Resources;
+        let run_id = request.run_id.as_deref();
+        let client = match self.authorize(&request.server_id, access, run_id, &mut audit) {
+            Ok(client) => client,
+            Err(error) => return self.finish(&audit, Err(error), start),
+        };
+        if let Ok(mut state) = self.state.lock() {
+            state
+                .subscriptions
+                .remove(&(request.server_id.clone(), request.uri.clone()));
+        }
+        let called = Instant::now();
+        let outcome = client
+            .unsubscribe_resource(&request.uri, timeout(request.timeout_ms))
+            .await
+            .map(|()| McpToolResult::empty())
+            .map_err(ToolExecutionError::from);
+        self.record_call(&request.server_id, &outcome, called);
+        self.finish(&audit, outcome, start)
+    }
+
+    /// Renders a prompt; its messages become the result's content.
+    pub async fn get_prompt(&self, request: PromptRequest) -> McpToolResult {
+        let start = Instant::now();
+        let params = json!({
+            "name": request.name,
+            "arguments": request.arguments.clone().unwrap_or_default(),
+        });
+        let mut audit = AuditRecord {
+            tool_id: format!("{}:prompts/get", request.server_id),
+            request_id: &request.request_id,
+            run_id: request.run_id.as_deref(),
+            grant_ids: &request.grant_ids,
+            redact_keys: request.redact_keys.as_ref(),
+            input: &params,
+            policy_decision: None,
+            approval: None,
+            consumed_grants: Vec::new(),
+            original_result_bytes: None,
+        };
+        let access = ServerAccess::Prompts;
+        let run_id = request.run_id.as_deref();
+        let client = match self.authorize(&request.server_id, access, run_id, &mut audit) {
+            Ok(client) => client,
+            Err(error) => return self.finish(&audit, Err(error), start),
+        };
+        let called = Instant::now();
+        let outcome = client
+            .request("prompts", "prompts/get", params.clone(), timeout(request.timeout_ms))
+            .await
+            .map(|result| server_result_from_mcp(result, "messages"))
+            .map_err(ToolExecutionError::from);
+        self.record_call(&request.server_id, &outcome, called);
+        self.finish(&audit, outcome, start)
+    }
+
+    /// Checks a resource or prompt request against the server's access requirements and
+    /// network policy, returning the client to send it with.
+    fn authorize(
+        &self,
+        server_id: &str,
+        access: ServerAccess,
+        run_id: Option<&str>,
+        audit: &mut AuditRecord<'_>,
+    ) -> Result<Arc<McpClient>, ToolExecutionError> {
+        let (config, client) = {
+            let state = self.state.lock().map_err(|_| {
+                ToolExecutionError::Failure("Tool gateway unavailable".to_string())
+            })?;
+            let (Some(config), Some(client)) =
+                (state.servers.get(server_id), state.clients.get(server_id))
+            else {
+                return Err(ToolExecutionError::NotFound("Server not registered".to_string()));
+            };
+            (config.clone(), client.clone())
+        };
+        let requirements = match access {
+            ServerAccess::Resources => config.resource_access.as_ref(),
+            ServerAccess::Prompts => config.prompt_access.as_ref(),
         };
+        let requires_confirmation = requirements
+            .and_then(|requirements| requirements.requires_confirmation)
+            .unwrap_or(false);
+        let call = GrantUse {
+            grant_ids: audit.grant_ids,
+            run_id,
+            arguments: audit.input,
+        };
+        let check = self.validate_grants(requirements, &call, false)?;
+        if requires_confirmation && !check.approved {
+            return Err(unconfirmed());
+        }
+        check_network_access(&config)?;
+        if let Some(message) = self.monitor.unavailable(server_id) {
+            return Err(ToolExecutionError::Failure(message));
+        }
+        audit.consumed_grants = self.validate_grants(requirements, &call, true)?.consumed;
+        Ok(client)
+    }
 
+    fn finish(
+        &self,
+        audit: &AuditRecord<'_>,
+        outcome: Result<McpToolResult, ToolExecutionError>,
+        start: Instant,
+    ) -> McpToolResult {
+        let result = outcome.unwrap_or_else(McpToolResult::from);
         let duration_ms = start.elapsed().as_millis() as u64;
-        self.record_audit(&tool_entry, &invocation, &result, duration_ms);
+        self.record_audit(audit, &result, duration_ms);
         result
     }
 
+    /// Checks the call's grants: each listed grant must exist and be unexpired, and each
+    /// required capability needs a grant whose constraints admit the call. With `consume`
+    /// the use is counted against the grants relied on.
     fn validate_grants(
         &self,
-        tool: &McpTool,
-        invocation: &ToolInvocation,
-        grants: &[CapabilityGrant],
-        requires_confirmation: bool,
-    ) -> Option<McpToolResult> {
+        annotations: Option<&ToolAnnotations>,
+        call: &GrantUse<'_>,
+        consume: bool,
+    ) -> Result<GrantCheck, ToolExecutionError> {
         let mut required_scopes: Vec<String> = Vec::new();
-        if let Some(annotations) = &tool.annotations {
+        if let Some(annotations) = annotations {
             if let Some(scopes) = &annotations.required_scopes {
                 required_scopes.extend(scopes.iter().cloned());
             }
@@ -307,59 +1011,83 @@ impl ToolGatewayEngine {
                 required_scopes.push(policy_action.clone());
             }
         }
-
+        let mut state = self
+            .state
+            .lock()
+            .map_err(|_| ToolExecutionError::Failure("Tool gateway unavailable".to_string()))?;
         let now = now_epoch_ms();
-        let mut active_grants: Vec<&CapabilityGrant> = grants
-            .iter()
-            .filter(|grant| grant.expires_at.map_or(true, |expiry| expiry > now))
-            .collect();
-
-        active_grants.sort_by(|a, b| a.grant_id.cmp(&b.grant_id));
 
+        let mut active_grants: Vec<&CapabilityGrant> = Vec::new();
         let mut missing_grants = Vec::new();
         let mut seen_grants = HashSet::new();
-        for grant_id in &invocation.grant_ids {
+        for grant_id in call.grant_ids {
             if !seen_grants.insert(grant_id.as_str()) {
                 continue;
             }
-            let found = active_grants
-                .iter()
-                .any(|grant| grant.grant_id == *grant_id);
-            if !found {
-                missing_grants.push(grant_id.clone());
+            match state.grants.get(grant_id) {
+                Some(grant) if grant.expires_at.is_none_or(|expiry| expiry > now) => {
+                    active_grants.push(grant)
+                }
+                _ => missing_grants.push(grant_id.clone()),
             }
         }
+        active_grants.sort_by(|a, b| a.grant_id.cmp(&b.grant_id));
 
         if !missing_grants.is_empty() {
-            return Some(McpToolResult::permission_denied(format!(
+            return Err(ToolExecutionError::PermissionDenied(format!(
                 "Missing or expired grants: {}",
                 missing_grants.join(", ")
             )));
         }
 
+        let covers = |grant: &CapabilityGrant| {
+            grant_rejection(grant, state.grant_uses.get(&grant.grant_id), call, now)
+        };
+        let mut used: Vec<String> = Vec::new();
         for required in required_scopes {
-            let has_scope = active_grants
+            let mut rejection = None;
+            let grant = active_grants
                 .iter()
-                .any(|grant| grant.capability == required);
-            if !has_scope {
-                return Some(McpToolResult::permission_denied(format!(
-                    "Missing capability grant: {required}"
-                )));
+                .filter(|grant| grant.capability == required)
+                .find(|grant| match covers(grant) {
+                    None => true,
+                    Some(reason) => {
+                        rejection.get_or_insert(format!("grant {} {reason}", grant.grant_id));
+                        false
+                    }
+                });
+            let Some(grant) = grant else {
+                let message = match rejection {
+                    Some(rejection) => {
+                        format!("Missing capability grant: {required} ({rejection})")
+                    }
+                    None => format!("Missing capability grant: {required}"),
+                };
+                return Err(ToolExecutionError::PermissionDenied(message));
+            };
+            if !used.contains(&grant.grant_id) {
+                used.push(grant.grant_id.clone());
             }
         }
 
-        if requires_confirmation {
-            let has_approval = active_grants
-                .iter()
-                .any(|grant| grant.approval_id.is_some());
-            if !has_approval {
-                return Some(McpToolResult::permission_denied(
-                    "Confirmation required for tool invocation",
-                ));
-            }
+        let approval = active_grants
+            .iter()
+            .find(|grant| grant.approval_id.is_some() && covers(grant).is_none());
+        if let Some(grant) = approval
+            && !used.contains(&grant.grant_id)
+        {
+            used.push(grant.grant_id.clone());
         }
-
-        None
+        let approved = approval.is_some();
+
+        let consumed = if consume {
+            used.iter()
+                .filter_map(|grant_id| consume_grant(&mut state, grant_id, now))
+                .collect()
+        } else {
+            Vec::new()
+        };
+        Ok(GrantCheck { approved, consumed })
     }
 
     async fn execute_tool(
@@ -367,302 +1095,747 @@ impl ToolGatewayEngine {
         config: &McpServerConfig,
         call: &McpToolCall,
         timeout_ms: u64,
+        progress: Option<&ProgressSink>,
     ) -> Result<McpToolResult, ToolExecutionError> {
-        match config.transport {
-            McpTransport::Http => self.execute_http(config, call, timeout_ms).await,
-            McpTransport::Stdio => self.execute_stdio(config, call, timeout_ms).await,
-            McpTransport::Websocket => Err(ToolExecutionError::Failure(
-                "websocket transport not supported".to_string(),
-            )),
-        }
-    }
-
-    async fn execute_http(
-        &self,
-        config: &McpServerConfig,
-        call: &McpToolCall,
-        timeout_ms: u64,
-    ) -> Result<McpToolResult, ToolExecutionError> {
-        let Some(url) = config.url.clone() else {
+        check_network_access(config)?;
+        let client = {
+            let state = self.state.lock().map_err(|_| {
+                ToolExecutionError::Failure("ToolGateway state lock poisoned".to_string())
+            })?;
+            state.clients.get(&config.server_id).cloned()
+        };
+        let Some(client) = client else {
             return Err(ToolExecutionError::Failure(
-                "MCP http transport requires url".to_string(),
+                "Tool server not configured".to_string(),
             ));
         };
-        enforce_network_policy(config.sandbox.as_ref(), &url)?;
-
-        let request = self.http_client.post(url).json(call);
-        let response = tokio::time::timeout(Duration::from_millis(timeout_ms), request.send())
-            .await
-            .map_err(|_| ToolExecutionError::Timeout("HTTP request timed out".to_string()))
-            .and_then(|result| {
-                result.map_err(|error| ToolExecutionError::Failure(error.to_string()))
-            })?;
+        if let Some(message) = self.monitor.unavailable(&config.server_id) {
+            return Err(ToolExecutionError::Failure(message));
+        }
 
-        tokio::time::timeout(Duration::from_millis(timeout_ms), response.json::<McpToolResult>())
-            .await
-            .map_err(|_| ToolExecutionError::Timeout("HTTP response timed out".to_string()))
-            .and_then(|result| {
-                result.map_err(|error| ToolExecutionError::Failure(error.to_string()))
-            })
+        let called = Instant::now();
+        let outcome = match progress {
+            Some(progress) => stream_tool_call(&client, call, timeout_ms, progress).await,
+            None => client
+                .call_tool(&call.name, &call.arguments, Duration::from_millis(timeout_ms))
+                .await
+                .map_err(ToolExecutionError::from),
+        }
+        .map(tool_result_from_mcp);
+        self.record_call(&config.server_id, &outcome, called);
+        outcome
     }
 
-    #[cfg(not(test))]
-    async fn execute_stdio(
+    /// Counts a request that reached the server in its stats. Calls the client cancelled
+    /// say nothing about the server and are left out.
+    fn record_call(
         &self,
-        config: &McpServerConfig,
-        call: &McpToolCall,
-        timeout_ms: u64,
-    ) -> Result<McpToolResult, ToolExecutionError> {
-        let Some(command) = config.command.clone() else {
-            return Err(ToolExecutionError::Failure(
-                "MCP stdio transport requires command".to_string(),
-            ));
-        };
+        server_id: &str,
+        outcome: &Result<McpToolResult, ToolExecutionError>,
+        called: Instant,
+    ) {
+        if matches!(outcome, Err(ToolExecutionError::Cancelled(_))) {
+            return;
+        }
+        let success = outcome.as_ref().is_ok_and(|result| result.success);
+        let latency_ms = called.elapsed().as_millis() as u64;
+        self.monitor.record_call(server_id, success, latency_ms);
+    }
 
-        let payload = serde_json::to_string(call)
-            .map_err(|error| ToolExecutionError::Failure(error.to_string()))?;
-        let options = SandboxExecOptions {
-            cwd: None,
-            timeout_ms: Some(clamp_timeout(timeout_ms)),
-            stdin: Some(format!("{payload}\n")),
-            max_output_bytes: None,
-            env: build_env_vars(config.env.as_ref()),
-        };
-        let sandbox_config = resolve_sandbox_config(config.sandbox.as_ref());
-        let sandbox = create_sandbox(sandbox_config)
-            .map_err(|error| ToolExecutionError::SandboxViolation(error.to_string()))?;
+    fn record_audit(&self, audit: &AuditRecord<'_>, result: &McpToolResult, duration_ms: u64) {
+        let redaction_keys = build_redaction_keys(audit.redact_keys);
+        let input_hash = hash_payload(audit.input, &redaction_keys);
+        let output_value = serde_json::to_value(result).unwrap_or(Value::Null);
+        let output_hash = hash_payload(&output_value, &redaction_keys);
 
-        let result = sandbox
-            .execute(command, config.args.clone().unwrap_or_default(), Some(options))
-            .await
-            .map_err(|error| ToolExecutionError::Failure(error.to_string()))?;
+        let mut state = match self.state.lock() {
+            Ok(guard) => guard,
+            Err(_) => return,
+        };
+        let (input, output) = if state.audit_capture {
+            let output = redact_value(&output_value, &redaction_keys);
+            (Some(redact_value(audit.input, &redaction_keys)), Some(output))
+        } else {
+            (None, None)
+        };
 
-        if result.timed_out {
-            return Err(ToolExecutionError::Timeout(
-                "Tool execution timed out".to_string(),
-            ));
+        state.next_sequence += 1;
+        let mut event = ToolAuditEvent {
+            sequence: state.next_sequence,
+            tool_id: audit.tool_id.clone(),
+            request_id: audit.request_id.to_string(),
+            run_id: audit.run_id.map(str::to_string),
+            grant_ids: audit.grant_ids.to_vec(),
+            input_hash,
+            output_hash,
+            input,
+            output,
+            success: result.success,
+            duration_ms,
+            created_at: now_epoch_ms(),
+            policy_decision: audit.policy_decision.clone(),
+            approval: audit.approval.clone(),
+            consumed_grants: audit.consumed_grants.clone(),
+            original_result_bytes: audit.original_result_bytes,
+            prev_hash: state.last_audit_hash.clone(),
+            hash: String::new(),
+        };
+        event.hash = event_hash(&event);
+        state.last_audit_hash = event.hash.clone();
+        let writer = state.audit_sink.clone();
+        if let Some(writer) = &writer {
+            writer.queue(event.clone());
         }
+        state.audit_events.push(event);
+        drop(state);
 
-        if result.exit_code != 0 {
-            return Err(ToolExecutionError::Failure(result.stderr));
+        // A failing sink does not fail the call; the snapshot reports it and verification
+        // shows the gap.
+        if let Some(writer) = writer {
+            writer.flush();
         }
-
-        parse_tool_result(&result.stdout).map_err(|error| ToolExecutionError::Failure(error))
     }
+}
 
-    #[cfg(test)]
-    async fn execute_stdio(
-        &self,
-        config: &McpServerConfig,
-        call: &McpToolCall,
-        timeout_ms: u64,
-    ) -> Result<McpToolResult, ToolExecutionError> {
-        use std::process::Stdio;
-        use tokio::io::AsyncWriteExt;
+/// The events of a streaming tool call, ending with its result. Cancelling ends the call
+/// with a `CANCELLED` result.
+pub struct ToolCallStream {
+    pub(crate) receiver: mpsc::UnboundedReceiver<ToolStreamEvent>,
+    pub(crate) cancel: CancelSignal,
+}
 
-        let Some(command) = config.command.clone() else {
-            return Err(ToolExecutionError::Failure(
-                "MCP stdio transport requires command".to_string(),
-            ));
-        };
+struct ProgressSink {
+    sender: mpsc::UnboundedSender<ToolStreamEvent>,
+    cancel: CancelSignal,
+}
 
-        let mut cmd = tokio::process::Command::new(command);
-        if let Some(args) = &config.args {
-            cmd.args(args);
+/// Runs a call with a progress token, forwarding its progress notifications. Each one
+/// restarts the `timeout_ms` clock, up to `MAX_STREAM_DURATION` in all; an idle timeout or
+/// the caller's cancel ends the request with `notifications/cancelled`.
+async fn stream_tool_call(
+    client: &McpClient,
+    call: &McpToolCall,
+    timeout_ms: u64,
+    progress: &ProgressSink,
+) -> Result<Value, ToolExecutionError> {
+    // Starting the server does not count against the idle timeout.
+    let idle = Duration::from_millis(timeout_ms);
+    client.connect(idle).await?;
+    let token = Uuid::new_v4().to_string();
+    let mut notifications = client.subscribe();
+    let stop = CancelSignal::default();
+    let request = client.call_tool_with_progress(
+        &call.name,
+        &call.arguments,
+        &token,
+        MAX_STREAM_DURATION,
+        &stop,
+    );
+    tokio::pin!(request);
+
+    let mut idle_deadline = tokio::time::Instant::now() + idle;
+    let mut stopped: Option<ToolExecutionError> = None;
+    let forward = |notification: &McpNotification| {
+        let matches = notification.method == "notifications/progress"
+            && notification.params["progressToken"].as_str() == Some(token.as_str());
+        if matches {
+            let _ = progress.sender.send(ToolStreamEvent::progress(&notification.params));
         }
-        if let Some(env) = &config.env {
-            for (key, value) in env {
-                cmd.env(key, value);
+        matches
+    };
+    loop {
+        tokio::select! {
+            result = &mut request => {
+                // Progress sent just before the response may still be queued.
+                while let Ok(notification) = notifications.try_recv() {
+                    forward(&notification);
+                }
+                return match (result, stopped) {
+                    (Err(McpError::Cancelled(_)), Some(reason)) => Err(reason),
+                    (result, _) => result.map_err(ToolExecutionError::from),
+                };
+            }
+            notification = notifications.recv() => {
+                if let Ok(notification) = notification
+                    && forward(&notification)
+                {
+                    idle_deadline = tokio::time::Instant::now() + idle;
+                }
+            }
+            () = progress.cancel.cancelled(), if stopped.is_none() => {
+                stopped = Some(ToolExecutionError::Cancelled("Tool call cancelled".to_string()));
+                stop.cancel();
+            }
+            () = tokio::time::sleep_until(idle_deadline), if stopped.is_none() => {
+                stopped = Some(ToolExecutionError::Timeout(format!(
+                    "Tool reported no progress for {timeout_ms}ms"
+                )));
+                stop.cancel();
             }
         }
+    }
+}
 
-        cmd.stdin(Stdio::piped())
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped());
-
-        let mut child = cmd
-            .spawn()
-            .map_err(|error| ToolExecutionError::Failure(error.to_string()))?;
+/// What an audit event records. Resource and prompt requests are logged as
+/// `<serverId>:<method>` with their params as the input.
+struct AuditRecord<'a> {
+    tool_id: String,
+    request_id: &'a str,
+    run_id: Option<&'a str>,
+    grant_ids: &'a [String],
+    redact_keys: Option<&'a Vec<String>>,
+    input: &'a Value,
+    policy_decision: Option<ToolPolicyDecision>,
+    approval: Option<ToolApprovalRecord>,
+    consumed_grants: Vec<GrantConsumption>,
+    original_result_bytes: Option<u64>,
+}
 
-        if let Some(mut stdin) = child.stdin.take() {
-            let payload = serde_json::to_vec(call)
-                .map_err(|error| ToolExecutionError::Failure(error.to_string()))?;
-            stdin
-                .write_all(&payload)
-                .await
-                .map_err(|error| ToolExecutionError::Failure(error.to_string()))?;
-            stdin
-                .write_all(b"\n")
-                .await
-                .map_err(|error| ToolExecutionError::Failure(error.to_string()))?;
-            stdin
-                .shutdown()
-                .await
-                .map_err(|error| ToolExecutionError::Failure(error.to_string()))?;
+impl<'a> AuditRecord<'a> {
+    fn resource(request: &'a ResourceRequest, method: &str, params: &'a Value) -> Self {
+        Self {
+            tool_id: format!("{}:{method}", request.server_id),
+            request_id: &request.request_id,
+            run_id: request.run_id.as_deref(),
+            grant_ids: &request.grant_ids,
+            redact_keys: request.redact_keys.as_ref(),
+            input: params,
+            policy_decision: None,
+            approval: None,
+            consumed_grants: Vec::new(),
+            original_result_bytes: None,
         }
+    }
+}
 
-        let output = tokio::time::timeout(Duration::from_millis(timeout_ms), child.wait_with_output())
-            .await
-            .map_err(|_| ToolExecutionError::Timeout("Tool execution timed out".to_string()))
-            .and_then(|result| result.map_err(|error| ToolExecutionError::Failure(error.to_string())))?;
+/// Which of a server's access requirements a request is checked against.
+#[derive(Clone, Copy)]
+enum ServerAccess {
+    Resources,
+    Prompts,
+}
 
-        if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr).to_string();
-            return Err(ToolExecutionError::Failure(stderr));
-        }
+/// One of the lists kept in a server's catalog.
+#[derive(Clone, Copy)]
+enum CatalogKind {
+    Resources,
+    ResourceTemplates,
+    Prompts,
+}
 
-        let stdout = String::from_utf8_lossy(&output.stdout).to_string();
-        parse_tool_result(&stdout).map_err(|error| ToolExecutionError::Failure(error))
+impl CatalogKind {
+    const ALL: [CatalogKind; 3] = [
+        CatalogKind::Resources,
+        CatalogKind::ResourceTemplates,
+        CatalogKind::Prompts,
+    ];
+
+    /// The capability, list method and result key.
+    fn request(self) -> (&'static str, &'static str, &'static str) {
+        match self {
+            CatalogKind::Resources => ("resources", "resources/list", "resources"),
+            CatalogKind::ResourceTemplates => {
+                ("resources", "resources/templates/list", "resourceTemplates")
+            }
+            CatalogKind::Prompts => ("prompts", "prompts/list", "prompts"),
+        }
     }
 
-    fn record_audit(
-        &self,
-        tool_entry: &ToolRegistryEntry,
-        invocation: &ToolInvocation,
-        result: &McpToolResult,
-        duration_ms: u64,
-    ) {
-        let redaction_keys = build_redaction_keys(invocation.redact_keys.as_ref());
-        let input_hash = hash_payload(&invocation.arguments, &redaction_keys);
-        let output_value = serde_json::to_value(result).unwrap_or(Value::Null);
-        let output_hash = hash_payload(&output_value, &redaction_keys);
-
-        let mut state = match self.state.lock() {
-            Ok(guard) => guard,
-            Err(_) => return,
-        };
+    fn is_listed(self, catalog: &ServerCatalog) -> bool {
+        match self {
+            CatalogKind::Resources => catalog.resources.is_some(),
+            CatalogKind::ResourceTemplates => catalog.resource_templates.is_some(),
+            CatalogKind::Prompts => catalog.prompts.is_some(),
+        }
+    }
 
-        state.next_sequence += 1;
-        let event = ToolAuditEvent {
-            sequence: state.next_sequence,
-            tool_id: tool_entry.tool_id.clone(),
-            request_id: invocation.request_id.clone(),
-            grant_ids: invocation.grant_ids.clone(),
-            input_hash,
-            output_hash,
-            success: result.success,
-            duration_ms,
-            created_at: now_epoch_ms(),
-        };
-        state.audit_events.push(event);
+    fn store(self, catalog: &mut ServerCatalog, listing: McpListing) -> Result<(), String> {
+        let items = Value::Array(listing.items);
+        match self {
+            CatalogKind::Resources => catalog.resources = Some(parse_listing(items)?),
+            CatalogKind::ResourceTemplates => {
+                catalog.resource_templates = Some(parse_listing(items)?)
+            }
+            CatalogKind::Prompts => catalog.prompts = Some(parse_listing(items)?),
+        }
+        Ok(())
     }
 }
 
+fn parse_listing<T: DeserializeOwned>(items: Value) -> Result<Vec<T>, String> {
+    serde_json::from_value(items).map_err(|error| format!("Invalid MCP listing: {error}"))
+}
+
 #[derive(Debug)]
 enum ToolExecutionError {
     Timeout(String),
     PermissionDenied(String),
     SandboxViolation(String),
+    InvalidArguments(String),
+    NotFound(String),
+    Cancelled(String),
     Failure(String),
 }
 
-fn now_epoch_ms() -> i64 {
-    SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .map(|duration| duration.as_millis() as i64)
-        .unwrap_or(0)
+impl ToolExecutionError {
+    fn into_message(self) -> String {
+        match self {
+            ToolExecutionError::Timeout(message)
+            | ToolExecutionError::PermissionDenied(message)
+            | ToolExecutionError::SandboxViolation(message)
+            | ToolExecutionError::InvalidArguments(message)
+            | ToolExecutionError::NotFound(message)
+            | ToolExecutionError::Cancelled(message)
+            | ToolExecutionError::Failure(message) => message,
+        }
+    }
 }
 
-fn build_redaction_keys(custom: Option<&Vec<String>>) -> HashSet<String> {
-    let mut keys = HashSet::from([
-        "apikey".to_string(),
-        "api_key".to_string(),
-        "token".to_string(),
-        "secret".to_string(),
-        "password".to_string(),
-    ]);
-
-    if let Some(custom) = custom {
-        for key in custom {
-            keys.insert(key.to_ascii_lowercase());
+impl From<ToolExecutionError> for McpToolResult {
+    fn from(error: ToolExecutionError) -> Self {
+        match error {
+            ToolExecutionError::Timeout(message) => McpToolResult::timeout(message),
+            ToolExecutionError::PermissionDenied(message) => {
+                McpToolResult::permission_denied(message)
+            }
+            ToolExecutionError::SandboxViolation(message) => {
+                McpToolResult::failure(ToolErrorCode::SandboxViolation, message)
+            }
+            ToolExecutionError::InvalidArguments(message) => {
+                McpToolResult::failure(ToolErrorCode::InvalidArguments, message)
+            }
+            ToolExecutionError::NotFound(message) => McpToolResult::resource_not_found(message),
+            ToolExecutionError::Cancelled(message) => {
+                McpToolResult::failure(ToolErrorCode::Cancelled, message)
+            }
+            ToolExecutionError::Failure(message) => {
+                McpToolResult::failure(ToolErrorCode::ExecutionFailed, message)
+            }
         }
     }
+}
 
-    keys
+impl From<McpError> for ToolExecutionError {
+    fn from(error: McpError) -> Self {
+        match error {
+            McpError::Timeout(message) => ToolExecutionError::Timeout(message),
+            McpError::Sandbox(message) => ToolExecutionError::SandboxViolation(message),
+            McpError::InvalidParams(message) => ToolExecutionError::InvalidArguments(message),
+            McpError::NotFound(message) => ToolExecutionError::NotFound(message),
+            McpError::Cancelled(message) => ToolExecutionError::Cancelled(message),
+            McpError::Failure(message) => ToolExecutionError::Failure(message),
+        }
+    }
 }
 
-fn hash_payload(value: &Value, redaction_keys: &HashSet<String>) -> String {
-    let redacted = redact_value(value, redaction_keys);
-    let normalized = normalize_json(&redacted);
-    let payload = serde_json::to_vec(&normalized).unwrap_or_default();
-    let mut hasher = Sha256::new();
-    hasher.update(payload);
-    hex::encode(hasher.finalize())
+fn validate_tools(tools: &[McpTool]) -> Result<(), String> {
+    let mut tool_names = HashSet::new();
+    for tool in tools {
+        if tool.name.trim().is_empty() {
+            return Err("tool name is required".to_string());
+        }
+        if tool.description.trim().is_empty() {
+            return Err(format!("tool description is required: {}", tool.name));
+        }
+        if tool.input_schema.is_null() {
+            return Err(format!("tool inputSchema is required: {}", tool.name));
+        }
+        if !tool_names.insert(tool.name.clone()) {
+            return Err(format!("duplicate tool name: {}", tool.name));
+        }
+    }
+    Ok(())
 }
 
-fn normalize_json(value: &Value) -> Value {
-    match value {
-        Value::Object(map) => {
-            let mut entries: Vec<_> = map.iter().collect();
-            entries.sort_by(|(a, _), (b, _)| a.cmp(b));
-            let mut normalized = serde_json::Map::new();
-            for (key, value) in entries {
-                normalized.insert(key.clone(), normalize_json(value));
+/// Follows a server's notifications: list changes re-list what the gateway already
+/// holds (tools only when discovering), and resource updates mark their subscription.
+fn watch_server(
+    state: Arc<Mutex<ToolGatewayState>>,
+    server_id: String,
+    client: &Arc<McpClient>,
+    discover: bool,
+) {
+    let mut notifications = client.subscribe();
+    let client = Arc::downgrade(client);
+    tokio::spawn(async move {
+        loop {
+            // A lagged receiver may have missed a change, so it refreshes everything.
+            let (method, params) = match notifications.recv().await {
+                Ok(notification) => (notification.method, notification.params),
+                Err(RecvError::Lagged(_)) => (String::new(), Value::Null),
+                Err(RecvError::Closed) => break,
+            };
+            let Some(client) = client.upgrade() else {
+                break;
+            };
+            let catalogs: &[CatalogKind] = match method.as_str() {
+                "notifications/resources/updated" => {
+                    mark_resource_updated(&state, &server_id, &params);
+                    continue;
+                }
+                "notifications/tools/list_changed" => &[],
+                "notifications/resources/list_changed" => {
+                    &[CatalogKind::Resources, CatalogKind::ResourceTemplates]
+                }
+                "notifications/prompts/list_changed" => &[CatalogKind::Prompts],
+                "" => &CatalogKind::ALL,
+                _ => continue,
+            };
+            let tools = matches!(method.as_str(), "notifications/tools/list_changed" | "");
+            if discover && tools {
+                refresh_tools(&state, &server_id, &client).await;
+            }
+            for kind in catalogs {
+                let listed = state.lock().is_ok_and(|state| {
+                    let catalog = state.catalogs.get(&server_id);
+                    catalog.is_some_and(|catalog| kind.is_listed(catalog))
+                });
+                if listed {
+                    let _ = refresh_catalog(&state, &server_id, &client, *kind).await;
+                }
             }
-            Value::Object(normalized)
         }
-        Value::Array(values) => Value::Array(values.iter().map(normalize_json).collect()),
-        _ => value.clone(),
+    });
+}
+
+fn mark_resource_updated(state: &Mutex<ToolGatewayState>, server_id: &str, params: &Value) {
+    let Some(uri) = params.get("uri").and_then(Value::as_str) else {
+        return;
+    };
+    let Ok(mut state) = state.lock() else {
+        return;
+    };
+    if let Some(subscription) = state
+        .subscriptions
+        .get_mut(&(server_id.to_string(), uri.to_string()))
+    {
+        subscription.updated_at = Some(now_epoch_ms());
+        subscription.update_count += 1;
     }
 }
 
-fn redact_value(value: &Value, redaction_keys: &HashSet<String>) -> Value {
-    match value {
-        Value::Object(map) => {
-            let mut redacted = serde_json::Map::new();
-            for (key, value) in map {
-                if redaction_keys.contains(&key.to_ascii_lowercase()) {
-                    redacted.insert(key.clone(), Value::String("<redacted>".to_string()));
-                } else {
-                    redacted.insert(key.clone(), redact_value(value, redaction_keys));
-                }
+/// Lists one of a server's catalogs and stores it, unless the server was replaced while
+/// the listing was in flight.
+async fn refresh_catalog(
+    state: &Mutex<ToolGatewayState>,
+    server_id: &str,
+    client: &Arc<McpClient>,
+    kind: CatalogKind,
+) -> Result<ServerCatalog, String> {
+    check_network_access(client.config()).map_err(ToolExecutionError::into_message)?;
+    let (capability, method, key) = kind.request();
+    let listing = client
+        .list(capability, method, key, Duration::from_millis(DEFAULT_TIMEOUT_MS))
+        .await
+        .map_err(|error| error.to_string())?;
+
+    let mut state = state
+        .lock()
+        .map_err(|_| "ToolGateway state lock poisoned".to_string())?;
+    let current = state
+        .clients
+        .get(server_id)
+        .is_some_and(|registered| Arc::ptr_eq(registered, client));
+    if !current {
+        return Err(format!("server not registered: {server_id}"));
+    }
+    let catalog = state
+        .catalogs
+        .entry(server_id.to_string())
+        .or_insert_with(|| ServerCatalog {
+            server_id: server_id.to_string(),
+            ..ServerCatalog::default()
+        });
+    kind.store(catalog, listing)?;
+    Ok(catalog.clone())
+}
+
+/// Replaces a server's registry entries with its current `tools/list`. A failure is kept
+/// for the snapshot and leaves the previous tools in place.
+async fn refresh_tools(state: &Mutex<ToolGatewayState>, server_id: &str, client: &Arc<McpClient>) {
+    let manifest = match check_network_access(client.config()) {
+        Ok(()) => client
+            .list("tools", "tools/list", "tools", Duration::from_millis(DEFAULT_TIMEOUT_MS))
+            .await
+            .map_err(|error| error.to_string())
+            .and_then(|listing| discovered_manifest(client.config(), listing)),
+        Err(error) => Err(error.into_message()),
+    };
+
+    let Ok(mut state) = state.lock() else {
+        return;
+    };
+    // The server may have been reset or replaced while the listing was in flight.
+    let current = state
+        .clients
+        .get(server_id)
+        .is_some_and(|registered| Arc::ptr_eq(registered, client));
+    if !current {
+        return;
+    }
+
+    match manifest {
+        Ok(manifest) => {
+            state.registry.retain(|_, entry| entry.server_id != server_id);
+            for tool in &manifest.tools {
+                let tool_id = format!("{}:{}", server_id, tool.name);
+                state.registry.insert(
+                    tool_id.clone(),
+                    ToolRegistryEntry {
+                        tool_id,
+                        server_id: server_id.to_string(),
+                        tool: tool.clone(),
+                    },
+                );
             }
-            Value::Object(redacted)
+            state.manifests.insert(server_id.to_string(), manifest);
+            state.discovery_failures.remove(server_id);
+        }
+        Err(error) => {
+            state.discovery_failures.insert(
+                server_id.to_string(),
+                ToolDiscoveryFailure {
+                    server_id: server_id.to_string(),
+                    error,
+                    failed_at: now_epoch_ms(),
+                },
+            );
         }
-        Value::Array(values) => Value::Array(values.iter().map(|value| redact_value(value, redaction_keys)).collect()),
-        _ => value.clone(),
     }
 }
 
-#[cfg(not(test))]
-fn resolve_sandbox_config(config: Option<&ToolGatewaySandboxConfig>) -> sandbox_rs::SandboxConfig {
-    match config {
-        Some(config) => sandbox_rs::SandboxConfig {
-            network_access: config.network_access.clone(),
-            allowed_hosts: config.allowed_hosts.clone(),
-            allowed_roots: config.allowed_roots.clone(),
-            fs_isolation: config.fs_isolation.clone(),
-            working_directory: config.working_directory.clone(),
-        },
-        None => sandbox_rs::SandboxConfig {
-            network_access: "none".to_string(),
-            allowed_hosts: None,
-            allowed_roots: None,
-            fs_isolation: "none".to_string(),
-            working_directory: None,
-        },
+fn discovered_manifest(
+    config: &McpServerConfig,
+    listing: McpListing,
+) -> Result<McpManifest, String> {
+    let server_id = &config.server_id;
+    let tools = listing
+        .items
+        .iter()
+        .map(|tool| discovered_tool(config, tool))
+        .collect::<Result<Vec<_>, _>>()?;
+    validate_tools(&tools)?;
+    let info = |key: &str| {
+        listing
+            .server_info
+            .get(key)
+            .and_then(Value::as_str)
+            .map(str::to_string)
+    };
+    Ok(McpManifest {
+        server_id: server_id.to_string(),
+        name: info("name").unwrap_or_else(|| server_id.to_string()),
+        version: info("version").unwrap_or_default(),
+        description: None,
+        tools,
+    })
+}
+
+/// Maps an MCP `Tool` definition onto the registry shape. MCP descriptions are
+/// optional, so the title or name stands in; hints and output schemas go to metadata.
+/// Annotations come from the manifest tool of the same name, else the server's
+/// `toolAccess`, else a required grant for the tool id, so that server-declared tools are
+/// never ungated.
+fn discovered_tool(config: &McpServerConfig, tool: &Value) -> Result<McpTool, String> {
+    let name = tool
+        .get("name")
+        .and_then(Value::as_str)
+        .ok_or_else(|| "discovered tool is missing a name".to_string())?;
+    let description = ["description", "title"]
+        .iter()
+        .filter_map(|key| tool.get(*key).and_then(Value::as_str))
+        .find(|text| !text.trim().is_empty())
+        .unwrap_or(name);
+    let listed = config
+        .manifest
+        .iter()
+        .flat_map(|manifest| &manifest.tools)
+        .find(|listed| listed.name == name);
+    let annotations = match listed {
+        Some(listed) => listed.annotations.clone(),
+        None => Some(config.tool_access.clone().unwrap_or_else(|| ToolAnnotations {
+            required_scopes: Some(vec![format!("{}:{name}", config.server_id)]),
+            policy_action: None,
+            requires_confirmation: None,
+        })),
+    };
+    let mut metadata = serde_json::Map::new();
+    for key in ["title", "annotations", "outputSchema", "_meta"] {
+        if let Some(value) = tool.get(key) {
+            metadata.insert(key.to_string(), value.clone());
+        }
     }
+    Ok(McpTool {
+        name: name.to_string(),
+        description: description.to_string(),
+        input_schema: tool
+            .get("inputSchema")
+            .cloned()
+            .unwrap_or_else(|| serde_json::json!({ "type": "object" })),
+        annotations,
+        metadata: (!metadata.is_empty()).then_some(Value::Object(metadata)),
+    })
 }
 
-#[cfg(not(test))]
-fn build_env_vars(env: Option<&HashMap<String, String>>) -> Option<Vec<SandboxEnvVar>> {
-    let env = env?;
-    if env.is_empty() {
-        return None;
+async fn shutdown_clients(clients: Vec<Arc<McpClient>>) {
+    for client in clients {
+        client.shutdown().await;
+    }
+}
+
+/// Maps an MCP `CallToolResult` onto the gateway result. Tool-level failures
+/// (`isError`) keep the server's content and report its first text block as the error.
+fn tool_result_from_mcp(result: Value) -> McpToolResult {
+    let content = match result.get("content") {
+        Some(Value::Array(content)) => content.clone(),
+        _ => Vec::new(),
+    };
+    let mut meta = serde_json::Map::new();
+    for (key, name) in [("structuredContent", "structuredContent"), ("_meta", "meta")] {
+        if let Some(value) = result.get(key) {
+            meta.insert(name.to_string(), value.clone());
+        }
+    }
+    let is_error = result.get("isError").and_then(Value::as_bool).unwrap_or(false);
+    let error = is_error.then(|| {
+        let message = content
+            .iter()
+            .find_map(|item| item.get("text").and_then(Value::as_str))
+            .unwrap_or("Tool reported an error");
+        ToolError::new(ToolErrorCode::ExecutionFailed, message)
+    });
+    McpToolResult {
+        success: !is_error,
+        content,
+        error,
+        meta: (!meta.is_empty()).then_some(Value::Object(meta)),
+    }
+}
+
+/// Maps a `resources/read` or `prompts/get` result: the `key` array (contents or
+/// messages) becomes the content, and whatever else the server sent goes to `meta`.
+fn server_result_from_mcp(mut result: Value, key: &str) -> McpToolResult {
+    let content = match result.get_mut(key).map(Value::take) {
+        Some(Value::Array(content)) => content,
+        _ => Vec::new(),
+    };
+    let meta = match result {
+        Value::Object(mut meta) => {
+            meta.remove(key);
+            (!meta.is_empty()).then_some(Value::Object(meta))
+        }
+        _ => None,
+    };
+    McpToolResult {
+        success: true,
+        content,
+        error: None,
+        meta,
+    }
+}
+
+/// Counts a use of the grant, keeping the times of recent uses for its rate limit.
+fn consume_grant(
+    state: &mut ToolGatewayState,
+    grant_id: &str,
+    now: i64,
+) -> Option<GrantConsumption> {
+    let grant = state.grants.get_mut(grant_id)?;
+    grant.uses += 1;
+    let constraints = grant.constraints.as_ref();
+    let consumption = GrantConsumption {
+        grant_id: grant.grant_id.clone(),
+        capability: grant.capability.clone(),
+        uses: grant.uses,
+        remaining: constraints
+            .and_then(|constraints| constraints.max_invocations)
+            .map(|max| max.saturating_sub(grant.uses)),
+    };
+    if let Some(limit) = constraints.and_then(|constraints| constraints.rate_limit.as_ref()) {
+        let since = now - limit.window_ms as i64;
+        let uses = state.grant_uses.entry(grant_id.to_string()).or_default();
+        uses.retain(|at| *at > since);
+        uses.push_back(now);
+    }
+    Some(consumption)
+}
+
+/// What a grant check found: whether a grant carrying an approval confirms the call, and
+/// the grants a consuming check counted the call against.
+struct GrantCheck {
+    approved: bool,
+    consumed: Vec<GrantConsumption>,
+}
+
+fn unconfirmed() -> ToolExecutionError {
+    ToolExecutionError::PermissionDenied("Confirmation required for tool invocation".to_string())
+}
+
+/// Coerces the arguments if asked to, then checks them against the tool's input schema,
+/// returning the failed result for any violations.
+fn check_arguments(
+    input_schema: &Value,
+    arguments: &mut Value,
+    coerce: bool,
+) -> Option<McpToolResult> {
+    if coerce {
+        schema::coerce(input_schema, arguments);
+    }
+    let violations = json_schema_rs::validate(input_schema, arguments);
+    (!violations.is_empty()).then(|| McpToolResult::invalid_arguments(&violations))
+}
+
+/// Why a settled approval stops the call, if it does.
+fn approval_error(record: &ToolApprovalRecord) -> Option<ToolExecutionError> {
+    match record.outcome {
+        ToolApprovalOutcome::Approved | ToolApprovalOutcome::Edited => None,
+        ToolApprovalOutcome::Denied => Some(ToolExecutionError::PermissionDenied(
+            match &record.reason {
+                Some(reason) => format!("Tool call denied: {reason}"),
+                None => "Tool call denied".to_string(),
+            },
+        )),
+        ToolApprovalOutcome::Expired => Some(ToolExecutionError::PermissionDenied(
+            "Approval timed out".to_string(),
+        )),
+        ToolApprovalOutcome::Cancelled => Some(ToolExecutionError::Cancelled(
+            "Tool call cancelled while awaiting approval".to_string(),
+        )),
     }
-    Some(
-        env.iter()
-            .map(|(key, value)| SandboxEnvVar {
-                key: key.clone(),
-                value: value.clone(),
-            })
-            .collect(),
-    )
 }
 
-#[cfg(not(test))]
-fn clamp_timeout(timeout_ms: u64) -> u32 {
-    u32::try_from(timeout_ms).unwrap_or(u32::MAX)
+fn pending_approvals(state: &ToolGatewayState) -> Vec<ToolApprovalRequest> {
+    let mut requests: Vec<ToolApprovalRequest> = state
+        .pending_approvals
+        .values()
+        .map(|pending| pending.request.clone())
+        .collect();
+    requests.sort_by(|a, b| {
+        (a.requested_at, &a.approval_id).cmp(&(b.requested_at, &b.approval_id))
+    });
+    requests
+}
+
+fn timeout(timeout_ms: Option<u64>) -> Duration {
+    Duration::from_millis(timeout_ms.unwrap_or(DEFAULT_TIMEOUT_MS))
+}
+
+pub(crate) fn now_epoch_ms() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_millis() as i64)
+        .unwrap_or(0)
+}
+
+/// Remote transports are subject to the server's network policy; stdio servers are
+/// confined by the sandbox instead.
+fn check_network_access(config: &McpServerConfig) -> Result<(), ToolExecutionError> {
+    match (&config.transport, &config.url) {
+        (McpTransport::Http | McpTransport::Websocket, Some(url)) => {
+            enforce_network_policy(config.sandbox.as_ref(), url)
+        }
+        _ => Ok(()),
+    }
 }
 
 fn enforce_network_policy(
@@ -716,30 +1889,10 @@ fn is_allowed_host(host: &str, allowed: &[String]) -> bool {
         .any(|domain| host == domain || host.ends_with(&format!(".{domain}")))
 }
 
-fn parse_tool_result(stdout: &str) -> Result<McpToolResult, String> {
-    if let Ok(result) = serde_json::from_str::<McpToolResult>(stdout) {
-        return Ok(result);
-    }
-
-    let mut last_error = None;
-    for line in stdout.lines().rev() {
-        let trimmed = line.trim();
-        if trimmed.is_empty() {
-            continue;
-        }
-        match serde_json::from_str::<McpToolResult>(trimmed) {
-            Ok(result) => return Ok(result),
-            Err(error) => last_error = Some(error.to_string()),
-        }
-    }
-
-    Err(last_error.unwrap_or_else(|| "Failed to parse tool result".to_string()))
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::types::{McpTool, ToolAnnotations};
+    use crate::types::{AuditSinkConfig, McpTool, ServerState, SupervisionConfig, ToolAnnotations};
     use serde_json::json;
 
     fn build_tool(name: &str) -> McpTool {
@@ -812,7 +1965,13 @@ mod tests {
                 url: Some("http://localhost".to_string()),
                 manifest: None,
                 sandbox: None,
+                discover_tools: None,
+                tool_access: None,
+                resource_access: None,
+                prompt_access: None,
+                supervision: None,
             })
+            .await
             .unwrap();
 
         let result = engine
@@ -824,6 +1983,8 @@ mod tests {
                 grant_ids: Vec::new(),
                 redact_keys: None,
                 timeout_ms: Some(10),
+                coerce_arguments: None,
+                policy_context: None,
             })
             .await;
 
@@ -834,6 +1995,1382 @@ mod tests {
             .is_some_and(|error| matches!(error.code, crate::types::ToolErrorCode::PermissionDenied)));
     }
 
+    const COUNTER_SERVER: &str = r#"
+import json, sys
+count, ready = 0, False
+for line in sys.stdin:
+    message = json.loads(line)
+    method = message.get("method")
+    if method == "notifications/initialized":
+        ready = True
+    if "id" not in message:
+        continue
+    reply = {"jsonrpc": "2.0", "id": message["id"]}
+    if method == "initialize":
+        reply["result"] = {"protocolVersion": message["params"]["protocolVersion"], "capabilities": {"tools": {}}, "serverInfo": {"name": "counter", "version": "1"}}
+    elif method == "ping":
+        reply["result"] = {}
+    elif method == "tools/call" and ready:
+        params = message["params"]
+        if params["name"] == "crash":
+            sys.exit(1)
+        by = params["arguments"].get("by")
+        if not isinstance(by, int):
+            reply["error"] = {"code": -32602, "message": "by must be an integer"}
+        else:
+            count += by
+            text = str(count) + params["arguments"].get("pad", "")
+            reply["result"] = {"content": [{"type": "text", "text": text}]}
+    else:
+        reply["error"] = {"code": -32600, "message": "not initialized"}
+    print("counter: handled " + str(method))
+    print(json.dumps(reply), flush=True)
+"#;
+
+    fn counter_server(server_id: &str, tools: Vec<McpTool>) -> McpServerConfig {
+        McpServerConfig {
+            server_id: server_id.to_string(),
+            transport: McpTransport::Stdio,
+            command: Some("python3".to_string()),
+            args: Some(vec!["-c".to_string(), COUNTER_SERVER.to_string()]),
+            env: None,
+            url: None,
+            manifest: Some(McpManifest {
+                server_id: server_id.to_string(),
+                name: "Counter".to_string(),
+                version: "1".to_string(),
+                description: None,
+                tools,
+            }),
+            sandbox: None,
+            discover_tools: None,
+            tool_access: None,
+            resource_access: None,
+            prompt_access: None,
+            supervision: None,
+        }
+    }
+
+    fn stdio_invocation(tool_id: &str, arguments: Value) -> ToolInvocation {
+        ToolInvocation {
+            tool_id: tool_id.to_string(),
+            request_id: Uuid::new_v4().to_string(),
+            run_id: None,
+            arguments,
+            grant_ids: Vec::new(),
+            redact_keys: None,
+            timeout_ms: Some(5_000),
+            coerce_arguments: None,
+            policy_context: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn stdio_sessions_keep_state_and_restart_after_a_crash() {
+        let engine = ToolGatewayEngine::new();
+        engine
+            .register_server(counter_server(
+                "counter",
+                vec![build_tool("count"), build_tool("crash")],
+            ))
+            .await
+            .unwrap();
+
+        let text = |result: &McpToolResult| result.content[0]["text"].as_str().map(str::to_string);
+        let first = engine
+            .call_tool(stdio_invocation("counter:count", json!({ "by": 1 })))
+            .await;
+        let second = engine
+            .call_tool(stdio_invocation("counter:count", json!({ "by": 2 })))
+            .await;
+        assert_eq!(text(&first).as_deref(), Some("1"));
+        assert_eq!(text(&second).as_deref(), Some("3"));
+
+        let invalid = engine
+            .call_tool(stdio_invocation("counter:count", json!({ "by": "two" })))
+            .await;
+        assert!(invalid
+            .error
+            .as_ref()
+            .is_some_and(|error| matches!(error.code, ToolErrorCode::InvalidArguments)));
+
+        let crashed = engine.call_tool(stdio_invocation("counter:crash", json!({}))).await;
+        assert!(!crashed.success);
+
+        let restarted = engine
+            .call_tool(stdio_invocation("counter:count", json!({ "by": 1 })))
+            .await;
+        assert_eq!(text(&restarted).as_deref(), Some("1"));
+        engine.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn arguments_are_checked_against_the_input_schema_before_the_call() {
+        let engine = ToolGatewayEngine::new();
+        let mut count = build_tool("count");
+        count.input_schema = json!({
+            "type": "object",
+            "properties": { "by": { "type": "integer", "minimum": 1 } },
+            "required": ["by"],
+            "additionalProperties": false
+        });
+        engine
+            .register_server(counter_server("checked", vec![count]))
+            .await
+            .unwrap();
+
+        let rejected = engine
+            .call_tool(stdio_invocation("checked:count", json!({ "by": "2", "step": 0 })))
+            .await;
+        let error = rejected.error.expect("schema violations fail the call");
+        assert!(matches!(error.code, ToolErrorCode::InvalidArguments));
+        let violations = &error.details.expect("violations are listed")["violations"];
+        assert_eq!(violations[0]["path"], "$.by");
+        assert_eq!(violations[0]["keyword"], "type");
+        assert_eq!(violations[1]["path"], "$.step");
+        assert_eq!(violations[1]["keyword"], "additionalProperties");
+
+        let mut coerced = stdio_invocation("checked:count", json!({ "by": " 2" }));
+        coerced.coerce_arguments = Some(true);
+        let result = engine.call_tool(coerced).await;
+        assert_eq!(result.content[0]["text"], "2");
+
+        let mut out_of_range = stdio_invocation("checked:count", json!({ "by": "0" }));
+        out_of_range.coerce_arguments = Some(true);
+        let error = engine.call_tool(out_of_range).await.error.unwrap();
+        assert_eq!(error.details.unwrap()["violations"][0]["keyword"], "minimum");
+        engine.shutdown().await;
+    }
+
+    const LISTING_SERVER: &str = r#"
+import json, sys
+tools = ["alpha", "beta", "gamma"]
+capabilities = {} if "--no-tools" in sys.argv else {"tools": {"listChanged": True}}
+def send(message):
+    print(json.dumps(message), flush=True)
+for line in sys.stdin:
+    message = json.loads(line)
+    method = message.get("method")
+    if "id" not in message:
+        continue
+    reply = {"jsonrpc": "2.0", "id": message["id"]}
+    if method == "initialize":
+        reply["result"] = {"protocolVersion": message["params"]["protocolVersion"], "capabilities": capabilities, "serverInfo": {"name": "lister", "version": "2"}}
+    elif method == "tools/list":
+        start = int(message["params"].get("cursor", "0"))
+        page = {"tools": [{"name": name, "inputSchema": {"type": "object"}} for name in tools[start:start + 2]]}
+        if start + 2 < len(tools):
+            page["nextCursor"] = str(start + 2)
+        reply["result"] = page
+    elif method == "tools/call":
+        tools.append(message["params"]["arguments"]["name"])
+        send({"jsonrpc": "2.0", "method": "notifications/tools/list_changed"})
+        reply["result"] = {"content": []}
+    send(reply)
+"#;
+
+    fn listing_server(server_id: &str, args: &[&str]) -> McpServerConfig {
+        let mut command_args = vec!["-c".to_string(), LISTING_SERVER.to_string()];
+        command_args.extend(args.iter().map(|arg| arg.to_string()));
+        McpServerConfig {
+            server_id: server_id.to_string(),
+            transport: McpTransport::Stdio,
+            command: Some("python3".to_string()),
+            args: Some(command_args),
+            env: None,
+            url: None,
+            manifest: None,
+            sandbox: None,
+            discover_tools: Some(true),
+            tool_access: None,
+            resource_access: None,
+            prompt_access: None,
+            supervision: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn discovery_pages_tools_and_follows_list_changes() {
+        let engine = ToolGatewayEngine::new();
+        engine.register_server(listing_server("lister", &[])).await.unwrap();
+        engine
+            .register_server(listing_server("silent", &["--no-tools"]))
+            .await
+            .unwrap();
+
+        let tool_ids = |engine: &ToolGatewayEngine| {
+            engine
+                .list_tools()
+                .into_iter()
+                .map(|entry| entry.tool_id)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(tool_ids(&engine), vec!["lister:alpha", "lister:beta", "lister:gamma"]);
+
+        let snapshot = engine.get_snapshot();
+        assert_eq!(snapshot.discovery_failures.len(), 1);
+        assert_eq!(snapshot.discovery_failures[0].server_id, "silent");
+        assert!(snapshot.discovery_failures[0].error.contains("tools capability"));
+
+        let grant_id = engine.grant_capability(CapabilityGrantInput {
+            grant_id: None,
+            capability: "lister:alpha".to_string(),
+            issued_at: None,
+            expires_at: None,
+            scope: None,
+            approval_id: None,
+            constraints: None,
+        });
+        let mut invocation = stdio_invocation("lister:alpha", json!({ "name": "delta" }));
+        invocation.grant_ids = vec![grant_id];
+        let result = engine.call_tool(invocation).await;
+        assert!(result.success);
+        for _ in 0..100 {
+            if tool_ids(&engine).len() == 4 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert!(tool_ids(&engine).contains(&"lister:delta".to_string()));
+        engine.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn discovered_tools_stay_gated() {
+        let engine = ToolGatewayEngine::new();
+        let mut annotated = listing_server("annotated", &[]);
+        annotated.manifest = Some(McpManifest {
+            server_id: "annotated".to_string(),
+            name: "Annotated".to_string(),
+            version: "1".to_string(),
+            description: None,
+            tools: vec![McpTool {
+                name: "beta".to_string(),
+                description: "Beta".to_string(),
+                input_schema: json!({ "type": "object" }),
+                annotations: Some(ToolAnnotations {
+                    required_scopes: Some(vec!["beta.run".to_string()]),
+                    policy_action: None,
+                    requires_confirmation: None,
+                }),
+                metadata: None,
+            }],
+        });
+        engine.register_server(annotated).await.unwrap();
+        let mut defaulted = listing_server("defaulted", &[]);
+        defaulted.tool_access = Some(ToolAnnotations {
+            required_scopes: Some(vec!["lister.run".to_string()]),
+            policy_action: None,
+            requires_confirmation: None,
+        });
+        engine.register_server(defaulted).await.unwrap();
+
+        let denial = |result: McpToolResult| {
+            assert!(!result.success);
+            result.error.unwrap().message
+        };
+        let call = |tool_id: &str| stdio_invocation(tool_id, json!({ "name": "delta" }));
+        let message = denial(engine.call_tool(call("annotated:alpha")).await);
+        assert!(message.contains("Missing capability grant: annotated:alpha"), "{message}");
+        let message = denial(engine.call_tool(call("annotated:beta")).await);
+        assert!(message.contains("Missing capability grant: beta.run"), "{message}");
+        let message = denial(engine.call_tool(call("defaulted:alpha")).await);
+        assert!(message.contains("Missing capability grant: lister.run"), "{message}");
+
+        let grant_id = engine.grant_capability(CapabilityGrantInput {
+            grant_id: None,
+            capability: "annotated:alpha".to_string(),
+            issued_at: None,
+            expires_at: None,
+            scope: None,
+            approval_id: None,
+            constraints: None,
+        });
+        let mut invocation = call("annotated:alpha");
+        invocation.grant_ids = vec![grant_id];
+        assert!(engine.call_tool(invocation).await.success);
+        engine.shutdown().await;
+    }
+
+    const CONTEXT_SERVER: &str = r##"
+import json, sys
+resources = {"file:///notes.md": "# Notes"}
+prompts = [{"name": "review", "description": "Review code", "arguments": [{"name": "lang", "required": True}]}]
+def send(message):
+    print(json.dumps(message), flush=True)
+for line in sys.stdin:
+    message = json.loads(line)
+    method, params = message.get("method"), message.get("params", {})
+    if "id" not in message:
+        continue
+    reply = {"jsonrpc": "2.0", "id": message["id"]}
+    if method == "initialize":
+        reply["result"] = {"protocolVersion": params["protocolVersion"], "capabilities": {"resources": {"subscribe": True, "listChanged": True}, "prompts": {"listChanged": True}}, "serverInfo": {"name": "context", "version": "1"}}
+    elif method == "resources/list":
+        reply["result"] = {"resources": [{"uri": uri, "name": uri.rsplit("/", 1)[-1], "mimeType": "text/markdown"} for uri in resources]}
+    elif method == "resources/templates/list":
+        reply["result"] = {"resourceTemplates": [{"uriTemplate": "file:///{path}", "name": "files"}]}
+    elif method == "resources/read":
+        if params["uri"] in resources:
+            reply["result"] = {"contents": [{"uri": params["uri"], "text": resources[params["uri"]]}]}
+        else:
+            reply["error"] = {"code": -32002, "message": "Resource not found"}
+    elif method == "resources/subscribe":
+        reply["result"] = {}
+        send(reply)
+        resources[params["uri"]] = "# Notes\n- updated"
+        send({"jsonrpc": "2.0", "method": "notifications/resources/updated", "params": {"uri": params["uri"]}})
+        continue
+    elif method == "prompts/list":
+        reply["result"] = {"prompts": prompts}
+    elif method == "prompts/get":
+        lang = params["arguments"]["lang"]
+        reply["result"] = {"description": "Review code", "messages": [{"role": "user", "content": {"type": "text", "text": "Review this " + lang}}]}
+        prompts.append({"name": "explain"})
+        send(reply)
+        send({"jsonrpc": "2.0", "method": "notifications/prompts/list_changed"})
+        continue
+    send(reply)
+"##;
+
+    fn resource_request(uri: &str, grant_ids: Vec<String>) -> ResourceRequest {
+        ResourceRequest {
+            server_id: "context".to_string(),
+            uri: uri.to_string(),
+            request_id: Uuid::new_v4().to_string(),
+            run_id: None,
+            grant_ids,
+            redact_keys: None,
+            timeout_ms: Some(5_000),
+        }
+    }
+
+    #[tokio::test]
+    async fn resources_and_prompts_are_gated_audited_and_kept_current() {
+        let engine = ToolGatewayEngine::new();
+        engine
+            .register_server(McpServerConfig {
+                server_id: "context".to_string(),
+                transport: McpTransport::Stdio,
+                command: Some("python3".to_string()),
+                args: Some(vec!["-c".to_string(), CONTEXT_SERVER.to_string()]),
+                env: None,
+                url: None,
+                manifest: Some(McpManifest {
+                    server_id: "context".to_string(),
+                    name: "Context".to_string(),
+                    version: "1".to_string(),
+                    description: None,
+                    tools: Vec::new(),
+                }),
+                sandbox: None,
+                discover_tools: None,
+                tool_access: None,
+                resource_access: Some(ToolAnnotations {
+                    required_scopes: Some(vec!["resources:read".to_string()]),
+                    policy_action: None,
+                    requires_confirmation: None,
+                }),
+                prompt_access: None,
+                supervision: None,
+            })
+            .await
+            .unwrap();
+
+        let resources = engine.list_resources("context").await.unwrap();
+        assert_eq!(resources[0].uri, "file:///notes.md");
+        assert_eq!(resources[0].mime_type.as_deref(), Some("text/markdown"));
+        let templates = engine.list_resource_templates("context").await.unwrap();
+        assert_eq!(templates[0].uri_template, "file:///{path}");
+
+        let denied = engine
+            .read_resource(resource_request("file:///notes.md", Vec::new()))
+            .await;
+        assert!(matches!(denied.error.unwrap().code, ToolErrorCode::PermissionDenied));
+
+        let grant_id = engine.grant_capability(CapabilityGrantInput {
+            grant_id: None,
+            capability: "resources:read".to_string(),
+            issued_at: None,
+            expires_at: None,
+            scope: None,
+            approval_id: None,
+            constraints: None,
+        });
+        let grants = vec![grant_id];
+        let read = engine
+            .read_resource(resource_request("file:///notes.md", grants.clone()))
+            .await;
+        assert_eq!(read.content[0]["text"], "# Notes");
+        let missing = engine
+            .read_resource(resource_request("file:///gone.md", grants.clone()))
+            .await;
+        assert!(matches!(missing.error.unwrap().code, ToolErrorCode::ResourceNotFound));
+
+        let subscribed = engine
+            .subscribe_resource(resource_request("file:///notes.md", grants.clone()))
+            .await;
+        assert!(subscribed.success);
+        let update_count = |engine: &ToolGatewayEngine| {
+            engine.get_snapshot().resource_subscriptions[0].update_count
+        };
+        for _ in 0..100 {
+            if update_count(&engine) == 1 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(update_count(&engine), 1);
+
+        assert_eq!(engine.list_prompts("context").await.unwrap()[0].name, "review");
+        let prompt = engine
+            .get_prompt(PromptRequest {
+                server_id: "context".to_string(),
+                name: "review".to_string(),
+                arguments: Some(HashMap::from([("lang".to_string(), "rust".to_string())])),
+                request_id: Uuid::new_v4().to_string(),
+                run_id: None,
+                grant_ids: Vec::new(),
+                redact_keys: None,
+                timeout_ms: Some(5_000),
+            })
+            .await;
+        assert_eq!(prompt.content[0]["content"]["text"], "Review this rust");
+        assert_eq!(prompt.meta.unwrap()["description"], "Review code");
+        let prompt_count = |engine: &ToolGatewayEngine| {
+            engine.get_snapshot().catalogs[0].prompts.as_ref().map_or(0, Vec::len)
+        };
+        for _ in 0..100 {
+            if prompt_count(&engine) == 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(prompt_count(&engine), 2);
+
+        let audited: Vec<String> = engine
+            .drain_audit_events(None, None)
+            .into_iter()
+            .map(|event| event.tool_id)
+            .collect();
+        assert_eq!(
+            audited,
+            vec![
+                "context:resources/read",
+                "context:resources/read",
+                "context:resources/read",
+                "context:resources/subscribe",
+                "context:prompts/get",
+            ]
+        );
+        engine.shutdown().await;
+    }
+
+    const PROGRESS_SERVER: &str = r#"
+import json, sys, time
+cancelled = []
+def send(message):
+    print(json.dumps(message), flush=True)
+for line in sys.stdin:
+    message = json.loads(line)
+    method, params = message.get("method"), message.get("params", {})
+    if method == "notifications/cancelled":
+        cancelled.append(params["requestId"])
+    if "id" not in message:
+        continue
+    reply = {"jsonrpc": "2.0", "id": message["id"]}
+    if method == "initialize":
+        reply["result"] = {"protocolVersion": params["protocolVersion"], "capabilities": {"tools": {}}, "serverInfo": {"name": "progress", "version": "1"}}
+    elif params.get("name") == "build":
+        token = params["_meta"]["progressToken"]
+        for step in range(1, 4):
+            time.sleep(0.2)
+            progress = {"progressToken": token, "progress": step, "total": 3, "message": "step " + str(step)}
+            if step == 2:
+                progress["content"] = [{"type": "text", "text": "half built"}]
+            send({"jsonrpc": "2.0", "method": "notifications/progress", "params": progress})
+        reply["result"] = {"content": [{"type": "text", "text": "built"}]}
+    elif params.get("name") == "hang":
+        continue
+    else:
+        reply["result"] = {"content": [{"type": "text", "text": str(len(cancelled))}]}
+    send(reply)
+"#;
+
+    async fn collect_stream(mut stream: ToolCallStream) -> Vec<ToolStreamEvent> {
+        let mut events = Vec::new();
+        while let Some(event) = stream.receiver.recv().await {
+            events.push(event);
+        }
+        events
+    }
+
+    #[tokio::test]
+    async fn streaming_calls_report_progress_and_can_be_cancelled() {
+        let engine = ToolGatewayEngine::new();
+        engine
+            .register_server(McpServerConfig {
+                server_id: "progress".to_string(),
+                transport: McpTransport::Stdio,
+                command: Some("python3".to_string()),
+                args: Some(vec!["-c".to_string(), PROGRESS_SERVER.to_string()]),
+                env: None,
+                url: None,
+                manifest: Some(McpManifest {
+                    server_id: "progress".to_string(),
+                    name: "Progress".to_string(),
+                    version: "1".to_string(),
+                    description: None,
+                    tools: vec![build_tool("build"), build_tool("hang"), build_tool("cancelled")],
+                }),
+                sandbox: None,
+                discover_tools: None,
+                tool_access: None,
+                resource_access: None,
+                prompt_access: None,
+                supervision: None,
+            })
+            .await
+            .unwrap();
+
+        // Three progress steps 200ms apart outlast the 450ms timeout, which each one resets.
+        let mut build = stdio_invocation("progress:build", json!({}));
+        build.timeout_ms = Some(450);
+        let events = collect_stream(engine.call_tool_stream(build)).await;
+        let types: Vec<&str> = events.iter().map(|event| event.event_type.as_str()).collect();
+        assert_eq!(types, vec!["progress", "partial", "progress", "result"]);
+        assert_eq!(events[1].content.as_ref().unwrap()[0]["text"], "half built");
+        assert_eq!(events[2].message.as_deref(), Some("step 3"));
+        let result = events[3].result.as_ref().unwrap();
+        assert_eq!(result.content[0]["text"], "built");
+
+        let hang = engine.call_tool_stream(stdio_invocation("progress:hang", json!({})));
+        hang.cancel.cancel();
+        let events = collect_stream(hang).await;
+        let error = events[0].result.as_ref().unwrap().error.as_ref().unwrap();
+        assert!(matches!(error.code, ToolErrorCode::Cancelled));
+
+        let mut idle = stdio_invocation("progress:hang", json!({}));
+        idle.timeout_ms = Some(200);
+        let events = collect_stream(engine.call_tool_stream(idle)).await;
+        let error = events[0].result.as_ref().unwrap().error.as_ref().unwrap();
+        assert!(matches!(error.code, ToolErrorCode::Timeout));
+
+        let cancelled = engine
+            .call_tool(stdio_invocation("progress:cancelled", json!({})))
+            .await;
+        assert_eq!(cancelled.content[0]["text"], "2");
+        engine.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn policy_decisions_are_enforced_and_confirmations_wait_for_approval() {
+        let engine = ToolGatewayEngine::new();
+        let mut tool = build_tool("count");
+        tool.annotations = Some(ToolAnnotations {
+            required_scopes: Some(vec!["file.write".to_string()]),
+            policy_action: Some("file.write".to_string()),
+            requires_confirmation: None,
+        });
+        engine
+            .register_server(counter_server("counter", vec![tool]))
+            .await
+            .unwrap();
+        engine
+            .set_policy(ToolPolicyConfig {
+                cowork_policy: Some(json!({
+                    "version": "1.0",
+                    "defaults": { "fallback": "deny" },
+                    "rules": [
+                        {
+                            "id": "secrets",
+                            "action": "file.write",
+                            "when": { "matchesPattern": ["**/.env"] },
+                            "decision": "deny",
+                            "reason": "Secrets are read-only"
+                        },
+                        {
+                            "id": "outputs",
+                            "action": "file.write",
+                            "when": { "pathWithinOutputRoot": true },
+                            "decision": "allow"
+                        },
+                        {
+                            "id": "elsewhere",
+                            "action": "file.write",
+                            "decision": "allow_with_confirm",
+                            "reason": "Writes outside the output root"
+                        }
+                    ]
+                })),
+                approvals: Some(true),
+                approval_timeout_ms: Some(5_000),
+            })
+            .unwrap();
+        let grant_id = engine.grant_capability(CapabilityGrantInput {
+            grant_id: None,
+            capability: "file.write".to_string(),
+            issued_at: None,
+            expires_at: None,
+            scope: None,
+            approval_id: None,
+            constraints: None,
+        });
+        let invocation = |by: i64, path: &str| {
+            let mut invocation = stdio_invocation("counter:count", json!({ "by": by }));
+            invocation.grant_ids = vec![grant_id.clone()];
+            invocation.policy_context = Some(crate::types::ToolPolicyContext {
+                path: Some(path.to_string()),
+                output_roots: Some(vec!["/work/out".to_string()]),
+                ..Default::default()
+            });
+            invocation
+        };
+        let last_audit = || engine.drain_audit_events(None, None).pop().unwrap();
+        let rule_id = |event: &ToolAuditEvent| event.policy_decision.clone().unwrap().rule_id;
+
+        let allowed = engine.call_tool(invocation(1, "/work/out/a.txt")).await;
+        assert_eq!(allowed.content[0]["text"], "1");
+        assert_eq!(rule_id(&last_audit()).as_deref(), Some("outputs"));
+
+        let denied = engine.call_tool(invocation(1, "/work/.env")).await;
+        let error = denied.error.unwrap();
+        assert!(matches!(error.code, ToolErrorCode::PermissionDenied));
+        assert_eq!(error.message, "Denied by policy: Secrets are read-only");
+        assert_eq!(rule_id(&last_audit()).as_deref(), Some("secrets"));
+        let mut ungranted = invocation(1, "/work/.env");
+        ungranted.grant_ids.clear();
+        let denied = engine.call_tool(ungranted).await;
+        assert_eq!(denied.error.unwrap().message, "Denied by policy: Secrets are read-only");
+
+        let parked = |by: i64| {
+            let engine = engine.clone();
+            let invocation = invocation(by, "/work/notes.txt");
+            tokio::spawn(async move { engine.call_tool(invocation).await })
+        };
+        let next_approval = || async {
+            loop {
+                if let Some(request) = engine.list_approvals().pop() {
+                    return request;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+
+        let edited = parked(2);
+        let request = next_approval().await;
+        assert_eq!(request.reason, "Writes outside the output root");
+        assert_eq!(request.arguments, json!({ "by": 2 }));
+        engine
+            .resolve_approval(ToolApprovalDecisionInput {
+                approval_id: request.approval_id.clone(),
+                action: ToolApprovalAction::Edit,
+                arguments: Some(json!({ "by": 5 })),
+                reason: None,
+            })
+            .unwrap();
+        assert_eq!(edited.await.unwrap().content[0]["text"], "6");
+        let approval = last_audit().approval.unwrap();
+        assert_eq!(approval.approval_id, request.approval_id);
+        assert_eq!(approval.outcome, ToolApprovalOutcome::Edited);
+        assert!(approval.edited_input_hash.is_some());
+        assert!(engine.list_approvals().is_empty());
+
+        let rejected = parked(3);
+        let request = next_approval().await;
+        engine
+            .resolve_approval(ToolApprovalDecisionInput {
+                approval_id: request.approval_id.clone(),
+                action: ToolApprovalAction::Deny,
+                arguments: None,
+                reason: Some("not now".to_string()),
+            })
+            .unwrap();
+        let error = rejected.await.unwrap().error.unwrap();
+        assert_eq!(error.message, "Tool call denied: not now");
+        assert_eq!(last_audit().approval.unwrap().outcome, ToolApprovalOutcome::Denied);
+        assert!(engine
+            .resolve_approval(ToolApprovalDecisionInput {
+                approval_id: request.approval_id,
+                action: ToolApprovalAction::Approve,
+                arguments: None,
+                reason: None,
+            })
+            .is_err());
+        engine.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn grant_constraints_bound_arguments_runs_and_usage() {
+        let engine = ToolGatewayEngine::new();
+        let scoped_tool = |name: &str, scope: &str| {
+            let mut tool = build_tool(name);
+            tool.annotations = Some(ToolAnnotations {
+                required_scopes: Some(vec![scope.to_string()]),
+                policy_action: None,
+                requires_confirmation: None,
+            });
+            tool
+        };
+        engine
+            .register_server(counter_server(
+                "counter",
+                vec![scoped_tool("write", "files:write"), scoped_tool("fetch", "net")],
+            ))
+            .await
+            .unwrap();
+        let grant = |capability: &str, constraints: Value| {
+            engine.grant_capability(CapabilityGrantInput {
+                grant_id: Some(format!("{capability}-grant")),
+                capability: capability.to_string(),
+                issued_at: None,
+                expires_at: None,
+                scope: None,
+                approval_id: None,
+                constraints: Some(serde_json::from_value(constraints).unwrap()),
+            })
+        };
+        let docs = grant(
+            "files:write",
+            json!({
+                "runId": "run-1",
+                "maxInvocations": 2,
+                "arguments": [{ "argument": "path", "pathGlobs": ["docs/**"] }]
+            }),
+        );
+        let net = grant(
+            "net",
+            json!({
+                "rateLimit": { "maxInvocations": 1, "windowMs": 60_000 },
+                "arguments": [{ "argument": "url", "hostPatterns": ["example.com"] }]
+            }),
+        );
+        let call = |tool: &str, grant_id: &str, run_id: &str, arguments: Value| {
+            let mut invocation = stdio_invocation(tool, arguments);
+            invocation.grant_ids = vec![grant_id.to_string()];
+            invocation.run_id = Some(run_id.to_string());
+            engine.call_tool(invocation)
+        };
+        let denial = |result: McpToolResult| result.error.map(|error| error.message);
+        let write = |run_id: &'static str, path: &'static str| {
+            call("counter:write", &docs, run_id, json!({ "by": 1, "path": path }))
+        };
+
+        assert!(write("run-1", "docs/guide/intro.md").await.success);
+        let consumed = engine.drain_audit_events(None, None).pop().unwrap().consumed_grants;
+        assert_eq!(consumed.len(), 1);
+        assert_eq!((consumed[0].uses, consumed[0].remaining), (1, Some(1)));
+
+        let escaped = denial(write("run-1", "docs/../secrets.txt").await).unwrap();
+        assert!(escaped.contains("path docs/../secrets.txt is outside docs/**"), "{escaped}");
+        let other_run = denial(write("run-2", "docs/a.md").await).unwrap();
+        assert!(other_run.contains("bound to run run-1"), "{other_run}");
+        assert!(write("run-1", "docs/a.md").await.success);
+        let exhausted = denial(write("run-1", "docs/b.md").await).unwrap();
+        assert!(exhausted.contains("used 2 of 2 times"), "{exhausted}");
+
+        let fetch = |url: &str| call("counter:fetch", &net, "run-1", json!({ "by": 1, "url": url }));
+        let foreign = denial(fetch("https://example.org/feed").await).unwrap();
+        assert!(foreign.contains("host example.org is not in example.com"), "{foreign}");
+        assert!(fetch("https://api.example.com/feed").await.success);
+        let limited = denial(fetch("https://api.example.com/feed").await).unwrap();
+        assert!(limited.contains("rate limit of 1 per 60000ms reached"), "{limited}");
+
+        let uses: Vec<u64> = engine.get_snapshot().grants.iter().map(|grant| grant.uses).collect();
+        assert_eq!(uses, vec![2, 1]);
+        engine.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn audit_log_is_chained_persisted_and_verified() {
+        let dir = std::env::temp_dir().join(format!("tool-gateway-audit-{}", Uuid::new_v4()));
+        let path = dir.join("audit.jsonl");
+        let config = ToolAuditConfig {
+            capture_payloads: Some(true),
+            sink: Some(AuditSinkConfig::File {
+                path: path.to_string_lossy().to_string(),
+            }),
+        };
+        let counter_engine = || async {
+            let engine = ToolGatewayEngine::new();
+            engine
+                .register_server(counter_server("counter", vec![build_tool("count")]))
+                .await
+                .unwrap();
+            engine.configure_audit(config.clone()).unwrap();
+            engine
+        };
+        let count = || stdio_invocation("counter:count", json!({ "by": 1, "token": "hunter2" }));
+
+        let engine = counter_engine().await;
+        assert!(engine.call_tool(count()).await.success);
+        assert!(engine.call_tool(count()).await.success);
+        let events = engine.drain_audit_events(None, None);
+        assert_eq!(events[0].prev_hash, GENESIS_HASH);
+        assert_eq!(events[1].prev_hash, events[0].hash);
+        assert_eq!(events[0].input, Some(json!({ "by": 1, "token": "<redacted>" })));
+        assert_eq!(events[1].output.as_ref().unwrap()["content"][0]["text"], "2");
+        engine.shutdown().await;
+
+        // A restarted gateway continues the stored chain.
+        let engine = counter_engine().await;
+        assert!(engine.call_tool(count()).await.success);
+        let event = engine.drain_audit_events(None, None).pop().unwrap();
+        assert_eq!((event.sequence, &event.prev_hash), (3, &events[1].hash));
+        let report = engine.verify_audit_chain(None).unwrap();
+        assert!(report.valid && report.checked == 3, "{report:?}");
+
+        let gap = engine.verify_audit_chain(Some(vec![events[0].clone(), event])).unwrap();
+        assert_eq!(gap.broken_at, Some(3));
+        let stored = std::fs::read_to_string(&path).unwrap();
+        let stored = stored.replacen("\"success\":true", "\"success\":false", 1);
+        std::fs::write(&path, stored).unwrap();
+        let edited = engine.verify_audit_chain(None).unwrap();
+        assert_eq!((edited.valid, edited.broken_at), (false, Some(1)));
+        engine.shutdown().await;
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    /// Holds each append until the test releases it.
+    struct GatedSink {
+        gate: Mutex<std::sync::mpsc::Receiver<()>>,
+        appended: Arc<Mutex<Vec<u64>>>,
+    }
+
+    impl crate::audit::AuditSink for GatedSink {
+        fn append(&self, event: &ToolAuditEvent) -> Result<(), String> {
+            let _ = self.gate.lock().unwrap().recv();
+            self.appended.lock().unwrap().push(event.sequence);
+            Ok(())
+        }
+
+        fn load(&self) -> Result<Vec<ToolAuditEvent>, String> {
+            Ok(Vec::new())
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn audit_appends_do_not_hold_the_gateway_state() {
+        let engine = ToolGatewayEngine::new();
+        engine
+            .register_server(counter_server("counter", vec![build_tool("count")]))
+            .await
+            .unwrap();
+        let (release, gate) = std::sync::mpsc::channel();
+        let appended = Arc::new(Mutex::new(Vec::new()));
+        let sink = GatedSink {
+            gate: Mutex::new(gate),
+            appended: appended.clone(),
+        };
+        engine.state.lock().unwrap().audit_sink = Some(Arc::new(AuditWriter::new(Box::new(sink))));
+
+        let calling = tokio::spawn({
+            let engine = engine.clone();
+            async move {
+                let count = stdio_invocation("counter:count", json!({ "by": 1 }));
+                engine.call_tool(count).await
+            }
+        });
+        // The event is visible while its append is still waiting on the sink. The wait
+        // sleeps the thread, as the blocked append may be holding the runtime's timer.
+        let mut polls = 0;
+        while engine.drain_audit_events(None, None).is_empty() {
+            polls += 1;
+            assert!(polls < 500, "the audit event was never recorded");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert!(engine.get_snapshot().audit_sink_error.is_none());
+        release.send(()).unwrap();
+        assert!(calling.await.unwrap().success);
+        assert_eq!(*appended.lock().unwrap(), vec![1]);
+        engine.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn audit_log_missing_its_head_fails_verification() {
+        let dir = std::env::temp_dir().join(format!("tool-gateway-audit-{}", Uuid::new_v4()));
+        let path = dir.join("audit.jsonl");
+        let config = ToolAuditConfig {
+            capture_payloads: None,
+            sink: Some(AuditSinkConfig::File {
+                path: path.to_string_lossy().to_string(),
+            }),
+        };
+        let engine = ToolGatewayEngine::new();
+        engine
+            .register_server(counter_server("counter", vec![build_tool("count")]))
+            .await
+            .unwrap();
+        engine.configure_audit(config.clone()).unwrap();
+        for _ in 0..3 {
+            let count = stdio_invocation("counter:count", json!({ "by": 1 }));
+            assert!(engine.call_tool(count).await.success);
+        }
+        assert!(engine.verify_audit_chain(None).unwrap().valid);
+
+        let stored = std::fs::read_to_string(&path).unwrap();
+        let tail: Vec<&str> = stored.lines().skip(2).collect();
+        std::fs::write(&path, tail.join("\n") + "\n").unwrap();
+        let truncated = engine.verify_audit_chain(None).unwrap();
+        assert_eq!((truncated.valid, truncated.broken_at), (false, Some(1)));
+        engine.shutdown().await;
+
+        // Nor can a gateway continue it.
+        let restarted = ToolGatewayEngine::new();
+        let error = restarted.configure_audit(config).unwrap_err();
+        assert!(error.contains("events before it are missing"), "{error}");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn supervised_servers_are_restarted_until_the_cap() {
+        let engine = ToolGatewayEngine::new();
+        engine
+            .register_server(McpServerConfig {
+                supervision: Some(SupervisionConfig {
+                    ping_interval_ms: Some(50),
+                    restart_backoff_ms: Some(10),
+                    max_restarts: Some(1),
+                    ..SupervisionConfig::default()
+                }),
+                ..counter_server("counter", vec![build_tool("count"), build_tool("crash")])
+            })
+            .await
+            .unwrap();
+        let health = || engine.get_snapshot().servers.pop().unwrap();
+        let settled = |restarts: u32, state: ServerState| async move {
+            for _ in 0..100 {
+                let health = health();
+                if health.restarts == restarts && health.state == Some(state) {
+                    return health;
+                }
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+            panic!("server never settled: {:?}", health());
+        };
+
+        settled(0, ServerState::Ready).await;
+        let count = || engine.call_tool(stdio_invocation("counter:count", json!({ "by": 1 })));
+        assert!(count().await.success);
+        assert!(!engine.call_tool(stdio_invocation("counter:crash", json!({}))).await.success);
+        let restarted = settled(1, ServerState::Ready).await;
+        assert_eq!((restarted.calls, restarted.failures), (2, 1));
+        assert!(restarted.p95_latency_ms.is_some());
+        // The restarted process starts counting afresh.
+        assert_eq!(count().await.content[0]["text"], "1");
+
+        assert!(!engine.call_tool(stdio_invocation("counter:crash", json!({}))).await.success);
+        settled(1, ServerState::Crashed).await;
+        let refused = count().await.error.unwrap().message;
+        assert!(refused.contains("is no longer restarted"), "{refused}");
+        let states: Vec<ServerState> =
+            engine.get_snapshot().lifecycle_events.iter().map(|event| event.state).collect();
+        assert_eq!(
+            states,
+            vec![
+                ServerState::Starting,
+                ServerState::Ready,
+                ServerState::Crashed,
+                ServerState::Starting,
+                ServerState::Ready,
+                ServerState::Crashed,
+            ]
+        );
+        // Stopping supervision lifts the refusal; the next call starts the server again.
+        engine.shutdown().await;
+        assert!(count().await.success);
+        engine.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn supervised_servers_that_stay_up_are_forgiven_their_restarts() {
+        let engine = ToolGatewayEngine::new();
+        engine
+            .register_server(McpServerConfig {
+                supervision: Some(SupervisionConfig {
+                    ping_interval_ms: Some(20),
+                    restart_backoff_ms: Some(10),
+                    max_backoff_ms: Some(300),
+                    max_restarts: Some(1),
+                    ..SupervisionConfig::default()
+                }),
+                ..counter_server("counter", vec![build_tool("count"), build_tool("crash")])
+            })
+            .await
+            .unwrap();
+        let health = || engine.get_snapshot().servers.pop().unwrap();
+        let settled = |restarts: u32, state: ServerState| async move {
+            for _ in 0..100 {
+                let health = health();
+                if health.restarts == restarts && health.state == Some(state) {
+                    return;
+                }
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+            panic!("server never settled: {:?}", health());
+        };
+        let crash = || engine.call_tool(stdio_invocation("counter:crash", json!({})));
+
+        settled(0, ServerState::Ready).await;
+        crash().await;
+        settled(1, ServerState::Ready).await;
+        // Up for longer than the longest backoff, so the next crash is restarted too.
+        tokio::time::sleep(Duration::from_millis(600)).await;
+        crash().await;
+        settled(2, ServerState::Ready).await;
+        crash().await;
+        settled(2, ServerState::Crashed).await;
+        let refused = engine
+            .call_tool(stdio_invocation("counter:count", json!({ "by": 1 })))
+            .await;
+        assert!(refused.error.unwrap().message.contains("after 1 restarts"));
+        engine.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn oversize_results_are_truncated_and_spilled_to_artifacts() {
+        let engine = ToolGatewayEngine::new();
+        engine
+            .register_server(counter_server("counter", vec![build_tool("count")]))
+            .await
+            .unwrap();
+        let dir = std::env::temp_dir().join(format!("tool-gateway-spill-{}", Uuid::new_v4()));
+        let limits = json!({
+            "default": { "maxTokens": 10_000 },
+            "tools": { "counter:count": { "maxBytes": 500, "strategy": "middle_elide" } },
+            "artifactDir": dir.to_string_lossy(),
+        });
+        engine.set_result_limits(serde_json::from_value(limits).unwrap()).unwrap();
+        let count = |pad: String| {
+            engine.call_tool(stdio_invocation("counter:count", json!({ "by": 1, "pad": pad })))
+        };
+
+        let small = count(String::new()).await;
+        assert_eq!(small.content, vec![json!({ "type": "text", "text": "1" })]);
+        assert_eq!(engine.drain_audit_events(None, None)[0].original_result_bytes, None);
+
+        let large = count(format!("{}{}", "a".repeat(300), "z".repeat(300))).await;
+        assert!(large.success);
+        assert!(serde_json::to_vec(&large.content).unwrap().len() <= 500);
+        let text = large.content[0]["text"].as_str().unwrap();
+        assert!(text.starts_with("2aaa") && text.ends_with("zzz"), "{text}");
+        assert!(text.contains("[Result truncated from"), "{text}");
+        let uri = large.content[1]["resource"]["uri"].as_str().unwrap();
+        let path = reqwest::Url::parse(uri).unwrap().to_file_path().unwrap();
+        let spilled: Vec<Value> = serde_json::from_slice(&std::fs::read(path).unwrap()).unwrap();
+        assert_eq!(spilled[0]["text"].as_str().map(str::len), Some(601));
+        let original = serde_json::to_vec(&spilled).unwrap().len() as u64;
+        let event = engine.drain_audit_events(None, None).pop().unwrap();
+        assert_eq!(event.original_result_bytes, Some(original));
+
+        engine.shutdown().await;
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    fn remote_server(server_id: &str, transport: McpTransport, url: String) -> McpServerConfig {
+        McpServerConfig {
+            server_id: server_id.to_string(),
+            transport,
+            command: None,
+            args: None,
+            env: None,
+            url: Some(url),
+            manifest: Some(McpManifest {
+                server_id: server_id.to_string(),
+                name: "Remote".to_string(),
+                version: "1".to_string(),
+                description: None,
+                tools: vec![build_tool("echo")],
+            }),
+            sandbox: None,
+            discover_tools: None,
+            tool_access: None,
+            resource_access: None,
+            prompt_access: None,
+            supervision: None,
+        }
+    }
+
+    fn initialize_result(id: &Value) -> Value {
+        json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "result": {
+                "protocolVersion": "2025-06-18",
+                "capabilities": { "tools": {} },
+                "serverInfo": { "name": "remote", "version": "1" },
+            },
+        })
+    }
+
+    /// Reads one HTTP/1.1 request: method, lower-cased headers and JSON body.
+    async fn read_http_request(
+        socket: &mut tokio::net::TcpStream,
+    ) -> (String, HashMap<String, String>, Value) {
+        use tokio::io::AsyncReadExt;
+
+        let mut data = Vec::new();
+        let mut buffer = [0u8; 4096];
+        loop {
+            let read = socket.read(&mut buffer).await.unwrap();
+            data.extend_from_slice(&buffer[..read]);
+            let Some(end) = data.windows(4).position(|window| window == b"\r\n\r\n") else {
+                continue;
+            };
+            let head = String::from_utf8_lossy(&data[..end]).to_string();
+            let mut lines = head.lines();
+            let method = lines.next().unwrap().split(' ').next().unwrap().to_string();
+            let headers: HashMap<String, String> = lines
+                .filter_map(|line| line.split_once(':'))
+                .map(|(name, value)| (name.to_ascii_lowercase(), value.trim().to_string()))
+                .collect();
+            let length: usize = headers
+                .get("content-length")
+                .map_or(0, |value| value.parse().unwrap());
+            while data.len() < end + 4 + length {
+                let read = socket.read(&mut buffer).await.unwrap();
+                data.extend_from_slice(&buffer[..read]);
+            }
+            let body = &data[end + 4..end + 4 + length];
+            let body = serde_json::from_slice(body).unwrap_or(Value::Null);
+            return (method, headers, body);
+        }
+    }
+
+    /// A Streamable HTTP server that drops the SSE stream of `tools/call` after one event
+    /// and delivers the result only when the client resumes with `Last-Event-ID`.
+    async fn spawn_streamable_server(seen: Arc<Mutex<Vec<String>>>) -> String {
+        use tokio::io::AsyncWriteExt;
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let call_id = Arc::new(Mutex::new(Value::Null));
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let (method, headers, body) = read_http_request(&mut socket).await;
+                let session = headers.get("mcp-session-id").cloned().unwrap_or_default();
+                let rpc = body["method"].as_str().unwrap_or_default().to_string();
+                seen.lock().unwrap().push(format!("{method} {rpc} session={session}"));
+                let head = "HTTP/1.1 200 OK\r\nconnection: close\r\n";
+                let empty = |status: &str| {
+                    format!("HTTP/1.1 {status}\r\nconnection: close\r\ncontent-length: 0\r\n\r\n")
+                };
+                let response = match (method.as_str(), rpc.as_str()) {
+                    ("POST", "initialize") => {
+                        let reply = initialize_result(&body["id"]).to_string();
+                        let length = reply.len();
+                        format!(
+                            "{head}mcp-session-id: s-1\r\ncontent-type: application/json\r\n\
+                             content-length: {length}\r\n\r\n{reply}"
+                        )
+                    }
+                    ("POST", "tools/call") => {
+                        *call_id.lock().unwrap() = body["id"].clone();
+                        let log = json!({ "jsonrpc": "2.0", "method": "notifications/message" });
+                        format!(
+                            "{head}content-type: text/event-stream\r\n\r\n\
+                             retry: 10\nid: 1\ndata: {log}\n\n"
+                        )
+                    }
+                    ("GET", _) if headers.get("last-event-id").map(String::as_str) == Some("1") => {
+                        let id = call_id.lock().unwrap().clone();
+                        let reply = json!({
+                            "jsonrpc": "2.0",
+                            "id": id,
+                            "result": { "content": [{ "type": "text", "text": "resumed" }] },
+                        });
+                        let event = format!("id: 2\ndata: {reply}\n\n");
+                        format!("{head}content-type: text/event-stream\r\n\r\n{event}")
+                    }
+                    ("GET", _) => empty("405 Method Not Allowed"),
+                    _ => empty("202 Accepted"),
+                };
+                let _ = socket.write_all(response.as_bytes()).await;
+            }
+        });
+        format!("http://{address}/mcp")
+    }
+
+    #[tokio::test]
+    async fn streamable_http_keeps_the_session_and_resumes_dropped_streams() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let url = spawn_streamable_server(seen.clone()).await;
+        let engine = ToolGatewayEngine::new();
+        engine
+            .register_server(remote_server("remote", McpTransport::Http, url))
+            .await
+            .unwrap();
+
+        let result = engine
+            .call_tool(stdio_invocation("remote:echo", json!({ "text": "hi" })))
+            .await;
+        assert_eq!(result.content[0]["text"], "resumed");
+        engine.shutdown().await;
+
+        let seen = seen.lock().unwrap().clone();
+        assert_eq!(seen[0], "POST initialize session=");
+        assert!(seen.contains(&"POST notifications/initialized session=s-1".to_string()));
+        assert!(seen.contains(&"POST tools/call session=s-1".to_string()));
+        assert!(seen.contains(&"DELETE  session=s-1".to_string()));
+    }
+
+    #[tokio::test]
+    async fn http_posts_are_sent_concurrently_within_the_call_deadline() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let dropped = Arc::new(AtomicBool::new(false));
+        let silent_dropped = dropped.clone();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let dropped = silent_dropped.clone();
+                tokio::spawn(async move {
+                    let (_, _, body) = read_http_request(&mut socket).await;
+                    let reply = match body["method"].as_str() {
+                        Some("initialize") => initialize_result(&body["id"]),
+                        Some("tools/call") => {
+                            let text = body["params"]["arguments"]["text"].clone();
+                            if text == "silent" {
+                                // Opens an event stream, never writes to it and notes when
+                                // the client hangs up.
+                                let head = "HTTP/1.1 200 OK\r\n\
+                                            content-type: text/event-stream\r\n\r\n";
+                                let _ = socket.write_all(head.as_bytes()).await;
+                                let _ = socket.read(&mut [0u8; 1]).await;
+                                dropped.store(true, Ordering::SeqCst);
+                                return;
+                            }
+                            if text == "slow" {
+                                tokio::time::sleep(Duration::from_millis(1_500)).await;
+                            }
+                            json!({
+                                "jsonrpc": "2.0",
+                                "id": body["id"],
+                                "result": { "content": [{ "type": "text", "text": text }] },
+                            })
+                        }
+                        _ => {
+                            let accepted = "HTTP/1.1 202 Accepted\r\ncontent-length: 0\r\n\r\n";
+                            let _ = socket.write_all(accepted.as_bytes()).await;
+                            return;
+                        }
+                    };
+                    let reply = reply.to_string();
+                    let response = format!(
+                        "HTTP/1.1 200 OK\r\nconnection: close\r\nmcp-session-id: s-1\r\n\
+                         content-type: application/json\r\ncontent-length: {}\r\n\r\n{reply}",
+                        reply.len()
+                    );
+                    let _ = socket.write_all(response.as_bytes()).await;
+                });
+            }
+        });
+        let engine = ToolGatewayEngine::new();
+        let url = format!("http://{address}/mcp");
+        engine
+            .register_server(remote_server("remote", McpTransport::Http, url))
+            .await
+            .unwrap();
+
+        let started = Instant::now();
+        let slow = engine.call_tool(stdio_invocation("remote:echo", json!({ "text": "slow" })));
+        let fast = async {
+            let result = engine
+                .call_tool(stdio_invocation("remote:echo", json!({ "text": "fast" })))
+                .await;
+            (result, started.elapsed())
+        };
+        let (slow, (fast, fast_elapsed)) = tokio::join!(slow, fast);
+        assert_eq!(fast.content[0]["text"], "fast");
+        assert!(fast_elapsed < Duration::from_millis(1_000));
+        assert_eq!(slow.content[0]["text"], "slow");
+
+        let mut hurried = stdio_invocation("remote:echo", json!({ "text": "slow" }));
+        hurried.timeout_ms = Some(200);
+        let timed_out = engine.call_tool(hurried).await;
+        assert!(timed_out
+            .error
+            .as_ref()
+            .is_some_and(|error| matches!(error.code, ToolErrorCode::Timeout)));
+
+        let mut silent = stdio_invocation("remote:echo", json!({ "text": "silent" }));
+        silent.timeout_ms = Some(200);
+        assert!(!engine.call_tool(silent).await.success);
+        for _ in 0..50 {
+            if dropped.load(Ordering::SeqCst) {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert!(dropped.load(Ordering::SeqCst));
+        engine.shutdown().await;
+    }
+
+    #[tokio::test]
+    async fn websocket_servers_answer_calls_and_respect_network_policy() {
+        use futures_util::{SinkExt, StreamExt};
+        use tokio_tungstenite::tungstenite::Message;
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                // Like most servers, it ignores the `mcp` subprotocol the client offers.
+                let Ok(mut socket) = tokio_tungstenite::accept_async(stream).await else {
+                    continue;
+                };
+                while let Some(Ok(Message::Text(text))) = socket.next().await {
+                    let message: Value = serde_json::from_str(text.as_str()).unwrap();
+                    let reply = match message["method"].as_str() {
+                        Some("initialize") => initialize_result(&message["id"]),
+                        Some("tools/call") => {
+                            let text = &message["params"]["arguments"]["text"];
+                            json!({
+                                "jsonrpc": "2.0",
+                                "id": message["id"],
+                                "result": { "content": [{ "type": "text", "text": text }] },
+                            })
+                        }
+                        _ => continue,
+                    };
+                    socket.send(Message::text(reply.to_string())).await.unwrap();
+                }
+            }
+        });
+
+        let engine = ToolGatewayEngine::new();
+        let url = format!("ws://{address}/mcp");
+        engine
+            .register_server(remote_server("ws", McpTransport::Websocket, url.clone()))
+            .await
+            .unwrap();
+        let mut blocked = remote_server("blocked", McpTransport::Websocket, url);
+        blocked.sandbox = Some(ToolGatewaySandboxConfig {
+            network_access: "allowlist".to_string(),
+            allowed_hosts: Some(vec!["example.com".to_string()]),
+            allowed_roots: None,
+            fs_isolation: "none".to_string(),
+            working_directory: None,
+        });
+        engine.register_server(blocked).await.unwrap();
+
+        let echoed = engine
+            .call_tool(stdio_invocation("ws:echo", json!({ "text": "over ws" })))
+            .await;
+        assert_eq!(echoed.content[0]["text"], "over ws");
+
+        let denied = engine
+            .call_tool(stdio_invocation("blocked:echo", json!({ "text": "nope" })))
+            .await;
+        assert!(denied
+            .error
+            .as_ref()
+            .is_some_and(|error| matches!(error.code, ToolErrorCode::PermissionDenied)));
+        engine.shutdown().await;
+    }
+
     #[test]
     fn audit_hashes_are_deterministic() {
         let redaction_keys = build_redaction_keys(Some(&vec!["token".to_string()]));
@@ -0,0 +1,154 @@
+use crate::types::{ArgumentConstraint, CapabilityGrant};
+use regex::Regex;
+use reqwest::Url;
+use serde_json::Value;
+use std::collections::{HashMap, VecDeque};
+use std::sync::{LazyLock, Mutex, PoisonError};
+
+/// Grants come and go, so the glob cache is dropped rather than grown once it holds this
+/// many.
+const MAX_CACHED_GLOBS: usize = 256;
+
+/// `pathGlobs` compiled to regexes, by glob; `None` for ones that fail to compile.
+static GLOBS: LazyLock<Mutex<HashMap<String, Option<Regex>>>> = LazyLock::new(Default::default);
+
+/// The parts of a call that grant constraints are checked against.
+pub(crate) struct GrantUse<'a> {
+    pub(crate) grant_ids: &'a [String],
+    pub(crate) run_id: Option<&'a str>,
+    pub(crate) arguments: &'a Value,
+}
+
+/// Why `grant` does not cover the call, if it does not. `recent_uses` are the times of
+/// its uses inside the longest rate-limit window.
+pub(crate) fn grant_rejection(
+    grant: &CapabilityGrant,
+    recent_uses: Option<&VecDeque<i64>>,
+    call: &GrantUse<'_>,
+    now: i64,
+) -> Option<String> {
+    let constraints = grant.constraints.as_ref()?;
+    if let Some(run_id) = &constraints.run_id
+        && call.run_id != Some(run_id.as_str())
+    {
+        return Some(format!("bound to run {run_id}"));
+    }
+    if let Some(max) = constraints.max_invocations
+        && grant.uses >= max
+    {
+        return Some(format!("used {max} of {max} times"));
+    }
+    if let Some(limit) = &constraints.rate_limit {
+        let since = now - limit.window_ms as i64;
+        let used = recent_uses.map_or(0, |uses| uses.iter().filter(|at| **at > since).count());
+        if used as u64 >= limit.max_invocations {
+            return Some(format!(
+                "rate limit of {} per {}ms reached",
+                limit.max_invocations, limit.window_ms
+            ));
+        }
+    }
+    constraints
+        .arguments
+        .iter()
+        .flatten()
+        .find_map(|constraint| argument_rejection(constraint, call.arguments))
+}
+
+fn argument_rejection(constraint: &ArgumentConstraint, arguments: &Value) -> Option<String> {
+    let name = &constraint.argument;
+    let value = if name.starts_with('/') {
+        arguments.pointer(name)
+    } else {
+        arguments.get(name)
+    };
+    let values: Vec<&str> = match value {
+        Some(Value::String(value)) => vec![value.as_str()],
+        Some(Value::Array(items)) => {
+            let strings: Option<Vec<&str>> = items.iter().map(Value::as_str).collect();
+            match strings {
+                Some(strings) => strings,
+                None => return Some(format!("argument {name} is not a list of strings")),
+            }
+        }
+        Some(_) => return Some(format!("argument {name} is not a string")),
+        None => return Some(format!("argument {name} is missing")),
+    };
+
+    for value in values {
+        if let Some(globs) = &constraint.path_globs
+            && !globs.iter().any(|glob| path_matches(value, glob))
+        {
+            return Some(format!("{name} {value} is outside {}", globs.join(", ")));
+        }
+        if let Some(patterns) = &constraint.host_patterns {
+            let host = host_of(value);
+            if !patterns.iter().any(|pattern| host_matches(&host, pattern)) {
+                return Some(format!("{name} host {host} is not in {}", patterns.join(", ")));
+            }
+        }
+    }
+    None
+}
+
+fn path_matches(path: &str, glob: &str) -> bool {
+    glob_regex(glob).is_some_and(|regex| regex.is_match(&normalize_path(path)))
+}
+
+fn glob_regex(glob: &str) -> Option<Regex> {
+    let mut globs = GLOBS.lock().unwrap_or_else(PoisonError::into_inner);
+    if let Some(regex) = globs.get(glob) {
+        return regex.clone();
+    }
+    if globs.len() >= MAX_CACHED_GLOBS {
+        globs.clear();
+    }
+    let pattern = regex::escape(&normalize_path(glob))
+        .replace("\\*\\*/", "(?:.*/)?")
+        .replace("\\*\\*", ".*")
+        .replace("\\*", "[^/]*")
+        .replace("\\?", "[^/]");
+    let regex = Regex::new(&format!("^{pattern}$")).ok();
+    globs.insert(glob.to_string(), regex.clone());
+    regex
+}
+
+/// Resolves `.` and `..` lexically. A relative path that climbs above its start keeps the
+/// leading `..`, so it cannot match a glob rooted below it.
+fn normalize_path(path: &str) -> String {
+    let path = path.replace('\\', "/");
+    let absolute = path.starts_with('/');
+    let mut parts: Vec<&str> = Vec::new();
+    for part in path.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                if parts.last().is_some_and(|last| *last != "..") {
+                    parts.pop();
+                } else if !absolute {
+                    parts.push("..");
+                }
+            }
+            _ => parts.push(part),
+        }
+    }
+    let joined = parts.join("/");
+    if absolute { format!("/{joined}") } else { joined }
+}
+
+fn host_of(value: &str) -> String {
+    let host = match Url::parse(value) {
+        Ok(url) => url.host_str().map(str::to_string),
+        Err(_) => None,
+    };
+    let host = host.unwrap_or_else(|| value.split([':', '/']).next().unwrap_or("").to_string());
+    host.to_ascii_lowercase()
+}
+
+fn host_matches(host: &str, pattern: &str) -> bool {
+    let pattern = pattern.to_ascii_lowercase();
+    match pattern.strip_prefix("*.") {
+        Some(domain) => host.ends_with(&format!(".{domain}")),
+        None => host == pattern || host.ends_with(&format!(".{pattern}")),
+    }
+}
@@ -0,0 +1,318 @@
+use crate::mcp::{request_id, Channel, McpError};
+use futures_util::StreamExt;
+use reqwest::header::{HeaderMap, HeaderValue, ACCEPT, CONTENT_TYPE};
+use reqwest::{Client, Method, RequestBuilder, Response, StatusCode};
+use serde_json::Value;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+
+const SESSION_HEADER: &str = "mcp-session-id";
+const PROTOCOL_HEADER: &str = "mcp-protocol-version";
+const LAST_EVENT_HEADER: &str = "last-event-id";
+/// How long a notification POST, GET or DELETE may take to return headers; requests are
+/// bounded by their caller's deadline instead, and SSE bodies may stream for longer.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(30);
+const DEFAULT_RETRY: Duration = Duration::from_millis(500);
+const MAX_RESUMES: u32 = 5;
+
+/// One MCP Streamable HTTP session: every message is POSTed to the endpoint, and replies
+/// arrive as a JSON body or as an SSE stream that can be resumed with `Last-Event-ID`.
+pub(crate) struct HttpSession {
+    client: Client,
+    url: String,
+    session_id: Mutex<Option<String>>,
+    protocol_version: Mutex<Option<String>>,
+}
+
+/// Starts the session's writer task, which POSTs each queued message on its own task.
+pub(crate) fn open(
+    url: &str,
+    client: Client,
+    channel: &Arc<Channel>,
+    queued: mpsc::UnboundedReceiver<Value>,
+) -> Arc<HttpSession> {
+    let session = Arc::new(HttpSession {
+        client,
+        url: url.to_string(),
+        session_id: Mutex::new(None),
+        protocol_version: Mutex::new(None),
+    });
+    tokio::spawn(write_messages(session.clone(), channel.clone(), queued));
+    session
+}
+
+impl HttpSession {
+    pub(crate) fn set_protocol_version(&self, version: &str) {
+        *lock(&self.protocol_version) = Some(version.to_string());
+    }
+
+    /// Opens the optional GET stream the server uses for unsolicited notifications.
+    pub(crate) fn listen(self: &Arc<Self>, channel: &Arc<Channel>) {
+        let session = self.clone();
+        let channel = channel.clone();
+        tokio::spawn(async move {
+            // Servers without a listening stream answer 405, which is not an error.
+            if let Ok(response) = session.open_stream(None).await {
+                read_events(&session, &channel, response, None).await;
+            }
+        });
+    }
+
+    /// Ends the server-side session, if one was issued.
+    pub(crate) async fn terminate(&self) {
+        if lock(&self.session_id).is_none() {
+            return;
+        }
+        let request = self.request(Method::DELETE).timeout(RESPONSE_TIMEOUT);
+        let _ = request.send().await;
+    }
+
+    fn request(&self, method: Method) -> RequestBuilder {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            ACCEPT,
+            HeaderValue::from_static("application/json, text/event-stream"),
+        );
+        for (name, value) in [
+            (SESSION_HEADER, lock(&self.session_id).clone()),
+            (PROTOCOL_HEADER, lock(&self.protocol_version).clone()),
+        ] {
+            if let Some(value) = value.and_then(|value| HeaderValue::from_str(&value).ok()) {
+                headers.insert(name, value);
+            }
+        }
+        self.client.request(method, &self.url).headers(headers)
+    }
+
+    async fn post(&self, message: &Value, deadline: Instant) -> Result<Response, String> {
+        let request = self.request(Method::POST).json(message);
+        let response = tokio::time::timeout_at(deadline, request.send())
+            .await
+            .map_err(|_| "MCP HTTP request timed out".to_string())?
+            .map_err(|error| error.to_string())?;
+        if let Some(session_id) = response
+            .headers()
+            .get(SESSION_HEADER)
+            .and_then(|value| value.to_str().ok())
+        {
+            *lock(&self.session_id) = Some(session_id.to_string());
+        }
+        Ok(response)
+    }
+
+    async fn open_stream(&self, last_event_id: Option<&str>) -> Result<Response, String> {
+        let mut request = self
+            .request(Method::GET)
+            .header(ACCEPT, "text/event-stream");
+        if let Some(last_event_id) = last_event_id {
+            request = request.header(LAST_EVENT_HEADER, last_event_id);
+        }
+        let response = tokio::time::timeout(RESPONSE_TIMEOUT, request.send())
+            .await
+            .map_err(|_| "MCP HTTP stream timed out".to_string())?
+            .map_err(|error| error.to_string())?;
+        if !response.status().is_success() {
+            return Err(format!("MCP HTTP stream returned {}", response.status()));
+        }
+        Ok(response)
+    }
+
+    fn has_session(&self) -> bool {
+        lock(&self.session_id).is_some()
+    }
+}
+
+/// POSTs queued messages concurrently, so a slow reply holds up nothing else on the
+/// session. Only `initialize` is awaited before the next message: its reply carries the
+/// session id every later message must send.
+async fn write_messages(
+    session: Arc<HttpSession>,
+    channel: Arc<Channel>,
+    mut queued: mpsc::UnboundedReceiver<Value>,
+) {
+    while let Some(message) = queued.recv().await {
+        if message.get("method").and_then(Value::as_str) == Some("initialize") {
+            deliver(session.clone(), channel.clone(), message).await;
+        } else {
+            tokio::spawn(deliver(session.clone(), channel.clone(), message));
+        }
+    }
+}
+
+/// POSTs one message and routes its reply, giving up when the caller of a request stops
+/// waiting for it.
+async fn deliver(session: Arc<HttpSession>, channel: Arc<Channel>, message: Value) {
+    let id = request_id(&message);
+    let deadline = match id {
+        Some(id) => match channel.deadline(id) {
+            Some(deadline) => deadline,
+            None => return,
+        },
+        None => Instant::now() + RESPONSE_TIMEOUT,
+    };
+    let fail = |error: String| {
+        if let Some(id) = id {
+            channel.resolve(id, Err(McpError::Failure(error)));
+        }
+    };
+    let response = match session.post(&message, deadline).await {
+        Ok(response) => response,
+        Err(error) => return fail(error),
+    };
+
+    let status = response.status();
+    // A 404 for a request carrying a session id means the session expired; the client
+    // starts a new one on its next request.
+    if status == StatusCode::NOT_FOUND && session.has_session() {
+        channel.close();
+        return;
+    }
+    if !status.is_success() {
+        return fail(format!("MCP server returned {status}"));
+    }
+    if status == StatusCode::ACCEPTED || id.is_none() {
+        return;
+    }
+
+    let is_stream = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("text/event-stream"));
+    if is_stream {
+        tokio::spawn(read_stream(session, channel.clone(), response, id, deadline));
+        return;
+    }
+    match tokio::time::timeout_at(deadline, response.json::<Value>()).await {
+        Ok(Ok(reply)) => channel.handle_message(&reply),
+        Ok(Err(error)) => fail(format!("Invalid MCP HTTP response: {error}")),
+        Err(_) => fail("MCP HTTP request timed out".to_string()),
+    }
+}
+
+/// Reads a request's event stream, dropping it at the caller's deadline even if it never
+/// sends anything.
+async fn read_stream(
+    session: Arc<HttpSession>,
+    channel: Arc<Channel>,
+    response: Response,
+    id: Option<u64>,
+    deadline: Instant,
+) {
+    let _ = tokio::time::timeout_at(deadline, read_events(&session, &channel, response, id)).await;
+}
+
+/// Dispatches the messages of an SSE stream. A stream that drops before it has done its
+/// job (answering request `id`, or listening while the channel is open) is resumed from
+/// the last event id it saw.
+async fn read_events(
+    session: &HttpSession,
+    channel: &Channel,
+    mut response: Response,
+    id: Option<u64>,
+) {
+    let waiting = || match id {
+        Some(id) => channel.is_pending(id),
+        None => !channel.is_closed(),
+    };
+    let mut decoder = EventDecoder::default();
+    let mut resumes = 0;
+    loop {
+        let mut stream = response.bytes_stream();
+        while let Some(chunk) = stream.next().await {
+            let Ok(chunk) = chunk else {
+                break;
+            };
+            for data in decoder.push(&chunk) {
+                if let Ok(message) = serde_json::from_str::<Value>(&data) {
+                    channel.handle_message(&message);
+                    resumes = 0;
+                }
+            }
+            if !waiting() {
+                return;
+            }
+        }
+        drop(stream);
+
+        if !waiting() {
+            return;
+        }
+        let last_event_id = decoder.last_event_id.clone();
+        resumes += 1;
+        if resumes > MAX_RESUMES || (id.is_some() && last_event_id.is_none()) {
+            if let Some(id) = id {
+                let error = "MCP event stream closed before the response".to_string();
+                channel.resolve(id, Err(McpError::Failure(error)));
+            }
+            return;
+        }
+        tokio::time::sleep(decoder.retry.unwrap_or(DEFAULT_RETRY)).await;
+        decoder.restart();
+        response = match session.open_stream(last_event_id.as_deref()).await {
+            Ok(response) => response,
+            Err(error) => {
+                if let Some(id) = id {
+                    channel.resolve(id, Err(McpError::Failure(error)));
+                }
+                return;
+            }
+        };
+    }
+}
+
+/// Incremental `text/event-stream` decoder yielding the data of each event.
+#[derive(Default)]
+struct EventDecoder {
+    buffer: Vec<u8>,
+    data: Vec<String>,
+    last_event_id: Option<String>,
+    retry: Option<Duration>,
+}
+
+impl EventDecoder {
+    fn push(&mut self, chunk: &[u8]) -> Vec<String> {
+        self.buffer.extend_from_slice(chunk);
+        let mut events = Vec::new();
+        while let Some(end) = self.buffer.iter().position(|byte| *byte == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            let line = line.trim_end_matches(['\n', '\r']);
+            if line.is_empty() {
+                if !self.data.is_empty() {
+                    events.push(self.data.join("\n"));
+                    self.data.clear();
+                }
+                continue;
+            }
+            if line.starts_with(':') {
+                continue;
+            }
+            let (field, value) = line.split_once(':').unwrap_or((line, ""));
+            let value = value.strip_prefix(' ').unwrap_or(value);
+            match field {
+                "data" => self.data.push(value.to_string()),
+                "id" if !value.contains('\0') => self.last_event_id = Some(value.to_string()),
+                "retry" => {
+                    if let Ok(retry) = value.parse::<u64>() {
+                        self.retry = Some(Duration::from_millis(retry));
+                    }
+                }
+                _ => {}
+            }
+        }
+        events
+    }
+
+    /// Drops any partial event before reading a resumed stream.
+    fn restart(&mut self) {
+        self.buffer.clear();
+        self.data.clear();
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
@@ -1,16 +1,39 @@
 import type { NativeToolGatewayBinding } from "./types";
 
 export type {
+  AuditChainReport,
+  AuditSinkConfig,
   CapabilityGrant,
+  GrantConstraints,
+  GrantConsumption,
   McpManifest,
+  McpPrompt,
+  McpPromptRequest,
+  McpResource,
+  McpResourceRequest,
+  McpResourceTemplate,
   McpServerConfig,
+  McpServerHealth,
+  McpServerLifecycleEvent,
+  McpServerState,
+  McpSupervisionConfig,
   McpTransport,
   NativeToolGatewayBinding,
+  ToolApprovalDecisionInput,
+  ToolApprovalRequest,
+  ToolAuditConfig,
   ToolAuditEvent,
+  ToolDiscoveryFailure,
   ToolGatewayBinding,
   ToolGatewaySnapshot,
   ToolInvocation,
+  ToolPolicyConfig,
   ToolRegistryEntry,
+  ToolResultLimit,
+  ToolResultLimits,
+  ToolStreamEvent,
+  ToolStreamHandle,
+  ToolTruncationStrategy,
 } from "./types";
 
 const browserError = new Error("Tool gateway native bindings are not available in browser.");
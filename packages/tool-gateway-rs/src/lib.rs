@@ -1,11 +1,24 @@
+mod audit;
+mod cancel;
 mod engine;
+mod grants;
+mod http;
+mod limits;
+mod mcp;
+mod policy;
+mod schema;
+mod supervisor;
 mod types;
+mod websocket;
 
-use engine::ToolGatewayEngine;
+use cancel::CancelSignal;
+use engine::{ToolCallStream, ToolGatewayEngine};
 use napi::bindgen_prelude::Result as NapiResult;
 use napi_derive::napi;
 use serde::de::DeserializeOwned;
 use serde_json::Value;
+use std::sync::Arc;
+use tokio::sync::mpsc;
 
 fn to_napi_error(error: impl std::fmt::Display) -> napi::Error {
     napi::Error::from_reason(error.to_string())
@@ -20,6 +33,12 @@ pub struct ToolGatewayBinding {
     engine: ToolGatewayEngine,
 }
 
+impl Default for ToolGatewayBinding {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[napi]
 impl ToolGatewayBinding {
     #[napi(constructor)]
@@ -38,9 +57,9 @@ impl ToolGatewayBinding {
     }
 
     #[napi(js_name = "registerServer")]
-    pub fn register_server(&self, config: Value) -> NapiResult<()> {
+    pub async fn register_server(&self, config: Value) -> NapiResult<()> {
         let config = parse_input::<types::McpServerConfig>(config, "server config")?;
-        self.engine.register_server(config).map_err(to_napi_error)
+        self.engine.register_server(config).await.map_err(to_napi_error)
     }
 
     #[napi(js_name = "listTools")]
@@ -55,6 +74,91 @@ impl ToolGatewayBinding {
         serde_json::to_value(result).map_err(to_napi_error)
     }
 
+    /// Starts a streaming call. Spawning the call needs the runtime, hence `async`.
+    #[napi(js_name = "callToolStream")]
+    pub async fn call_tool_stream(&self, invocation: Value) -> NapiResult<ToolStreamHandle> {
+        let invocation = parse_input::<types::ToolInvocation>(invocation, "tool invocation")?;
+        let ToolCallStream { receiver, cancel } = self.engine.call_tool_stream(invocation);
+        Ok(ToolStreamHandle {
+            receiver: Arc::new(tokio::sync::Mutex::new(receiver)),
+            cancel,
+        })
+    }
+
+    #[napi(js_name = "listResources")]
+    pub async fn list_resources(&self, server_id: String) -> NapiResult<Value> {
+        let resources = self.engine.list_resources(&server_id).await.map_err(to_napi_error)?;
+        serde_json::to_value(resources).map_err(to_napi_error)
+    }
+
+    #[napi(js_name = "listResourceTemplates")]
+    pub async fn list_resource_templates(&self, server_id: String) -> NapiResult<Value> {
+        let templates = self
+            .engine
+            .list_resource_templates(&server_id)
+            .await
+            .map_err(to_napi_error)?;
+        serde_json::to_value(templates).map_err(to_napi_error)
+    }
+
+    #[napi(js_name = "readResource")]
+    pub async fn read_resource(&self, request: Value) -> NapiResult<Value> {
+        let request = parse_input::<types::ResourceRequest>(request, "resource request")?;
+        let result = self.engine.read_resource(request).await;
+        serde_json::to_value(result).map_err(to_napi_error)
+    }
+
+    #[napi(js_name = "subscribeResource")]
+    pub async fn subscribe_resource(&self, request: Value) -> NapiResult<Value> {
+        let request = parse_input::<types::ResourceRequest>(request, "resource request")?;
+        let result = self.engine.subscribe_resource(request).await;
+        serde_json::to_value(result).map_err(to_napi_error)
+    }
+
+    #[napi(js_name = "unsubscribeResource")]
+    pub async fn unsubscribe_resource(&self, request: Value) -> NapiResult<Value> {
+        let request = parse_input::<types::ResourceRequest>(request, "resource request")?;
+        let result = self.engine.unsubscribe_resource(request).await;
+        serde_json::to_value(result).map_err(to_napi_error)
+    }
+
+    #[napi(js_name = "listPrompts")]
+    pub async fn list_prompts(&self, server_id: String) -> NapiResult<Value> {
+        let prompts = self.engine.list_prompts(&server_id).await.map_err(to_napi_error)?;
+        serde_json::to_value(prompts).map_err(to_napi_error)
+    }
+
+    #[napi(js_name = "getPrompt")]
+    pub async fn get_prompt(&self, request: Value) -> NapiResult<Value> {
+        let request = parse_input::<types::PromptRequest>(request, "prompt request")?;
+        let result = self.engine.get_prompt(request).await;
+        serde_json::to_value(result).map_err(to_napi_error)
+    }
+
+    #[napi(js_name = "setPolicy")]
+    pub fn set_policy(&self, config: Value) -> NapiResult<()> {
+        let config = parse_input::<types::ToolPolicyConfig>(config, "policy config")?;
+        self.engine.set_policy(config).map_err(to_napi_error)
+    }
+
+    #[napi(js_name = "setResultLimits")]
+    pub fn set_result_limits(&self, limits: Value) -> NapiResult<()> {
+        let limits = parse_input::<types::ToolResultLimits>(limits, "result limits")?;
+        self.engine.set_result_limits(limits).map_err(to_napi_error)
+    }
+
+    #[napi(js_name = "listApprovals")]
+    pub fn list_approvals(&self) -> NapiResult<Value> {
+        serde_json::to_value(self.engine.list_approvals()).map_err(to_napi_error)
+    }
+
+    #[napi(js_name = "resolveApproval")]
+    pub fn resolve_approval(&self, decision: Value) -> NapiResult<()> {
+        let decision =
+            parse_input::<types::ToolApprovalDecisionInput>(decision, "approval decision")?;
+        self.engine.resolve_approval(decision).map_err(to_napi_error)
+    }
+
     #[napi(js_name = "grantCapability")]
     pub fn grant_capability(&self, grant: Value) -> NapiResult<String> {
         let grant = parse_input::<types::CapabilityGrantInput>(grant, "capability grant")?;
@@ -73,6 +177,31 @@ impl ToolGatewayBinding {
         serde_json::to_value(events).map_err(to_napi_error)
     }
 
+    /// Opens and reads the sink on a blocking thread, off the JS thread.
+    #[napi(js_name = "configureAudit")]
+    pub async fn configure_audit(&self, config: Value) -> NapiResult<()> {
+        let config = parse_input::<types::ToolAuditConfig>(config, "audit config")?;
+        let engine = self.engine.clone();
+        tokio::task::spawn_blocking(move || engine.configure_audit(config))
+            .await
+            .map_err(to_napi_error)?
+            .map_err(to_napi_error)
+    }
+
+    /// Reads the sink's log on a blocking thread, off the JS thread.
+    #[napi(js_name = "verifyAuditChain")]
+    pub async fn verify_audit_chain(&self, events: Option<Value>) -> NapiResult<Value> {
+        let events = events
+            .map(|events| parse_input::<Vec<types::ToolAuditEvent>>(events, "audit events"))
+            .transpose()?;
+        let engine = self.engine.clone();
+        let report = tokio::task::spawn_blocking(move || engine.verify_audit_chain(events))
+            .await
+            .map_err(to_napi_error)?
+            .map_err(to_napi_error)?;
+        serde_json::to_value(report).map_err(to_napi_error)
+    }
+
     #[napi(js_name = "getSnapshot")]
     pub fn get_snapshot(&self) -> NapiResult<Value> {
         serde_json::to_value(self.engine.get_snapshot()).map_err(to_napi_error)
@@ -82,6 +211,35 @@ impl ToolGatewayBinding {
     pub fn reset(&self) {
         self.engine.reset();
     }
+
+    #[napi]
+    pub async fn shutdown(&self) {
+        self.engine.shutdown().await;
+    }
+}
+
+#[napi]
+pub struct ToolStreamHandle {
+    receiver: Arc<tokio::sync::Mutex<mpsc::UnboundedReceiver<types::ToolStreamEvent>>>,
+    cancel: CancelSignal,
+}
+
+#[napi]
+impl ToolStreamHandle {
+    #[napi]
+    pub async fn next(&self) -> NapiResult<Option<Value>> {
+        let mut receiver = self.receiver.lock().await;
+        match receiver.recv().await {
+            Some(event) => serde_json::to_value(event).map(Some).map_err(to_napi_error),
+            None => Ok(None),
+        }
+    }
+
+    /// Sends `notifications/cancelled`; the stream then ends with a `CANCELLED` result.
+    #[napi]
+    pub fn cancel(&self) {
+        self.cancel.cancel();
+    }
 }
 
 pub use types::*;
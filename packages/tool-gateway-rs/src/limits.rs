@@ -0,0 +1,134 @@
+use crate::types::{McpToolResult, ResultLimit, ToolResultLimits, TruncationStrategy};
+use reqwest::Url;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::path::PathBuf;
+
+const BYTES_PER_TOKEN: u64 = 4;
+const ARTIFACT_DIR: &str = "tool-gateway-artifacts";
+
+pub(crate) fn validate_limits(limits: &ToolResultLimits) -> Result<(), String> {
+    let named = limits.tools.iter().flatten().map(|(tool_id, limit)| (tool_id.as_str(), limit));
+    for (name, limit) in limits.default.iter().map(|limit| ("default", limit)).chain(named) {
+        if limit.max_bytes == Some(0) || limit.max_tokens == Some(0) {
+            return Err(format!("Result limit for {name} must be positive"));
+        }
+    }
+    Ok(())
+}
+
+/// Cuts `result` down to the tool's limit when its content is over it. The full content is
+/// first spilled to a file named by its hash, which the truncated result links to as a
+/// `resource` item; only text content is kept inline. Returns the original size in bytes.
+pub(crate) fn limit_result(
+    limits: &ToolResultLimits,
+    tool_id: &str,
+    result: &mut McpToolResult,
+) -> Option<u64> {
+    let limit = limits
+        .tools
+        .as_ref()
+        .and_then(|tools| tools.get(tool_id))
+        .or(limits.default.as_ref())?;
+    let max_bytes = max_bytes(limit)?;
+    let payload = serde_json::to_vec(&result.content).unwrap_or_default();
+    if payload.len() as u64 <= max_bytes {
+        return None;
+    }
+
+    let original_bytes = payload.len() as u64;
+    let (notice, resource) = match spill(limits, &payload) {
+        Ok(uri) => (
+            format!("[Result truncated from {original_bytes} bytes; full result in {uri}]"),
+            Some(json!({
+                "type": "resource",
+                "resource": { "uri": uri, "mimeType": "application/json" },
+            })),
+        ),
+        Err(error) => (
+            format!("[Result truncated from {original_bytes} bytes; full result lost: {error}]"),
+            None,
+        ),
+    };
+    let text = result
+        .content
+        .iter()
+        .filter(|item| item.get("type").and_then(Value::as_str) == Some("text"))
+        .filter_map(|item| item.get("text").and_then(Value::as_str))
+        .collect::<Vec<_>>()
+        .join("\n");
+    let content = |text: String| -> Vec<Value> {
+        let text = json!({ "type": "text", "text": text });
+        std::iter::once(text).chain(resource.clone()).collect()
+    };
+    // Text that needs escaping can still take the content a little over the limit.
+    let strategy = limit.strategy.unwrap_or_default();
+    let overhead = serde_json::to_vec(&content(truncate("", 0, &notice, strategy)))
+        .map_or(0, |skeleton| skeleton.len());
+    let budget = (max_bytes as usize).saturating_sub(overhead);
+    result.content = content(truncate(&text, budget, &notice, strategy));
+    Some(original_bytes)
+}
+
+fn max_bytes(limit: &ResultLimit) -> Option<u64> {
+    let from_tokens = limit
+        .max_tokens
+        .map(|tokens| tokens.saturating_mul(BYTES_PER_TOKEN));
+    [limit.max_bytes, from_tokens].into_iter().flatten().min()
+}
+
+/// Keeps up to `budget` bytes of `text`, marking the cut with `notice`.
+fn truncate(text: &str, budget: usize, notice: &str, strategy: TruncationStrategy) -> String {
+    match strategy {
+        TruncationStrategy::Head => format!("{}\n{notice}", head(text, budget)),
+        TruncationStrategy::Tail => format!("{notice}\n{}", tail(text, budget)),
+        TruncationStrategy::MiddleElide => {
+            let start = head(text, budget / 2);
+            let end = tail(text, budget - start.len());
+            format!("{start}\n{notice}\n{end}")
+        }
+    }
+}
+
+fn head(text: &str, bytes: usize) -> &str {
+    if bytes >= text.len() {
+        return text;
+    }
+    let mut end = bytes;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    &text[..end]
+}
+
+fn tail(text: &str, bytes: usize) -> &str {
+    if bytes >= text.len() {
+        return text;
+    }
+    let mut start = text.len() - bytes;
+    while !text.is_char_boundary(start) {
+        start += 1;
+    }
+    &text[start..]
+}
+
+/// Writes `payload` to `<sha256>.json` in the artifact directory, unless it is already
+/// there, and returns its `file://` URI.
+fn spill(limits: &ToolResultLimits, payload: &[u8]) -> Result<String, String> {
+    let dir = match &limits.artifact_dir {
+        Some(dir) => PathBuf::from(dir),
+        None => std::env::temp_dir().join(ARTIFACT_DIR),
+    };
+    std::fs::create_dir_all(&dir)
+        .map_err(|error| format!("failed to create artifact directory: {error}"))?;
+    let dir = std::fs::canonicalize(&dir)
+        .map_err(|error| format!("failed to resolve artifact directory: {error}"))?;
+    let path = dir.join(format!("{}.json", hex::encode(Sha256::digest(payload))));
+    if !path.exists() {
+        std::fs::write(&path, payload)
+            .map_err(|error| format!("failed to write artifact: {error}"))?;
+    }
+    Url::from_file_path(&path)
+        .map(String::from)
+        .map_err(|()| format!("artifact path {} is not absolute", path.display()))
+}
@@ -0,0 +1,818 @@
+use crate::cancel::CancelSignal;
+use crate::http::{self, HttpSession};
+use crate::types::{McpServerConfig, McpTransport};
+#[cfg(not(test))]
+use crate::types::ToolGatewaySandboxConfig;
+use crate::websocket;
+#[cfg(not(test))]
+use sandbox_rs::{create_sandbox, EnvVar as SandboxEnvVar, ExecOptions as SandboxExecOptions};
+use serde_json::{json, Value};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::process::Stdio;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::process::{Child, ChildStderr, ChildStdin, ChildStdout};
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio::time::Instant;
+
+/// Protocol revision sent in `initialize`; servers may answer with any supported one.
+const PROTOCOL_VERSION: &str = "2025-06-18";
+const SUPPORTED_PROTOCOL_VERSIONS: &[&str] = &["2025-06-18", "2025-03-26", "2024-11-05"];
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(2);
+const RENEW_TIMEOUT: Duration = Duration::from_secs(30);
+const STDERR_TAIL_BYTES: usize = 4096;
+
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const RESOURCE_NOT_FOUND: i64 = -32002;
+
+#[derive(Debug)]
+pub enum McpError {
+    Timeout(String),
+    #[cfg_attr(test, allow(dead_code))]
+    Sandbox(String),
+    InvalidParams(String),
+    NotFound(String),
+    Cancelled(String),
+    Failure(String),
+}
+
+/// A notification sent by the server, e.g. `notifications/tools/list_changed`.
+#[derive(Clone, Debug)]
+pub struct McpNotification {
+    pub method: String,
+    pub params: Value,
+}
+
+/// Every page of a `tools/list`, `resources/list` or `prompts/list` call, plus the
+/// `serverInfo` from `initialize`.
+pub struct McpListing {
+    pub server_info: Value,
+    pub items: Vec<Value>,
+}
+
+impl std::fmt::Display for McpError {
+    fn fmt(&self, formatter: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            McpError::Timeout(message)
+            | McpError::Sandbox(message)
+            | McpError::InvalidParams(message)
+            | McpError::NotFound(message)
+            | McpError::Cancelled(message)
+            | McpError::Failure(message) => formatter.write_str(message),
+        }
+    }
+}
+
+/// A request waiting for its response, which transports must deliver by `deadline`.
+struct PendingResponse {
+    sender: oneshot::Sender<Result<Value, McpError>>,
+    deadline: Instant,
+}
+
+/// A long-lived MCP client for one server. The connection (a stdio child, a Streamable
+/// HTTP session or a WebSocket) is opened and initialized on first use, and again on the
+/// next request after it closes. Resource subscriptions are renewed on reconnect.
+pub struct McpClient {
+    config: McpServerConfig,
+    http_client: reqwest::Client,
+    connection: tokio::sync::Mutex<Option<Arc<Connection>>>,
+    notifications: broadcast::Sender<McpNotification>,
+    subscriptions: Mutex<BTreeSet<String>>,
+}
+
+struct Connection {
+    endpoint: Endpoint,
+    channel: Arc<Channel>,
+    capabilities: Value,
+    server_info: Value,
+}
+
+enum Endpoint {
+    Process(tokio::sync::Mutex<Child>),
+    Http(Arc<HttpSession>),
+    WebSocket,
+}
+
+/// The JSON-RPC side of a connection. Outgoing messages are queued for the transport's
+/// writer task; readers hand incoming messages to `handle_message`.
+pub(crate) struct Channel {
+    outbound: Mutex<Option<mpsc::UnboundedSender<Value>>>,
+    pending: Mutex<HashMap<u64, PendingResponse>>,
+    next_id: AtomicU64,
+    closed: AtomicBool,
+    /// Fired by `close`, for watchers of the connection.
+    on_close: CancelSignal,
+    stderr_tail: Mutex<String>,
+    notifications: broadcast::Sender<McpNotification>,
+}
+
+impl McpClient {
+    pub fn new(config: McpServerConfig, http_client: reqwest::Client) -> Self {
+        let (notifications, _) = broadcast::channel(64);
+        Self {
+            config,
+            http_client,
+            connection: tokio::sync::Mutex::new(None),
+            notifications,
+            subscriptions: Mutex::new(BTreeSet::new()),
+        }
+    }
+
+    pub fn config(&self) -> &McpServerConfig {
+        &self.config
+    }
+
+    /// Notifications from the server, across restarts. The stream ends once the client
+    /// is dropped.
+    pub fn subscribe(&self) -> broadcast::Receiver<McpNotification> {
+        self.notifications.subscribe()
+    }
+
+    pub async fn call_tool(
+        &self,
+        name: &str,
+        arguments: &Value,
+        timeout: Duration,
+    ) -> Result<Value, McpError> {
+        let params = json!({ "name": name, "arguments": arguments });
+        self.request("tools", "tools/call", params, timeout).await
+    }
+
+    /// Calls a tool with a `progressToken`, so the server can report progress through
+    /// `notifications/progress`. Cancelling `cancel` sends `notifications/cancelled`.
+    pub async fn call_tool_with_progress(
+        &self,
+        name: &str,
+        arguments: &Value,
+        progress_token: &str,
+        timeout: Duration,
+        cancel: &CancelSignal,
+    ) -> Result<Value, McpError> {
+        let deadline = Instant::now() + timeout;
+        let connection = self.connection(deadline).await?;
+        self.require_capability(&connection, "tools")?;
+        let params = json!({
+            "name": name,
+            "arguments": arguments,
+            "_meta": { "progressToken": progress_token },
+        });
+        connection
+            .channel
+            .request_until("tools/call", params, deadline, Some(cancel))
+            .await
+    }
+
+    /// Starts and initializes the server now rather than on the first request.
+    pub async fn connect(&self, timeout: Duration) -> Result<(), McpError> {
+        self.connection(Instant::now() + timeout).await.map(|_| ())
+    }
+
+    /// Pings the open connection. Unlike other requests this never starts the server, so a
+    /// dead one stays dead for its supervisor to see.
+    pub async fn ping(&self, timeout: Duration) -> Result<(), McpError> {
+        let deadline = Instant::now() + timeout;
+        let connection = self.open_connection(deadline).await?;
+        let Some(connection) = connection else {
+            return Err(McpError::Failure("MCP server is not running".to_string()));
+        };
+        connection
+            .channel
+            .request("ping", json!({}), deadline)
+            .await
+            .map(|_| ())
+    }
+
+    /// Fires when the open connection closes, for whatever reason; `None` when there is
+    /// no open connection.
+    pub async fn closed(&self) -> Option<CancelSignal> {
+        let deadline = Instant::now() + RENEW_TIMEOUT;
+        let connection = self.open_connection(deadline).await.ok()??;
+        Some(connection.channel.on_close.clone())
+    }
+
+    /// Sends one request to a server that declared `capability`.
+    pub async fn request(
+        &self,
+        capability: &str,
+        method: &str,
+        params: Value,
+        timeout: Duration,
+    ) -> Result<Value, McpError> {
+        let deadline = Instant::now() + timeout;
+        let connection = self.connection(deadline).await?;
+        self.require_capability(&connection, capability)?;
+        connection.channel.request(method, params, deadline).await
+    }
+
+    /// Fetches every page of a list `method` within one deadline, collecting the `key`
+    /// array of each page.
+    pub async fn list(
+        &self,
+        capability: &str,
+        method: &str,
+        key: &str,
+        timeout: Duration,
+    ) -> Result<McpListing, McpError> {
+        let deadline = Instant::now() + timeout;
+        let connection = self.connection(deadline).await?;
+        self.require_capability(&connection, capability)?;
+        let mut items = Vec::new();
+        let mut cursors = HashSet::new();
+        let mut cursor: Option<String> = None;
+        loop {
+            let params = match &cursor {
+                Some(cursor) => json!({ "cursor": cursor }),
+                None => json!({}),
+            };
+            let page = connection.channel.request(method, params, deadline).await?;
+            if let Some(Value::Array(page_items)) = page.get(key) {
+                items.extend(page_items.iter().cloned());
+            }
+            match page.get("nextCursor").and_then(Value::as_str) {
+                Some(next) if !next.is_empty() => {
+                    if !cursors.insert(next.to_string()) {
+                        return Err(McpError::Failure(format!(
+                            "MCP server {} repeated {method} cursor {next}",
+                            self.config.server_id
+                        )));
+                    }
+                    cursor = Some(next.to_string());
+                }
+                _ => break,
+            }
+        }
+        Ok(McpListing {
+            server_info: connection.server_info.clone(),
+            items,
+        })
+    }
+
+    /// Asks for `notifications/resources/updated` about `uri`, now and after reconnects.
+    pub async fn subscribe_resource(&self, uri: &str, timeout: Duration) -> Result<(), McpError> {
+        let deadline = Instant::now() + timeout;
+        let connection = self.connection(deadline).await?;
+        self.require_subscriptions(&connection)?;
+        let params = json!({ "uri": uri });
+        connection
+            .channel
+            .request("resources/subscribe", params, deadline)
+            .await?;
+        lock(&self.subscriptions).insert(uri.to_string());
+        Ok(())
+    }
+
+    pub async fn unsubscribe_resource(&self, uri: &str, timeout: Duration) -> Result<(), McpError> {
+        lock(&self.subscriptions).remove(uri);
+        let params = json!({ "uri": uri });
+        self.request("resources", "resources/unsubscribe", params, timeout)
+            .await
+            .map(|_| ())
+    }
+
+    /// Closes the connection: stdio servers get a moment to exit after stdin closes
+    /// before they are killed, and HTTP sessions are deleted.
+    pub async fn shutdown(&self) {
+        let connection = self.connection.lock().await.take();
+        if let Some(connection) = connection {
+            connection.shutdown().await;
+        }
+    }
+
+    async fn open_connection(
+        &self,
+        deadline: Instant,
+    ) -> Result<Option<Arc<Connection>>, McpError> {
+        let slot = tokio::time::timeout_at(deadline, self.connection.lock())
+            .await
+            .map_err(|_| McpError::Timeout("MCP server start timed out".to_string()))?;
+        let connection = slot.as_ref().filter(|connection| !connection.channel.is_closed());
+        Ok(connection.cloned())
+    }
+
+    async fn connection(&self, deadline: Instant) -> Result<Arc<Connection>, McpError> {
+        let mut slot = tokio::time::timeout_at(deadline, self.connection.lock())
+            .await
+            .map_err(|_| McpError::Timeout("MCP server start timed out".to_string()))?;
+        if let Some(connection) = slot.as_ref()
+            && !connection.channel.is_closed()
+        {
+            return Ok(connection.clone());
+        }
+        if let Some(closed) = slot.take() {
+            closed.shutdown().await;
+        }
+        let connection = Connection::start(self, deadline).await?;
+        *slot = Some(connection.clone());
+        self.renew_subscriptions(&connection);
+        Ok(connection)
+    }
+
+    /// Re-subscribes a fresh connection in the background; the server forgot the
+    /// subscriptions of the one it replaced.
+    fn renew_subscriptions(&self, connection: &Arc<Connection>) {
+        let uris: Vec<String> = lock(&self.subscriptions).iter().cloned().collect();
+        if uris.is_empty() || self.require_subscriptions(connection).is_err() {
+            return;
+        }
+        let connection = connection.clone();
+        tokio::spawn(async move {
+            for uri in uris {
+                let deadline = Instant::now() + RENEW_TIMEOUT;
+                let params = json!({ "uri": uri });
+                let _ = connection
+                    .channel
+                    .request("resources/subscribe", params, deadline)
+                    .await;
+            }
+        });
+    }
+
+    fn require_capability(
+        &self,
+        connection: &Connection,
+        capability: &str,
+    ) -> Result<(), McpError> {
+        if connection.capabilities.get(capability).is_some() {
+            return Ok(());
+        }
+        Err(McpError::Failure(format!(
+            "MCP server {} did not declare the {capability} capability",
+            self.config.server_id
+        )))
+    }
+
+    fn require_subscriptions(&self, connection: &Connection) -> Result<(), McpError> {
+        self.require_capability(connection, "resources")?;
+        let subscribe = connection.capabilities["resources"]["subscribe"].as_bool();
+        if subscribe == Some(true) {
+            return Ok(());
+        }
+        Err(McpError::Failure(format!(
+            "MCP server {} does not support resource subscriptions",
+            self.config.server_id
+        )))
+    }
+}
+
+impl Connection {
+    async fn start(client: &McpClient, deadline: Instant) -> Result<Arc<Self>, McpError> {
+        let config = &client.config;
+        let (outbound, queued) = mpsc::unbounded_channel();
+        let channel = Arc::new(Channel {
+            outbound: Mutex::new(Some(outbound)),
+            pending: Mutex::new(HashMap::new()),
+            next_id: AtomicU64::new(1),
+            closed: AtomicBool::new(false),
+            on_close: CancelSignal::default(),
+            stderr_tail: Mutex::new(String::new()),
+            notifications: client.notifications.clone(),
+        });
+        let endpoint = match config.transport {
+            McpTransport::Stdio => Endpoint::Process(spawn_stdio(config, &channel, queued)?),
+            McpTransport::Http => {
+                let url = remote_url(config)?;
+                Endpoint::Http(http::open(url, client.http_client.clone(), &channel, queued))
+            }
+            McpTransport::Websocket => {
+                websocket::open(remote_url(config)?, &channel, queued, deadline).await?;
+                Endpoint::WebSocket
+            }
+        };
+        let mut connection = Self {
+            endpoint,
+            channel,
+            capabilities: Value::Null,
+            server_info: Value::Null,
+        };
+
+        match connection.initialize(config, deadline).await {
+            Ok(result) => {
+                connection.capabilities =
+                    result.get("capabilities").cloned().unwrap_or_else(|| json!({}));
+                connection.server_info = result.get("serverInfo").cloned().unwrap_or(Value::Null);
+                Ok(Arc::new(connection))
+            }
+            Err(error) => {
+                connection.shutdown().await;
+                Err(error)
+            }
+        }
+    }
+
+    /// Runs the `initialize` handshake and returns the server's `InitializeResult`.
+    async fn initialize(
+        &self,
+        config: &McpServerConfig,
+        deadline: Instant,
+    ) -> Result<Value, McpError> {
+        let params = json!({
+            "protocolVersion": PROTOCOL_VERSION,
+            "capabilities": {},
+            "clientInfo": {
+                "name": "tool-gateway-rs",
+                "version": env!("CARGO_PKG_VERSION"),
+            },
+        });
+        let result = self.channel.request("initialize", params, deadline).await?;
+        let version = result
+            .get("protocolVersion")
+            .and_then(Value::as_str)
+            .unwrap_or_default();
+        if !SUPPORTED_PROTOCOL_VERSIONS.contains(&version) {
+            return Err(McpError::Failure(format!(
+                "MCP server {} answered with unsupported protocol version {version:?}",
+                config.server_id
+            )));
+        }
+        if let Endpoint::Http(session) = &self.endpoint {
+            session.set_protocol_version(version);
+        }
+        self.channel
+            .send(json!({ "jsonrpc": "2.0", "method": "notifications/initialized" }))?;
+        if let Endpoint::Http(session) = &self.endpoint {
+            session.listen(&self.channel);
+        }
+        Ok(result)
+    }
+
+    async fn shutdown(&self) {
+        self.channel.close();
+        match &self.endpoint {
+            Endpoint::Process(child) => {
+                let mut child = child.lock().await;
+                if tokio::time::timeout(SHUTDOWN_GRACE, child.wait()).await.is_err() {
+                    let _ = child.kill().await;
+                }
+            }
+            Endpoint::Http(session) => session.terminate().await,
+            Endpoint::WebSocket => {}
+        }
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.channel.close();
+    }
+}
+
+impl Channel {
+    async fn request(
+        &self,
+        method: &str,
+        params: Value,
+        deadline: Instant,
+    ) -> Result<Value, McpError> {
+        self.request_until(method, params, deadline, None).await
+    }
+
+    /// Sends a request and waits for its response until the deadline passes or `cancel`
+    /// fires; either way the server is told to stop with `notifications/cancelled`.
+    async fn request_until(
+        &self,
+        method: &str,
+        params: Value,
+        deadline: Instant,
+        cancel: Option<&CancelSignal>,
+    ) -> Result<Value, McpError> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (sender, receiver) = oneshot::channel();
+        self.lock_pending()
+            .insert(id, PendingResponse { sender, deadline });
+        if self.is_closed() {
+            self.lock_pending().remove(&id);
+            return Err(self.closed_error());
+        }
+
+        let message = json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params });
+        if let Err(error) = self.send(message) {
+            self.lock_pending().remove(&id);
+            return Err(error);
+        }
+
+        let cancelled = async {
+            match cancel {
+                Some(cancel) => cancel.cancelled().await,
+                None => std::future::pending().await,
+            }
+        };
+        let (reason, error) = tokio::select! {
+            response = tokio::time::timeout_at(deadline, receiver) => match response {
+                Ok(Ok(response)) => return response,
+                Ok(Err(_)) => return Err(self.closed_error()),
+                Err(_) => (
+                    "timed out",
+                    McpError::Timeout(format!("MCP {method} request timed out")),
+                ),
+            },
+            () = cancelled => (
+                "cancelled by client",
+                McpError::Cancelled(format!("MCP {method} request cancelled")),
+            ),
+        };
+        self.lock_pending().remove(&id);
+        let _ = self.send(json!({
+            "jsonrpc": "2.0",
+            "method": "notifications/cancelled",
+            "params": { "requestId": id, "reason": reason },
+        }));
+        Err(error)
+    }
+
+    fn send(&self, message: Value) -> Result<(), McpError> {
+        let outbound = self
+            .outbound
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        match outbound.as_ref() {
+            Some(outbound) => outbound.send(message).map_err(|_| self.closed_error()),
+            None => Err(self.closed_error()),
+        }
+    }
+
+    /// Routes one incoming message, or each message of a batch.
+    pub(crate) fn handle_message(&self, message: &Value) {
+        if let Value::Array(batch) = message {
+            for message in batch {
+                self.handle_message(message);
+            }
+            return;
+        }
+        let method = message.get("method").and_then(Value::as_str);
+        match (method, message.get("id")) {
+            (Some(method), Some(id)) => self.answer(id.clone(), method),
+            (Some(method), None) => {
+                let _ = self.notifications.send(McpNotification {
+                    method: method.to_string(),
+                    params: message.get("params").cloned().unwrap_or(Value::Null),
+                });
+            }
+            (None, Some(id)) => {
+                if let Some(id) = id.as_u64() {
+                    self.dispatch_response(id, message);
+                }
+            }
+            (None, None) => {}
+        }
+    }
+
+    fn dispatch_response(&self, id: u64, message: &Value) {
+        let response = match message.get("error") {
+            Some(error) => {
+                let code = error.get("code").and_then(Value::as_i64).unwrap_or_default();
+                let text = error
+                    .get("message")
+                    .and_then(Value::as_str)
+                    .unwrap_or("MCP request failed")
+                    .to_string();
+                match code {
+                    INVALID_PARAMS => Err(McpError::InvalidParams(text)),
+                    RESOURCE_NOT_FOUND => Err(McpError::NotFound(text)),
+                    _ => Err(McpError::Failure(text)),
+                }
+            }
+            None => Ok(message.get("result").cloned().unwrap_or(Value::Null)),
+        };
+        self.resolve(id, response);
+    }
+
+    /// Answers a server-initiated request. Only `ping` is supported; the client declares
+    /// no capabilities that would let the server ask for anything else.
+    fn answer(&self, id: Value, method: &str) {
+        let reply = if method == "ping" {
+            json!({ "jsonrpc": "2.0", "id": id, "result": {} })
+        } else {
+            json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "error": {
+                    "code": METHOD_NOT_FOUND,
+                    "message": format!("Method not found: {method}"),
+                },
+            })
+        };
+        let _ = self.send(reply);
+    }
+
+    pub(crate) fn resolve(&self, id: u64, response: Result<Value, McpError>) {
+        if let Some(pending) = self.lock_pending().remove(&id) {
+            let _ = pending.sender.send(response);
+        }
+    }
+
+    pub(crate) fn is_pending(&self, id: u64) -> bool {
+        self.lock_pending().contains_key(&id)
+    }
+
+    /// When the caller of request `id` stops waiting for it, if it still is.
+    pub(crate) fn deadline(&self, id: u64) -> Option<Instant> {
+        self.lock_pending().get(&id).map(|pending| pending.deadline)
+    }
+
+    pub(crate) fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Acquire)
+    }
+
+    /// Marks the connection dead, stops its writer and fails every request still
+    /// waiting on it.
+    pub(crate) fn close(&self) {
+        self.closed.store(true, Ordering::Release);
+        self.on_close.cancel();
+        self.outbound
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
+        let pending: Vec<PendingResponse> = self
+            .lock_pending()
+            .drain()
+            .map(|(_, pending)| pending)
+            .collect();
+        for pending in pending {
+            let _ = pending.sender.send(Err(self.closed_error()));
+        }
+    }
+
+    fn closed_error(&self) -> McpError {
+        let tail = self
+            .stderr_tail
+            .lock()
+            .map(|tail| tail.trim().to_string())
+            .unwrap_or_default();
+        if tail.is_empty() {
+            McpError::Failure("MCP server connection closed".to_string())
+        } else {
+            McpError::Failure(format!("MCP server exited: {tail}"))
+        }
+    }
+
+    fn lock_pending(&self) -> MutexGuard<'_, HashMap<u64, PendingResponse>> {
+        self.pending.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// The id of a client request, which transports use to fail it when delivery fails.
+pub(crate) fn request_id(message: &Value) -> Option<u64> {
+    message.get("method")?;
+    message.get("id")?.as_u64()
+}
+
+fn remote_url(config: &McpServerConfig) -> Result<&str, McpError> {
+    config.url.as_deref().ok_or_else(|| {
+        McpError::Failure(format!("MCP {:?} transport requires url", config.transport))
+    })
+}
+
+fn spawn_stdio(
+    config: &McpServerConfig,
+    channel: &Arc<Channel>,
+    queued: mpsc::UnboundedReceiver<Value>,
+) -> Result<tokio::sync::Mutex<Child>, McpError> {
+    let mut cmd = stdio_command(config)?;
+    cmd.stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    let mut child = cmd
+        .spawn()
+        .map_err(|error| McpError::Failure(format!("failed to start MCP server: {error}")))?;
+    let (Some(stdin), Some(stdout), Some(stderr)) =
+        (child.stdin.take(), child.stdout.take(), child.stderr.take())
+    else {
+        return Err(McpError::Failure("MCP server stdio is not piped".to_string()));
+    };
+    tokio::spawn(write_stdin(stdin, queued, channel.clone()));
+    tokio::spawn(read_stdout(stdout, channel.clone()));
+    tokio::spawn(read_stderr(stderr, channel.clone()));
+    Ok(tokio::sync::Mutex::new(child))
+}
+
+/// Writes queued messages as lines until the channel closes, which closes stdin.
+async fn write_stdin(
+    mut stdin: ChildStdin,
+    mut queued: mpsc::UnboundedReceiver<Value>,
+    channel: Arc<Channel>,
+) {
+    while let Some(message) = queued.recv().await {
+        let mut line = message.to_string().into_bytes();
+        line.push(b'\n');
+        let written = match stdin.write_all(&line).await {
+            Ok(()) => stdin.flush().await,
+            Err(error) => Err(error),
+        };
+        if written.is_err() {
+            channel.close();
+            break;
+        }
+    }
+}
+
+async fn read_stdout(stdout: ChildStdout, channel: Arc<Channel>) {
+    let mut lines = BufReader::new(stdout).lines();
+    while let Ok(Some(line)) = lines.next_line().await {
+        // Servers may only write JSON-RPC messages to stdout, but stray log lines are
+        // common enough that they are skipped rather than treated as fatal.
+        if let Ok(message) = serde_json::from_str::<Value>(line.trim()) {
+            channel.handle_message(&message);
+        }
+    }
+    channel.close();
+}
+
+async fn read_stderr(mut stderr: ChildStderr, channel: Arc<Channel>) {
+    let mut buffer = [0u8; 1024];
+    while let Ok(read) = stderr.read(&mut buffer).await {
+        if read == 0 {
+            break;
+        }
+        let Ok(mut tail) = channel.stderr_tail.lock() else {
+            break;
+        };
+        tail.push_str(&String::from_utf8_lossy(&buffer[..read]));
+        if tail.len() > STDERR_TAIL_BYTES {
+            let mut cut = tail.len() - STDERR_TAIL_BYTES;
+            while !tail.is_char_boundary(cut) {
+                cut += 1;
+            }
+            tail.drain(..cut);
+        }
+    }
+}
+
+#[cfg(not(test))]
+fn stdio_command(config: &McpServerConfig) -> Result<tokio::process::Command, McpError> {
+    let Some(command) = config.command.as_deref() else {
+        return Err(McpError::Failure("MCP stdio transport requires command".to_string()));
+    };
+    let sandbox = create_sandbox(resolve_sandbox_config(config.sandbox.as_ref()))
+        .map_err(|error| McpError::Sandbox(error.to_string()))?;
+    let options = SandboxExecOptions {
+        cwd: None,
+        timeout_ms: None,
+        stdin: None,
+        max_output_bytes: None,
+        env: build_env_vars(config.env.as_ref()),
+    };
+    let args = config.args.clone().unwrap_or_default();
+    sandbox
+        .command(command, &args, &options)
+        .map(tokio::process::Command::from)
+        .map_err(|error| McpError::Sandbox(error.to_string()))
+}
+
+#[cfg(test)]
+fn stdio_command(config: &McpServerConfig) -> Result<tokio::process::Command, McpError> {
+    let Some(command) = config.command.as_deref() else {
+        return Err(McpError::Failure("MCP stdio transport requires command".to_string()));
+    };
+    let mut cmd = tokio::process::Command::new(command);
+    if let Some(args) = &config.args {
+        cmd.args(args);
+    }
+    if let Some(env) = &config.env {
+        cmd.envs(env);
+    }
+    Ok(cmd)
+}
+
+#[cfg(not(test))]
+fn resolve_sandbox_config(config: Option<&ToolGatewaySandboxConfig>) -> sandbox_rs::SandboxConfig {
+    match config {
+        Some(config) => sandbox_rs::SandboxConfig {
+            network_access: config.network_access.clone(),
+            allowed_hosts: config.allowed_hosts.clone(),
+            allowed_roots: config.allowed_roots.clone(),
+            fs_isolation: config.fs_isolation.clone(),
+            working_directory: config.working_directory.clone(),
+        },
+        None => sandbox_rs::SandboxConfig {
+            network_access: "none".to_string(),
+            allowed_hosts: None,
+            allowed_roots: None,
+            fs_isolation: "none".to_string(),
+            working_directory: None,
+        },
+    }
+}
+
+#[cfg(not(test))]
+fn build_env_vars(env: Option<&HashMap<String, String>>) -> Option<Vec<SandboxEnvVar>> {
+    let env = env?;
+    if env.is_empty() {
+        return None;
+    }
+    Some(
+        env.iter()
+            .map(|(key, value)| SandboxEnvVar {
+                key: key.clone(),
+                value: value.clone(),
+            })
+            .collect(),
+    )
+}
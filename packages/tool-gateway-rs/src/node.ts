@@ -3,16 +3,39 @@ import { loadNativeBinding, resolvePackageRoot } from "@ku0/native-bindings/node
 import type { NativeToolGatewayBinding } from "./types";
 
 export type {
+  AuditChainReport,
+  AuditSinkConfig,
   CapabilityGrant,
+  GrantConstraints,
+  GrantConsumption,
   McpManifest,
+  McpPrompt,
+  McpPromptRequest,
+  McpResource,
+  McpResourceRequest,
+  McpResourceTemplate,
   McpServerConfig,
+  McpServerHealth,
+  McpServerLifecycleEvent,
+  McpServerState,
+  McpSupervisionConfig,
   McpTransport,
   NativeToolGatewayBinding,
+  ToolApprovalDecisionInput,
+  ToolApprovalRequest,
+  ToolAuditConfig,
   ToolAuditEvent,
+  ToolDiscoveryFailure,
   ToolGatewayBinding,
   ToolGatewaySnapshot,
   ToolInvocation,
+  ToolPolicyConfig,
   ToolRegistryEntry,
+  ToolResultLimit,
+  ToolResultLimits,
+  ToolStreamEvent,
+  ToolStreamHandle,
+  ToolTruncationStrategy,
 } from "./types";
 
 let cachedBinding: NativeToolGatewayBinding | null | undefined;
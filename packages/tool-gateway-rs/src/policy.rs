@@ -0,0 +1,28 @@
+use crate::types::ToolPolicyContext;
+use cowork_policy_rs::{CoworkPolicyDecision, CoworkPolicyEngine, CoworkPolicyInput};
+
+/// Rules on tool calls by their annotated `policyAction`.
+pub(crate) trait ToolPolicy: Send + Sync {
+    fn evaluate(&self, input: &CoworkPolicyInput) -> CoworkPolicyDecision;
+}
+
+impl ToolPolicy for CoworkPolicyEngine {
+    fn evaluate(&self, input: &CoworkPolicyInput) -> CoworkPolicyDecision {
+        CoworkPolicyEngine::evaluate(self, input)
+    }
+}
+
+pub(crate) fn policy_input(action: &str, context: Option<&ToolPolicyContext>) -> CoworkPolicyInput {
+    let context = context.cloned().unwrap_or_default();
+    CoworkPolicyInput {
+        action: action.to_string(),
+        path: context.path,
+        grant_roots: context.grant_roots.unwrap_or_default(),
+        output_roots: context.output_roots.unwrap_or_default(),
+        file_size_bytes: context.file_size_bytes,
+        host: context.host,
+        host_allowlist: context.host_allowlist.unwrap_or_default(),
+        connector_scope_allowed: context.connector_scope_allowed,
+        case_insensitive_paths: context.case_insensitive_paths,
+    }
+}
@@ -0,0 +1,130 @@
+use json_schema_rs::{resolve_ref, type_matches};
+use serde_json::Value;
+use std::cell::RefCell;
+
+/// Repairs the argument mistakes models make most often: numbers and booleans sent as
+/// strings (and the reverse), objects or arrays sent as JSON text, and a lone value where
+/// an array is expected. Only values that fail their declared `type` are rewritten;
+/// returns the paths that changed.
+pub fn coerce(schema: &Value, instance: &mut Value) -> Vec<String> {
+    let mut coerced = Vec::new();
+    let coercer = Coercer {
+        root: schema,
+        entered: RefCell::default(),
+    };
+    coercer.coerce(schema, instance, "$", &mut coerced);
+    coerced
+}
+
+struct Coercer<'a> {
+    root: &'a Value,
+    /// `$ref` targets being applied and the value each is applied to, so that references
+    /// that loop back on themselves are followed once.
+    entered: RefCell<Vec<(*const Value, *const Value)>>,
+}
+
+impl Coercer<'_> {
+    fn coerce(&self, schema: &Value, instance: &mut Value, path: &str, out: &mut Vec<String>) {
+        let Value::Object(schema) = schema else {
+            return;
+        };
+        if let Some(target) = schema
+            .get("$ref")
+            .and_then(|value| value.as_str())
+            .and_then(|reference| resolve_ref(self.root, reference))
+        {
+            let entry = (target as *const Value, &*instance as *const Value);
+            if !self.entered.borrow().contains(&entry) {
+                self.entered.borrow_mut().push(entry);
+                self.coerce(target, instance, path, out);
+                self.entered.borrow_mut().pop();
+            }
+        }
+        if let Some(branches) = schema.get("allOf").and_then(|value| value.as_array()) {
+            for branch in branches {
+                self.coerce(branch, instance, path, out);
+            }
+        }
+
+        let names: Vec<&str> = match schema.get("type") {
+            Some(Value::String(name)) => vec![name.as_str()],
+            Some(Value::Array(names)) => names.iter().filter_map(|name| name.as_str()).collect(),
+            _ => Vec::new(),
+        };
+        if !names.is_empty()
+            && !names.iter().any(|name| type_matches(name, instance))
+            && let Some(value) = names.iter().find_map(|name| convert(name, instance))
+        {
+            *instance = value;
+            out.push(path.to_string());
+        }
+
+        match instance {
+            Value::Object(map) => {
+                let properties = schema.get("properties").and_then(|value| value.as_object());
+                let additional = schema.get("additionalProperties");
+                for (key, value) in map.iter_mut() {
+                    let property_schema = properties
+                        .and_then(|properties| properties.get(key))
+                        .or(additional);
+                    if let Some(property_schema) = property_schema {
+                        self.coerce(property_schema, value, &format!("{path}.{key}"), out);
+                    }
+                }
+            }
+            Value::Array(items) => {
+                let prefix = schema
+                    .get("prefixItems")
+                    .and_then(|value| value.as_array())
+                    .map(|value| value.as_slice())
+                    .unwrap_or_default();
+                for (index, item) in items.iter_mut().enumerate() {
+                    if let Some(item_schema) = prefix.get(index).or_else(|| schema.get("items")) {
+                        self.coerce(item_schema, item, &format!("{path}[{index}]"), out);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The value `instance` would have as type `name`, if there is an unambiguous one.
+fn convert(name: &str, instance: &Value) -> Option<Value> {
+    match (name, instance) {
+        ("integer", Value::String(text)) => {
+            let text = text.trim();
+            text.parse::<i64>()
+                .map(Value::from)
+                .or_else(|_| text.parse::<u64>().map(Value::from))
+                .ok()
+        }
+        ("number", Value::String(text)) => {
+            let text = text.trim();
+            match text.parse::<i64>() {
+                Ok(number) => Some(Value::from(number)),
+                Err(_) => text
+                    .parse::<f64>()
+                    .ok()
+                    .and_then(serde_json::Number::from_f64)
+                    .map(Value::Number),
+            }
+        }
+        ("boolean", Value::String(text)) => match text.trim() {
+            "true" => Some(Value::Bool(true)),
+            "false" => Some(Value::Bool(false)),
+            _ => None,
+        },
+        ("string", Value::Number(_) | Value::Bool(_)) => Some(Value::String(instance.to_string())),
+        ("object", Value::String(text)) => serde_json::from_str::<Value>(text)
+            .ok()
+            .filter(|value| value.is_object()),
+        ("array", Value::String(text)) => serde_json::from_str::<Value>(text)
+            .ok()
+            .filter(|value| value.is_array())
+            .or_else(|| Some(Value::Array(vec![instance.clone()]))),
+        ("array", Value::Null) => None,
+        ("array", _) => Some(Value::Array(vec![instance.clone()])),
+        _ => None,
+    }
+}
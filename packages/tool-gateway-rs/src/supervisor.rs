@@ -0,0 +1,320 @@
+use crate::cancel::CancelSignal;
+use crate::engine::now_epoch_ms;
+use crate::mcp::McpClient;
+use crate::types::{ServerHealth, ServerLifecycleEvent, ServerState, SupervisionConfig};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, Weak};
+use std::time::Duration;
+use tokio::time::Instant;
+
+const DEFAULT_PING_INTERVAL_MS: u64 = 30_000;
+const DEFAULT_PING_TIMEOUT_MS: u64 = 10_000;
+const DEFAULT_MAX_PING_FAILURES: u32 = 3;
+const DEFAULT_RESTART_BACKOFF_MS: u64 = 500;
+const DEFAULT_MAX_BACKOFF_MS: u64 = 30_000;
+const DEFAULT_MAX_RESTARTS: u32 = 5;
+/// Calls kept per server for the latency percentile.
+const LATENCY_WINDOW: usize = 100;
+const MAX_LIFECYCLE_EVENTS: usize = 100;
+
+/// Health of every registered server: call stats for all of them, and the lifecycle of
+/// supervised ones. Kept apart from the gateway state so supervisors never contend for it.
+#[derive(Default)]
+pub(crate) struct ServerMonitor {
+    inner: Mutex<MonitorState>,
+}
+
+#[derive(Default)]
+struct MonitorState {
+    servers: HashMap<String, ServerRecord>,
+    events: VecDeque<ServerLifecycleEvent>,
+    next_sequence: u64,
+}
+
+#[derive(Default)]
+struct ServerRecord {
+    state: Option<ServerState>,
+    calls: u64,
+    failures: u64,
+    latencies: VecDeque<u64>,
+    restarts: u32,
+    /// Restarts since the server last stayed up for longer than the longest backoff; the
+    /// cap and the backoff apply to these.
+    recent_restarts: u32,
+    last_error: Option<String>,
+    /// Set once the restart cap is reached; calls are refused until supervision stops.
+    given_up: bool,
+}
+
+impl ServerMonitor {
+    pub(crate) fn add(&self, server_id: &str) {
+        self.lock()
+            .servers
+            .insert(server_id.to_string(), ServerRecord::default());
+    }
+
+    pub(crate) fn record_call(&self, server_id: &str, success: bool, latency_ms: u64) {
+        let mut state = self.lock();
+        let Some(record) = state.servers.get_mut(server_id) else {
+            return;
+        };
+        record.calls += 1;
+        if !success {
+            record.failures += 1;
+        }
+        if record.latencies.len() == LATENCY_WINDOW {
+            record.latencies.pop_front();
+        }
+        record.latencies.push_back(latency_ms);
+    }
+
+    /// Why calls to the server are refused, if its supervisor gave up on it.
+    pub(crate) fn unavailable(&self, server_id: &str) -> Option<String> {
+        let state = self.lock();
+        let record = state.servers.get(server_id).filter(|record| record.given_up)?;
+        Some(format!(
+            "MCP server {server_id} crashed after {} restarts and is no longer restarted",
+            record.recent_restarts
+        ))
+    }
+
+    pub(crate) fn health(&self) -> Vec<ServerHealth> {
+        let state = self.lock();
+        let mut servers: Vec<ServerHealth> = state
+            .servers
+            .iter()
+            .map(|(server_id, record)| ServerHealth {
+                server_id: server_id.clone(),
+                state: record.state,
+                calls: record.calls,
+                failures: record.failures,
+                p95_latency_ms: p95(&record.latencies),
+                restarts: record.restarts,
+                last_error: record.last_error.clone(),
+            })
+            .collect();
+        servers.sort_by(|a, b| a.server_id.cmp(&b.server_id));
+        servers
+    }
+
+    pub(crate) fn lifecycle_events(&self) -> Vec<ServerLifecycleEvent> {
+        self.lock().events.iter().cloned().collect()
+    }
+
+    pub(crate) fn clear(&self) {
+        *self.lock() = MonitorState::default();
+    }
+
+    /// Moves a supervised server to `next`, recording an event when its state changes.
+    fn transition(&self, server_id: &str, next: ServerState, reason: Option<String>) {
+        let mut state = self.lock();
+        let state = &mut *state;
+        let Some(record) = state.servers.get_mut(server_id) else {
+            return;
+        };
+        if reason.is_some() {
+            record.last_error = reason.clone();
+        }
+        if record.state == Some(next) {
+            return;
+        }
+        record.state = Some(next);
+        state.next_sequence += 1;
+        if state.events.len() == MAX_LIFECYCLE_EVENTS {
+            state.events.pop_front();
+        }
+        state.events.push_back(ServerLifecycleEvent {
+            sequence: state.next_sequence,
+            server_id: server_id.to_string(),
+            state: next,
+            reason,
+            created_at: now_epoch_ms(),
+        });
+    }
+
+    fn recent_restarts(&self, server_id: &str) -> u32 {
+        self.lock()
+            .servers
+            .get(server_id)
+            .map_or(0, |record| record.recent_restarts)
+    }
+
+    fn count_restart(&self, server_id: &str) {
+        if let Some(record) = self.lock().servers.get_mut(server_id) {
+            record.restarts += 1;
+            record.recent_restarts += 1;
+        }
+    }
+
+    fn forgive_restarts(&self, server_id: &str) {
+        if let Some(record) = self.lock().servers.get_mut(server_id) {
+            record.recent_restarts = 0;
+        }
+    }
+
+    fn give_up(&self, server_id: &str) {
+        if let Some(record) = self.lock().servers.get_mut(server_id) {
+            record.given_up = true;
+        }
+    }
+
+    /// Clears the state of servers once their supervision stops, so even those given up
+    /// on are started again by their next call.
+    pub(crate) fn unsupervise(&self) {
+        for record in self.lock().servers.values_mut() {
+            record.state = None;
+            record.recent_restarts = 0;
+            record.given_up = false;
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, MonitorState> {
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+struct Settings {
+    ping_interval: Duration,
+    ping_timeout: Duration,
+    max_ping_failures: u32,
+    restart_backoff: Duration,
+    max_backoff: Duration,
+    max_restarts: u32,
+}
+
+impl From<&SupervisionConfig> for Settings {
+    fn from(config: &SupervisionConfig) -> Self {
+        let millis = |value: Option<u64>, default| Duration::from_millis(value.unwrap_or(default));
+        Self {
+            ping_interval: millis(config.ping_interval_ms, DEFAULT_PING_INTERVAL_MS),
+            ping_timeout: millis(config.ping_timeout_ms, DEFAULT_PING_TIMEOUT_MS),
+            max_ping_failures: config
+                .max_ping_failures
+                .unwrap_or(DEFAULT_MAX_PING_FAILURES)
+                .max(1),
+            restart_backoff: millis(config.restart_backoff_ms, DEFAULT_RESTART_BACKOFF_MS),
+            max_backoff: millis(config.max_backoff_ms, DEFAULT_MAX_BACKOFF_MS),
+            max_restarts: config.max_restarts.unwrap_or(DEFAULT_MAX_RESTARTS),
+        }
+    }
+}
+
+impl Settings {
+    fn backoff(&self, restarts: u32) -> Duration {
+        let factor = 2u32.saturating_pow(restarts);
+        self.restart_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff)
+    }
+}
+
+/// Starts a stdio server and keeps it running until `stop` fires or the client is
+/// dropped: it is pinged every interval, marked degraded while pings fail and restarted
+/// with backoff when it exits or stops answering, until the restart cap is reached. A
+/// server that stays up for longer than the longest backoff is forgiven its restarts.
+pub(crate) fn supervise(
+    monitor: Arc<ServerMonitor>,
+    server_id: String,
+    client: &Arc<McpClient>,
+    config: &SupervisionConfig,
+    stop: CancelSignal,
+) {
+    let settings = Settings::from(config);
+    let client = Arc::downgrade(client);
+    monitor.transition(&server_id, ServerState::Starting, None);
+    tokio::spawn(async move {
+        loop {
+            let outcome = tokio::select! {
+                biased;
+                () = stop.cancelled() => return,
+                outcome = run_server(&monitor, &server_id, &client, &settings) => outcome,
+            };
+            // The client is gone with the server's registration.
+            let Err(reason) = outcome else {
+                return;
+            };
+            monitor.transition(&server_id, ServerState::Crashed, Some(reason));
+            let restarts = monitor.recent_restarts(&server_id);
+            if restarts >= settings.max_restarts {
+                monitor.give_up(&server_id);
+                return;
+            }
+            tokio::select! {
+                biased;
+                () = stop.cancelled() => return,
+                () = tokio::time::sleep(settings.backoff(restarts)) => {}
+            }
+            monitor.count_restart(&server_id);
+            monitor.transition(&server_id, ServerState::Starting, None);
+        }
+    });
+}
+
+/// Runs one life of the server: starts it if needed, then pings it until it exits or
+/// stops answering, which is returned as the error. `Ok` means the client was dropped.
+async fn run_server(
+    monitor: &ServerMonitor,
+    server_id: &str,
+    client: &Weak<McpClient>,
+    settings: &Settings,
+) -> Result<(), String> {
+    let closed = {
+        let Some(client) = client.upgrade() else {
+            return Ok(());
+        };
+        client
+            .connect(settings.ping_timeout)
+            .await
+            .map_err(|error| format!("Failed to start: {error}"))?;
+        client.closed().await
+    };
+    // A call may have found it dead and started it again in the meantime; the next life
+    // picks that one up.
+    let Some(closed) = closed else {
+        return Err("Exited during start".to_string());
+    };
+    monitor.transition(server_id, ServerState::Ready, None);
+
+    let mut failures = 0;
+    let mut healthy_since = Instant::now();
+    loop {
+        tokio::select! {
+            biased;
+            () = closed.cancelled() => return Err("Server process exited".to_string()),
+            () = tokio::time::sleep(settings.ping_interval) => {}
+        }
+        let Some(client) = client.upgrade() else {
+            return Ok(());
+        };
+        match client.ping(settings.ping_timeout).await {
+            Ok(()) => {
+                failures = 0;
+                monitor.transition(server_id, ServerState::Ready, None);
+                if healthy_since.elapsed() > settings.max_backoff {
+                    monitor.forgive_restarts(server_id);
+                }
+            }
+            Err(_) if closed.is_cancelled() => return Err("Server process exited".to_string()),
+            Err(error) => {
+                failures += 1;
+                healthy_since = Instant::now();
+                if failures >= settings.max_ping_failures {
+                    client.shutdown().await;
+                    return Err(format!("No answer to {failures} pings: {error}"));
+                }
+                monitor.transition(server_id, ServerState::Degraded, Some(error.to_string()));
+            }
+        }
+    }
+}
+
+/// The 95th percentile, by nearest rank.
+fn p95(latencies: &VecDeque<u64>) -> Option<u64> {
+    if latencies.is_empty() {
+        return None;
+    }
+    let mut sorted: Vec<u64> = latencies.iter().copied().collect();
+    sorted.sort_unstable();
+    let rank = (sorted.len() * 95).div_ceil(100);
+    sorted.get(rank.saturating_sub(1)).copied()
+}
@@ -1,3 +1,5 @@
+use cowork_policy_rs::{CoworkPolicyDecision, CoworkPolicyDecisionType, CoworkRiskTag};
+use json_schema_rs::SchemaViolation;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::collections::HashMap;
@@ -23,6 +25,76 @@ pub struct McpServerConfig {
     pub url: Option<String>,
     pub manifest: Option<McpManifest>,
     pub sandbox: Option<ToolGatewaySandboxConfig>,
+    /// Fetch tools with `tools/list` on registration and whenever the server reports
+    /// `notifications/tools/list_changed`. Discovered tools replace any manifest tools but
+    /// keep the annotations of the manifest tool with the same name.
+    pub discover_tools: Option<bool>,
+    /// Annotations for discovered tools that the manifest does not list. Without them such
+    /// a tool needs a grant for its tool id (`serverId:name`).
+    pub tool_access: Option<ToolAnnotations>,
+    /// Grants needed to read or subscribe to the server's resources, checked the way a
+    /// tool's annotations are.
+    pub resource_access: Option<ToolAnnotations>,
+    /// Grants needed to get the server's prompts.
+    pub prompt_access: Option<ToolAnnotations>,
+    /// Keep a stdio server running: start it on registration, ping it, and restart it when
+    /// it crashes or stops answering. Unsupervised servers start on first use.
+    pub supervision: Option<SupervisionConfig>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SupervisionConfig {
+    /// Defaults to 30s.
+    pub ping_interval_ms: Option<u64>,
+    /// How long a start or a ping may take. Defaults to 10s.
+    pub ping_timeout_ms: Option<u64>,
+    /// Failed pings in a row after which a degraded server is restarted. Defaults to 3.
+    pub max_ping_failures: Option<u32>,
+    /// Delay before the first restart, doubled for each one after up to `maxBackoffMs`.
+    /// Defaults to 500ms.
+    pub restart_backoff_ms: Option<u64>,
+    /// Defaults to 30s.
+    pub max_backoff_ms: Option<u64>,
+    /// Restarts allowed before the server is given up on and its calls are refused. A
+    /// server that stays up for longer than `maxBackoffMs` is allowed them afresh.
+    /// Defaults to 5.
+    pub max_restarts: Option<u32>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ServerState {
+    Starting,
+    Ready,
+    /// Running, but failing pings.
+    Degraded,
+    Crashed,
+}
+
+/// Call stats for a server, and the state of a supervised one.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServerHealth {
+    pub server_id: String,
+    /// `None` unless the server is supervised.
+    pub state: Option<ServerState>,
+    pub calls: u64,
+    pub failures: u64,
+    /// Over the most recent calls.
+    pub p95_latency_ms: Option<u64>,
+    pub restarts: u32,
+    pub last_error: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServerLifecycleEvent {
+    pub sequence: u64,
+    pub server_id: String,
+    pub state: ServerState,
+    pub reason: Option<String>,
+    pub created_at: i64,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -50,6 +122,7 @@ pub struct CapabilityGrantInput {
     pub expires_at: Option<i64>,
     pub scope: Option<String>,
     pub approval_id: Option<String>,
+    pub constraints: Option<GrantConstraints>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -61,6 +134,56 @@ pub struct CapabilityGrant {
     pub expires_at: Option<i64>,
     pub scope: Option<String>,
     pub approval_id: Option<String>,
+    pub constraints: Option<GrantConstraints>,
+    /// Calls that have used the grant so far.
+    #[serde(default)]
+    pub uses: u64,
+}
+
+/// Narrows what a grant covers beyond its capability name. A grant whose constraints do
+/// not admit a call does not count towards it.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GrantConstraints {
+    /// Only calls from this run may use the grant.
+    pub run_id: Option<String>,
+    /// Uses allowed over the grant's lifetime.
+    pub max_invocations: Option<u64>,
+    pub rate_limit: Option<GrantRateLimit>,
+    /// Every listed argument must be present and match.
+    pub arguments: Option<Vec<ArgumentConstraint>>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GrantRateLimit {
+    pub max_invocations: u64,
+    pub window_ms: u64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ArgumentConstraint {
+    /// A top-level argument name, or a JSON pointer such as `/target/path`. String and
+    /// string-array values are checked; anything else fails the constraint.
+    pub argument: String,
+    /// `*` matches within a path segment and `**` across segments. Paths are normalized
+    /// first, so `docs/../secrets` does not match `docs/**`.
+    pub path_globs: Option<Vec<String>>,
+    /// Hosts, or URLs reduced to their host. A pattern matches the host and its
+    /// subdomains; a leading `*.` matches subdomains only.
+    pub host_patterns: Option<Vec<String>>,
+}
+
+/// A grant a call used, with its count after the call.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct GrantConsumption {
+    pub grant_id: String,
+    pub capability: String,
+    pub uses: u64,
+    /// Uses left under `maxInvocations`, when the grant has one.
+    pub remaining: Option<u64>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -73,6 +196,141 @@ pub struct ToolInvocation {
     pub grant_ids: Vec<String>,
     pub redact_keys: Option<Vec<String>>,
     pub timeout_ms: Option<u64>,
+    /// Repair common type mistakes (such as numbers sent as strings) before the arguments
+    /// are validated against the tool's input schema.
+    pub coerce_arguments: Option<bool>,
+    /// What the call touches, for tools with a `policyAction`. The action itself comes
+    /// from the tool's annotations.
+    pub policy_context: Option<ToolPolicyContext>,
+}
+
+/// The `CoworkPolicyInput` fields other than `action`.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ToolPolicyContext {
+    pub path: Option<String>,
+    pub grant_roots: Option<Vec<String>>,
+    pub output_roots: Option<Vec<String>>,
+    pub file_size_bytes: Option<u64>,
+    pub host: Option<String>,
+    pub host_allowlist: Option<Vec<String>>,
+    pub connector_scope_allowed: Option<bool>,
+    pub case_insensitive_paths: Option<bool>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ToolPolicyConfig {
+    /// A `CoworkPolicyConfig` evaluated against each tool's `policyAction`.
+    pub cowork_policy: Option<Value>,
+    /// Park calls that need confirmation until `resolveApproval` instead of rejecting
+    /// those without an approved grant.
+    pub approvals: Option<bool>,
+    /// How long a parked call waits for a decision before it is denied.
+    pub approval_timeout_ms: Option<u64>,
+}
+
+/// How the policy ruled on a call.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ToolPolicyDecision {
+    pub decision: CoworkPolicyDecisionType,
+    pub reason: String,
+    pub risk_tags: Vec<CoworkRiskTag>,
+    pub rule_id: Option<String>,
+}
+
+impl From<CoworkPolicyDecision> for ToolPolicyDecision {
+    fn from(decision: CoworkPolicyDecision) -> Self {
+        Self {
+            decision: decision.decision,
+            reason: decision.reason,
+            risk_tags: decision.risk_tags,
+            rule_id: decision.rule_id,
+        }
+    }
+}
+
+/// A call parked until someone approves, denies or edits it.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ToolApprovalRequest {
+    pub approval_id: String,
+    pub tool_id: String,
+    pub request_id: String,
+    pub run_id: Option<String>,
+    pub arguments: Value,
+    pub reason: String,
+    pub policy: Option<ToolPolicyDecision>,
+    pub requested_at: i64,
+    pub expires_at: i64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ToolApprovalAction {
+    Approve,
+    Deny,
+    /// Approve with replacement `arguments`, which are validated again.
+    Edit,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ToolApprovalDecisionInput {
+    pub approval_id: String,
+    pub action: ToolApprovalAction,
+    pub arguments: Option<Value>,
+    pub reason: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ToolApprovalOutcome {
+    Approved,
+    Denied,
+    Edited,
+    Expired,
+    Cancelled,
+}
+
+/// How an approval was settled, as recorded in the call's audit event.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ToolApprovalRecord {
+    pub approval_id: String,
+    pub outcome: ToolApprovalOutcome,
+    pub reason: Option<String>,
+    pub decided_at: i64,
+    /// Hash of the arguments an `edit` substituted; `inputHash` covers the originals.
+    pub edited_input_hash: Option<String>,
+}
+
+/// A request to read, subscribe to or unsubscribe from one server resource.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResourceRequest {
+    pub server_id: String,
+    pub uri: String,
+    pub request_id: String,
+    pub run_id: Option<String>,
+    pub grant_ids: Vec<String>,
+    pub redact_keys: Option<Vec<String>>,
+    pub timeout_ms: Option<u64>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PromptRequest {
+    pub server_id: String,
+    pub name: String,
+    /// Values for the prompt's declared arguments.
+    pub arguments: Option<HashMap<String, String>>,
+    pub request_id: String,
+    pub run_id: Option<String>,
+    pub grant_ids: Vec<String>,
+    pub redact_keys: Option<Vec<String>>,
+    pub timeout_ms: Option<u64>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -91,12 +349,56 @@ pub struct ToolAuditEvent {
     pub sequence: u64,
     pub tool_id: String,
     pub request_id: String,
+    pub run_id: Option<String>,
     pub grant_ids: Vec<String>,
     pub input_hash: String,
     pub output_hash: String,
+    /// The redacted input and output, when payload capture is on.
+    pub input: Option<Value>,
+    pub output: Option<Value>,
     pub success: bool,
     pub duration_ms: u64,
     pub created_at: i64,
+    pub policy_decision: Option<ToolPolicyDecision>,
+    pub approval: Option<ToolApprovalRecord>,
+    pub consumed_grants: Vec<GrantConsumption>,
+    /// Size of the result content before it was truncated to fit the tool's limit.
+    pub original_result_bytes: Option<u64>,
+    /// The hash of the event before this one, chaining the log.
+    pub prev_hash: String,
+    /// SHA-256 of the event without this field.
+    pub hash: String,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ToolAuditConfig {
+    /// Record redacted inputs and outputs alongside their hashes.
+    pub capture_payloads: Option<bool>,
+    /// Where events are persisted as they are recorded. An existing log is continued.
+    pub sink: Option<AuditSinkConfig>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", rename_all_fields = "camelCase")]
+pub enum AuditSinkConfig {
+    /// Newline-delimited JSON.
+    File { path: String },
+    /// The persistence store's `tool_events` table.
+    PersistenceStore {
+        db_path: String,
+        encryption_key_ref: Option<String>,
+    },
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditChainReport {
+    pub valid: bool,
+    /// How many events verified before the first broken one.
+    pub checked: u64,
+    pub broken_at: Option<u64>,
+    pub error: Option<String>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -105,6 +407,87 @@ pub struct ToolGatewaySnapshot {
     pub tools: Vec<ToolRegistryEntry>,
     pub grants: Vec<CapabilityGrant>,
     pub audit_cursor: u64,
+    pub discovery_failures: Vec<ToolDiscoveryFailure>,
+    pub catalogs: Vec<ServerCatalog>,
+    pub resource_subscriptions: Vec<ResourceSubscription>,
+    pub pending_approvals: Vec<ToolApprovalRequest>,
+    /// The last error writing to the audit sink, if any write failed.
+    pub audit_sink_error: Option<String>,
+    pub servers: Vec<ServerHealth>,
+    /// The most recent state changes of supervised servers, oldest first.
+    pub lifecycle_events: Vec<ServerLifecycleEvent>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ToolDiscoveryFailure {
+    pub server_id: String,
+    pub error: String,
+    pub failed_at: i64,
+}
+
+/// The resources, resource templates and prompts last listed for a server. Lists that
+/// were never requested are `None`.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServerCatalog {
+    pub server_id: String,
+    pub resources: Option<Vec<McpResource>>,
+    pub resource_templates: Option<Vec<McpResourceTemplate>>,
+    pub prompts: Option<Vec<McpPrompt>>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct McpResource {
+    pub uri: String,
+    pub name: String,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub mime_type: Option<String>,
+    pub size: Option<u64>,
+    pub annotations: Option<Value>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct McpResourceTemplate {
+    /// An RFC 6570 URI template, e.g. `file:///{path}`.
+    pub uri_template: String,
+    pub name: String,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub mime_type: Option<String>,
+    pub annotations: Option<Value>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct McpPrompt {
+    pub name: String,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub arguments: Option<Vec<McpPromptArgument>>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct McpPromptArgument {
+    pub name: String,
+    pub description: Option<String>,
+    pub required: Option<bool>,
+}
+
+/// An active `resources/subscribe`. Each `notifications/resources/updated` for the uri
+/// bumps `update_count`; callers re-read the resource when it changes.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResourceSubscription {
+    pub server_id: String,
+    pub uri: String,
+    pub subscribed_at: i64,
+    pub updated_at: Option<i64>,
+    pub update_count: u64,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -133,6 +516,41 @@ pub struct McpToolCall {
     pub arguments: Value,
 }
 
+/// Caps on tool result size. Oversize content is truncated and the full content spilled to
+/// an artifact file, referenced from the result as a `resource` item.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ToolResultLimits {
+    /// Applies to every tool without its own entry in `tools`.
+    pub default: Option<ResultLimit>,
+    /// Keyed by tool id; replaces the default for that tool.
+    pub tools: Option<HashMap<String, ResultLimit>>,
+    /// Where full results are spilled. Defaults to `tool-gateway-artifacts` in the
+    /// system temp directory.
+    pub artifact_dir: Option<String>,
+}
+
+/// Limits on the serialized size of a result's content. With both set, the lower wins.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResultLimit {
+    pub max_bytes: Option<u64>,
+    /// Estimated at four bytes per token.
+    pub max_tokens: Option<u64>,
+    /// Which part of the text is kept. Defaults to `head`.
+    pub strategy: Option<TruncationStrategy>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TruncationStrategy {
+    #[default]
+    Head,
+    Tail,
+    /// Keeps the start and the end.
+    MiddleElide,
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct McpToolResult {
@@ -159,6 +577,70 @@ pub enum ToolErrorCode {
     SandboxViolation,
     InvalidArguments,
     ResourceNotFound,
+    Cancelled,
+}
+
+/// One item from a streaming tool call. `progress` and `partial` events may arrive in any
+/// number, after an `approval` event if the call had to wait for one; the stream always
+/// ends with exactly one `result`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ToolStreamEvent {
+    #[serde(rename = "type")]
+    pub event_type: String,
+    pub progress: Option<f64>,
+    pub total: Option<f64>,
+    pub message: Option<String>,
+    /// Content a server attached to its progress notification, ahead of the result.
+    pub content: Option<Vec<Value>>,
+    pub approval: Option<ToolApprovalRequest>,
+    pub result: Option<McpToolResult>,
+}
+
+impl ToolStreamEvent {
+    fn of(event_type: &str) -> Self {
+        Self {
+            event_type: event_type.to_string(),
+            progress: None,
+            total: None,
+            message: None,
+            content: None,
+            approval: None,
+            result: None,
+        }
+    }
+
+    /// Maps `notifications/progress` params. Progress carrying a `content` array is a
+    /// `partial` event.
+    pub fn progress(params: &Value) -> Self {
+        let content = params.get("content").and_then(Value::as_array).cloned();
+        let event_type = if content.is_some() { "partial" } else { "progress" };
+        Self {
+            progress: params.get("progress").and_then(Value::as_f64),
+            total: params.get("total").and_then(Value::as_f64),
+            message: params
+                .get("message")
+                .and_then(Value::as_str)
+                .map(str::to_string),
+            content,
+            ..Self::of(event_type)
+        }
+    }
+
+    /// The call is parked until `request` is resolved.
+    pub fn approval(request: ToolApprovalRequest) -> Self {
+        Self {
+            approval: Some(request),
+            ..Self::of("approval")
+        }
+    }
+
+    pub fn result(result: McpToolResult) -> Self {
+        Self {
+            result: Some(result),
+            ..Self::of("result")
+        }
+    }
 }
 
 impl ToolError {
@@ -193,4 +675,30 @@ impl McpToolResult {
     pub fn resource_not_found(message: impl Into<String>) -> Self {
         Self::failure(ToolErrorCode::ResourceNotFound, message)
     }
+
+    pub fn empty() -> Self {
+        Self {
+            success: true,
+            content: Vec::new(),
+            error: None,
+            meta: None,
+        }
+    }
+
+    /// Lists every schema violation in `error.details.violations`, so the caller can tell
+    /// the model exactly which arguments to fix.
+    pub fn invalid_arguments(violations: &[SchemaViolation]) -> Self {
+        let summary: Vec<String> = violations
+            .iter()
+            .map(|violation| format!("{}: {}", violation.path, violation.message))
+            .collect();
+        let mut result = Self::failure(
+            ToolErrorCode::InvalidArguments,
+            format!("Invalid tool arguments: {}", summary.join("; ")),
+        );
+        if let Some(error) = result.error.as_mut() {
+            error.details = Some(serde_json::json!({ "violations": violations }));
+        }
+        result
+    }
 }
@@ -1,36 +1,97 @@
 import type {
+  AuditChainReport,
+  AuditSinkConfig,
   CapabilityGrant,
+  GrantConstraints,
+  GrantConsumption,
   MCPToolResult,
   McpManifest,
+  McpPrompt,
+  McpPromptRequest,
+  McpResource,
+  McpResourceRequest,
+  McpResourceTemplate,
   McpServerConfig,
+  McpServerHealth,
+  McpServerLifecycleEvent,
+  McpServerState,
+  McpSupervisionConfig,
   McpTransport,
+  ToolApprovalDecisionInput,
+  ToolApprovalRequest,
+  ToolAuditConfig,
   ToolAuditEvent,
+  ToolDiscoveryFailure,
   ToolGatewaySnapshot,
   ToolInvocation,
+  ToolPolicyConfig,
   ToolRegistryEntry,
+  ToolResultLimit,
+  ToolResultLimits,
+  ToolStreamEvent,
+  ToolStreamHandle,
+  ToolTruncationStrategy,
 } from "@ku0/agent-runtime-core";
 
 export type {
+  AuditChainReport,
+  AuditSinkConfig,
   CapabilityGrant,
+  GrantConstraints,
+  GrantConsumption,
   McpManifest,
+  McpPrompt,
+  McpPromptRequest,
+  McpResource,
+  McpResourceRequest,
+  McpResourceTemplate,
   McpServerConfig,
+  McpServerHealth,
+  McpServerLifecycleEvent,
+  McpServerState,
+  McpSupervisionConfig,
   McpTransport,
+  ToolApprovalDecisionInput,
+  ToolApprovalRequest,
+  ToolAuditConfig,
   ToolAuditEvent,
+  ToolDiscoveryFailure,
   ToolGatewaySnapshot,
   ToolInvocation,
+  ToolPolicyConfig,
   ToolRegistryEntry,
+  ToolResultLimit,
+  ToolResultLimits,
+  ToolStreamEvent,
+  ToolStreamHandle,
+  ToolTruncationStrategy,
 };
 
 export type ToolGatewayBinding = {
   registerManifest: (manifest: McpManifest) => void;
-  registerServer: (config: McpServerConfig) => void;
+  registerServer: (config: McpServerConfig) => Promise<void>;
   listTools: () => ToolRegistryEntry[];
   callTool: (invocation: ToolInvocation) => Promise<MCPToolResult>;
+  callToolStream: (invocation: ToolInvocation) => Promise<ToolStreamHandle>;
+  listResources: (serverId: string) => Promise<McpResource[]>;
+  listResourceTemplates: (serverId: string) => Promise<McpResourceTemplate[]>;
+  readResource: (request: McpResourceRequest) => Promise<MCPToolResult>;
+  subscribeResource: (request: McpResourceRequest) => Promise<MCPToolResult>;
+  unsubscribeResource: (request: McpResourceRequest) => Promise<MCPToolResult>;
+  listPrompts: (serverId: string) => Promise<McpPrompt[]>;
+  getPrompt: (request: McpPromptRequest) => Promise<MCPToolResult>;
+  setPolicy: (config: ToolPolicyConfig) => void;
+  setResultLimits: (limits: ToolResultLimits) => void;
+  listApprovals: () => ToolApprovalRequest[];
+  resolveApproval: (decision: ToolApprovalDecisionInput) => void;
   grantCapability: (grant: CapabilityGrant) => string;
   revokeCapability: (grantId: string) => void;
   drainAuditEvents: (after?: number, limit?: number) => ToolAuditEvent[];
+  configureAudit: (config: ToolAuditConfig) => Promise<void>;
+  verifyAuditChain: (events?: ToolAuditEvent[]) => Promise<AuditChainReport>;
   getSnapshot: () => ToolGatewaySnapshot;
   reset: () => void;
+  shutdown: () => Promise<void>;
 };
 
 export type NativeToolGatewayBinding = {
@@ -0,0 +1,77 @@
+use crate::mcp::{Channel, McpError};
+use futures_util::{SinkExt, StreamExt};
+use serde_json::Value;
+use std::sync::Arc;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::error::{Error, ProtocolError};
+use tokio_tungstenite::tungstenite::http::HeaderValue;
+use tokio_tungstenite::tungstenite::Message;
+
+/// Connects, offering the `mcp` subprotocol, and starts the reader and writer tasks. Each
+/// text frame carries one JSON-RPC message.
+pub(crate) async fn open(
+    url: &str,
+    channel: &Arc<Channel>,
+    mut queued: mpsc::UnboundedReceiver<Value>,
+    deadline: Instant,
+) -> Result<(), McpError> {
+    // Most servers do not answer with the subprotocol, which fails the handshake, so they
+    // are connected to again without offering it.
+    let mut offer_subprotocol = true;
+    let socket = loop {
+        let mut request = url
+            .into_client_request()
+            .map_err(|error| McpError::Failure(format!("Invalid MCP WebSocket url: {error}")))?;
+        if offer_subprotocol {
+            request
+                .headers_mut()
+                .insert("sec-websocket-protocol", HeaderValue::from_static("mcp"));
+        }
+        let connected =
+            tokio::time::timeout_at(deadline, tokio_tungstenite::connect_async(request))
+                .await
+                .map_err(|_| McpError::Timeout("MCP WebSocket connect timed out".to_string()))?;
+        match connected {
+            Ok((socket, _)) => break socket,
+            Err(Error::Protocol(ProtocolError::SecWebSocketSubProtocolError(_)))
+                if offer_subprotocol =>
+            {
+                offer_subprotocol = false;
+            }
+            Err(error) => {
+                let message = format!("MCP WebSocket connect failed: {error}");
+                return Err(McpError::Failure(message));
+            }
+        }
+    };
+
+    let (mut sink, mut source) = socket.split();
+    let writer = channel.clone();
+    tokio::spawn(async move {
+        while let Some(message) = queued.recv().await {
+            if sink.send(Message::text(message.to_string())).await.is_err() {
+                writer.close();
+                return;
+            }
+        }
+        let _ = sink.send(Message::Close(None)).await;
+    });
+    let reader = channel.clone();
+    tokio::spawn(async move {
+        while let Some(frame) = source.next().await {
+            let message = match frame {
+                Ok(Message::Text(text)) => serde_json::from_str::<Value>(text.as_str()),
+                Ok(Message::Binary(bytes)) => serde_json::from_slice::<Value>(&bytes),
+                Ok(Message::Close(_)) | Err(_) => break,
+                Ok(_) => continue,
+            };
+            if let Ok(message) = message {
+                reader.handle_message(&message);
+            }
+        }
+        reader.close();
+    });
+    Ok(())
+}